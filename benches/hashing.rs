@@ -0,0 +1,115 @@
+//! Hashing throughput over synthetic trees, run with `cargo bench --bench hashing`.
+
+use std::{fs, fs::File, path::Path};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use secret_online_patcher::{
+    cancellation::CancellationToken,
+    client::update_client::index_install,
+    indexer::{
+        file_hasher::{ReadStrategy, hash_content},
+        hash_algorithm::HashAlgorithm,
+    },
+};
+use tempfile::TempDir;
+
+const ALGORITHMS: [HashAlgorithm; 3] = [
+    HashAlgorithm::Sha256,
+    HashAlgorithm::Blake3,
+    HashAlgorithm::Xxh3,
+];
+
+/// Pseudo-random bytes, so that the content looks like the assets of a real application.
+fn synthetic_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = 0x2545f4914f6cdd1d ^ seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Write a tree of `dir_count` directories holding `files_per_dir` files of `file_size` bytes,
+/// returning its total size.
+fn synthetic_tree(root: &Path, dir_count: usize, files_per_dir: usize, file_size: usize) -> u64 {
+    for dir in 0..dir_count {
+        let dir_path = root.join(format!("dir_{}", dir));
+        fs::create_dir_all(&dir_path).unwrap();
+        for file in 0..files_per_dir {
+            let seed = (dir * files_per_dir + file) as u64;
+            fs::write(
+                dir_path.join(format!("file_{}.bin", file)),
+                synthetic_bytes(file_size, seed),
+            )
+            .unwrap();
+        }
+    }
+    (dir_count * files_per_dir * file_size) as u64
+}
+
+fn read_strategies(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("large.pak");
+    let file_size = 64 * 1024 * 1024;
+    fs::write(&path, synthetic_bytes(file_size, 1)).unwrap();
+
+    let cancellation = CancellationToken::new();
+    let mut group = c.benchmark_group("read_strategy");
+    group.throughput(Throughput::Bytes(file_size as u64));
+    group.sample_size(10);
+    for algorithm in ALGORITHMS {
+        for strategy in [ReadStrategy::Buffered, ReadStrategy::Mmap] {
+            let id = BenchmarkId::new(algorithm.to_string(), format!("{:?}", strategy));
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    let mut file = File::open(&path).unwrap();
+                    let mut hasher = algorithm.hasher();
+                    hash_content(
+                        &mut file,
+                        file_size as u64,
+                        strategy,
+                        hasher.as_mut(),
+                        &cancellation,
+                    )
+                    .unwrap();
+                    hasher.finalize_hex()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn trees(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    // Many small files, like scripts and configuration, and a few large ones, like asset archives
+    let small_files = TempDir::new().unwrap();
+    let small_size = synthetic_tree(small_files.path(), 20, 100, 4 * 1024);
+    let large_files = TempDir::new().unwrap();
+    let large_size = synthetic_tree(large_files.path(), 2, 2, 32 * 1024 * 1024);
+
+    let mut group = c.benchmark_group("tree");
+    group.sample_size(10);
+    for (name, root, size) in [
+        ("small_files", small_files.path(), small_size),
+        ("large_files", large_files.path(), large_size),
+    ] {
+        group.throughput(Throughput::Bytes(size));
+        for algorithm in ALGORITHMS {
+            group.bench_function(BenchmarkId::new(algorithm.to_string(), name), |b| {
+                b.iter(|| {
+                    runtime
+                        .block_on(index_install(root, algorithm, &CancellationToken::new()))
+                        .unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, read_strategies, trees);
+criterion_main!(benches);
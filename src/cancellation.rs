@@ -0,0 +1,108 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, Read},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Error returned by operations stopped through their cancellation token.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Cancelled {
+    /// Whether the error, or the I/O error it wraps, comes from a cancellation.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            cause.is::<Cancelled>()
+                || cause
+                    .downcast_ref::<io::Error>()
+                    .and_then(|e| e.get_ref())
+                    .is_some_and(|inner| inner.is::<Cancelled>())
+        })
+    }
+}
+
+/// Shared flag asking long running operations to stop. Indexing and patch creation check it
+/// between files and while reading them. Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fail with `Cancelled` if the token was cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Wrap a reader so that reads fail once the token is cancelled.
+    pub fn reader<R: Read>(&self, inner: R) -> CancellableReader<R> {
+        CancellableReader {
+            inner,
+            token: self.clone(),
+        }
+    }
+}
+
+pub struct CancellableReader<R> {
+    inner: R,
+    token: CancellationToken,
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Not `Interrupted`, which readers retry
+        self.token.check().map_err(io::Error::other)?;
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::{CancellationToken, Cancelled};
+
+    #[test]
+    fn cancellable_reader_test() {
+        let token = CancellationToken::new();
+        let mut reader = token.reader(&b"abc"[..]);
+        let mut buf = [0; 2];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+
+        token.clone().cancel();
+        let error = reader.read(&mut buf).unwrap_err();
+        assert!(Cancelled::is(&anyhow::Error::from(error)));
+        assert!(Cancelled::is(
+            &anyhow::Error::from(Cancelled).context("Error")
+        ));
+        assert!(!Cancelled::is(&anyhow::Error::from(io::Error::other(
+            "Error"
+        ))));
+    }
+}
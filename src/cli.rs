@@ -1,41 +1,216 @@
 use std::{
-    fs,
+    collections::HashSet,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
 use anyhow::anyhow;
 use clap::{Parser, ValueEnum};
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+use tokio::net::TcpListener;
 
 use crate::{
-    indexer::{dir_hasher::DirHasher, file_change::FileChange, indexer_config::IndexerConfig},
-    service::app_manager::AppManager,
-    storage::{application_data::Application, patch_zip::PatchZip, patcher_db::PatcherDatabase},
+    cancellation::CancellationToken,
+    client::{
+        install_repairer::InstallRepairer, patch_client::PatchClient, update_client::UpdateClient,
+    },
+    indexer::{chunking::ChunkingConfig, hash_algorithm::HashAlgorithm, tree_diff::diff_trees},
+    patcher::{DiffPatch, PatchOutcome, Patcher, PatcherConfig, create_diff_patch},
+    progress::{Progress, ProgressReporter, ProgressStage},
+    server::patch_server::PatchServer,
+    service::{
+        app_manager::AppManager,
+        index_watcher::IndexWatcher,
+        install_manager::{InstallManager, InstallStatus},
+    },
+    storage::{
+        application_data::{AppSettings, AppTarget, DEFAULT_CHANNEL, DEFAULT_PLATFORM},
+        blob_store::BlobStore,
+        patch_compression::PatchCompression,
+        patch_config::PatchConfig,
+        patch_reader::PatchReader,
+        patch_zip::remove_patch_files,
+        patcher_db::PatcherDatabase,
+        version_scheme::VersionScheme,
+    },
 };
 
+/// Database the CLI records applications and installs in.
+pub const DATABASE_PATH: &str = "resources/app_data.db";
+
 #[derive(Parser, Debug)]
 pub struct Args {
     /// Operation to perform
     #[arg(help = "Operation to perform")]
     pub op: Operation,
 
+    #[arg(help = "Old and new directories to compare when operation is diff")]
+    pub dirs: Vec<PathBuf>,
+
     // Application name to add
     #[arg(
         long,
-        help = "Name of the application to add, required when operation is add-app"
+        visible_alias = "app",
+        help = "Name of the application, required by the operations working on one application"
     )]
     pub app_name: Option<String>,
 
     #[arg(
         long,
-        help = "Version of the application to add, required when operation is add-app"
+        help = "Version of the application to add, required when operation is add-app, update or promote"
     )]
     pub app_version: Option<String>,
 
     #[arg(
         long,
-        help = "Path to the application to add, required when operation is add-app"
+        help = "Path to the application to add, or to an install, required when operation is add-app, client-update, repair, add-install or remove-install"
     )]
     pub app_path: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = PatchCompression::default(),
+        help = "Compression used for patch entries when operation is update, e.g. stored, deflate:9, zstd:19 or bzip2"
+    )]
+    pub compression: PatchCompression,
+
+    #[arg(
+        long,
+        help = "Compress every file in the patch, even the ones that are already compressed (png, ogg, zip...)"
+    )]
+    pub no_auto_store: bool,
+
+    #[arg(
+        long,
+        value_parser = parse_size,
+        help = "Maximum size of a single patch file when operation is update, e.g. 500M or 2G, larger patches are split into several parts"
+    )]
+    pub max_part_size: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Path to the patch file (first part for multi-part patches), required when operation is inspect or repair"
+    )]
+    pub patch_path: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Directory of the content-addressed blob store, patches only reference file contents stored there when set, required when operation is gc"
+    )]
+    pub blob_store: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Number of most recent versions to retain for each application when operation is gc"
+    )]
+    pub keep_versions: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Split large files into content-defined chunks when operation is add-app or update, so that patches only ship the chunks that changed"
+    )]
+    pub chunking: bool,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8080",
+        help = "Address to listen on when operation is serve"
+    )]
+    pub listen: SocketAddr,
+
+    #[arg(
+        long,
+        help = "URL of the patch server, required when operation is client-update. Latest versions are read from it when operation is list-installs"
+    )]
+    pub server: Option<String>,
+
+    #[arg(
+        long,
+        help = "Directory keeping downloaded patches when operation is client-update, so that interrupted downloads can be resumed. Defaults to a directory under the system temp dir"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Hash every install again before listing them when operation is list-installs"
+    )]
+    pub verify: bool,
+
+    #[arg(
+        long,
+        default_value = DEFAULT_CHANNEL,
+        value_parser = parse_channel,
+        help = "Release channel of the application, e.g. stable, beta or nightly. Every channel has its own versions and patches"
+    )]
+    pub channel: String,
+
+    #[arg(
+        long,
+        default_value = DEFAULT_PLATFORM,
+        value_parser = parse_platform,
+        help = "Platform of the application tree, e.g. linux-x86_64, linux-aarch64 or windows-x86_64. Every platform has its own source, versions and patches"
+    )]
+    pub platform: String,
+
+    #[arg(
+        long,
+        default_value_t = VersionScheme::default(),
+        help = "How versions are validated and ordered when operation is add-app: semver, or free-form to accept any version ordered by release"
+    )]
+    pub version_scheme: VersionScheme,
+
+    #[arg(
+        long,
+        default_value_t = HashAlgorithm::default(),
+        help = "Algorithm files are hashed with when operation is add-app or diff: sha256, blake3, or xxh3 for fast checks that don't need a cryptographic hash"
+    )]
+    pub hash_algorithm: HashAlgorithm,
+
+    #[arg(
+        long,
+        help = "Accept a version that is not greater than the current one when operation is update, or replace a recorded patch that still exists when operation is create-patch"
+    )]
+    pub force: bool,
+
+    #[arg(
+        long,
+        value_parser = parse_channel,
+        help = "Channel to promote the version to, required when operation is promote"
+    )]
+    pub to_channel: Option<String>,
+
+    #[arg(
+        long,
+        help = "Directory to write the patch between the two directories to when operation is diff, --app-name, --base-version and --app-version are then required"
+    )]
+    pub patch_out: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Version of the old directory recorded in the patch when operation is diff"
+    )]
+    pub base_version: Option<String>,
+
+    #[arg(
+        long,
+        help = "Directory keeping the files of every released version when operation is add-app or update, required when operation is create-patch or when updating an application added with it"
+    )]
+    pub snapshot_store: Option<PathBuf>,
+
+    #[arg(
+        long,
+        visible_alias = "from",
+        help = "Released version the patch applies to, required when operation is create-patch"
+    )]
+    pub from_version: Option<String>,
+
+    #[arg(
+        long,
+        visible_alias = "to",
+        help = "Released version the patch leads to, required when operation is create-patch"
+    )]
+    pub to_version: Option<String>,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -46,6 +221,60 @@ pub enum Operation {
     RemoveApp,
     Check,
     Update,
+    Inspect,
+    Gc,
+    Serve,
+    ClientUpdate,
+    Package,
+    Repair,
+    AddInstall,
+    RemoveInstall,
+    ListInstalls,
+    Promote,
+    Watch,
+    Diff,
+    CreatePatch,
+}
+
+/// Parse a channel name, which is also used as a directory name for its patches.
+fn parse_channel(s: &str) -> Result<String, String> {
+    if is_valid_file_name(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("Invalid channel: {}", s))
+    }
+}
+
+/// Parse a platform name, which is also used in the file names of its patches.
+fn parse_platform(s: &str) -> Result<String, String> {
+    if is_valid_file_name(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("Invalid platform: {}", s))
+    }
+}
+
+fn is_valid_file_name(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('.')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Parse a size in bytes with an optional K, M or G suffix (powers of 1024).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1024),
+        Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size: {}", s))
 }
 
 pub async fn list_apps(db: &PatcherDatabase) {
@@ -53,15 +282,17 @@ pub async fn list_apps(db: &PatcherDatabase) {
     let apps = db.list_applications().await;
     for app in apps {
         tracing::info!(
-            "ID: {}, Name: {}, Version: {}, Hash: {:?}",
+            "ID: {}, Name: {}, Channel: {}, Platform: {}, Version: {}, Hash: {:?}",
             app.id,
             app.name,
+            app.channel,
+            app.platform,
             app.version,
             app.hash_code
         );
 
         let app_id = app.id;
-        let root = app.install_path.display().to_string();
+        let root = app.source_path.display().to_string();
         let indexed_files = db.get_files_in_directory(app_id, &root).await;
         if let Ok(files) = indexed_files {
             tracing::info!("  Indexed files for app {}:", app.name);
@@ -74,138 +305,605 @@ pub async fn list_apps(db: &PatcherDatabase) {
 
 pub async fn add_app(
     name: &str,
+    target: &AppTarget,
     version: &str,
+    settings: AppSettings,
     path: &PathBuf,
+    chunking: Option<ChunkingConfig>,
     app_manager: &AppManager,
 ) -> Result<(), anyhow::Error> {
     // Implementation for adding an app
-    let _app = app_manager.create_application(name, version, path).await?;
+    let _app = app_manager
+        .create_application(name, target, version, settings, path, chunking)
+        .await?;
 
     Ok(())
 }
 
-pub async fn remove_app(name: &str, db: &PatcherDatabase) {
-    db.remove_application(name).await;
+pub async fn remove_app(
+    name: &str,
+    target: &AppTarget,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    db.remove_application(name, target).await?;
+    Ok(())
 }
 
-pub async fn check_app(name: &str, db: &PatcherDatabase) -> Result<(), anyhow::Error> {
-    let app = db.get_application(name).await?;
-    match app {
-        Some(app) => {
-            tracing::info!(
-                "ID: {}, Name: {}, Version: {}, Hash: {:?}",
-                app.id,
-                app.name,
-                app.version,
-                app.hash_code
-            );
-            if app.hash_code.is_none() {
-                return Err(anyhow!(
-                    "Failed to check application due to missing hash code, it might not be initialized properly!"
-                ));
+pub async fn check_app(
+    name: &str,
+    target: &AppTarget,
+    progress: Option<Arc<dyn ProgressReporter>>,
+    cancellation: &CancellationToken,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let config = patcher_config(&PatchConfig::default(), None)
+        .with_progress(progress)
+        .with_cancellation(cancellation.clone());
+    let result = Patcher::with_database(db.clone(), config)
+        .check(name, target)
+        .await?;
+    let app = &result.app;
+    tracing::info!(
+        "ID: {}, Name: {}, Version: {}, Hash: {:?}",
+        app.id,
+        app.name,
+        app.version,
+        app.hash_code
+    );
+    if result.from_live_index {
+        tracing::info!("Using the live index of the running watcher");
+    }
+    if result.has_changes() {
+        tracing::info!("Changes detected for application {}!", app.name);
+        for change in &result.changes {
+            tracing::info!(" - {}", change);
+        }
+        tracing::info!("New hash: {}", result.hash_code);
+    } else {
+        tracing::info!("No changes detected for application {}", app.name);
+    }
+    Ok(())
+}
+
+/// Build the patch of a new version of an application. The new version has to be greater than the
+/// current one unless `force` is set.
+pub async fn update_app(
+    name: &str,
+    target: &AppTarget,
+    version: &str,
+    force: bool,
+    chunking: Option<ChunkingConfig>,
+    patch_config: &PatchConfig,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let patcher = Patcher::with_database(db.clone(), patcher_config(patch_config, chunking));
+    if force {
+        tracing::warn!("Skipping version order check for {}", version);
+    }
+    match patcher.create_patch(name, target, version, force).await? {
+        PatchOutcome::Unchanged => {
+            tracing::info!("No changes detected for application {}", name);
+            tracing::info!("Skip updating...");
+        }
+        PatchOutcome::Created(patch) => {
+            tracing::info!("Changes detected for application {}!", name);
+            for change in &patch.changes {
+                tracing::info!(" - {}", change);
             }
-            let old_hash = app.hash_code.clone().unwrap();
-
-            let indexer_config = IndexerConfig::new(app.id, db.clone(), false);
-            let hasher = DirHasher::new(indexer_config);
-            let new_hash = hasher.dir_hash(&PathBuf::from(&app.install_path)).await?;
-            let (new_hash, file_changes) = new_hash.finalize().await;
-            if new_hash == old_hash {
-                tracing::info!("No changes detected for application {}", app.name);
-            } else {
-                tracing::info!("Changes detected for application {}!", app.name);
-                for change in &file_changes {
-                    tracing::info!(" - [{}] {}", change.change_type, change.file_path);
+            for shared in &patch.shared_patches {
+                match &shared.error {
+                    Some(e) => {
+                        tracing::warn!("Cannot share files with {}: {}", shared.patch_path, e)
+                    }
+                    None => tracing::info!(
+                        "Shared {} file(s) with the {} patch of version {}",
+                        shared.file_count,
+                        shared.platform,
+                        patch.version
+                    ),
                 }
-                tracing::info!("New hash: {}", new_hash);
+            }
+            tracing::info!(
+                "Updated {} from {} to {} (hash {}), patch {} validated",
+                name,
+                patch.base_version,
+                patch.version,
+                patch.hash_code,
+                patch.patch_path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Build the patch between two released versions of an application from their snapshots.
+/// A recorded patch that still exists is only replaced if `force` is set.
+pub async fn create_patch(
+    name: &str,
+    target: &AppTarget,
+    from: &str,
+    to: &str,
+    force: bool,
+    patch_config: &PatchConfig,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let patcher = Patcher::with_database(db.clone(), patcher_config(patch_config, None));
+    let patch = patcher
+        .create_patch_between(name, target, from, to, force)
+        .await?;
+    for change in &patch.changes {
+        tracing::info!(" - {}", change);
+    }
+    tracing::info!(
+        "Patch of {} from {} to {} created at {} ({} part(s))",
+        name,
+        patch.base_version,
+        patch.version,
+        patch.patch_path.display(),
+        patch.part_count
+    );
+    Ok(())
+}
+
+/// Remove the blobs no recorded version references from the blob store, and the files no recorded
+/// version contains from the snapshot store. When `keep_versions` is given, only the most recent
+/// versions of each application are retained, and the patches of the others are removed.
+pub async fn collect_garbage(
+    blob_store: Option<&BlobStore>,
+    snapshot_store: Option<&BlobStore>,
+    keep_versions: Option<usize>,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    if let Some(keep_versions) = keep_versions {
+        if keep_versions == 0 {
+            return Err(anyhow!("At least one version must be kept"));
+        }
+        let pruned = db.prune_versions(keep_versions).await?;
+        // Patches promoted to another channel are still used by the version there
+        let retained: HashSet<String> = db.list_patch_paths().await?.into_iter().collect();
+        let removed_patches: HashSet<&String> = pruned
+            .iter()
+            .filter_map(|version| version.patch_path.as_ref())
+            .filter(|patch_path| !retained.contains(*patch_path))
+            .collect();
+        for patch_path in &removed_patches {
+            remove_patch_files(Path::new(patch_path));
+            tracing::info!("Removed patch {}", patch_path);
+        }
+        tracing::info!(
+            "Removed {} old version(s) and {} patch(es)",
+            pruned.len(),
+            removed_patches.len()
+        );
+        if !pruned.is_empty() {
+            tracing::warn!("Installs still on a removed version can't be updated with patches");
+        }
+    }
+
+    let mut sweeps: Vec<(&BlobStore, HashSet<String>)> = Vec::new();
+    if let Some(blob_store) = blob_store {
+        let referenced = db.list_referenced_blobs().await?;
+        sweeps.push((blob_store, referenced.into_iter().collect()));
+    }
+    if let Some(snapshot_store) = snapshot_store {
+        let referenced = db.list_snapshot_blobs().await?;
+        // A directory used as both stores keeps what either of them references
+        match sweeps
+            .iter_mut()
+            .find(|(store, _)| store.root == snapshot_store.root)
+        {
+            Some((_, blobs)) => blobs.extend(referenced),
+            None => sweeps.push((snapshot_store, referenced.into_iter().collect())),
+        }
+    }
+    for (store, referenced) in sweeps {
+        let mut removed = 0;
+        for hash_code in store.list_blobs()? {
+            if !referenced.contains(&hash_code) {
+                store.remove(&hash_code)?;
+                tracing::info!("Removed blob {}", hash_code);
+                removed += 1;
             }
         }
-        None => {
-            return Err(anyhow!("Application not found"));
+        tracing::info!(
+            "Removed {} unreferenced blob(s) from {}",
+            removed,
+            store.root.display()
+        );
+    }
+    Ok(())
+}
+
+pub async fn inspect_patch(zip_path: &Path) -> Result<(), anyhow::Error> {
+    let reader = PatchReader::new(zip_path);
+    let patch_db = reader.open_patch_db().await?;
+    let patch_info = patch_db
+        .db
+        .get_patch_info()
+        .await?
+        .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+    if patch_info.is_full_package {
+        tracing::info!(
+            "Full package for {}: {}, created at {}",
+            patch_info.app_name,
+            patch_info.patch_version,
+            patch_info.created_at
+        );
+    } else {
+        tracing::info!(
+            "Patch for {}: {} -> {}, created at {}",
+            patch_info.app_name,
+            patch_info.base_version,
+            patch_info.patch_version,
+            patch_info.created_at
+        );
+    }
+    tracing::info!("  Platform: {}", patch_info.platform);
+    for (i, part) in reader.part_paths().iter().enumerate() {
+        tracing::info!("  Part {}: {}", i + 1, part.display());
+    }
+
+    let file_changes = patch_db.db.list_file_changes(patch_info.id).await?;
+    let file_chunks = patch_db.db.list_file_chunks(patch_info.id).await?;
+    patch_db.db.close().await;
+    for change in &file_changes {
+        let chunks: Vec<_> = file_chunks
+            .iter()
+            .filter(|chunk| chunk.file_change_id == change.id)
+            .collect();
+        let file_path = match &change.renamed_from {
+            Some(from) => format!("{} -> {}", from, change.file_path),
+            None => change.file_path.clone(),
+        };
+        match change.part_number {
+            Some(part_number) => tracing::info!(
+                " - [{}] {} (part {})",
+                change.change_type,
+                file_path,
+                part_number
+            ),
+            None if !chunks.is_empty() => tracing::info!(
+                " - [{}] {} ({} chunk(s), {} from the base version)",
+                change.change_type,
+                file_path,
+                chunks.len(),
+                chunks
+                    .iter()
+                    .filter(|chunk| chunk.entry_name.is_none())
+                    .count()
+            ),
+            None => tracing::info!(" - [{}] {}", change.change_type, file_path),
         }
     }
     Ok(())
 }
 
-pub async fn update_app(
+/// Run the patch server until it fails, blobs are served from the blob store if given.
+pub async fn serve(
+    listen: SocketAddr,
+    blob_store: Option<BlobStore>,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(listen).await?;
+    PatchServer::new(db.clone(), blob_store)
+        .serve(listener)
+        .await
+}
+
+/// Watch the source tree of an application until interrupted, keeping its live index up to date
+/// for the check operation.
+pub async fn watch_app(
     name: &str,
-    version: &str,
+    target: &AppTarget,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let app = db
+        .get_application(name, target)
+        .await?
+        .ok_or_else(|| anyhow!("Application not found"))?;
+    IndexWatcher::new(app, db.clone())
+        .run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+}
+
+/// Update a local install of an application to the latest version published on a patch server.
+/// The new state of the install is recorded if it is registered.
+pub async fn client_update(
+    server_url: &str,
+    name: &str,
+    target: &AppTarget,
+    install_path: &Path,
+    cache_dir: Option<&Path>,
     db: &PatcherDatabase,
 ) -> Result<(), anyhow::Error> {
-    let app = db.get_application(name).await?;
-    match app {
-        Some(app) => {
+    let cache_dir = match cache_dir {
+        Some(cache_dir) => cache_dir.to_path_buf(),
+        None => std::env::temp_dir().join("secret-online-patcher"),
+    };
+    let client = UpdateClient::new(server_url, &cache_dir)?.with_target(target);
+    let outcome = client.update(name, install_path).await?;
+    if outcome.updated {
+        tracing::info!("{} updated to version {}", name, outcome.version);
+    }
+
+    let Some(install) = InstallManager::new(db.clone())
+        .find_install(install_path)
+        .await?
+    else {
+        return Ok(());
+    };
+    // The install was verified against the version it was left at by the update
+    if outcome.updated {
+        db.record_install_patch(
+            install.id,
+            &outcome.version,
+            &outcome.hash_code,
+            outcome.hash_algorithm,
+        )
+        .await?
+    } else {
+        db.record_install_verification(
+            install.id,
+            Some(&outcome.version),
+            &outcome.hash_code,
+            outcome.hash_algorithm,
+        )
+        .await?
+    }
+    Ok(())
+}
+
+/// Register an installed copy of an application, recording the version it matches.
+pub async fn add_install(
+    name: &str,
+    target: &AppTarget,
+    install_path: &Path,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let install = InstallManager::new(db.clone())
+        .register_install(name, target, install_path)
+        .await?;
+    tracing::info!(
+        "Registered {} ({}) at {}",
+        install.app_name,
+        install.target(),
+        install.install_path.display()
+    );
+    Ok(())
+}
+
+pub async fn remove_install(
+    install_path: &Path,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let install = InstallManager::new(db.clone())
+        .find_install(install_path)
+        .await?
+        .ok_or_else(|| anyhow!("{} is not a registered install", install_path.display()))?;
+    db.remove_install(&install.install_path).await?;
+    Ok(())
+}
+
+/// List the registered installs and whether they are up to date. The latest versions are read
+/// from the patch server if given, or from the applications published from this database.
+pub async fn list_installs(
+    server_url: Option<&str>,
+    verify: bool,
+    db: &PatcherDatabase,
+) -> Result<(), anyhow::Error> {
+    let install_manager = InstallManager::new(db.clone());
+    let client = server_url.map(PatchClient::new).transpose()?;
+    for install in db.list_installs().await? {
+        let install = if verify {
+            install_manager.verify_install(&install).await?
+        } else {
+            install
+        };
+        let latest_version = match &client {
+            Some(client) => client
+                .clone()
+                .with_target(&install.target())
+                .latest_version(&install.app_name)
+                .await
+                .inspect_err(|e| tracing::info!("No latest version of {}: {}", install.app_name, e))
+                .ok()
+                .map(|latest| latest.version),
+            None => db
+                .get_application(&install.app_name, &install.target())
+                .await?
+                .map(|app| app.version),
+        };
+        let status = InstallManager::status(&install, latest_version.as_deref());
+        tracing::info!(
+            "{} ({}) at {}: version {}, {}",
+            install.app_name,
+            install.target(),
+            install.install_path.display(),
+            install.installed_version.as_deref().unwrap_or("unknown"),
+            status
+        );
+        if let Some(verified_at) = install.verified_at {
             tracing::info!(
-                "ID: {}, Name: {}, Current Version: {}, Hash: {:?}",
-                app.id,
-                app.name,
-                app.version,
-                app.hash_code
+                "  Last verified at {} (hash {})",
+                verified_at,
+                install.verified_hash.as_deref().unwrap_or("unknown")
             );
-            if app.hash_code.is_none() {
-                return Err(anyhow!(
-                    "Failed to update application due to missing hash code, it might not be initialized properly!"
-                ));
-            }
-            let old_hash = app.hash_code.clone().unwrap();
-
-            let indexer_config = IndexerConfig::new(app.id, db.clone(), true);
-            let hasher = DirHasher::new(indexer_config);
-            let new_hash = hasher.dir_hash(&PathBuf::from(&app.install_path)).await?;
-            let (new_hash, file_changes) = new_hash.finalize().await;
-            if new_hash == old_hash {
-                tracing::info!("No changes detected for application {}", app.name);
-                tracing::info!("Skip updating...");
-            } else {
-                tracing::info!("Changes detected for application {}!", app.name);
-                for change in &file_changes {
-                    tracing::info!(" - [{}] {}", change.change_type, change.file_path);
-                }
-                tracing::info!("New hash: {}", new_hash);
-                tracing::info!("Updating version to {}...", version);
-                let new_version = Application {
-                    id: app.id,
-                    name: app.name.clone(),
-                    version: version.to_string(),
-                    install_path: app.install_path.clone(),
-                    hash_code: Some(new_hash),
-                };
-                db.update_application(
-                    &new_version.id,
-                    &new_version.version,
-                    new_version.hash_code.as_ref().unwrap(),
-                )
-                .await;
-
-                // Create the zip package for the update
-                // TODO: use prod directory
-                let out_dir = PathBuf::from("fs_tests/patches");
-                create_zip_package(&app, &version, &file_changes, &out_dir).await?;
-            }
         }
-        None => {
-            return Err(anyhow!("Application not found"));
+        if let (Some(version), Some(patched_at)) =
+            (&install.last_patch_version, install.last_patched_at)
+        {
+            tracing::info!("  Last patched to {} at {}", version, patched_at);
+        }
+        if status != InstallStatus::UpToDate {
+            tracing::info!("  Run client-update to bring it up to date");
         }
     }
     Ok(())
 }
 
-async fn create_zip_package(
-    app: &Application,
-    new_version: &str,
-    file_changes: &[FileChange],
-    out_dir: &Path,
+/// Create a full package of the current version of an application, which installs can be
+/// repaired from. Returns the path to the package.
+pub async fn package_app(
+    name: &str,
+    target: &AppTarget,
+    patch_config: &PatchConfig,
+    db: &PatcherDatabase,
+) -> Result<PathBuf, anyhow::Error> {
+    Patcher::with_database(db.clone(), patcher_config(patch_config, None))
+        .create_package(name, target)
+        .await
+}
+
+/// Promote the current version of a channel to another channel of the same platform, reusing its
+/// patches.
+pub async fn promote_app(
+    name: &str,
+    from: &AppTarget,
+    to_channel: &str,
+    version: &str,
+    app_manager: &AppManager,
 ) -> Result<(), anyhow::Error> {
-    // Make sure output directory exists
-    fs::create_dir_all(out_dir)?;
+    let promoted = app_manager
+        .promote_version(name, from, to_channel, version)
+        .await?;
+    for version in &promoted {
+        tracing::info!(" - {} ({:?})", version.version, version.patch_path);
+    }
+    if !promoted.is_empty() {
+        tracing::info!(
+            "Sync the source directory of the {} channel with version {} before updating it",
+            to_channel,
+            version
+        );
+    }
+    Ok(())
+}
+
+/// Restore the missing, corrupted and extra files of an install from a full package.
+pub async fn repair_install(
+    package_path: &Path,
+    install_path: &Path,
+    blob_store: Option<&BlobStore>,
+) -> Result<(), anyhow::Error> {
+    let reader = match blob_store {
+        Some(blob_store) => PatchReader::with_blob_store(package_path, blob_store),
+        None => PatchReader::new(package_path),
+    };
+    reader.validate().await?;
+    InstallRepairer::new(install_path)?.repair(&reader).await?;
+    Ok(())
+}
+
+/// Renders the progress of indexing and packaging runs as a progress bar on the terminal, with a
+/// new bar for every run.
+#[derive(Default)]
+pub struct ProgressBarReporter {
+    // Bar of the run in progress, None between runs
+    bar: Mutex<Option<ProgressBar>>,
+}
 
-    // Initialize the patch (creates the database and zip file)
-    let mut zip = PatchZip::new(out_dir, app);
-    zip.initialize_patch(new_version).await?;
-    for change in file_changes {
-        // Add change to the patch database
-        zip.append_changed_file(change).await?;
+impl ProgressReporter for ProgressBarReporter {
+    fn on_progress(&self, progress: &Progress) {
+        let mut bar = self.bar.lock().unwrap();
+        let bar = bar.get_or_insert_with(|| {
+            let style = ProgressStyle::with_template("{prefix:>9} [{bar:30}] {pos}/{len} {msg}")
+                .unwrap()
+                .progress_chars("=> ");
+            ProgressBar::no_length().with_style(style)
+        });
+        bar.set_prefix(progress.stage.to_string());
+        if let Some(total_files) = progress.total_files {
+            bar.set_length(total_files);
+        }
+        bar.set_position(progress.files_scanned);
+        bar.set_message(progress_message(progress));
+    }
+
+    fn on_finish(&self, progress: &Progress) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.set_position(progress.files_scanned);
+            bar.finish_with_message(progress_message(progress));
+        }
+    }
+}
+
+fn progress_message(progress: &Progress) -> String {
+    let mut message = match progress.stage {
+        ProgressStage::Indexing => {
+            format!("files, {} hashed", HumanBytes(progress.bytes_hashed))
+        }
+        ProgressStage::Packaging => {
+            format!(
+                "changes, {} compressed",
+                HumanBytes(progress.bytes_compressed)
+            )
+        }
+    };
+    if let Some(eta) = progress.eta() {
+        message.push_str(&format!(", ETA {}", HumanDuration(eta)));
+    }
+    message
+}
+
+/// Compare two directories without recording anything, optionally writing the patch between
+/// them into `patch_out`.
+pub async fn diff_dirs(
+    old_dir: &Path,
+    new_dir: &Path,
+    hash_algorithm: HashAlgorithm,
+    patch_out: Option<(&Path, DiffPatch)>,
+    patch_config: &PatchConfig,
+) -> Result<(), anyhow::Error> {
+    let diff = diff_trees(
+        old_dir,
+        new_dir,
+        hash_algorithm,
+        patch_config.progress.clone(),
+        &patch_config.cancellation,
+    )
+    .await?;
+    tracing::info!("Old hash: {}", diff.old_hash);
+    tracing::info!("New hash: {}", diff.new_hash);
+    if !diff.has_changes() {
+        tracing::info!("No differences between the directories");
+        return Ok(());
+    }
+    tracing::info!("{} change(s) found:", diff.changes.len());
+    for change in &diff.changes {
+        tracing::info!(" - {}", change);
+    }
+    if let Some((out_dir, labels)) = patch_out {
+        let patch_path = create_diff_patch(&diff, &labels, out_dir, patch_config).await?;
+        tracing::info!(
+            "Patch {} -> {} written to {}",
+            labels.base_version,
+            labels.version,
+            patch_path.display()
+        );
     }
-    zip.finalize().await?;
     Ok(())
 }
+
+/// Config of the patcher the CLI operations go through, writing patches next to the tests.
+fn patcher_config(patch_config: &PatchConfig, chunking: Option<ChunkingConfig>) -> PatcherConfig {
+    // TODO: use prod directory
+    PatcherConfig::new(Path::new(DATABASE_PATH), Path::new("fs_tests/patches"))
+        .with_patch_config(patch_config.clone())
+        .with_chunking(chunking)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_size_test() {
+        assert_eq!(super::parse_size("1024"), Ok(1024));
+        assert_eq!(super::parse_size("4K"), Ok(4 * 1024));
+        assert_eq!(super::parse_size("500m"), Ok(500 * 1024 * 1024));
+        assert_eq!(super::parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert!(super::parse_size("2T").is_err());
+        assert!(super::parse_size("").is_err());
+        assert!(super::parse_size("99999999999G").is_err());
+    }
+}
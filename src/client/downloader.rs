@@ -0,0 +1,242 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use reqwest::{Client, StatusCode, Url, header};
+
+use crate::{indexer::hash_algorithm::HashAlgorithm, storage::blob_store::copy_and_hash};
+
+/// How many times a failed download is attempted and how long to wait in between.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Time to wait after the given failed attempt, doubling every time.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(5, Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+/// What a downloaded file is checked against, from the manifest published by the server.
+#[derive(Clone, Debug, Default)]
+pub struct ExpectedFile {
+    pub size: Option<u64>,
+    // Hash of the whole file, computed with `hash_algorithm`
+    pub hash_code: Option<String>,
+    // SHA-256 for patch parts, blobs are named after their hash in the algorithm of their
+    // application
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// Downloads files over HTTP into a cache directory.
+///
+/// Data is written to `<file>.partial` and only renamed to its final name once verified,
+/// so an interrupted download is resumed with a range request on the next attempt or run.
+/// A partial file that fails verification is thrown away and downloaded again from scratch.
+#[derive(Clone)]
+pub struct Downloader {
+    http: Client,
+    pub retry_policy: RetryPolicy,
+}
+
+enum DownloadError {
+    // Worth trying again, like a dropped connection or a server error
+    Retryable(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+impl Downloader {
+    pub fn new(retry_policy: RetryPolicy) -> Self {
+        Downloader {
+            http: Client::new(),
+            retry_policy,
+        }
+    }
+
+    /// Download a file unless a verified copy is already at the destination.
+    pub async fn download(
+        &self,
+        url: Url,
+        destination: &Path,
+        expected: &ExpectedFile,
+    ) -> Result<(), anyhow::Error> {
+        if destination.exists() {
+            if verify_file(destination, expected).is_ok() {
+                return Ok(());
+            }
+            fs::remove_file(destination)?;
+        }
+
+        let partial_path = partial_path(destination);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let error = match self.try_download(&url, &partial_path, expected).await {
+                Ok(()) => match verify_file(&partial_path, expected) {
+                    Ok(()) => {
+                        fs::rename(&partial_path, destination)?;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        // Resuming would keep the bad data, start again from scratch
+                        fs::remove_file(&partial_path)?;
+                        e
+                    }
+                },
+                Err(DownloadError::Retryable(e)) => e,
+                Err(DownloadError::Fatal(e)) => return Err(e),
+            };
+
+            if attempt >= self.retry_policy.max_attempts {
+                return Err(anyhow!(
+                    "Download of {} failed after {} attempt(s): {}",
+                    url,
+                    attempt,
+                    error
+                ));
+            }
+            let backoff = self.retry_policy.backoff(attempt);
+            tracing::warn!(
+                "Download of {} failed ({}), retrying in {} ms",
+                url,
+                error,
+                backoff.as_millis()
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Download the missing bytes of a partial file.
+    async fn try_download(
+        &self,
+        url: &Url,
+        partial_path: &Path,
+        expected: &ExpectedFile,
+    ) -> Result<(), DownloadError> {
+        let downloaded = fs::metadata(partial_path).map(|m| m.len()).unwrap_or(0);
+        if let Some(size) = expected.size {
+            if downloaded == size {
+                return Ok(());
+            }
+            if downloaded > size {
+                fs::remove_file(partial_path).map_err(|e| DownloadError::Fatal(e.into()))?;
+                return Box::pin(self.try_download(url, partial_path, expected)).await;
+            }
+        }
+
+        let mut request = self.http.get(url.clone());
+        if downloaded > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", downloaded));
+        }
+        let mut response = request
+            .send()
+            .await
+            .map_err(|e| DownloadError::Retryable(e.into()))?;
+        let status = response.status();
+        let file = match status {
+            StatusCode::PARTIAL_CONTENT => {
+                tracing::info!("Resuming download of {} from byte {}", url, downloaded);
+                OpenOptions::new().append(true).open(partial_path)
+            }
+            // The partial download already holds the whole file
+            StatusCode::RANGE_NOT_SATISFIABLE if downloaded > 0 => return Ok(()),
+            // The server ignored the range, start over
+            _ if status.is_success() => File::create(partial_path),
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => {
+                return Err(DownloadError::Retryable(anyhow!("{}", status)));
+            }
+            _ if status.is_server_error() => {
+                return Err(DownloadError::Retryable(anyhow!("{}", status)));
+            }
+            _ => {
+                return Err(DownloadError::Fatal(anyhow!(
+                    "Download of {} failed: {}",
+                    url,
+                    status
+                )));
+            }
+        };
+        let mut file = file.map_err(|e| DownloadError::Fatal(e.into()))?;
+        loop {
+            match response.chunk().await {
+                Ok(Some(bytes)) => file
+                    .write_all(&bytes)
+                    .map_err(|e| DownloadError::Fatal(e.into()))?,
+                Ok(None) => break,
+                // Keep what was received so far, the next attempt resumes from there
+                Err(e) => return Err(DownloadError::Retryable(e.into())),
+            }
+        }
+        file.flush().map_err(|e| DownloadError::Fatal(e.into()))?;
+        Ok(())
+    }
+}
+
+/// Path of the file holding an unfinished download.
+pub fn partial_path(destination: &Path) -> PathBuf {
+    let mut file_name = destination.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".partial");
+    destination.with_file_name(file_name)
+}
+
+fn verify_file(path: &Path, expected: &ExpectedFile) -> Result<(), anyhow::Error> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if let Some(expected_size) = expected.size
+        && size != expected_size
+    {
+        return Err(anyhow!(
+            "Downloaded {} bytes instead of {}",
+            size,
+            expected_size
+        ));
+    }
+    if let Some(expected_hash) = &expected.hash_code
+        && &copy_and_hash(&mut file, &mut io::sink(), expected.hash_algorithm)? != expected_hash
+    {
+        return Err(anyhow!("Downloaded file doesn't match its hash"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+    }
+}
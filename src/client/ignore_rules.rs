@@ -0,0 +1,88 @@
+use std::{fs, io, path::Path};
+
+use anyhow::anyhow;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Name of the file listing the paths of an install that belong to the user.
+pub const IGNORE_FILE_NAME: &str = ".patcherignore";
+
+/// Paths of an install that are left alone when it is repaired, read from `.patcherignore`
+/// at the root of the install.
+///
+/// Every line is a glob pattern relative to the install, empty lines and lines starting with
+/// `#` are skipped. Patterns without a `/` match a name at any depth, the others are anchored
+/// at the root. Everything under a matching directory is ignored as well.
+pub struct IgnoreRules {
+    globs: GlobSet,
+}
+
+impl IgnoreRules {
+    /// Load the rules of an install, an install without `.patcherignore` ignores nothing.
+    pub fn load(install_path: &Path) -> Result<Self, anyhow::Error> {
+        match fs::read_to_string(install_path.join(IGNORE_FILE_NAME)) {
+            Ok(content) => IgnoreRules::parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => IgnoreRules::parse(""),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let pattern = line.trim_end_matches('/');
+            let pattern = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{}", pattern),
+            };
+            let glob = GlobBuilder::new(&pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| anyhow!("Invalid pattern {} in {}: {}", line, IGNORE_FILE_NAME, e))?;
+            builder.add(glob);
+        }
+        Ok(IgnoreRules {
+            globs: builder.build()?,
+        })
+    }
+
+    /// Whether a path relative to the install is ignored, the ignore file itself always is.
+    pub fn is_ignored(&self, relative_path: &Path) -> bool {
+        relative_path == Path::new(IGNORE_FILE_NAME)
+            || relative_path
+                .ancestors()
+                .filter(|path| !path.as_os_str().is_empty())
+                .any(|path| self.globs.is_match(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::IgnoreRules;
+
+    #[test]
+    fn is_ignored_test() {
+        let rules = IgnoreRules::parse(
+            "# User files\n\
+             saves/\n\
+             *.log\n\
+             /config/user.ini\n\n",
+        )
+        .unwrap();
+        assert!(rules.is_ignored(Path::new(".patcherignore")));
+        assert!(rules.is_ignored(Path::new("saves")));
+        assert!(rules.is_ignored(Path::new("saves/slot1.dat")));
+        assert!(rules.is_ignored(Path::new("data/saves/slot1.dat")));
+        assert!(rules.is_ignored(Path::new("game.log")));
+        assert!(rules.is_ignored(Path::new("logs/debug.log")));
+        assert!(rules.is_ignored(Path::new("config/user.ini")));
+        assert!(!rules.is_ignored(Path::new("data/config/user.ini")));
+        assert!(!rules.is_ignored(Path::new("config/default.ini")));
+        assert!(!rules.is_ignored(Path::new("game.exe")));
+    }
+}
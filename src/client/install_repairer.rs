@@ -0,0 +1,240 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    client::{ignore_rules::IgnoreRules, patch_applier::PatchApplier},
+    indexer::hash_algorithm::HashAlgorithm,
+    storage::{blob_store::copy_and_hash, patch_reader::PatchReader},
+};
+
+/// Differences between an install and the manifest of a full package.
+/// Paths are relative to the install.
+#[derive(Debug, Default, PartialEq)]
+pub struct RepairReport {
+    pub missing: Vec<PathBuf>,
+    // Files and directories that are not part of the package and not ignored
+    pub extra: Vec<PathBuf>,
+    // Files whose content doesn't match the hash recorded in the package
+    pub corrupted: Vec<PathBuf>,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+/// A file or directory recorded in a full package.
+struct ManifestEntry {
+    // Path as recorded in the package
+    file_path: String,
+    file_type: String,
+    hash_code: Option<String>,
+    hash_algorithm: HashAlgorithm,
+}
+
+/// Restores an install to the state recorded in a full package.
+///
+/// Only missing and corrupted files are extracted from the package (or its blob store), and
+/// files that are not part of the package are removed. Paths matched by the install's
+/// `.patcherignore` are never checked or touched.
+pub struct InstallRepairer {
+    pub install_path: PathBuf,
+    pub ignore_rules: IgnoreRules,
+}
+
+impl InstallRepairer {
+    pub fn new(install_path: &Path) -> Result<Self, anyhow::Error> {
+        Ok(InstallRepairer {
+            install_path: install_path.to_path_buf(),
+            ignore_rules: IgnoreRules::load(install_path)?,
+        })
+    }
+
+    /// Compare the install with the package without modifying anything.
+    pub async fn scan(&self, reader: &PatchReader) -> Result<RepairReport, anyhow::Error> {
+        let manifest = self.read_manifest(reader).await?;
+        self.compare(&manifest)
+    }
+
+    /// Repair the install, returning the differences that were fixed.
+    pub async fn repair(&self, reader: &PatchReader) -> Result<RepairReport, anyhow::Error> {
+        let manifest = self.read_manifest(reader).await?;
+        let report = self.compare(&manifest)?;
+        if report.is_clean() {
+            tracing::info!("{} is intact", self.install_path.display());
+            return Ok(report);
+        }
+        for path in &report.missing {
+            tracing::info!(" - [MISSING] {}", path.display());
+        }
+        for path in &report.corrupted {
+            tracing::info!(" - [CORRUPTED] {}", path.display());
+        }
+        for path in &report.extra {
+            tracing::info!(" - [EXTRA] {}", path.display());
+        }
+
+        self.remove_extra(&report.extra)?;
+        // Make room for entries replaced by one of a different type
+        for path in &report.missing {
+            let full_path = self.install_path.join(path);
+            match fs::symlink_metadata(&full_path) {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&full_path)?,
+                Ok(_) => fs::remove_file(&full_path)?,
+                Err(_) => {}
+            }
+        }
+        let restored: HashSet<&str> = report
+            .missing
+            .iter()
+            .chain(&report.corrupted)
+            .map(|path| manifest[path].file_path.as_str())
+            .collect();
+        PatchApplier::new(&self.install_path)
+            .apply_filtered(reader, |change| {
+                restored.contains(change.file_path.as_str())
+            })
+            .await?;
+
+        if !self.compare(&manifest)?.is_clean() {
+            return Err(anyhow!(
+                "{} still differs from the package after being repaired",
+                self.install_path.display()
+            ));
+        }
+        tracing::info!(
+            "Repaired {}: {} missing, {} corrupted and {} extra file(s)",
+            self.install_path.display(),
+            report.missing.len(),
+            report.corrupted.len(),
+            report.extra.len()
+        );
+        Ok(report)
+    }
+
+    /// Read the files recorded in a full package by path relative to the install.
+    async fn read_manifest(
+        &self,
+        reader: &PatchReader,
+    ) -> Result<BTreeMap<PathBuf, ManifestEntry>, anyhow::Error> {
+        let patch_db = reader.open_patch_db().await?;
+        let patch_info = patch_db
+            .db
+            .get_patch_info()
+            .await?
+            .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+        let file_changes = patch_db.db.list_file_changes(patch_info.id).await?;
+        patch_db.db.close().await;
+        if !patch_info.is_full_package {
+            return Err(anyhow!(
+                "{} is an update from version {}, a full package is required",
+                reader.zip_path.display(),
+                patch_info.base_version
+            ));
+        }
+
+        let mut manifest = BTreeMap::new();
+        for change in file_changes {
+            let relative_path = Path::new(&change.file_path)
+                .strip_prefix(&patch_info.source_path)
+                .map_err(|_| {
+                    anyhow!(
+                        "{} is outside of the packaged application",
+                        change.file_path
+                    )
+                })?
+                .to_path_buf();
+            if relative_path.as_os_str().is_empty() || self.ignore_rules.is_ignored(&relative_path)
+            {
+                continue;
+            }
+            manifest.insert(
+                relative_path,
+                ManifestEntry {
+                    file_path: change.file_path,
+                    file_type: change.file_type,
+                    hash_code: change.hash_code,
+                    hash_algorithm: patch_info.hash_algorithm,
+                },
+            );
+        }
+        Ok(manifest)
+    }
+
+    fn compare(
+        &self,
+        manifest: &BTreeMap<PathBuf, ManifestEntry>,
+    ) -> Result<RepairReport, anyhow::Error> {
+        let mut report = RepairReport::default();
+        for (relative_path, entry) in manifest {
+            let path = self.install_path.join(relative_path);
+            let metadata = fs::metadata(&path);
+            if entry.file_type == "DIRECTORY" {
+                if !metadata.is_ok_and(|metadata| metadata.is_dir()) {
+                    report.missing.push(relative_path.clone());
+                }
+            } else if !metadata.is_ok_and(|metadata| metadata.is_file()) {
+                report.missing.push(relative_path.clone());
+            } else {
+                let hash_code = copy_and_hash(
+                    &mut File::open(&path)?,
+                    &mut io::sink(),
+                    entry.hash_algorithm,
+                )?;
+                if entry.hash_code.as_ref() != Some(&hash_code) {
+                    report.corrupted.push(relative_path.clone());
+                }
+            }
+        }
+        self.find_extra(&self.install_path, manifest, &mut report.extra)?;
+        report.extra.sort();
+        Ok(report)
+    }
+
+    /// Collect the entries of a directory that are neither in the package nor ignored.
+    fn find_extra(
+        &self,
+        dir: &Path,
+        manifest: &BTreeMap<PathBuf, ManifestEntry>,
+        extra: &mut Vec<PathBuf>,
+    ) -> Result<(), anyhow::Error> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative_path = path.strip_prefix(&self.install_path)?.to_path_buf();
+            if self.ignore_rules.is_ignored(&relative_path) {
+                continue;
+            }
+            if !manifest.contains_key(&relative_path) {
+                extra.push(relative_path);
+            }
+            if entry.file_type()?.is_dir() {
+                self.find_extra(&path, manifest, extra)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove extra entries, directories are kept if they still hold ignored files.
+    fn remove_extra(&self, extra: &[PathBuf]) -> Result<(), anyhow::Error> {
+        let mut extra = extra.to_vec();
+        // Remove the content of directories first
+        extra.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+        for relative_path in extra {
+            let path = self.install_path.join(relative_path);
+            if !fs::symlink_metadata(&path)?.is_dir() {
+                fs::remove_file(&path)?;
+            } else if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
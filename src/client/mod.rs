@@ -0,0 +1,6 @@
+pub mod downloader;
+pub mod ignore_rules;
+pub mod install_repairer;
+pub mod patch_applier;
+pub mod patch_client;
+pub mod update_client;
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+use crate::{
+    indexer::merkle::MODE_EXECUTABLE,
+    storage::{
+        base_chunk::BaseChunk,
+        blob_store::{BlobStore, copy_and_hash},
+        patch_file_change::PatchFileChange,
+        patch_file_chunk::PatchFileChunk,
+        patch_info::PatchInfo,
+        patch_reader::PatchReader,
+    },
+};
+
+/// Applies patches built by `PatchZip` to an installed copy of an application.
+///
+/// The new content of every changed file is first staged next to the install and checked
+/// against its recorded hash, so a corrupted patch is detected before anything is modified.
+/// The install is only touched once everything has been staged.
+pub struct PatchApplier {
+    pub install_path: PathBuf,
+}
+
+/// Where the new file contents of a patch can be read from.
+struct PatchContents<'a> {
+    patch_info: PatchInfo,
+    archives: Vec<ZipArchive<File>>,
+    chunks_by_change: HashMap<i64, Vec<PatchFileChunk>>,
+    base_chunks: HashMap<String, BaseChunk>,
+    blob_store: Option<&'a BlobStore>,
+}
+
+/// New content of a file waiting to be moved into the install.
+struct StagedFile {
+    staged_path: PathBuf,
+    target: PathBuf,
+    unix_mode: Option<u32>,
+}
+
+impl PatchApplier {
+    pub fn new(install_path: &Path) -> Self {
+        PatchApplier {
+            install_path: install_path.to_path_buf(),
+        }
+    }
+
+    pub async fn apply(&self, reader: &PatchReader) -> Result<(), anyhow::Error> {
+        self.apply_filtered(reader, |_| true).await
+    }
+
+    /// Apply only the recorded changes accepted by the filter.
+    pub async fn apply_filtered(
+        &self,
+        reader: &PatchReader,
+        filter: impl Fn(&PatchFileChange) -> bool,
+    ) -> Result<(), anyhow::Error> {
+        let patch_db = reader.open_patch_db().await?;
+        let patch_info = patch_db
+            .db
+            .get_patch_info()
+            .await?
+            .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+        let mut file_changes = patch_db.db.list_file_changes(patch_info.id).await?;
+        let file_chunks = patch_db.db.list_file_chunks(patch_info.id).await?;
+        let base_chunks = patch_db.db.list_base_chunks().await?;
+        patch_db.db.close().await;
+        file_changes.retain(|change| filter(change));
+        tracing::info!(
+            "Applying patch {} -> {} of {} to {}",
+            patch_info.base_version,
+            patch_info.patch_version,
+            patch_info.app_name,
+            self.install_path.display()
+        );
+
+        let mut chunks_by_change: HashMap<i64, Vec<PatchFileChunk>> = HashMap::new();
+        for chunk in file_chunks {
+            chunks_by_change
+                .entry(chunk.file_change_id)
+                .or_default()
+                .push(chunk);
+        }
+        let base_chunks: HashMap<String, BaseChunk> = base_chunks
+            .into_iter()
+            .map(|chunk| (chunk.hash_code.clone(), chunk))
+            .collect();
+
+        // Stage next to the install so that files can be moved in place without copying
+        let staging_parent = self
+            .install_path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let staging_dir = tempfile::Builder::new()
+            .prefix(".patch-staging")
+            .tempdir_in(staging_parent)?;
+        let mut contents = PatchContents {
+            patch_info,
+            archives: reader.open_parts()?,
+            chunks_by_change,
+            base_chunks,
+            blob_store: reader.blob_store.as_ref(),
+        };
+        let mut staged_files = Vec::new();
+        for change in &file_changes {
+            let staged = self
+                .stage_file(change, &mut contents, &staging_dir)
+                .map_err(|e| anyhow!("Error staging {}: {}", change.file_path, e))?;
+            staged_files.extend(staged);
+        }
+
+        let patch_info = contents.patch_info;
+        self.commit(&file_changes, &patch_info, staged_files)?;
+        tracing::info!("Patch {} applied", patch_info.patch_version);
+        Ok(())
+    }
+
+    /// Path in the install of a file recorded in the patch.
+    fn install_file(
+        &self,
+        file_path: &str,
+        patch_info: &PatchInfo,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let relative_path = Path::new(file_path)
+            .strip_prefix(&patch_info.source_path)
+            .map_err(|_| anyhow!("{} is outside of the patched application", file_path))?;
+        Ok(self.install_path.join(relative_path))
+    }
+
+    /// Write the new content of a changed file into the staging directory and verify it.
+    fn stage_file(
+        &self,
+        change: &PatchFileChange,
+        contents: &mut PatchContents,
+        staging_dir: &TempDir,
+    ) -> Result<Option<StagedFile>, anyhow::Error> {
+        let patch_info = &contents.patch_info;
+        if change.file_type != "FILE" || !(change.has_content() || change.renamed_from.is_some()) {
+            return Ok(None);
+        }
+        let target = self.install_file(&change.file_path, patch_info)?;
+        let staged_path = staging_dir.path().join(change.id.to_string());
+        let mut staged_file = File::create(&staged_path)?;
+        let mut unix_mode = None;
+        let hash_algorithm = patch_info.hash_algorithm;
+
+        let hex_hash = if let Some(from) = &change.renamed_from {
+            let source = self.install_file(from, patch_info)?;
+            unix_mode = Some(fs::metadata(&source)?.permissions().mode());
+            copy_and_hash(&mut File::open(&source)?, &mut staged_file, hash_algorithm)?
+        } else if let Some(entry_name) = &change.entry_name {
+            let archive = part(&mut contents.archives, change.part_number)?;
+            let mut entry = archive.by_name(entry_name)?;
+            unix_mode = entry.unix_mode();
+            copy_and_hash(&mut entry, &mut staged_file, hash_algorithm)?
+        } else if let Some(chunks) = contents.chunks_by_change.get(&change.id) {
+            let mut content = Vec::new();
+            for chunk in chunks {
+                self.read_chunk(
+                    chunk,
+                    patch_info,
+                    &mut contents.archives,
+                    &contents.base_chunks,
+                    &mut content,
+                )?;
+                staged_file.write_all(&content)?;
+                content.clear();
+            }
+            // Re-read the whole file to verify the reassembled content
+            staged_file.flush()?;
+            copy_and_hash(
+                &mut File::open(&staged_path)?,
+                &mut io::sink(),
+                hash_algorithm,
+            )?
+        } else if patch_info.uses_blob_store {
+            let blob_store = contents
+                .blob_store
+                .ok_or_else(|| anyhow!("Patch contents are stored in a blob store"))?;
+            let hash_code = change
+                .hash_code
+                .as_ref()
+                .ok_or_else(|| anyhow!("Missing hash for changed file"))?;
+            copy_and_hash(
+                &mut blob_store.open(hash_code)?,
+                &mut staged_file,
+                hash_algorithm,
+            )?
+        } else {
+            return Err(anyhow!("The patch doesn't contain the new content"));
+        };
+
+        if change.hash_code.as_ref() != Some(&hex_hash) {
+            return Err(anyhow!("Content doesn't match the recorded hash"));
+        }
+        // The recorded mode keeps the tree hash of the install in line with the publisher
+        if let Some(mode) = change.mode {
+            unix_mode = Some(permissions_for(mode, unix_mode));
+        }
+        Ok(Some(StagedFile {
+            staged_path,
+            target,
+            unix_mode,
+        }))
+    }
+
+    /// Read the content of a chunk, either from the patch or from the base version.
+    fn read_chunk(
+        &self,
+        chunk: &PatchFileChunk,
+        patch_info: &PatchInfo,
+        archives: &mut [ZipArchive<File>],
+        base_chunks: &HashMap<String, BaseChunk>,
+        content: &mut Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(entry_name) = &chunk.entry_name {
+            let archive = part(archives, chunk.part_number)?;
+            archive.by_name(entry_name)?.read_to_end(content)?;
+        } else {
+            let base_chunk = base_chunks
+                .get(&chunk.hash_code)
+                .ok_or_else(|| anyhow!("Chunk {} not found", chunk.hash_code))?;
+            let mut base_file = File::open(self.install_file(&base_chunk.file_path, patch_info)?)?;
+            base_file.seek(SeekFrom::Start(base_chunk.chunk_offset as u64))?;
+            base_file
+                .take(base_chunk.chunk_length as u64)
+                .read_to_end(content)?;
+        }
+        if content.len() as i64 != chunk.chunk_length {
+            return Err(anyhow!("Chunk {} is truncated", chunk.hash_code));
+        }
+        Ok(())
+    }
+
+    /// Apply deletions and move the staged files into the install.
+    fn commit(
+        &self,
+        file_changes: &[PatchFileChange],
+        patch_info: &PatchInfo,
+        staged_files: Vec<StagedFile>,
+    ) -> Result<(), anyhow::Error> {
+        let mut removed_files = Vec::new();
+        let mut removed_dirs = Vec::new();
+        let mut created_dirs = Vec::new();
+        for change in file_changes {
+            let path = self.install_file(&change.file_path, patch_info)?;
+            match (change.change_type.as_str(), change.file_type.as_str()) {
+                ("DELETED", "DIRECTORY") => removed_dirs.push(path),
+                ("DELETED", _) => removed_files.push(path),
+                ("CREATED", "DIRECTORY") => created_dirs.push(path),
+                ("RENAMED", _) => {
+                    if let Some(from) = &change.renamed_from {
+                        removed_files.push(self.install_file(from, patch_info)?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for path in removed_files {
+            remove_if_exists(fs::remove_file(&path))?;
+        }
+        // Remove nested directories first
+        removed_dirs.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+        for path in removed_dirs {
+            remove_if_exists(fs::remove_dir_all(&path))?;
+        }
+        for path in created_dirs {
+            fs::create_dir_all(&path)?;
+        }
+        for staged in staged_files {
+            if let Some(parent) = staged.target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&staged.staged_path, &staged.target)?;
+            if let Some(unix_mode) = staged.unix_mode {
+                fs::set_permissions(&staged.target, fs::Permissions::from_mode(unix_mode))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn part(
+    archives: &mut [ZipArchive<File>],
+    part_number: Option<i64>,
+) -> Result<&mut ZipArchive<File>, anyhow::Error> {
+    let part_number = part_number.unwrap_or(1);
+    archives
+        .get_mut((part_number - 1) as usize)
+        .ok_or_else(|| anyhow!("Part {} of the patch is missing", part_number))
+}
+
+/// Unix permissions of a file with the given Merkle tree mode, based on its current permissions.
+fn permissions_for(mode: u32, unix_mode: Option<u32>) -> u32 {
+    let unix_mode = unix_mode.unwrap_or(0o644) & 0o7777;
+    if mode == MODE_EXECUTABLE {
+        unix_mode | 0o111
+    } else {
+        unix_mode & !0o111
+    }
+}
+
+fn remove_if_exists(result: io::Result<()>) -> Result<(), anyhow::Error> {
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use reqwest::{Client, Url};
+
+use crate::{
+    client::downloader::{Downloader, ExpectedFile, RetryPolicy},
+    indexer::hash_algorithm::HashAlgorithm,
+    server::api::{AppSummary, LatestVersion, PartSummary, VersionSummary},
+    storage::application_data::AppTarget,
+};
+
+/// HTTP client for the endpoints of `PatchServer`.
+#[derive(Clone)]
+pub struct PatchClient {
+    pub server_url: Url,
+    // Release channel and platform of the applications requested from the server
+    pub target: AppTarget,
+    http: Client,
+    downloader: Downloader,
+}
+
+impl PatchClient {
+    pub fn new(server_url: &str) -> Result<Self, anyhow::Error> {
+        let server_url = Url::parse(server_url)
+            .map_err(|e| anyhow!("Invalid server URL {}: {}", server_url, e))?;
+        if server_url.cannot_be_a_base() {
+            return Err(anyhow!("Invalid server URL {}", server_url));
+        }
+        Ok(PatchClient {
+            server_url,
+            target: AppTarget::default(),
+            http: Client::new(),
+            downloader: Downloader::new(RetryPolicy::default()),
+        })
+    }
+
+    pub fn with_target(mut self, target: &AppTarget) -> Self {
+        self.target = target.clone();
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.downloader.retry_policy = retry_policy;
+        self
+    }
+
+    pub async fn list_apps(&self) -> Result<Vec<AppSummary>, anyhow::Error> {
+        self.get_json(self.url(&["apps"])).await
+    }
+
+    pub async fn latest_version(&self, app_name: &str) -> Result<LatestVersion, anyhow::Error> {
+        self.get_json(self.app_url(&["apps", app_name, "latest"]))
+            .await
+    }
+
+    pub async fn list_versions(
+        &self,
+        app_name: &str,
+    ) -> Result<Vec<VersionSummary>, anyhow::Error> {
+        self.get_json(self.app_url(&["apps", app_name, "versions"]))
+            .await
+    }
+
+    /// Download a part of a patch to the given file and verify it against its manifest entry.
+    pub async fn download_part(
+        &self,
+        app_name: &str,
+        version: &str,
+        part: &PartSummary,
+        destination: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let part_number = part.part_number.to_string();
+        let url = self.app_url(&["apps", app_name, "versions", version, "parts", &part_number]);
+        let expected = ExpectedFile {
+            size: Some(part.size),
+            hash_code: part.hash_code.clone(),
+            // Parts are always hashed with SHA-256, see `PatchReader::hash_parts`
+            hash_algorithm: HashAlgorithm::Sha256,
+        };
+        self.downloader.download(url, destination, &expected).await
+    }
+
+    /// Download a blob to the given file and verify that it matches its hash, computed with the
+    /// algorithm of the application it belongs to.
+    pub async fn download_blob(
+        &self,
+        hash_code: &str,
+        hash_algorithm: HashAlgorithm,
+        destination: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let expected = ExpectedFile {
+            size: None,
+            hash_code: Some(hash_code.to_string()),
+            hash_algorithm,
+        };
+        self.downloader
+            .download(self.url(&["blobs", hash_code]), destination, &expected)
+            .await
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: Url) -> Result<T, anyhow::Error> {
+        let response = self.http.get(url.clone()).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Request to {} failed: {}", url, response.status()));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// URL of an endpoint about an application, in the channel and platform of the client.
+    fn app_url(&self, segments: &[&str]) -> Url {
+        let mut url = self.url(segments);
+        url.query_pairs_mut()
+            .append_pair("channel", &self.target.channel)
+            .append_pair("platform", &self.target.platform);
+        url
+    }
+
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.server_url.clone();
+        // Checked when the client was created
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+}
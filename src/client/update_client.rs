@@ -0,0 +1,328 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use crate::{
+    cancellation::CancellationToken,
+    client::{patch_applier::PatchApplier, patch_client::PatchClient},
+    indexer::{
+        dir_hasher::DirHasher,
+        file_change::FileChange,
+        hash_algorithm::HashAlgorithm,
+        indexer_config::IndexerConfig,
+        merkle::{self, LEGACY_FORMAT_VERSION, MerkleEntry},
+    },
+    server::api::VersionSummary,
+    storage::{
+        application_data::AppTarget,
+        blob_store::BlobStore,
+        index_store::MemoryIndexStore,
+        patch_reader::PatchReader,
+        patch_zip::{part_path, remove_patch_files},
+    },
+};
+
+/// Name of the first part of a downloaded patch, the other parts are named after it.
+const PATCH_FILE_NAME: &str = "patch.zip";
+
+/// State an install was left in by an update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateOutcome {
+    // Version the install matches, the latest one available on the server
+    pub version: String,
+    // Hash of the install, computed with the algorithm of the application
+    pub hash_code: String,
+    pub hash_algorithm: HashAlgorithm,
+    // Whether patches were applied, false if the install was already up to date
+    pub updated: bool,
+}
+
+/// Brings a local install of an application up to date with a patch server.
+pub struct UpdateClient {
+    pub client: PatchClient,
+    // Downloaded patches and blobs are kept here so that interrupted updates can be resumed
+    pub cache_dir: PathBuf,
+}
+
+impl UpdateClient {
+    pub fn new(server_url: &str, cache_dir: &Path) -> Result<Self, anyhow::Error> {
+        Ok(UpdateClient {
+            client: PatchClient::new(server_url)?,
+            cache_dir: cache_dir.to_path_buf(),
+        })
+    }
+
+    /// Follow the given release channel and platform instead of the default ones.
+    pub fn with_target(mut self, target: &AppTarget) -> Self {
+        self.client = self.client.with_target(target);
+        self
+    }
+
+    /// Update the install to the latest version available on the server.
+    ///
+    /// The local version is found by hashing the install with the algorithm of the application
+    /// and matching the hash against the released versions, then the patches leading from there
+    /// to the latest version, following the version each patch applies to, are downloaded and
+    /// validated before being applied in order. Returns the version the install was left at and
+    /// its hash, so that callers don't have to ask the server again.
+    pub async fn update(
+        &self,
+        app_name: &str,
+        install_path: &Path,
+    ) -> Result<UpdateOutcome, anyhow::Error> {
+        let hash_algorithm = self.client.latest_version(app_name).await?.hash_algorithm;
+        let local_hash = InstallHash::compute(install_path, hash_algorithm).await?;
+        // Listed in version order, the latest one last
+        let versions = self.client.list_versions(app_name).await?;
+        let latest = versions
+            .last()
+            .ok_or_else(|| anyhow!("No version of {} is available", app_name))?;
+        if local_hash.matches(&latest.hash_code, latest.hash_format) {
+            tracing::info!("{} is up to date (version {})", app_name, latest.version);
+            return Ok(UpdateOutcome {
+                version: latest.version.clone(),
+                hash_code: local_hash.hash_code,
+                hash_algorithm,
+                updated: false,
+            });
+        }
+
+        let position = versions
+            .iter()
+            .rposition(|version| local_hash.matches(&version.hash_code, version.hash_format))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Local install doesn't match any released version of {}",
+                    app_name
+                )
+            })?;
+        let current = &versions[position];
+        let chain = resolve_chain(&versions, current, latest)?;
+        tracing::info!(
+            "Updating {} from version {} to {} ({} patch(es))",
+            app_name,
+            current.version,
+            latest.version,
+            chain.len()
+        );
+
+        // Make sure the whole chain is available before touching the install
+        let mut readers = Vec::new();
+        for version in &chain {
+            readers.push(self.download_patch(app_name, version).await?);
+        }
+        let applier = PatchApplier::new(install_path);
+        let mut hash_code = local_hash.hash_code;
+        for (version, reader) in chain.iter().zip(&readers) {
+            applier.apply(reader).await?;
+            let local_hash = InstallHash::compute(install_path, hash_algorithm).await?;
+            if !local_hash.matches(&version.hash_code, version.hash_format) {
+                return Err(anyhow!(
+                    "Install doesn't match version {} after applying its patch",
+                    version.version
+                ));
+            }
+            remove_patch_files(&reader.zip_path);
+            hash_code = local_hash.hash_code;
+        }
+        Ok(UpdateOutcome {
+            version: latest.version.clone(),
+            hash_code,
+            hash_algorithm,
+            updated: true,
+        })
+    }
+
+    /// Download the parts of a patch, and the blobs it references if any, then validate it.
+    async fn download_patch(
+        &self,
+        app_name: &str,
+        version: &VersionSummary,
+    ) -> Result<PatchReader, anyhow::Error> {
+        let patch_dir = self
+            .cache_dir
+            .join(app_name.replace(['/', '\\'], "_"))
+            .join(&self.client.target.channel)
+            .join(&self.client.target.platform)
+            .join(&version.version);
+        fs::create_dir_all(&patch_dir)?;
+        let zip_path = patch_dir.join(PATCH_FILE_NAME);
+        for part in &version.parts {
+            let path = part_path(&zip_path, part.part_number);
+            tracing::info!(
+                "Downloading part {} of version {}",
+                part.part_number,
+                version.version
+            );
+            self.client
+                .download_part(app_name, &version.version, part, &path)
+                .await?;
+        }
+
+        let blob_store = BlobStore::new(&self.cache_dir.join("blobs"));
+        let reader = PatchReader::with_blob_store(&zip_path, &blob_store);
+        let result = match self.download_blobs(&reader, &blob_store).await {
+            Ok(()) => reader.validate().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            remove_patch_files(&zip_path);
+            return Err(anyhow!(
+                "Patch for version {} is invalid: {}",
+                version.version,
+                e
+            ));
+        }
+        Ok(reader)
+    }
+
+    /// Download the blobs referenced by a patch that are not in the local store yet.
+    async fn download_blobs(
+        &self,
+        reader: &PatchReader,
+        blob_store: &BlobStore,
+    ) -> Result<(), anyhow::Error> {
+        let patch_db = reader.open_patch_db().await?;
+        let patch_info = patch_db
+            .db
+            .get_patch_info()
+            .await?
+            .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+        let file_changes = patch_db.db.list_file_changes(patch_info.id).await?;
+        patch_db.db.close().await;
+        if !patch_info.uses_blob_store {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&blob_store.root)?;
+        for hash_code in file_changes
+            .iter()
+            .filter(|change| change.has_content())
+            .filter_map(|change| change.hash_code.as_ref())
+        {
+            if blob_store.contains(hash_code) {
+                continue;
+            }
+            let download_path = blob_store.root.join(format!("{}.download", hash_code));
+            self.client
+                .download_blob(hash_code, patch_info.hash_algorithm, &download_path)
+                .await?;
+            let result = blob_store.put_file(hash_code, &download_path, patch_info.hash_algorithm);
+            fs::remove_file(&download_path)?;
+            result?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the patches leading from the current version to the latest one, each applying to the
+/// version created by the previous one.
+fn resolve_chain<'a>(
+    versions: &'a [VersionSummary],
+    current: &'a VersionSummary,
+    latest: &'a VersionSummary,
+) -> Result<Vec<&'a VersionSummary>, anyhow::Error> {
+    let mut chain = Vec::new();
+    let mut base_version = &current.version;
+    while base_version != &latest.version {
+        // Every version appears once, so a longer chain would loop
+        let next = versions
+            .iter()
+            .find(|version| version.base_version.as_ref() == Some(base_version))
+            .filter(|version| !version.parts.is_empty() && chain.len() < versions.len())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No patch from version {} to {} is available",
+                    base_version,
+                    latest.version
+                )
+            })?;
+        chain.push(next);
+        base_version = &next.version;
+    }
+    Ok(chain)
+}
+
+/// Hash of an install in every format the hashes of released versions may be recorded in.
+pub struct InstallHash {
+    pub hash_code: String,
+    // Hash in the format of versions released before the Merkle tree format, which were always
+    // hashed with SHA-256, None for installs hashed with another algorithm
+    pub legacy_hash: Option<String>,
+}
+
+impl InstallHash {
+    /// Hash a local install from scratch, see `hash_install`.
+    pub async fn compute(
+        install_path: &Path,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, anyhow::Error> {
+        let (hash_code, entries) =
+            index_install(install_path, hash_algorithm, &CancellationToken::new()).await?;
+        let legacy_hash = match hash_algorithm {
+            HashAlgorithm::Sha256 => {
+                let mut tree: BTreeMap<PathBuf, MerkleEntry> = entries
+                    .iter()
+                    .map(|entry| {
+                        let path = PathBuf::from(&entry.file_path);
+                        let hash_code = entry.hash_code.as_deref().unwrap_or_default();
+                        let mode = entry.mode.unwrap_or_default();
+                        let merkle_entry =
+                            MerkleEntry::new(&path, &entry.file_type, mode, hash_code);
+                        (path, merkle_entry)
+                    })
+                    .collect();
+                Some(merkle::rehash_tree(
+                    hash_algorithm,
+                    LEGACY_FORMAT_VERSION,
+                    install_path,
+                    &mut tree,
+                )?)
+            }
+            _ => None,
+        };
+        Ok(InstallHash {
+            hash_code,
+            legacy_hash,
+        })
+    }
+
+    /// Whether the install matches a hash recorded in the given format version.
+    pub fn matches(&self, hash_code: &str, hash_format: u8) -> bool {
+        if hash_format == LEGACY_FORMAT_VERSION {
+            self.legacy_hash.as_deref() == Some(hash_code)
+        } else {
+            self.hash_code == hash_code
+        }
+    }
+}
+
+/// Hash a local install the same way the patch producer hashes applications.
+/// There is no index for it, so every file is hashed.
+pub async fn hash_install(
+    install_path: &Path,
+    hash_algorithm: HashAlgorithm,
+) -> Result<String, anyhow::Error> {
+    let (hash, _) = index_install(install_path, hash_algorithm, &CancellationToken::new()).await?;
+    Ok(hash)
+}
+
+/// Hash a directory from scratch, returning its hash along with every file and directory
+/// in it, all reported as created.
+pub async fn index_install(
+    install_path: &Path,
+    hash_algorithm: HashAlgorithm,
+    cancellation: &CancellationToken,
+) -> Result<(String, Vec<FileChange>), anyhow::Error> {
+    let indexer_config = IndexerConfig::new(0, MemoryIndexStore::new(), false)
+        .with_hash_algorithm(hash_algorithm)
+        .with_cancellation(cancellation.clone());
+    let hasher = DirHasher::new(indexer_config)
+        .dir_hash(&install_path.to_path_buf())
+        .await?;
+    hasher.finalize().await
+}
@@ -0,0 +1,154 @@
+use std::io::Read;
+
+use anyhow::anyhow;
+use fastcdc::v2020::{self, StreamCDC};
+
+use crate::indexer::hash_algorithm::HashAlgorithm;
+
+/// Options for splitting large files into content-defined chunks while indexing them.
+///
+/// Chunk boundaries depend on the content rather than on fixed offsets, so inserting or
+/// removing bytes in a file only changes the chunks around the edit and the others can be
+/// reused from the previous version when building a patch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkingConfig {
+    // Files smaller than this are never chunked
+    pub min_file_size: u64,
+    pub min_chunk_size: usize,
+    pub avg_chunk_size: usize,
+    pub max_chunk_size: usize,
+}
+
+impl ChunkingConfig {
+    pub fn new(
+        min_file_size: u64,
+        min_chunk_size: usize,
+        avg_chunk_size: usize,
+        max_chunk_size: usize,
+    ) -> Result<Self, anyhow::Error> {
+        if !(v2020::MINIMUM_MIN..=v2020::MINIMUM_MAX).contains(&min_chunk_size)
+            || !(v2020::AVERAGE_MIN..=v2020::AVERAGE_MAX).contains(&avg_chunk_size)
+            || !(v2020::MAXIMUM_MIN..=v2020::MAXIMUM_MAX).contains(&max_chunk_size)
+        {
+            return Err(anyhow!(
+                "Unsupported chunk sizes {}/{}/{}",
+                min_chunk_size,
+                avg_chunk_size,
+                max_chunk_size
+            ));
+        }
+        if min_chunk_size > avg_chunk_size || avg_chunk_size > max_chunk_size {
+            return Err(anyhow!(
+                "Chunk sizes must be ordered as minimum <= average <= maximum"
+            ));
+        }
+        Ok(ChunkingConfig {
+            min_file_size,
+            min_chunk_size,
+            avg_chunk_size,
+            max_chunk_size,
+        })
+    }
+
+    /// Whether a file of the given size should be split into chunks.
+    pub fn should_chunk(&self, file_size: u64) -> bool {
+        file_size >= self.min_file_size
+    }
+
+    /// Split the content of the reader into chunks, calling `on_data` with the content of
+    /// each chunk in order so that the caller can hash the whole file in the same pass.
+    pub fn split(
+        &self,
+        reader: impl Read,
+        hash_algorithm: HashAlgorithm,
+        mut on_data: impl FnMut(&[u8]),
+    ) -> Result<Vec<FileChunk>, anyhow::Error> {
+        let chunker = StreamCDC::new(
+            reader,
+            self.min_chunk_size,
+            self.avg_chunk_size,
+            self.max_chunk_size,
+        );
+        let mut chunks = Vec::new();
+        for chunk in chunker {
+            let chunk = chunk.map_err(|e| anyhow!("Error splitting file into chunks: {}", e))?;
+            on_data(&chunk.data);
+            chunks.push(FileChunk {
+                offset: chunk.offset,
+                length: chunk.length as u64,
+                hash_code: hash_algorithm.digest(&chunk.data),
+            });
+        }
+        Ok(chunks)
+    }
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        ChunkingConfig {
+            min_file_size: 4 * 1024 * 1024,
+            min_chunk_size: 16 * 1024,
+            avg_chunk_size: 64 * 1024,
+            max_chunk_size: 256 * 1024,
+        }
+    }
+}
+
+/// A content-defined chunk of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileChunk {
+    pub offset: u64,
+    pub length: u64,
+    // Hash of the chunk content, with the algorithm of the application
+    pub hash_code: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkingConfig;
+    use crate::indexer::hash_algorithm::HashAlgorithm;
+
+    #[test]
+    fn split_test() {
+        let config = ChunkingConfig::new(0, 64, 256, 1024).unwrap();
+        // Pseudo-random content so that boundaries are found by content and not by max size
+        let mut state: u32 = 1;
+        let content: Vec<u8> = (0..16 * 1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+
+        let mut data = Vec::new();
+        let chunks = config
+            .split(content.as_slice(), HashAlgorithm::Sha256, |chunk| {
+                data.extend_from_slice(chunk)
+            })
+            .unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(data, content);
+        let mut offset = 0;
+        for chunk in &chunks {
+            assert_eq!(chunk.offset, offset);
+            assert!(chunk.length <= 1024);
+            offset += chunk.length;
+        }
+        assert_eq!(offset, content.len() as u64);
+
+        // Changing the beginning of the file keeps most of the following chunks
+        let mut edited = vec![0xAB; 100];
+        edited.extend_from_slice(&content);
+        let edited_chunks = config
+            .split(edited.as_slice(), HashAlgorithm::Sha256, |_| {})
+            .unwrap();
+        let reused = edited_chunks
+            .iter()
+            .filter(|chunk| chunks.iter().any(|c| c.hash_code == chunk.hash_code))
+            .count();
+        assert!(reused * 10 >= chunks.len() * 8);
+
+        assert!(ChunkingConfig::new(0, 1, 256, 1024).is_err());
+        assert!(ChunkingConfig::new(0, 512, 256, 1024).is_err());
+    }
+}
@@ -1,14 +1,22 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
 
 use crate::{
     indexer::{
-        file_change::FileChangeType, file_hasher::FileHasher, file_info::FileInfo,
-        indexed_hasher::IndexedHasher, indexer_config::IndexerConfig,
+        file_change::{FileChangeType, detect_renames},
+        file_hasher::FileHasher,
+        file_info::FileInfo,
+        indexed_hasher::IndexedHasher,
+        indexer_config::IndexerConfig,
+        merkle,
     },
-    storage::{db_utils, patcher_db::PatcherDatabase},
+    storage::db_utils,
 };
 
 pub struct DirHasher {
@@ -20,7 +28,25 @@ impl DirHasher {
         DirHasher { config }
     }
 
+    /// Hash a directory recursively, collecting the changes since the last index.
+    /// Deleted and created files with the same content are reported as renames.
     pub async fn dir_hash(&self, file_path: &PathBuf) -> Result<IndexedHasher, anyhow::Error> {
+        let result = match &self.config.progress {
+            Some(progress) => {
+                progress.set_total_files(count_files(file_path)?);
+                let result = self.hash_dir(file_path).await;
+                progress.finish();
+                result
+            }
+            None => self.hash_dir(file_path).await,
+        };
+        let mut dir_hasher = result?;
+        let changed_files = std::mem::take(&mut dir_hasher.changed_files);
+        dir_hasher.changed_files = detect_renames(changed_files);
+        Ok(dir_hasher)
+    }
+
+    async fn hash_dir(&self, file_path: &PathBuf) -> Result<IndexedHasher, anyhow::Error> {
         let mut entries = Vec::new();
         let metadata = fs::metadata(file_path)?;
         if !metadata.is_dir() {
@@ -30,16 +56,21 @@ impl DirHasher {
         let modified_time = DateTime::<Utc>::from(modified_time).naive_utc();
 
         // Check if we have a cached hash for this directory to see if any files are deleted
-        let last_index = db_utils::last_index(self.config.app_id, file_path, &self.config.db).await;
+        let last_index =
+            db_utils::last_index(self.config.app_id, file_path, self.config.store.as_ref()).await;
         let mut previous_children = HashMap::new();
         if let Some(_index) = &last_index {
-            let previous_files =
-                db_utils::list_indexed_files(self.config.app_id, file_path, true, &self.config.db)
-                    .await?;
+            let previous_files = db_utils::list_indexed_files(
+                self.config.app_id,
+                file_path,
+                true,
+                self.config.store.as_ref(),
+            )
+            .await?;
             for file in previous_files {
                 previous_children.insert(
                     file.file_path.clone(),
-                    FileInfo::new(&file.file_path, &file.file_type),
+                    FileInfo::with_hash(&file.file_path, &file.file_type, file.hash_code),
                 );
             }
         }
@@ -59,15 +90,22 @@ impl DirHasher {
         let mut current_children = HashMap::new();
 
         // Recompute the hash by combining the hashes of all entries
-        let mut dir_hasher =
-            IndexedHasher::new(file_path, "DIRECTORY", modified_time, self.config.clone());
+        let mut dir_hasher = IndexedHasher::new(
+            file_path,
+            "DIRECTORY",
+            merkle::MODE_DIRECTORY,
+            modified_time,
+            self.config.clone(),
+        );
         for entry_path in &entries {
+            self.config.cancellation.check()?;
             let path_str = entry_path.display().to_string();
             let metadata = fs::metadata(entry_path)?;
 
             // Find the last index entry for this path, if any
             let last_entry =
-                db_utils::last_index(self.config.app_id, entry_path, &self.config.db).await;
+                db_utils::last_index(self.config.app_id, entry_path, self.config.store.as_ref())
+                    .await;
             if metadata.is_dir() {
                 // Add to current children
                 current_children.insert(path_str.clone(), FileInfo::new(&path_str, "DIRECTORY"));
@@ -79,8 +117,8 @@ impl DirHasher {
 
                 // Recursively hash the directory
                 let hasher = DirHasher::new(self.config.clone());
-                let result = Box::pin(hasher.dir_hash(entry_path)).await?;
-                let hex_hash = dir_hasher.extend(result).await;
+                let result = Box::pin(hasher.hash_dir(entry_path)).await?;
+                let hex_hash = dir_hasher.extend(result).await?;
                 if let Some(entry) = last_entry
                     && entry.hash_code != Some(hex_hash)
                 {
@@ -98,7 +136,7 @@ impl DirHasher {
                 // Hash the file
                 let hasher = FileHasher::new(self.config.clone());
                 let result = hasher.file_hash(entry_path).await?;
-                dir_hasher.extend(result).await;
+                dir_hasher.extend(result).await?;
             };
         }
 
@@ -111,44 +149,50 @@ impl DirHasher {
                         self.config.app_id,
                         &PathBuf::from(&file_path),
                         false,
-                        &self.config.db,
+                        self.config.store.as_ref(),
                     )
                     .await?;
                     for file in previous_files {
                         // Also delete it from the database if needed
                         if self.config.update_index {
-                            delete_file_index(self.config.app_id, &file.file_path, &self.config.db)
-                                .await?;
+                            delete_file_index(&self.config, &file.file_path).await?;
                         }
-                        dir_hasher.append_changed_file(
+                        dir_hasher.append_deleted_file(
                             file.file_path,
                             file.file_type,
-                            FileChangeType::Deleted,
+                            file.hash_code,
                         );
                     }
                 }
 
                 // Also delete it from the database if needed
                 if self.config.update_index {
-                    delete_file_index(self.config.app_id, &file_path, &self.config.db).await?;
+                    delete_file_index(&self.config, &file_path).await?;
                 }
-                dir_hasher.append_changed_file(
-                    file_path,
-                    file_info.file_type,
-                    FileChangeType::Deleted,
-                );
+                dir_hasher.append_deleted_file(file_path, file_info.file_type, file_info.hash_code);
             }
         }
         Ok(dir_hasher)
     }
 }
 
-async fn delete_file_index(
-    app_id: i64,
-    file_path: &str,
-    db: &PatcherDatabase,
-) -> Result<bool, anyhow::Error> {
-    db.delete_file_index(app_id, file_path)
+/// Count the files under a directory, used as the total when reporting progress.
+fn count_files(dir_path: &Path) -> Result<u64, anyhow::Error> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir_path)? {
+        let entry_path = entry?.path();
+        if fs::metadata(&entry_path)?.is_dir() {
+            count += count_files(&entry_path)?;
+        } else {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+async fn delete_file_index(config: &IndexerConfig, file_path: &str) -> Result<(), anyhow::Error> {
+    config
+        .delete_file_index(file_path)
         .await
         .map_err(|e| anyhow::anyhow!("Error deleting old file index entry: {}", e))
 }
@@ -1,16 +1,41 @@
-use std::fmt::{Display, Formatter};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+};
 
-#[derive(PartialEq, Debug)]
+use crate::indexer::chunking::FileChunk;
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum FileChangeType {
     Created,
     Modified,
     Deleted,
+    // The file was moved from another path without changing its content
+    Renamed { from: String },
 }
 
+#[derive(Clone, Debug)]
 pub struct FileChange {
     pub file_path: String,
     pub file_type: String,
     pub change_type: FileChangeType,
+    // Content hash of the file, the previous one for deleted files
+    pub hash_code: Option<String>,
+    // Mode of the new entry in the Merkle tree, None for deleted files
+    pub mode: Option<u32>,
+    // Content-defined chunks of the new content, only set for chunked files
+    pub chunks: Option<Vec<FileChunk>>,
+}
+
+impl FileChange {
+    /// Whether the new content of the file has to be shipped with this change.
+    pub fn has_content(&self) -> bool {
+        self.file_type == "FILE"
+            && matches!(
+                self.change_type,
+                FileChangeType::Created | FileChangeType::Modified
+            )
+    }
 }
 
 impl Display for FileChangeType {
@@ -19,7 +44,125 @@ impl Display for FileChangeType {
             FileChangeType::Created => "Created",
             FileChangeType::Modified => "Modified",
             FileChangeType::Deleted => "Deleted",
+            FileChangeType::Renamed { .. } => "Renamed",
         };
         write!(f, "{}", change_str)
     }
 }
+
+impl Display for FileChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.change_type {
+            FileChangeType::Renamed { from } => {
+                write!(f, "[{}] {} -> {}", self.change_type, from, self.file_path)
+            }
+            _ => write!(f, "[{}] {}", self.change_type, self.file_path),
+        }
+    }
+}
+
+/// Pair deleted and created files having the same content into renames.
+///
+/// Each deleted file is paired with at most one created file, preferring one with the same
+/// file name, so copies of a moved file are still reported as created. Other changes are
+/// kept in their original order.
+pub fn detect_renames(changes: Vec<FileChange>) -> Vec<FileChange> {
+    // Deleted files by content hash
+    let mut deleted: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        if change.file_type == "FILE"
+            && change.change_type == FileChangeType::Deleted
+            && let Some(hash_code) = &change.hash_code
+        {
+            deleted.entry(hash_code.clone()).or_default().push(i);
+        }
+    }
+
+    // Created file index mapped to the deleted file index it was moved from
+    let mut renamed_from = HashMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        if change.file_type != "FILE" || change.change_type != FileChangeType::Created {
+            continue;
+        }
+        let Some(candidates) = change
+            .hash_code
+            .as_ref()
+            .and_then(|hash_code| deleted.get_mut(hash_code))
+        else {
+            continue;
+        };
+        if candidates.is_empty() {
+            continue;
+        }
+        let position = candidates
+            .iter()
+            .position(|&j| file_name(&changes[j].file_path) == file_name(&change.file_path))
+            .unwrap_or(0);
+        renamed_from.insert(i, candidates.remove(position));
+    }
+
+    let mut from_paths: HashMap<usize, String> = renamed_from
+        .iter()
+        .map(|(&i, &j)| (i, changes[j].file_path.clone()))
+        .collect();
+    let paired_deletions: HashSet<usize> = renamed_from.into_values().collect();
+    changes
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !paired_deletions.contains(i))
+        .map(|(i, mut change)| {
+            if let Some(from) = from_paths.remove(&i) {
+                change.change_type = FileChangeType::Renamed { from };
+            }
+            change
+        })
+        .collect()
+}
+
+fn file_name(file_path: &str) -> &str {
+    file_path.rsplit('/').next().unwrap_or(file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileChange, FileChangeType, detect_renames};
+
+    fn change(file_path: &str, change_type: FileChangeType, hash_code: &str) -> FileChange {
+        FileChange {
+            file_path: file_path.to_string(),
+            file_type: "FILE".to_string(),
+            change_type,
+            hash_code: Some(hash_code.to_string()),
+            mode: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn detect_renames_test() {
+        let changes = vec![
+            change("/app/copy/asset.bin", FileChangeType::Created, "hash1"),
+            change("/app/new/asset.bin", FileChangeType::Created, "hash1"),
+            change("/app/asset.bin", FileChangeType::Deleted, "hash1"),
+            change("/app/other.txt", FileChangeType::Created, "hash2"),
+            change("/app/old.txt", FileChangeType::Deleted, "hash3"),
+        ];
+
+        let changes = detect_renames(changes);
+        assert_eq!(changes.len(), 4);
+        // Only one of the copies can be the moved file, the other one is created
+        assert_eq!(changes[0].file_path, "/app/copy/asset.bin");
+        assert_eq!(
+            changes[0].change_type,
+            FileChangeType::Renamed {
+                from: "/app/asset.bin".to_string()
+            }
+        );
+        assert_eq!(changes[1].file_path, "/app/new/asset.bin");
+        assert_eq!(changes[1].change_type, FileChangeType::Created);
+        assert_eq!(changes[2].file_path, "/app/other.txt");
+        assert_eq!(changes[2].change_type, FileChangeType::Created);
+        assert_eq!(changes[3].file_path, "/app/old.txt");
+        assert_eq!(changes[3].change_type, FileChangeType::Deleted);
+    }
+}
@@ -1,18 +1,119 @@
 use std::{
-    fs::File,
-    io::Read,
+    fs::{File, Metadata},
+    io::{self, Read},
     path::{Path, PathBuf},
 };
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
+use memmap2::Mmap;
 
 use crate::{
+    cancellation::{CancellationToken, Cancelled},
     indexer::{
-        file_change::FileChangeType, indexed_hasher::IndexedHasher, indexer_config::IndexerConfig,
+        file_change::FileChangeType, hash_algorithm::ContentHasher, indexed_hasher::IndexedHasher,
+        indexer_config::IndexerConfig, merkle,
     },
     storage::db_utils,
 };
 
+/// Files at least this large are memory-mapped instead of read through a buffer, when the indexer
+/// is configured to, see `IndexerConfig::with_mmap`.
+pub const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+// Buffer smaller files are read through, large enough to keep the number of reads low
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+// Memory-mapped files are hashed in slices of this size, checking for cancellation in between
+const MMAP_SLICE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How the content of a file is read while hashing it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadStrategy {
+    Buffered,
+    // Faster for large files, but if another process truncates the file while it is mapped,
+    // reading the pages past its new end raises SIGBUS and the whole process dies. Only safe for
+    // trees nothing else writes to while they are hashed.
+    Mmap,
+}
+
+impl ReadStrategy {
+    /// Strategy used by the indexer for a file of the given size, memory-mapping large files
+    /// only if `use_mmap` is set.
+    pub fn for_size(file_size: u64, use_mmap: bool) -> Self {
+        if use_mmap && file_size >= MMAP_THRESHOLD {
+            ReadStrategy::Mmap
+        } else {
+            ReadStrategy::Buffered
+        }
+    }
+}
+
+/// Feed the whole content of a file to the hasher. Fails instead of hashing partial content if
+/// the file can't be read to the end or doesn't have the expected size anymore, or with an error
+/// wrapping `Cancelled` if the token is cancelled meanwhile.
+pub fn hash_content(
+    file: &mut File,
+    file_size: u64,
+    strategy: ReadStrategy,
+    hasher: &mut dyn ContentHasher,
+    cancellation: &CancellationToken,
+) -> Result<(), io::Error> {
+    let bytes_read = match strategy {
+        ReadStrategy::Mmap => {
+            // SAFETY: the mapping is only read while hashing and dropped before returning. It is
+            // not safe against other processes: if the file is truncated meanwhile, the read
+            // raises SIGBUS and kills the process instead of failing, which callers opt in to by
+            // choosing this strategy.
+            let mmap = unsafe { Mmap::map(&*file)? };
+            for slice in mmap.chunks(MMAP_SLICE_SIZE) {
+                cancellation.check().map_err(io::Error::other)?;
+                hasher.update(slice);
+            }
+            mmap.len() as u64
+        }
+        ReadStrategy::Buffered => {
+            let mut buffer = vec![0; READ_BUFFER_SIZE];
+            let mut bytes_read = 0;
+            loop {
+                cancellation.check().map_err(io::Error::other)?;
+                let read = match file.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                hasher.update(&buffer[..read]);
+                bytes_read += read as u64;
+            }
+            bytes_read
+        }
+    };
+    if bytes_read != file_size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "read {} bytes instead of {}, the file changed while being hashed",
+                bytes_read, file_size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Feed the whole content of a file to the hasher, reading it the way the indexer does for its
+/// size. The indexer and the live index both hash files through here, so that the hashes of a
+/// watched tree always match the ones `DirHasher` computes.
+pub fn hash_file(
+    file: &mut File,
+    file_size: u64,
+    use_mmap: bool,
+    hasher: &mut dyn ContentHasher,
+    cancellation: &CancellationToken,
+) -> Result<(), io::Error> {
+    let strategy = ReadStrategy::for_size(file_size, use_mmap);
+    hash_content(file, file_size, strategy, hasher, cancellation)
+}
+
 pub struct FileHasher {
     config: IndexerConfig,
 }
@@ -23,6 +124,7 @@ impl FileHasher {
     }
 
     pub async fn file_hash(&self, file_path: &PathBuf) -> Result<IndexedHasher, anyhow::Error> {
+        self.config.cancellation.check()?;
         let mut file =
             File::open(file_path).map_err(|e| anyhow::anyhow!("Error opening file: {}", e))?;
         let metadata = file
@@ -34,36 +136,38 @@ impl FileHasher {
             return Err(anyhow::anyhow!("Provided path is a directory, not a file"));
         }
 
+        let mode = merkle::entry_mode(&metadata);
         let modified_time = metadata.modified()?;
         let modified_time = DateTime::<Utc>::from(modified_time).naive_utc();
         // Check if we have a cached hash for this file
         let hasher = if let Some(index) =
-            db_utils::last_index(self.config.app_id, file_path, &self.config.db).await
+            db_utils::last_index(self.config.app_id, file_path, self.config.store.as_ref()).await
         {
             // If the file has not been modified and we have a hash, return the cached hash
-            if index.file_type == "FILE"
-                && modified_time == index.modified_time
-                && index.hash_code.is_some()
+            if index.is_current_file(&modified_time, mode, self.config.hash_algorithm)
+                && let Some(hex_hash) = index.hash_code
             {
-                let hex_hash = index.hash_code.unwrap();
-
+                if let Some(progress) = &self.config.progress {
+                    progress.add_file(0);
+                }
                 IndexedHasher::from_hash(
                     file_path,
                     "FILE",
+                    mode,
                     modified_time,
                     &hex_hash,
                     self.config.clone(),
                 )
             } else {
                 // Otherwise, we will recompute the hash
-                let mut hasher = self.compute_file_hash(&mut file, file_path, modified_time);
+                let mut hasher = self.compute_file_hash(&mut file, file_path, mode, &metadata)?;
                 let path_str = file_path.display().to_string();
                 hasher.append_changed_file(&path_str, "FILE", FileChangeType::Modified);
                 hasher
             }
         } else {
             // No cache entry at all, this is a new file
-            let mut hasher = self.compute_file_hash(&mut file, file_path, modified_time);
+            let mut hasher = self.compute_file_hash(&mut file, file_path, mode, &metadata)?;
             let path_str = file_path.display().to_string();
             hasher.append_changed_file(&path_str, "FILE", FileChangeType::Created);
             hasher
@@ -76,19 +180,61 @@ impl FileHasher {
         &self,
         file: &mut File,
         file_path: &Path,
-        modified_time: NaiveDateTime,
-    ) -> IndexedHasher {
-        let mut buffer: [u8; 4096] = [0; 4096]; // Read in 4KB chunks
-
-        let mut hasher = IndexedHasher::new(file_path, "FILE", modified_time, self.config.clone());
-        while let Ok(bytes_read) = file.read(&mut buffer) {
-            // Reaching end of file
-            if bytes_read == 0 {
-                break;
+        mode: u32,
+        metadata: &Metadata,
+    ) -> Result<IndexedHasher, anyhow::Error> {
+        let modified_time = DateTime::<Utc>::from(metadata.modified()?).naive_utc();
+        let file_size = metadata.len();
+        let mut hasher =
+            IndexedHasher::new(file_path, "FILE", mode, modified_time, self.config.clone());
+        if let Some(chunking) = self.config.chunking
+            && chunking.should_chunk(file_size)
+        {
+            // The whole file hash is computed from the chunk contents in the same pass
+            let reader = self.config.cancellation.reader(file);
+            let chunks = chunking
+                .split(reader, self.config.hash_algorithm, |data| {
+                    hasher.append_hash(data)
+                })
+                .map_err(|e| self.cancelled_or(e))?;
+            let chunked_size: u64 = chunks.iter().map(|chunk| chunk.length).sum();
+            if chunked_size != file_size {
+                return Err(anyhow::anyhow!(
+                    "Error reading {}: the file changed while being hashed",
+                    file_path.display()
+                ));
             }
-            hasher.append_hash(&buffer[..bytes_read]);
+            hasher.chunks = Some(chunks);
+        } else {
+            let config = &self.config;
+            hash_file(
+                file,
+                file_size,
+                config.use_mmap,
+                hasher.hasher.as_mut(),
+                &config.cancellation,
+            )
+            .map_err(|e| {
+                self.cancelled_or(anyhow::anyhow!(
+                    "Error reading {}: {}",
+                    file_path.display(),
+                    e
+                ))
+            })?;
+        }
+        if let Some(progress) = &self.config.progress {
+            progress.add_file(file_size);
         }
+        Ok(hasher)
+    }
 
-        hasher
+    /// Errors caused by a cancellation are reported as `Cancelled` so that callers can tell them
+    /// apart from read failures.
+    fn cancelled_or(&self, error: anyhow::Error) -> anyhow::Error {
+        if self.config.cancellation.is_cancelled() {
+            Cancelled.into()
+        } else {
+            error
+        }
     }
 }
@@ -3,6 +3,8 @@ pub struct FileInfo {
     pub _path: String,
     /// FILE or DIRECTORY
     pub file_type: String,
+    /// Last indexed hash, if any
+    pub hash_code: Option<String>,
 }
 
 impl FileInfo {
@@ -10,6 +12,14 @@ impl FileInfo {
         FileInfo {
             _path: path.to_string(),
             file_type: file_type.to_string(),
+            hash_code: None,
+        }
+    }
+
+    pub fn with_hash(path: &str, file_type: &str, hash_code: Option<String>) -> Self {
+        FileInfo {
+            hash_code,
+            ..FileInfo::new(path, file_type)
         }
     }
 }
@@ -0,0 +1,148 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Incrementally hashes file contents with one of the supported algorithms.
+pub trait ContentHasher: Send {
+    fn update(&mut self, data: &[u8]);
+
+    /// Finish hashing and return the hash as a lowercase hexadecimal string.
+    fn finalize_hex(self: Box<Self>) -> String;
+}
+
+impl ContentHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize_hex(self: Box<Self>) -> String {
+        base16ct::lower::encode_string(&self.finalize())
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize_hex(self: Box<Self>) -> String {
+        base16ct::lower::encode_string(self.finalize().as_bytes())
+    }
+}
+
+impl ContentHasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+
+    fn finalize_hex(self: Box<Self>) -> String {
+        base16ct::lower::encode_string(&self.digest128().to_be_bytes())
+    }
+}
+
+/// Algorithm used to hash the files and directories of an application.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    // 128-bit XXH3, fast but not cryptographic, only for detecting accidental changes
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn hasher(&self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3::new()),
+        }
+    }
+
+    /// Hash the given data, returning the hash as a lowercase hexadecimal string.
+    pub fn digest(&self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize_hex()
+    }
+
+    /// Whether content can be identified by its hash alone, without trusting where it came from.
+    pub fn is_cryptographic(&self) -> bool {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => true,
+            HashAlgorithm::Xxh3 => false,
+        }
+    }
+
+    /// Length of a hash in bytes.
+    pub fn hash_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+            HashAlgorithm::Xxh3 => 16,
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+            HashAlgorithm::Blake3 => write!(f, "blake3"),
+            HashAlgorithm::Xxh3 => write!(f, "xxh3"),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            _ => Err(anyhow!("Unsupported hash algorithm: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashAlgorithm;
+
+    #[test]
+    fn digest_test() {
+        assert_eq!(
+            HashAlgorithm::Sha256.digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            HashAlgorithm::Blake3.digest(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        for algorithm in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Xxh3,
+        ] {
+            assert_eq!(algorithm.digest(b"abc").len(), algorithm.hash_len() * 2);
+            assert_eq!(
+                algorithm.to_string().parse::<HashAlgorithm>().unwrap(),
+                algorithm
+            );
+        }
+
+        // Incremental hashing gives the same result
+        let mut hasher = HashAlgorithm::Xxh3.hasher();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        assert_eq!(hasher.finalize_hex(), HashAlgorithm::Xxh3.digest(b"abc"));
+    }
+}
@@ -1,11 +1,13 @@
 use std::path::{Path, PathBuf};
 
 use chrono::NaiveDateTime;
-use sha2::{Digest, Sha256};
 
 use crate::indexer::{
+    chunking::FileChunk,
     file_change::{FileChange, FileChangeType},
+    hash_algorithm::ContentHasher,
     indexer_config::IndexerConfig,
+    merkle::{self, MerkleEntry},
 };
 
 // A struct representing a hasher with a list of indexed file paths.
@@ -15,9 +17,16 @@ pub struct IndexedHasher {
     pub file_path: PathBuf,
     // FILE or DIRECTORY
     pub file_type: String,
+    // Mode of the entry in the Merkle tree
+    pub mode: u32,
     pub modified_time: NaiveDateTime,
-    pub hasher: Sha256,
+    // Hashes the content of a file
+    pub hasher: Box<dyn ContentHasher>,
+    // Direct children of a directory
+    pub entries: Vec<MerkleEntry>,
     pub cached_hash: Option<String>,
+    // Content-defined chunks of the file, only set when the file was split while hashing
+    pub chunks: Option<Vec<FileChunk>>,
     pub changed_files: Vec<FileChange>,
     pub config: IndexerConfig,
 }
@@ -26,15 +35,19 @@ impl IndexedHasher {
     pub fn new(
         file_path: &Path,
         file_type: &str,
+        mode: u32,
         modified_time: NaiveDateTime,
         config: IndexerConfig,
     ) -> Self {
         IndexedHasher {
             file_path: file_path.to_path_buf(),
             file_type: file_type.to_string(),
+            mode,
             modified_time,
-            hasher: Sha256::new(),
+            hasher: config.hash_algorithm.hasher(),
+            entries: Vec::new(),
             cached_hash: None,
+            chunks: None,
             changed_files: Vec::new(),
             config,
         }
@@ -43,25 +56,29 @@ impl IndexedHasher {
     pub fn from_hash(
         file_path: &Path,
         file_type: &str,
+        mode: u32,
         modified_time: NaiveDateTime,
         hex_hash: impl AsRef<str>,
         config: IndexerConfig,
     ) -> Self {
-        let hasher = Sha256::new();
+        let hasher = config.hash_algorithm.hasher();
         IndexedHasher {
             file_path: file_path.to_path_buf(),
             file_type: file_type.to_string(),
+            mode,
             modified_time,
             hasher,
+            entries: Vec::new(),
             cached_hash: Some(hex_hash.as_ref().to_string()),
+            chunks: None,
             changed_files: Vec::new(),
             config,
         }
     }
 
-    /// Append a hexadecimal hash string to the current hash without adding the file path to the changed files list.
+    /// Append file content to the current hash without adding the file path to the changed files list.
     pub fn append_hash(&mut self, data: impl AsRef<[u8]>) {
-        self.hasher.update(data);
+        self.hasher.update(data.as_ref());
     }
 
     /// Append a changed file path to the list of changed files without updating the hash.
@@ -75,51 +92,92 @@ impl IndexedHasher {
             file_path: file_path.as_ref().to_string(),
             file_type: file_type.as_ref().to_string(),
             change_type,
+            hash_code: None,
+            mode: None,
+            chunks: None,
         });
     }
 
-    /// Extend the list of changed files with another IndexedHasher's changed files
-    /// and combine their hashes.
+    /// Append a deleted file path to the list of changed files, keeping its last known hash.
+    pub fn append_deleted_file(
+        &mut self,
+        file_path: impl AsRef<str>,
+        file_type: impl AsRef<str>,
+        hash_code: Option<String>,
+    ) {
+        self.changed_files.push(FileChange {
+            file_path: file_path.as_ref().to_string(),
+            file_type: file_type.as_ref().to_string(),
+            change_type: FileChangeType::Deleted,
+            hash_code,
+            mode: None,
+            chunks: None,
+        });
+    }
+
+    /// Extend the list of changed files with the changed files of a direct child
+    /// and add the child to the entries of this directory.
     ///
     /// The provided IndexedHasher is consumed in the process and a hexadecimal hash string
     /// is returned.
-    pub async fn extend(&mut self, other: IndexedHasher) -> String {
-        let (hex_hash, changed_files) = other.finalize().await;
+    pub async fn extend(&mut self, other: IndexedHasher) -> Result<String, anyhow::Error> {
+        let file_path = other.file_path.clone();
+        let file_type = other.file_type.clone();
+        let mode = other.mode;
+        let (hex_hash, changed_files) = other.finalize().await?;
 
-        self.hasher.update(hex_hash.as_bytes());
+        self.entries
+            .push(MerkleEntry::new(&file_path, &file_type, mode, &hex_hash));
         self.changed_files.extend(changed_files);
-        hex_hash
+        Ok(hex_hash)
     }
 
-    pub async fn finalize(self) -> (String, Vec<FileChange>) {
+    pub async fn finalize(self) -> Result<(String, Vec<FileChange>), anyhow::Error> {
         let path_str = self.file_path.display().to_string();
         if let Some(cached_hash) = self.cached_hash {
-            tracing::info!("hash: {}, entry: {} (cached)", cached_hash, path_str);
+            tracing::debug!("hash: {}, entry: {} (cached)", cached_hash, path_str);
             // If we have a cached hash, return it directly without recomputing,
             // and return an empty list of changed files.
-            return (cached_hash, Vec::new());
+            return Ok((cached_hash, Vec::new()));
         }
 
-        let hash = self.hasher.finalize();
-        // Encode the hash as a hexadecimal string
-        let hex_hash = base16ct::lower::encode_string(&hash);
-        tracing::info!("hash: {}, entry: {} (recomputed)", hex_hash, &path_str);
+        let hex_hash = if self.file_type == "DIRECTORY" {
+            // Hashes of unchanged children come from the index, which might not match the
+            // algorithm anymore
+            merkle::dir_hash(self.config.hash_algorithm, &self.entries)
+                .map_err(|e| anyhow::anyhow!("Error hashing {}: {}", self.file_path.display(), e))?
+        } else {
+            self.hasher.finalize_hex()
+        };
+        tracing::debug!("hash: {}, entry: {} (recomputed)", hex_hash, &path_str);
+
+        // Attach the new hash, mode and chunks to the change recorded for this entry
+        let mut changed_files = self.changed_files;
+        for change in changed_files
+            .iter_mut()
+            .filter(|change| change.file_path == path_str && change.hash_code.is_none())
+        {
+            change.hash_code = Some(hex_hash.clone());
+            change.mode = Some(self.mode);
+            change.chunks = self.chunks.clone();
+        }
 
         // Update index if needed
         if self.config.update_index {
             self.config
-                .db
                 .upsert_file_index(
-                    self.config.app_id,
                     &path_str,
                     &self.file_type,
                     &hex_hash,
+                    self.mode,
                     &self.modified_time,
                 )
-                .await
-                .unwrap();
+                .await?;
+            if let Some(chunks) = &self.chunks {
+                self.config.set_file_chunks(&path_str, chunks).await?;
+            }
         }
 
-        (hex_hash, self.changed_files)
+        Ok((hex_hash, changed_files))
     }
 }
@@ -1,18 +1,164 @@
-use crate::storage::patcher_db::PatcherDatabase;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+
+use crate::{
+    cancellation::CancellationToken,
+    indexer::{
+        chunking::{ChunkingConfig, FileChunk},
+        hash_algorithm::HashAlgorithm,
+        staged_index::{IndexUpdate, StagedIndex},
+    },
+    progress::{ProgressReporter, ProgressStage, ProgressTracker},
+    storage::{file_index::FileIndex, index_store::IndexStore},
+};
 
 #[derive(Clone)]
 pub struct IndexerConfig {
     pub app_id: i64,
-    pub db: PatcherDatabase,
+    // Where cached hashes are read from and, unless staged, index changes are written to
+    pub store: Arc<dyn IndexStore>,
     pub update_index: bool,
+    // When set, index changes are collected here instead of being written to the database
+    pub staged_index: Option<StagedIndex>,
+    // When set, large files are split into content-defined chunks recorded in the index
+    pub chunking: Option<ChunkingConfig>,
+    // Algorithm files and directories are hashed with
+    pub hash_algorithm: HashAlgorithm,
+    // When set, the files scanned and bytes hashed are reported here
+    pub progress: Option<ProgressTracker>,
+    // Checked between files and while reading them, hashing stops with `Cancelled` once cancelled
+    pub cancellation: CancellationToken,
+    // When set, large files are memory-mapped instead of read through a buffer, see
+    // `ReadStrategy::Mmap` for why this is off by default
+    pub use_mmap: bool,
 }
 
 impl IndexerConfig {
-    pub fn new(app_id: i64, db: PatcherDatabase, update_index: bool) -> Self {
+    pub fn new(app_id: i64, store: impl IndexStore + 'static, update_index: bool) -> Self {
         IndexerConfig {
             app_id,
-            db,
+            store: Arc::new(store),
             update_index,
+            staged_index: None,
+            chunking: None,
+            hash_algorithm: HashAlgorithm::default(),
+            progress: None,
+            cancellation: CancellationToken::default(),
+            use_mmap: false,
+        }
+    }
+
+    /// Create a config that updates the index through the given stage.
+    /// Nothing is written to the store until the staged changes are committed.
+    pub fn staged(
+        app_id: i64,
+        store: impl IndexStore + 'static,
+        staged_index: StagedIndex,
+    ) -> Self {
+        IndexerConfig {
+            app_id,
+            store: Arc::new(store),
+            update_index: true,
+            staged_index: Some(staged_index),
+            chunking: None,
+            hash_algorithm: HashAlgorithm::default(),
+            progress: None,
+            cancellation: CancellationToken::default(),
+            use_mmap: false,
         }
     }
+
+    pub fn with_chunking(mut self, chunking: Option<ChunkingConfig>) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Report the progress of indexing to the given reporter, shared by the clones of the config.
+    pub fn with_progress(mut self, reporter: Option<Arc<dyn ProgressReporter>>) -> Self {
+        self.progress =
+            reporter.map(|reporter| ProgressTracker::new(ProgressStage::Indexing, reporter));
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Memory-map large files while hashing them. Faster, but a file truncated by another process
+    /// while it is hashed crashes the process, see `ReadStrategy::Mmap`.
+    pub fn with_mmap(mut self, use_mmap: bool) -> Self {
+        self.use_mmap = use_mmap;
+        self
+    }
+
+    /// Record a new hash for the given path, either directly in the store or in the stage.
+    pub async fn upsert_file_index(
+        &self,
+        file_path: &str,
+        file_type: &str,
+        hash_code: &str,
+        mode: u32,
+        modified_time: &NaiveDateTime,
+    ) -> Result<(), anyhow::Error> {
+        let index = FileIndex {
+            app_id: self.app_id,
+            file_path: file_path.to_string(),
+            file_type: file_type.to_string(),
+            hash_code: Some(hash_code.to_string()),
+            hash_algorithm: self.hash_algorithm,
+            mode,
+            modified_time: *modified_time,
+        };
+        if let Some(staged_index) = &self.staged_index {
+            staged_index.push(IndexUpdate::Upsert(index));
+            return Ok(());
+        }
+
+        self.store.upsert_file_index(&index).await
+    }
+
+    /// Remove the given path from the index, either directly in the store or in the stage.
+    pub async fn delete_file_index(&self, file_path: &str) -> Result<(), anyhow::Error> {
+        if let Some(staged_index) = &self.staged_index {
+            staged_index.push(IndexUpdate::Delete {
+                app_id: self.app_id,
+                file_path: file_path.to_string(),
+            });
+            return Ok(());
+        }
+
+        self.store
+            .delete_file_index(self.app_id, file_path)
+            .await
+            .map(|_| ())
+    }
+
+    /// Record the chunks of a file, either directly in the store or in the stage.
+    /// The chunks of a file are cleared whenever its index is updated, so this must be
+    /// called after `upsert_file_index`.
+    pub async fn set_file_chunks(
+        &self,
+        file_path: &str,
+        chunks: &[FileChunk],
+    ) -> Result<(), anyhow::Error> {
+        if let Some(staged_index) = &self.staged_index {
+            staged_index.push(IndexUpdate::Chunks {
+                app_id: self.app_id,
+                file_path: file_path.to_string(),
+                chunks: chunks.to_vec(),
+            });
+            return Ok(());
+        }
+
+        self.store
+            .set_file_chunks(self.app_id, file_path, chunks)
+            .await
+    }
 }
@@ -0,0 +1,370 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::{
+    cancellation::CancellationToken,
+    indexer::{
+        file_change::{FileChange, FileChangeType, detect_renames},
+        file_hasher,
+        hash_algorithm::HashAlgorithm,
+        merkle::{self, MODE_DIRECTORY, MerkleEntry},
+    },
+    storage::{application_data::Application, file_index::FileIndex, patcher_db::PatcherDatabase},
+};
+
+/// An in-memory index of the source tree of an application, updated path by path as files change
+/// on disk. Hashes follow the same Merkle format as `DirHasher` so the root hash can be compared
+/// with the released one.
+pub struct LiveIndex {
+    app_id: i64,
+    hash_algorithm: HashAlgorithm,
+    root: PathBuf,
+    db: PatcherDatabase,
+    entries: BTreeMap<PathBuf, FileIndex>,
+    // Changes made since the last flush, along with the type of the changed entry
+    pending: BTreeMap<PathBuf, (FileChangeType, String)>,
+}
+
+impl LiveIndex {
+    /// Load the live index of an application, starting from the index of its last release, and
+    /// bring it up to date with the source tree. Only files modified since the release are rehashed.
+    pub async fn load(app: &Application, db: PatcherDatabase) -> Result<Self, anyhow::Error> {
+        let entries = db
+            .list_file_index(app.id)
+            .await?
+            .into_iter()
+            .map(|index| (PathBuf::from(&index.file_path), index))
+            .collect();
+        let mut live_index = LiveIndex {
+            app_id: app.id,
+            hash_algorithm: app.hash_algorithm,
+            root: PathBuf::from(&app.source_path),
+            db,
+            entries,
+            pending: BTreeMap::new(),
+        };
+        let root = live_index.root.clone();
+        live_index.sync_path(&root)?;
+        live_index.pending.clear();
+
+        // Replace whatever a previous watcher left behind
+        let hash_code = live_index.root_hash()?;
+        let entries: Vec<_> = live_index.entries.values().cloned().collect();
+        live_index.db.clear_live_index(app.id).await?;
+        live_index
+            .db
+            .apply_live_index(app.id, &entries, &[], &hash_code)
+            .await?;
+        Ok(live_index)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Hash of the whole source tree.
+    pub fn root_hash(&self) -> Result<String, anyhow::Error> {
+        self.entries
+            .get(&self.root)
+            .and_then(|index| index.hash_code.clone())
+            .ok_or_else(|| anyhow::anyhow!("{} does not exist", self.root.display()))
+    }
+
+    /// Bring a path of the source tree up to date with the disk, along with the hashes of its
+    /// parent directories. A file is always rehashed, files under a directory only when their
+    /// modified time changed.
+    pub fn sync_path(&mut self, path: &Path) -> Result<(), anyhow::Error> {
+        if !path.starts_with(&self.root) {
+            return Ok(());
+        }
+        // A path under a directory we don't know yet is synced along with the whole directory
+        let path = path
+            .ancestors()
+            .take_while(|dir| dir.starts_with(&self.root))
+            .filter(|dir| *dir != path && !self.entries.contains_key(*dir))
+            .last()
+            .unwrap_or(path)
+            .to_path_buf();
+        self.sync_entry(&path, true)?;
+
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) {
+                break;
+            }
+            let modified_time = fs::metadata(dir).and_then(|metadata| metadata.modified());
+            match modified_time {
+                Ok(modified_time) => self.rehash_dir(dir, modified_time)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => self.remove_tree(dir),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the changes made since the last flush to the database and return them.
+    pub async fn flush(&mut self) -> Result<Vec<FileChange>, anyhow::Error> {
+        let hash_code = self.root_hash()?;
+        let pending = std::mem::take(&mut self.pending);
+        let mut upserts = Vec::new();
+        let mut deletes = Vec::new();
+        let mut changes = Vec::new();
+        for (path, (change_type, file_type)) in pending {
+            let file_path = path.display().to_string();
+            if change_type == FileChangeType::Deleted {
+                deletes.push(file_path.clone());
+                changes.push(FileChange {
+                    file_path,
+                    file_type,
+                    change_type,
+                    hash_code: None,
+                    mode: None,
+                    chunks: None,
+                });
+            } else if let Some(index) = self.entries.get(&path) {
+                upserts.push(index.clone());
+                changes.push(FileChange {
+                    file_path,
+                    file_type: index.file_type.clone(),
+                    change_type,
+                    hash_code: index.hash_code.clone(),
+                    mode: Some(index.mode),
+                    chunks: None,
+                });
+            }
+        }
+        self.db
+            .apply_live_index(self.app_id, &upserts, &deletes, &hash_code)
+            .await?;
+        Ok(changes)
+    }
+
+    fn sync_entry(&mut self, path: &Path, force: bool) -> Result<(), anyhow::Error> {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.remove_tree(path);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mode = merkle::entry_mode(&metadata);
+        let modified_time = metadata.modified()?;
+
+        if metadata.is_dir() {
+            let mut children = Vec::new();
+            for entry in fs::read_dir(path)? {
+                children.push(entry?.path());
+            }
+            children.sort();
+            let removed: Vec<_> = self
+                .direct_children(path)
+                .map(|index| PathBuf::from(&index.file_path))
+                .filter(|child| children.binary_search(child).is_err())
+                .collect();
+            for child in removed {
+                self.remove_tree(&child);
+            }
+            for child in children {
+                self.sync_entry(&child, false)?;
+            }
+            return self.rehash_dir(path, modified_time);
+        }
+
+        // The path might have been a directory before
+        self.remove_descendants(path);
+        let modified_time = naive_utc(modified_time);
+        if !force
+            && let Some(index) = self.entries.get(path)
+            && index.is_current_file(&modified_time, mode, self.hash_algorithm)
+        {
+            return Ok(());
+        }
+        match hash_file(path, self.hash_algorithm) {
+            Ok(hash_code) => self.record(path, "FILE", hash_code, mode, modified_time),
+            // Removed while we were looking at it
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.remove_tree(path),
+            // Still being written, the next event rehashes it
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Recompute the hash of a directory from the hashes of its direct children.
+    fn rehash_dir(
+        &mut self,
+        dir: &Path,
+        modified_time: std::time::SystemTime,
+    ) -> Result<(), anyhow::Error> {
+        let entries: Vec<_> = self
+            .direct_children(dir)
+            .map(|child| {
+                MerkleEntry::new(
+                    Path::new(&child.file_path),
+                    &child.file_type,
+                    child.mode,
+                    child.hash_code.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect();
+        let hash_code = merkle::dir_hash(self.hash_algorithm, &entries)?;
+        let modified_time = naive_utc(modified_time);
+        self.record(dir, "DIRECTORY", hash_code, MODE_DIRECTORY, modified_time);
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        path: &Path,
+        file_type: &str,
+        hash_code: String,
+        mode: u32,
+        time: NaiveDateTime,
+    ) {
+        let change_type = match (self.entries.get(path), self.pending.get(path)) {
+            (Some(index), _)
+                if index.file_type == file_type
+                    && index.mode == mode
+                    && index.hash_algorithm == self.hash_algorithm
+                    && index.hash_code.as_deref() == Some(hash_code.as_str()) =>
+            {
+                // Nothing changed but the modified time
+                self.entries.get_mut(path).unwrap().modified_time = time;
+                return;
+            }
+            (_, Some((FileChangeType::Created, _))) => FileChangeType::Created,
+            (None, None) => FileChangeType::Created,
+            _ => FileChangeType::Modified,
+        };
+        self.pending
+            .insert(path.to_path_buf(), (change_type, file_type.to_string()));
+        self.entries.insert(
+            path.to_path_buf(),
+            FileIndex {
+                app_id: self.app_id,
+                file_path: path.display().to_string(),
+                file_type: file_type.to_string(),
+                hash_code: Some(hash_code),
+                hash_algorithm: self.hash_algorithm,
+                mode,
+                modified_time: time,
+            },
+        );
+    }
+
+    /// Remove a path and everything under it from the index.
+    fn remove_tree(&mut self, path: &Path) {
+        self.remove_descendants(path);
+        if let Some(index) = self.entries.remove(path) {
+            self.mark_removed(index);
+        }
+    }
+
+    fn remove_descendants(&mut self, path: &Path) {
+        let descendants: Vec<_> = self
+            .entries
+            .range::<Path, _>((Bound::Excluded(path), Bound::Unbounded))
+            .map(|(child, _)| child)
+            .take_while(|child| child.starts_with(path))
+            .cloned()
+            .collect();
+        for child in descendants {
+            if let Some(index) = self.entries.remove(&child) {
+                self.mark_removed(index);
+            }
+        }
+    }
+
+    fn mark_removed(&mut self, index: FileIndex) {
+        let path = PathBuf::from(&index.file_path);
+        // A path created and removed between two flushes was never written
+        if matches!(self.pending.get(&path), Some((FileChangeType::Created, _))) {
+            self.pending.remove(&path);
+        } else {
+            self.pending
+                .insert(path, (FileChangeType::Deleted, index.file_type));
+        }
+    }
+
+    fn direct_children<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = &'a FileIndex> + 'a {
+        self.entries
+            .range::<Path, _>((Bound::Excluded(dir), Bound::Unbounded))
+            .take_while(move |(child, _)| child.starts_with(dir))
+            .filter(move |(child, _)| child.parent() == Some(dir))
+            .map(|(_, index)| index)
+    }
+}
+
+/// Compare the live index of an application with the index of its last release.
+pub fn diff_live_index(root: &Path, release: &[FileIndex], live: &[FileIndex]) -> Vec<FileChange> {
+    let root = root.display().to_string();
+    let release: HashMap<_, _> = release
+        .iter()
+        .map(|index| (index.file_path.as_str(), index))
+        .collect();
+    let live: HashMap<_, _> = live
+        .iter()
+        .map(|index| (index.file_path.as_str(), index))
+        .collect();
+
+    let mut changes = Vec::new();
+    for (file_path, index) in &live {
+        let change_type = match release.get(file_path) {
+            None => FileChangeType::Created,
+            Some(released)
+                if released.hash_code != index.hash_code || released.mode != index.mode =>
+            {
+                FileChangeType::Modified
+            }
+            Some(_) => continue,
+        };
+        changes.push(change_from_index(index, change_type));
+    }
+    for (file_path, index) in &release {
+        if !live.contains_key(file_path) {
+            changes.push(change_from_index(index, FileChangeType::Deleted));
+        }
+    }
+    // The root is not a change of its own
+    changes.retain(|change| change.file_path != root);
+    changes.sort_by(|a, b| Path::new(&a.file_path).cmp(Path::new(&b.file_path)));
+    detect_renames(changes)
+}
+
+fn change_from_index(index: &FileIndex, change_type: FileChangeType) -> FileChange {
+    FileChange {
+        file_path: index.file_path.clone(),
+        file_type: index.file_type.clone(),
+        change_type,
+        hash_code: index.hash_code.clone(),
+        mode: Some(index.mode),
+        chunks: None,
+    }
+}
+
+fn hash_file(path: &Path, hash_algorithm: HashAlgorithm) -> Result<String, io::Error> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let mut hasher = hash_algorithm.hasher();
+    // The watcher only stops between events, and files it watches are being written to, so they
+    // are never memory-mapped
+    file_hasher::hash_file(
+        &mut file,
+        file_size,
+        false,
+        hasher.as_mut(),
+        &CancellationToken::new(),
+    )?;
+    Ok(hasher.finalize_hex())
+}
+
+fn naive_utc(time: std::time::SystemTime) -> NaiveDateTime {
+    DateTime::<Utc>::from(time).naive_utc()
+}
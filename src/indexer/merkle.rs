@@ -0,0 +1,301 @@
+//! Merkle tree format of application hashes, version 1.
+//!
+//! Hashes are computed with the algorithm of the application, SHA-256 unless another one was
+//! chosen when adding it, see [`HashAlgorithm`].
+//!
+//! - The hash of a file is the hash of its content.
+//! - The hash of a directory is the hash of the ASCII tag `sop-merkle`, the format version
+//!   as one byte, then one record per direct child sorted by the bytes of its name:
+//!   - the type, `F` for a file or `D` for a directory, as one byte
+//!   - the mode as a 32-bit big-endian integer, see [`entry_mode`]
+//!   - the length of the UTF-8 name as a 32-bit big-endian integer, followed by the name
+//!   - the raw bytes of the hash of the child, 32 bytes for SHA-256
+//! - The hash of an application is the hash of its source directory.
+//!
+//! Hashes are written as lowercase hexadecimal strings.
+//!
+//! Versions recorded before this format have directory hashes in format version 0: the SHA-256
+//! hash of the hexadecimal hashes of the direct children, concatenated in path order, without
+//! names, types or modes. Their format version is recorded along with their hash so that installs
+//! can still be matched against them.
+
+use std::{
+    collections::BTreeMap,
+    fs::Metadata,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::indexer::hash_algorithm::HashAlgorithm;
+
+pub const MERKLE_FORMAT_VERSION: u8 = 1;
+/// Format of the directory hashes recorded before the Merkle tree format.
+pub const LEGACY_FORMAT_VERSION: u8 = 0;
+
+const MERKLE_TAG: &[u8] = b"sop-merkle";
+
+// Modes recorded for tree entries, only the executable bit of files is kept
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_DIRECTORY: u32 = 0o040000;
+
+/// Mode of a file or directory as recorded in the tree.
+pub fn entry_mode(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() {
+        MODE_DIRECTORY
+    } else if is_executable(metadata) {
+        MODE_EXECUTABLE
+    } else {
+        MODE_FILE
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &Metadata) -> bool {
+    false
+}
+
+/// A direct child of a directory in the tree.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MerkleEntry {
+    pub name: String,
+    // FILE or DIRECTORY
+    pub file_type: String,
+    pub mode: u32,
+    pub hash_code: String,
+}
+
+impl MerkleEntry {
+    /// Entry of a child of a directory, named after the last component of its path.
+    pub fn new(path: &Path, file_type: &str, mode: u32, hash_code: &str) -> Self {
+        MerkleEntry {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            file_type: file_type.to_string(),
+            mode,
+            hash_code: hash_code.to_string(),
+        }
+    }
+}
+
+/// Compute the hash of a directory from its direct children, in any order.
+pub fn dir_hash(
+    hash_algorithm: HashAlgorithm,
+    entries: &[MerkleEntry],
+) -> Result<String, anyhow::Error> {
+    let mut entries: Vec<_> = entries.iter().collect();
+    entries.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+
+    let mut hasher = hash_algorithm.hasher();
+    hasher.update(MERKLE_TAG);
+    hasher.update(&[MERKLE_FORMAT_VERSION]);
+    for entry in entries {
+        let type_tag = match entry.file_type.as_str() {
+            "FILE" => b'F',
+            "DIRECTORY" => b'D',
+            other => return Err(anyhow!("Unsupported entry type: {}", other)),
+        };
+        let hash_len = hash_algorithm.hash_len();
+        let mut hash = vec![0; hash_len];
+        base16ct::lower::decode(&entry.hash_code, &mut hash)
+            .ok()
+            .filter(|decoded| decoded.len() == hash_len)
+            .ok_or_else(|| anyhow!("Invalid hash for {}: {}", entry.name, entry.hash_code))?;
+        hasher.update(&[type_tag]);
+        hasher.update(&entry.mode.to_be_bytes());
+        hasher.update(&(entry.name.len() as u32).to_be_bytes());
+        hasher.update(entry.name.as_bytes());
+        hasher.update(&hash);
+    }
+    Ok(hasher.finalize_hex())
+}
+
+/// Compute the hash of a directory from its direct children in format version 0, see the
+/// module documentation. Children are given in path order.
+pub fn legacy_dir_hash(entries: &[MerkleEntry]) -> String {
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    for entry in entries {
+        hasher.update(entry.hash_code.as_bytes());
+    }
+    hasher.finalize_hex()
+}
+
+/// Recompute the hash of every directory of a tree in the given format, from the hashes of its
+/// files. The tree is given as every file and directory under `root` by path, the root included
+/// or not, and directory hashes are replaced in place. Returns the hash of the root.
+pub fn rehash_tree(
+    hash_algorithm: HashAlgorithm,
+    format_version: u8,
+    root: &Path,
+    tree: &mut BTreeMap<PathBuf, MerkleEntry>,
+) -> Result<String, anyhow::Error> {
+    let mut dirs: Vec<PathBuf> = tree
+        .iter()
+        .filter(|(path, entry)| entry.file_type == "DIRECTORY" && path.starts_with(root))
+        .map(|(path, _)| path.clone())
+        .collect();
+    if !tree.contains_key(root) {
+        dirs.push(root.to_path_buf());
+    }
+    // Deepest directories first, so that their hash is up to date when hashing their parent
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    let mut root_hash = String::new();
+    for dir in dirs {
+        let children: Vec<_> = tree
+            .range::<Path, _>((Bound::Excluded(dir.as_path()), Bound::Unbounded))
+            .take_while(|(path, _)| path.starts_with(&dir))
+            .filter(|(path, _)| path.parent() == Some(dir.as_path()))
+            .map(|(_, entry)| entry.clone())
+            .collect();
+        let hash_code = match format_version {
+            LEGACY_FORMAT_VERSION => legacy_dir_hash(&children),
+            MERKLE_FORMAT_VERSION => dir_hash(hash_algorithm, &children)?,
+            other => return Err(anyhow!("Unsupported hash format version {}", other)),
+        };
+        if let Some(entry) = tree.get_mut(&dir) {
+            entry.hash_code = hash_code.clone();
+        }
+        if dir == root {
+            root_hash = hash_code;
+        }
+    }
+    Ok(root_hash)
+}
+
+/// Proof that a file with a given hash is part of a tree, without the rest of the tree.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub format_version: u8,
+    // Older proofs were always hashed with SHA-256
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    // Path of the file relative to the root, separated by slashes
+    pub file_path: String,
+    pub hash_code: String,
+    // Children of every directory on the path of the file, from the root down
+    pub levels: Vec<Vec<MerkleEntry>>,
+}
+
+impl InclusionProof {
+    /// Compute the root hash the proof leads to, checking that every level contains the
+    /// directory or file below it.
+    pub fn root_hash(&self) -> Result<String, anyhow::Error> {
+        if self.format_version != MERKLE_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Unsupported hash format version {}",
+                self.format_version
+            ));
+        }
+        let names: Vec<_> = self.file_path.split('/').collect();
+        if names.len() != self.levels.len() {
+            return Err(anyhow!(
+                "Proof doesn't match the depth of {}",
+                self.file_path
+            ));
+        }
+
+        let mut hash_code = self.hash_code.clone();
+        let mut file_type = "FILE";
+        for (name, entries) in names.iter().zip(&self.levels).rev() {
+            let included = entries.iter().any(|entry| {
+                entry.name == *name && entry.file_type == file_type && entry.hash_code == hash_code
+            });
+            if !included {
+                return Err(anyhow!("{} is missing from the proof", name));
+            }
+            hash_code = dir_hash(self.hash_algorithm, entries)?;
+            file_type = "DIRECTORY";
+        }
+        Ok(hash_code)
+    }
+
+    /// Whether the proof shows that the file is part of the tree with the given root hash.
+    pub fn verify(&self, root_hash: &str) -> bool {
+        self.root_hash()
+            .is_ok_and(|hash_code| hash_code == root_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MODE_DIRECTORY, MODE_EXECUTABLE, MODE_FILE, MerkleEntry};
+    use crate::indexer::hash_algorithm::HashAlgorithm;
+
+    fn dir_hash(entries: &[MerkleEntry]) -> Result<String, anyhow::Error> {
+        super::dir_hash(HashAlgorithm::Sha256, entries)
+    }
+
+    fn entry(name: &str, file_type: &str, mode: u32, hash_code: &str) -> MerkleEntry {
+        MerkleEntry {
+            name: name.to_string(),
+            file_type: file_type.to_string(),
+            mode,
+            hash_code: hash_code.to_string(),
+        }
+    }
+
+    #[test]
+    fn dir_hash_covers_names_types_and_modes() {
+        let hash_a = "a".repeat(64);
+        let hash_b = "b".repeat(64);
+        let base = dir_hash(&[
+            entry("a.txt", "FILE", MODE_FILE, &hash_a),
+            entry("b.txt", "FILE", MODE_FILE, &hash_b),
+        ])
+        .unwrap();
+
+        // The order the children are given in doesn't matter
+        let reordered = dir_hash(&[
+            entry("b.txt", "FILE", MODE_FILE, &hash_b),
+            entry("a.txt", "FILE", MODE_FILE, &hash_a),
+        ])
+        .unwrap();
+        assert_eq!(base, reordered);
+
+        // Renaming a file changes the hash even if the order stays the same
+        let renamed = dir_hash(&[
+            entry("a2.txt", "FILE", MODE_FILE, &hash_a),
+            entry("b.txt", "FILE", MODE_FILE, &hash_b),
+        ])
+        .unwrap();
+        assert_ne!(base, renamed);
+
+        let executable = dir_hash(&[
+            entry("a.txt", "FILE", MODE_EXECUTABLE, &hash_a),
+            entry("b.txt", "FILE", MODE_FILE, &hash_b),
+        ])
+        .unwrap();
+        assert_ne!(base, executable);
+
+        let directory = dir_hash(&[
+            entry("a.txt", "DIRECTORY", MODE_DIRECTORY, &hash_a),
+            entry("b.txt", "FILE", MODE_FILE, &hash_b),
+        ])
+        .unwrap();
+        assert_ne!(base, directory);
+
+        assert!(dir_hash(&[entry("a.txt", "FILE", MODE_FILE, "not a hash")]).is_err());
+
+        // Hashes must have the length of the algorithm
+        let hash_xxh3 = "c".repeat(32);
+        assert!(dir_hash(&[entry("a.txt", "FILE", MODE_FILE, &hash_xxh3)]).is_err());
+        let xxh3_hash = super::dir_hash(
+            HashAlgorithm::Xxh3,
+            &[entry("a.txt", "FILE", MODE_FILE, &hash_xxh3)],
+        )
+        .unwrap();
+        assert_eq!(xxh3_hash.len(), 32);
+    }
+}
@@ -1,6 +1,12 @@
+pub mod chunking;
 pub mod dir_hasher;
 pub mod file_change;
 pub mod file_hasher;
 mod file_info;
+pub mod hash_algorithm;
 mod indexed_hasher;
 pub mod indexer_config;
+pub mod live_index;
+pub mod merkle;
+pub mod staged_index;
+pub mod tree_diff;
@@ -0,0 +1,49 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{indexer::chunking::FileChunk, storage::file_index::FileIndex};
+
+/// A pending change to the file index.
+#[derive(Clone)]
+pub enum IndexUpdate {
+    Upsert(FileIndex),
+    Delete {
+        app_id: i64,
+        file_path: String,
+    },
+    // Replace the chunk list of an indexed file
+    Chunks {
+        app_id: i64,
+        file_path: String,
+        chunks: Vec<FileChunk>,
+    },
+}
+
+/// Collects file index changes made while hashing instead of writing them to the database,
+/// so that they can be committed later in a single transaction or thrown away on failure.
+#[derive(Clone, Default)]
+pub struct StagedIndex {
+    updates: Arc<Mutex<Vec<IndexUpdate>>>,
+}
+
+impl StagedIndex {
+    pub fn new() -> Self {
+        StagedIndex::default()
+    }
+
+    pub fn push(&self, update: IndexUpdate) {
+        self.updates.lock().unwrap().push(update);
+    }
+
+    /// Take all staged changes in the order they were made, leaving the stage empty.
+    pub fn take(&self) -> Vec<IndexUpdate> {
+        std::mem::take(&mut *self.updates.lock().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    cancellation::CancellationToken,
+    indexer::{
+        dir_hasher::DirHasher,
+        file_change::{FileChange, FileChangeType, detect_renames},
+        hash_algorithm::HashAlgorithm,
+        indexer_config::IndexerConfig,
+    },
+    progress::ProgressReporter,
+    storage::index_store::{IndexStore, MemoryIndexStore},
+};
+
+/// Changes turning one directory tree into another.
+#[derive(Debug)]
+pub struct TreeDiff {
+    pub old_dir: PathBuf,
+    pub new_dir: PathBuf,
+    pub old_hash: String,
+    pub new_hash: String,
+    pub hash_algorithm: HashAlgorithm,
+    // Paths are under the new directory, deleted ones included
+    pub changes: Vec<FileChange>,
+}
+
+impl TreeDiff {
+    pub fn has_changes(&self) -> bool {
+        self.old_hash != self.new_hash
+    }
+}
+
+/// File or directory of a tree, as compared by `diff_entries`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeEntry {
+    pub file_type: String,
+    pub hash_code: Option<String>,
+    pub mode: u32,
+}
+
+/// Hash two directory trees and compare their content, without any persistent state.
+///
+/// Both trees are hashed from scratch with an in-memory index, so files are compared by content
+/// and mode rather than by modified time. Changes are reported like the indexer reports them
+/// between two versions, with their paths under `new_dir`.
+pub async fn diff_trees(
+    old_dir: &Path,
+    new_dir: &Path,
+    hash_algorithm: HashAlgorithm,
+    progress: Option<Arc<dyn ProgressReporter>>,
+    cancellation: &CancellationToken,
+) -> Result<TreeDiff, anyhow::Error> {
+    let (old_hash, old_entries) =
+        hash_tree(old_dir, hash_algorithm, progress.clone(), cancellation).await?;
+    let (new_hash, new_entries) =
+        hash_tree(new_dir, hash_algorithm, progress, cancellation).await?;
+    Ok(TreeDiff {
+        old_dir: old_dir.to_path_buf(),
+        new_dir: new_dir.to_path_buf(),
+        old_hash,
+        new_hash,
+        hash_algorithm,
+        changes: diff_entries(old_entries, new_entries, new_dir),
+    })
+}
+
+/// Compare two trees given by their entries, keyed by path relative to the tree. Entries are
+/// compared by type, hash and mode, and changes are reported with their paths under `new_dir`.
+/// Deleted and created files with the same content are reported as renames.
+pub fn diff_entries(
+    mut old_entries: BTreeMap<PathBuf, TreeEntry>,
+    new_entries: BTreeMap<PathBuf, TreeEntry>,
+    new_dir: &Path,
+) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    for (path, entry) in new_entries {
+        match old_entries.remove(&path) {
+            None => changes.push(change(new_dir, &path, entry, FileChangeType::Created)),
+            Some(old_entry) if old_entry.file_type != entry.file_type => {
+                changes.push(change(new_dir, &path, old_entry, FileChangeType::Deleted));
+                changes.push(change(new_dir, &path, entry, FileChangeType::Created));
+            }
+            Some(old_entry) => {
+                if old_entry != entry {
+                    changes.push(change(new_dir, &path, entry, FileChangeType::Modified));
+                }
+            }
+        }
+    }
+    // Whatever is left only exists in the old tree
+    for (path, old_entry) in old_entries {
+        changes.push(change(new_dir, &path, old_entry, FileChangeType::Deleted));
+    }
+    detect_renames(changes)
+}
+
+/// Hash a tree into an empty in-memory index, returning its hash and the index entries of
+/// everything under it by path relative to the tree.
+async fn hash_tree(
+    dir: &Path,
+    hash_algorithm: HashAlgorithm,
+    progress: Option<Arc<dyn ProgressReporter>>,
+    cancellation: &CancellationToken,
+) -> Result<(String, BTreeMap<PathBuf, TreeEntry>), anyhow::Error> {
+    let store = MemoryIndexStore::new();
+    let indexer_config = IndexerConfig::new(0, store.clone(), true)
+        .with_hash_algorithm(hash_algorithm)
+        .with_progress(progress)
+        .with_cancellation(cancellation.clone());
+    let hasher = DirHasher::new(indexer_config)
+        .dir_hash(&dir.to_path_buf())
+        .await?;
+    let (hash, _) = hasher.finalize().await?;
+
+    let mut entries = BTreeMap::new();
+    for entry in store.list_file_index(0).await? {
+        let path = Path::new(&entry.file_path).strip_prefix(dir)?;
+        // The root itself is only compared through the tree hashes
+        if !path.as_os_str().is_empty() {
+            let entry = TreeEntry {
+                file_type: entry.file_type,
+                hash_code: entry.hash_code,
+                mode: entry.mode,
+            };
+            entries.insert(path.to_path_buf(), entry);
+        }
+    }
+    Ok((hash, entries))
+}
+
+fn change(
+    new_dir: &Path,
+    path: &Path,
+    entry: TreeEntry,
+    change_type: FileChangeType,
+) -> FileChange {
+    // Deleted entries keep their last hash, but have no mode anymore
+    let mode = (change_type != FileChangeType::Deleted).then_some(entry.mode);
+    FileChange {
+        file_path: new_dir.join(path).display().to_string(),
+        file_type: entry.file_type,
+        change_type,
+        hash_code: entry.hash_code,
+        mode,
+        chunks: None,
+    }
+}
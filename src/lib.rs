@@ -1,4 +1,9 @@
+pub mod cancellation;
 pub mod cli;
+pub mod client;
 pub mod indexer;
+pub mod patcher;
+pub mod progress;
+pub mod server;
 pub mod service;
 pub mod storage;
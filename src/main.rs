@@ -1,12 +1,22 @@
 use clap::Parser;
 use secret_online_patcher::{
-    cli::{self, Args, Operation},
+    cancellation::CancellationToken,
+    cli::{self, Args, Operation, ProgressBarReporter},
+    indexer::chunking::ChunkingConfig,
+    patcher::DiffPatch,
+    progress::ProgressReporter,
     service::app_manager::AppManager,
-    storage::patcher_db::PatcherDatabase,
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        blob_store::BlobStore,
+        patch_config::PatchConfig,
+        patcher_db::PatcherDatabase,
+    },
 };
 use sqlx::SqlitePool;
 use std::{
     path::Path,
+    sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
 use tracing_subscriber::{Layer, filter, layer::SubscriberExt, util::SubscriberInitExt};
@@ -18,6 +28,26 @@ async fn main() {
     // Initialize logger
     init_logger().await;
 
+    let progress: Arc<dyn ProgressReporter> = Arc::new(ProgressBarReporter::default());
+    let cancellation = CancellationToken::new();
+    if matches!(
+        args.op,
+        Operation::AddApp
+            | Operation::Check
+            | Operation::Update
+            | Operation::Package
+            | Operation::Diff
+            | Operation::CreatePatch
+    ) {
+        cancel_on_ctrl_c(cancellation.clone());
+    }
+
+    // Comparing directories doesn't use the database
+    if let Operation::Diff = args.op {
+        diff(&args, progress, cancellation).await;
+        return;
+    }
+
     // Ensure resources directory exists
     let resources_dir = Path::new("resources");
     if !resources_dir.exists() {
@@ -26,12 +56,20 @@ async fn main() {
 
     // Initialize database connection with file-based storage
     // Use create flag to ensure database file is created if it doesn't exist
-    let db_conn = "sqlite:resources/app_data.db?mode=rwc";
-    let db_pool = SqlitePool::connect(db_conn).await.unwrap();
+    let db_conn = format!("sqlite:{}?mode=rwc", cli::DATABASE_PATH);
+    let db_pool = SqlitePool::connect(&db_conn).await.unwrap();
     let patcher_db = PatcherDatabase::new(db_pool);
-    patcher_db.initialize().await;
-
-    let app_manager = AppManager::new(patcher_db.clone());
+    if let Err(e) = patcher_db.initialize().await {
+        tracing::error!("Error initializing database: {}", e);
+        return;
+    }
+    let snapshot_store = args.snapshot_store.as_deref().map(BlobStore::new);
+    let app_manager = AppManager::new(patcher_db.clone())
+        .with_progress(Some(progress.clone()))
+        .with_cancellation(cancellation.clone())
+        .with_snapshot_store(snapshot_store.clone());
+    let chunking = args.chunking.then(ChunkingConfig::default);
+    let target = AppTarget::new(&args.channel, &args.platform);
 
     let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     match args.op {
@@ -49,8 +87,11 @@ async fn main() {
             // Call the function to add an app
             if let Err(e) = cli::add_app(
                 args.app_name.as_ref().unwrap(),
+                &target,
                 args.app_version.as_ref().unwrap(),
+                AppSettings::new(args.version_scheme).with_hash_algorithm(args.hash_algorithm),
                 args.app_path.as_ref().unwrap(),
+                chunking,
                 &app_manager,
             )
             .await
@@ -64,7 +105,11 @@ async fn main() {
                 return;
             }
 
-            cli::remove_app(args.app_name.as_ref().unwrap(), &patcher_db).await;
+            if let Err(e) =
+                cli::remove_app(args.app_name.as_ref().unwrap(), &target, &patcher_db).await
+            {
+                tracing::error!("Error removing application: {}", e);
+            }
         }
         Operation::Check => {
             if args.app_name.is_none() {
@@ -72,7 +117,15 @@ async fn main() {
                 return;
             }
             // Call the function to check an app
-            if let Err(e) = cli::check_app(args.app_name.as_ref().unwrap(), &patcher_db).await {
+            if let Err(e) = cli::check_app(
+                args.app_name.as_ref().unwrap(),
+                &target,
+                Some(progress.clone()),
+                &cancellation,
+                &patcher_db,
+            )
+            .await
+            {
                 tracing::error!("Error checking application: {}", e);
             }
         }
@@ -86,15 +139,307 @@ async fn main() {
 
             let app_name = args.app_name.as_ref().unwrap();
             let new_version = args.app_version.as_ref().unwrap();
-            if let Err(e) = cli::update_app(app_name, new_version, &patcher_db).await {
+            let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+            let patch_config = PatchConfig::new(
+                args.compression,
+                !args.no_auto_store,
+                args.max_part_size,
+                blob_store,
+            )
+            .with_progress(Some(progress.clone()))
+            .with_cancellation(cancellation.clone())
+            .with_snapshot_store(snapshot_store);
+            if let Err(e) = cli::update_app(
+                app_name,
+                &target,
+                new_version,
+                args.force,
+                chunking,
+                &patch_config,
+                &patcher_db,
+            )
+            .await
+            {
                 tracing::error!("Error updating application: {}", e);
             }
         }
+        Operation::Inspect => {
+            if args.patch_path.is_none() {
+                tracing::error!("Error: --patch-path is required for inspect operation.");
+                return;
+            }
+
+            if let Err(e) = cli::inspect_patch(args.patch_path.as_ref().unwrap()).await {
+                tracing::error!("Error inspecting patch: {}", e);
+            }
+        }
+        Operation::Gc => {
+            if args.blob_store.is_none() && snapshot_store.is_none() {
+                tracing::error!(
+                    "Error: --blob-store or --snapshot-store is required for gc operation."
+                );
+                return;
+            }
+
+            let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+            if let Err(e) = cli::collect_garbage(
+                blob_store.as_ref(),
+                snapshot_store.as_ref(),
+                args.keep_versions,
+                &patcher_db,
+            )
+            .await
+            {
+                tracing::error!("Error collecting garbage: {}", e);
+            }
+        }
+        Operation::Serve => {
+            let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+            if let Err(e) = cli::serve(args.listen, blob_store, &patcher_db).await {
+                tracing::error!("Error serving patches: {}", e);
+            }
+        }
+        Operation::ClientUpdate => {
+            if args.server.is_none() || args.app_name.is_none() || args.app_path.is_none() {
+                tracing::error!(
+                    "Error: --server, --app-name and --app-path are required for client-update operation."
+                );
+                return;
+            }
+
+            if let Err(e) = cli::client_update(
+                args.server.as_ref().unwrap(),
+                args.app_name.as_ref().unwrap(),
+                &target,
+                args.app_path.as_ref().unwrap(),
+                args.cache_dir.as_deref(),
+                &patcher_db,
+            )
+            .await
+            {
+                tracing::error!("Error updating local install: {}", e);
+            }
+        }
+        Operation::Package => {
+            if args.app_name.is_none() {
+                tracing::error!("Error: --app-name is required for package operation.");
+                return;
+            }
+
+            let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+            let patch_config = PatchConfig::new(
+                args.compression,
+                !args.no_auto_store,
+                args.max_part_size,
+                blob_store,
+            )
+            .with_progress(Some(progress.clone()))
+            .with_cancellation(cancellation.clone());
+            match cli::package_app(
+                args.app_name.as_ref().unwrap(),
+                &target,
+                &patch_config,
+                &patcher_db,
+            )
+            .await
+            {
+                Ok(package_path) => {
+                    tracing::info!("Full package created at {}", package_path.display())
+                }
+                Err(e) => tracing::error!("Error packaging application: {}", e),
+            }
+        }
+        Operation::Repair => {
+            if args.app_path.is_none() || args.patch_path.is_none() {
+                tracing::error!(
+                    "Error: --app-path and --patch-path are required for repair operation."
+                );
+                return;
+            }
+
+            let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+            if let Err(e) = cli::repair_install(
+                args.patch_path.as_ref().unwrap(),
+                args.app_path.as_ref().unwrap(),
+                blob_store.as_ref(),
+            )
+            .await
+            {
+                tracing::error!("Error repairing install: {}", e);
+            }
+        }
+        Operation::AddInstall => {
+            if args.app_name.is_none() || args.app_path.is_none() {
+                tracing::error!(
+                    "Error: --app-name and --app-path are required for add-install operation."
+                );
+                return;
+            }
+
+            if let Err(e) = cli::add_install(
+                args.app_name.as_ref().unwrap(),
+                &target,
+                args.app_path.as_ref().unwrap(),
+                &patcher_db,
+            )
+            .await
+            {
+                tracing::error!("Error registering install: {}", e);
+            }
+        }
+        Operation::RemoveInstall => {
+            if args.app_path.is_none() {
+                tracing::error!("Error: --app-path is required for remove-install operation.");
+                return;
+            }
+
+            if let Err(e) = cli::remove_install(args.app_path.as_ref().unwrap(), &patcher_db).await
+            {
+                tracing::error!("Error removing install: {}", e);
+            }
+        }
+        Operation::ListInstalls => {
+            if let Err(e) =
+                cli::list_installs(args.server.as_deref(), args.verify, &patcher_db).await
+            {
+                tracing::error!("Error listing installs: {}", e);
+            }
+        }
+        Operation::Promote => {
+            if args.app_name.is_none() || args.app_version.is_none() || args.to_channel.is_none() {
+                tracing::error!(
+                    "Error: --app-name, --app-version and --to-channel are required for promote operation."
+                );
+                return;
+            }
+
+            if let Err(e) = cli::promote_app(
+                args.app_name.as_ref().unwrap(),
+                &target,
+                args.to_channel.as_ref().unwrap(),
+                args.app_version.as_ref().unwrap(),
+                &app_manager,
+            )
+            .await
+            {
+                tracing::error!("Error promoting version: {}", e);
+            }
+        }
+        Operation::CreatePatch => {
+            if args.app_name.is_none()
+                || args.from_version.is_none()
+                || args.to_version.is_none()
+                || snapshot_store.is_none()
+            {
+                tracing::error!(
+                    "Error: --app-name, --from-version, --to-version and --snapshot-store are required for create-patch operation."
+                );
+                return;
+            }
+
+            let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+            let patch_config = PatchConfig::new(
+                args.compression,
+                !args.no_auto_store,
+                args.max_part_size,
+                blob_store,
+            )
+            .with_progress(Some(progress.clone()))
+            .with_cancellation(cancellation.clone())
+            .with_snapshot_store(snapshot_store);
+            if let Err(e) = cli::create_patch(
+                args.app_name.as_ref().unwrap(),
+                &target,
+                args.from_version.as_ref().unwrap(),
+                args.to_version.as_ref().unwrap(),
+                args.force,
+                &patch_config,
+                &patcher_db,
+            )
+            .await
+            {
+                tracing::error!("Error creating patch: {}", e);
+            }
+        }
+        Operation::Diff => unreachable!("diff is handled before opening the database"),
+        Operation::Watch => {
+            if args.app_name.is_none() {
+                tracing::error!("Error: --app-name is required for watch operation.");
+                return;
+            }
+
+            if let Err(e) =
+                cli::watch_app(args.app_name.as_ref().unwrap(), &target, &patcher_db).await
+            {
+                tracing::error!("Error watching application: {}", e);
+            }
+        }
     }
     let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     tracing::info!("Operation took {} milliseconds", (end - start).as_millis());
 }
 
+async fn diff(args: &Args, progress: Arc<dyn ProgressReporter>, cancellation: CancellationToken) {
+    let [old_dir, new_dir] = args.dirs.as_slice() else {
+        tracing::error!("Error: diff takes the old and the new directory, e.g. diff <OLD> <NEW>.");
+        return;
+    };
+    let patch_out = match (
+        &args.patch_out,
+        &args.app_name,
+        &args.base_version,
+        &args.app_version,
+    ) {
+        (None, ..) => None,
+        (Some(out_dir), Some(app_name), Some(base_version), Some(version)) => Some((
+            out_dir.as_path(),
+            DiffPatch::new(app_name, base_version, version),
+        )),
+        _ => {
+            tracing::error!(
+                "Error: --app-name, --base-version and --app-version are required with --patch-out."
+            );
+            return;
+        }
+    };
+
+    let blob_store = args.blob_store.as_deref().map(BlobStore::new);
+    let patch_config = PatchConfig::new(
+        args.compression,
+        !args.no_auto_store,
+        args.max_part_size,
+        blob_store,
+    )
+    .with_progress(Some(progress))
+    .with_cancellation(cancellation);
+    if let Err(e) = cli::diff_dirs(
+        old_dir,
+        new_dir,
+        args.hash_algorithm,
+        patch_out,
+        &patch_config,
+    )
+    .await
+    {
+        tracing::error!("Error comparing directories: {}", e);
+    }
+}
+
+/// Cancel the operation on the first Ctrl-C, it stops at the next file and removes its partial
+/// output. A second Ctrl-C exits right away.
+fn cancel_on_ctrl_c(cancellation: CancellationToken) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        tracing::warn!("Cancelling, press Ctrl-C again to exit immediately");
+        cancellation.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+}
+
 async fn init_logger() {
     // Enables the user to choose log level by setting RUST_LOG=<level> environment variable
     let log_level_filter = filter::EnvFilter::try_from_default_env()
@@ -0,0 +1,732 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use sqlx::SqlitePool;
+
+use crate::{
+    cancellation::CancellationToken,
+    client::{
+        patch_applier::PatchApplier,
+        update_client::{hash_install, index_install},
+    },
+    indexer::{
+        chunking::ChunkingConfig,
+        dir_hasher::DirHasher,
+        file_change::{FileChange, FileChangeType},
+        indexer_config::IndexerConfig,
+        staged_index::StagedIndex,
+        tree_diff::{TreeDiff, diff_entries},
+    },
+    progress::ProgressReporter,
+    service::{
+        app_manager::AppManager, index_watcher::IndexWatcher, install_manager::InstallManager,
+    },
+    storage::{
+        app_version::Release,
+        application_data::{
+            AppSettings, AppTarget, Application, DEFAULT_CHANNEL, DEFAULT_PLATFORM,
+        },
+        blob_store::BlobStore,
+        file_chunk_index::FileChunkIndex,
+        patch_config::PatchConfig,
+        patch_reader::PatchReader,
+        patch_zip::{PatchZip, move_patch_files, remove_patch_files},
+        patcher_db::PatcherDatabase,
+        version_manifest::{manifest_tree, restore_snapshot, store_snapshot},
+        version_scheme::VersionScheme,
+    },
+};
+
+/// Options of a `Patcher`.
+#[derive(Clone)]
+pub struct PatcherConfig {
+    // SQLite database the applications are recorded in, created if missing
+    pub database_path: PathBuf,
+    // Patches are written under a directory per channel in here
+    pub patch_dir: PathBuf,
+    // How patches are built, along with the progress reporter and cancellation token used for
+    // indexing too, and the snapshot store released versions are kept in
+    pub patch_config: PatchConfig,
+    // When set, large files are split into content-defined chunks recorded in the index
+    pub chunking: Option<ChunkingConfig>,
+}
+
+impl PatcherConfig {
+    pub fn new(database_path: &Path, patch_dir: &Path) -> Self {
+        PatcherConfig {
+            database_path: database_path.to_path_buf(),
+            patch_dir: patch_dir.to_path_buf(),
+            patch_config: PatchConfig::default(),
+            chunking: None,
+        }
+    }
+
+    pub fn with_patch_config(mut self, patch_config: PatchConfig) -> Self {
+        self.patch_config = patch_config;
+        self
+    }
+
+    pub fn with_chunking(mut self, chunking: Option<ChunkingConfig>) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    pub fn with_snapshot_store(mut self, snapshot_store: Option<BlobStore>) -> Self {
+        self.patch_config.snapshot_store = snapshot_store;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Option<Arc<dyn ProgressReporter>>) -> Self {
+        self.patch_config.progress = progress;
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.patch_config.cancellation = cancellation;
+        self
+    }
+}
+
+/// Changes found in the source tree of an application since its current version.
+pub struct CheckResult {
+    pub app: Application,
+    // Hash of the source tree as it is now
+    pub hash_code: String,
+    pub changes: Vec<FileChange>,
+    // Answered from the live index of a running watcher instead of rescanning the tree
+    pub from_live_index: bool,
+}
+
+impl CheckResult {
+    pub fn has_changes(&self) -> bool {
+        self.app.hash_code.as_ref() != Some(&self.hash_code)
+    }
+}
+
+/// Patch built for a new version of an application.
+#[derive(Debug)]
+pub struct CreatedPatch {
+    pub version: String,
+    pub base_version: String,
+    pub hash_code: String,
+    // First part of the patch
+    pub patch_path: PathBuf,
+    pub part_count: usize,
+    pub changes: Vec<FileChange>,
+    // Patches of the other platforms of the version, which the patch reuses files from
+    pub shared_patches: Vec<SharedPatch>,
+}
+
+/// Patch of another platform of the same version, looked up to reuse the files both ship.
+#[derive(Debug)]
+pub struct SharedPatch {
+    pub platform: String,
+    pub patch_path: String,
+    // Number of files the patch stores, which are reused instead of compressed again
+    pub file_count: usize,
+    // Why the patch couldn't be read, in which case none of its files are reused
+    pub error: Option<String>,
+}
+
+/// Result of building the patch of a new version.
+#[derive(Debug)]
+pub enum PatchOutcome {
+    // The source tree still matches the current version, no version was recorded
+    Unchanged,
+    Created(CreatedPatch),
+}
+
+/// Patch applied to an install.
+#[derive(Debug)]
+pub struct AppliedPatch {
+    pub app_name: String,
+    pub base_version: String,
+    pub version: String,
+    // Hash of the install once patched
+    pub hash_code: String,
+    // The install is registered, and the patch was recorded for it
+    pub registered: bool,
+}
+
+/// Names recorded in the patch of a directory diff, which has no application behind it.
+#[derive(Clone, Debug)]
+pub struct DiffPatch {
+    pub app_name: String,
+    // Version of the old directory, installs have to be at this version to apply the patch
+    pub base_version: String,
+    // Version of the new directory
+    pub version: String,
+}
+
+impl DiffPatch {
+    pub fn new(app_name: &str, base_version: &str, version: &str) -> Self {
+        DiffPatch {
+            app_name: app_name.to_string(),
+            base_version: base_version.to_string(),
+            version: version.to_string(),
+        }
+    }
+}
+
+/// Entry point for embedding the patcher: records applications, builds their patches and
+/// applies patches to installs, returning what happened instead of logging it.
+pub struct Patcher {
+    db: PatcherDatabase,
+    config: PatcherConfig,
+}
+
+impl Patcher {
+    /// Open the database of the config, creating it if needed.
+    pub async fn open(config: PatcherConfig) -> Result<Self, anyhow::Error> {
+        if let Some(parent) = config.database_path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let db_conn = format!("sqlite:{}?mode=rwc", config.database_path.display());
+        let db_pool = SqlitePool::connect(&db_conn).await?;
+        let db = PatcherDatabase::new(db_pool);
+        db.initialize().await?;
+        Ok(Patcher { db, config })
+    }
+
+    /// Use an already opened database, the database path of the config is ignored.
+    pub fn with_database(db: PatcherDatabase, config: PatcherConfig) -> Self {
+        Patcher { db, config }
+    }
+
+    pub fn database(&self) -> &PatcherDatabase {
+        &self.db
+    }
+
+    /// Record a new application with the content of the given directory as its first version.
+    pub async fn add_app(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        version: &str,
+        settings: AppSettings,
+        path: &Path,
+    ) -> Result<Application, anyhow::Error> {
+        AppManager::new(self.db.clone())
+            .with_progress(self.config.patch_config.progress.clone())
+            .with_cancellation(self.config.patch_config.cancellation.clone())
+            .with_snapshot_store(self.config.patch_config.snapshot_store.clone())
+            .create_application(
+                name,
+                target,
+                version,
+                settings,
+                &path.to_path_buf(),
+                self.config.chunking,
+            )
+            .await?;
+        self.get_app(name, target).await
+    }
+
+    /// Find the changes in the source tree of an application since its current version.
+    pub async fn check(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<CheckResult, anyhow::Error> {
+        let app = self.get_app(name, target).await?;
+        if app.hash_code.is_none() {
+            return Err(anyhow!(
+                "Failed to check application due to missing hash code, it might not be initialized properly!"
+            ));
+        }
+
+        // A running watcher already knows the hash, otherwise rescan the source tree
+        if let Some(status) = IndexWatcher::live_status(&app, &self.db).await? {
+            return Ok(CheckResult {
+                app,
+                hash_code: status.hash_code,
+                changes: status.changes,
+                from_live_index: true,
+            });
+        }
+        let indexer_config = IndexerConfig::new(app.id, self.db.clone(), false)
+            .with_hash_algorithm(app.hash_algorithm)
+            .with_progress(self.config.patch_config.progress.clone())
+            .with_cancellation(self.config.patch_config.cancellation.clone());
+        let hasher = DirHasher::new(indexer_config);
+        let (hash_code, changes) = hasher.dir_hash(&app.source_path).await?.finalize().await?;
+        Ok(CheckResult {
+            app,
+            hash_code,
+            changes,
+            from_live_index: false,
+        })
+    }
+
+    /// Build the patch of a new version of an application from the changes in its source tree,
+    /// and record the version. The new version has to be greater than the current one unless
+    /// `force` is set.
+    pub async fn create_patch(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        version: &str,
+        force: bool,
+    ) -> Result<PatchOutcome, anyhow::Error> {
+        let app = self.get_app(name, target).await?;
+        let Some(old_hash) = app.hash_code.clone() else {
+            return Err(anyhow!(
+                "Failed to update application due to missing hash code, it might not be initialized properly!"
+            ));
+        };
+        app.version_scheme.validate(version)?;
+        let released = self.db.list_versions(app.id).await?;
+        if released.iter().any(|released| released.version == version) {
+            return Err(anyhow!("Version {} was already released", version));
+        }
+        if !force {
+            app.version_scheme.check_increasing(&app.version, version)?;
+        }
+        let patch_config = &self.config.patch_config;
+        // A version missing from the snapshot store couldn't be patched from or to later on
+        if app.keeps_snapshots && patch_config.snapshot_store.is_none() {
+            return Err(anyhow!(
+                "Versions of {} are kept in a snapshot store, which is needed to keep version {} too",
+                name,
+                version
+            ));
+        }
+
+        // Hash without touching the index, changes are only committed once the
+        // update package has been created successfully
+        if patch_config.blob_store.is_some() || patch_config.snapshot_store.is_some() {
+            BlobStore::check_algorithm(app.hash_algorithm)?;
+        }
+        let staged_index = StagedIndex::new();
+        let indexer_config = IndexerConfig::staged(app.id, self.db.clone(), staged_index.clone())
+            .with_chunking(self.config.chunking)
+            .with_hash_algorithm(app.hash_algorithm)
+            .with_progress(patch_config.progress.clone())
+            .with_cancellation(patch_config.cancellation.clone());
+        let hasher = DirHasher::new(indexer_config);
+        let new_hash = hasher.dir_hash(&app.source_path).await?;
+        let (new_hash, file_changes) = new_hash.finalize().await?;
+        if new_hash == old_hash {
+            // Keep the refreshed modified times so unchanged files are not rehashed next time
+            self.db.commit_index_updates(&staged_index.take()).await?;
+            return Ok(PatchOutcome::Unchanged);
+        }
+
+        // The index still describes the base version until the update is committed
+        if let Some(snapshot_store) = &self.config.patch_config.snapshot_store {
+            let base_index = self.db.list_file_index(app.id).await?;
+            store_snapshot(
+                snapshot_store,
+                app.hash_algorithm,
+                &base_index,
+                &file_changes,
+                &patch_config.cancellation,
+            )?;
+        }
+        let base_chunks = self.db.list_file_chunks(app.id).await?;
+        let (shared_entries, shared_patches) = self.shared_entries(&app, version).await?;
+        let zip_path = self
+            .create_zip_package(
+                &app,
+                version,
+                &file_changes,
+                base_chunks,
+                shared_entries,
+                false,
+            )
+            .await?;
+        // Recorded so that clients can verify their downloads
+        let parts = match PatchReader::new(&zip_path).hash_parts() {
+            Ok(parts) => parts,
+            Err(e) => {
+                remove_patch_files(&zip_path);
+                return Err(e);
+            }
+        };
+        // Last chance to stop before the update is recorded
+        if let Err(e) = patch_config.cancellation.check() {
+            remove_patch_files(&zip_path);
+            return Err(e.into());
+        }
+
+        let release = Release {
+            app_id: app.id,
+            version: version.to_string(),
+            base_version: Some(app.version.clone()),
+            hash_code: new_hash.clone(),
+            patch_path: Some(zip_path.display().to_string()),
+            blob_hashes: blob_hashes(&file_changes, patch_config),
+            parts: parts.clone(),
+            keeps_snapshot: patch_config.snapshot_store.is_some(),
+        };
+        let updates = staged_index.take();
+        if let Err(e) = self.db.commit_application_update(&release, &updates).await {
+            // The package doesn't match the recorded state anymore
+            remove_patch_files(&zip_path);
+            return Err(anyhow!("Failed to record application update: {}", e));
+        }
+        Ok(PatchOutcome::Created(CreatedPatch {
+            version: version.to_string(),
+            base_version: app.version,
+            hash_code: new_hash,
+            patch_path: zip_path,
+            part_count: parts.len(),
+            changes: file_changes,
+            shared_patches,
+        }))
+    }
+
+    /// Build the patch from one released version of an application to another from their
+    /// snapshots, whatever the current version is. When `from` is the version the patch of `to`
+    /// was built from, that patch is built again and recorded in place of the previous one,
+    /// e.g. to replace a lost patch, which is refused while the recorded patch still exists
+    /// unless `force` is set. Other patches are written under a `from_<version>` directory of
+    /// the channel and are not recorded.
+    pub async fn create_patch_between(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        from: &str,
+        to: &str,
+        force: bool,
+    ) -> Result<CreatedPatch, anyhow::Error> {
+        let snapshot_store = self
+            .config
+            .patch_config
+            .snapshot_store
+            .as_ref()
+            .ok_or_else(|| {
+                anyhow!("Building a patch between released versions needs a snapshot store")
+            })?;
+        let app = self.get_app(name, target).await?;
+        if from == to {
+            return Err(anyhow!(
+                "Cannot build a patch from version {} to itself",
+                from
+            ));
+        }
+        let versions = self.db.list_versions(app.id).await?;
+        let find_version = |version: &str| {
+            versions
+                .iter()
+                .find(|released| released.version == version)
+                .ok_or_else(|| anyhow!("Version {} of {} was not released", version, name))
+        };
+        let from_version = find_version(from)?;
+        let to_version = find_version(to)?;
+        let recorded = to_version.base_version.as_deref() == Some(from);
+        if recorded
+            && !force
+            && let Some(patch_path) = &to_version.patch_path
+            && Path::new(patch_path).exists()
+        {
+            return Err(anyhow!(
+                "The patch of version {} still exists at {}, force the build to replace it",
+                to,
+                patch_path
+            ));
+        }
+        let mut manifests = Vec::new();
+        for version in [from_version, to_version] {
+            let manifest = self.db.list_version_manifest(version.id).await?;
+            if manifest.is_empty() {
+                return Err(anyhow!(
+                    "No manifest was recorded for version {}, it was released before snapshots were kept",
+                    version.version
+                ));
+            }
+            manifests.push(manifest_tree(manifest));
+        }
+        let to_manifest = manifests.pop().unwrap();
+        let from_manifest = manifests.pop().unwrap();
+
+        // The files shipped by the patch are restored from the snapshot store, the patch is
+        // built from there as if it was the source directory
+        fs::create_dir_all(&self.config.patch_dir)?;
+        let staging_dir = tempfile::tempdir_in(&self.config.patch_dir)?;
+        let changes = diff_entries(from_manifest, to_manifest, staging_dir.path());
+        restore_snapshot(snapshot_store, staging_dir.path(), &changes)?;
+        let base = Application {
+            version: from.to_string(),
+            hash_code: Some(from_version.hash_code.clone()),
+            source_path: staging_dir.path().to_path_buf(),
+            ..app.clone()
+        };
+        let mut out_dir = self.config.patch_dir.join(&app.channel);
+        if !recorded {
+            out_dir = out_dir.join(format!("from_{}", from));
+        }
+        fs::create_dir_all(&out_dir)?;
+        // Built aside, so that the patch it replaces is kept if the build or validation fails
+        let build_dir = tempfile::tempdir_in(&out_dir)?;
+        let zip = PatchZip::new(build_dir.path(), &base, self.config.patch_config.clone());
+        let built_path = write_patch(zip, to, &changes).await?;
+        let parts = PatchReader::new(&built_path).hash_parts()?;
+        let blob_hashes = blob_hashes(&changes, &self.config.patch_config);
+        let zip_path = out_dir.join(built_path.file_name().unwrap_or_default());
+        move_patch_files(&built_path, &zip_path)?;
+        // Report the changes under the source directory, like the patches of new versions
+        let rebase = |path: &str| match Path::new(path).strip_prefix(staging_dir.path()) {
+            Ok(relative_path) => app.source_path.join(relative_path).display().to_string(),
+            Err(_) => path.to_string(),
+        };
+        let changes = changes
+            .into_iter()
+            .map(|mut change| {
+                change.file_path = rebase(&change.file_path);
+                if let FileChangeType::Renamed { from } = &mut change.change_type {
+                    *from = rebase(from);
+                }
+                change
+            })
+            .collect();
+        if recorded {
+            let patch_path = zip_path.display().to_string();
+            self.db
+                .replace_version_patch(to_version.id, &patch_path, &parts, &blob_hashes)
+                .await?;
+        }
+        Ok(CreatedPatch {
+            version: to.to_string(),
+            base_version: from.to_string(),
+            hash_code: to_version.hash_code.clone(),
+            patch_path: zip_path,
+            part_count: parts.len(),
+            changes,
+            shared_patches: Vec::new(),
+        })
+    }
+
+    /// Create a full package of the current version of an application, which installs can be
+    /// repaired from. Returns the path to the package.
+    pub async fn create_package(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let app = self.get_app(name, target).await?;
+        // Hash from scratch so that every file is recorded with its hash
+        let (hash_code, file_changes) = index_install(
+            &app.source_path,
+            app.hash_algorithm,
+            &self.config.patch_config.cancellation,
+        )
+        .await?;
+        if app.hash_code.as_ref() != Some(&hash_code) {
+            return Err(anyhow!(
+                "{} has changed since version {} was recorded, update the application first",
+                app.source_path.display(),
+                app.version
+            ));
+        }
+        self.create_zip_package(
+            &app,
+            &app.version,
+            &file_changes,
+            Vec::new(),
+            HashMap::new(),
+            true,
+        )
+        .await
+    }
+
+    /// Validate a patch and apply it to an install. The patch is recorded for the install if it
+    /// is registered.
+    pub async fn apply_patch(
+        &self,
+        install_path: &Path,
+        patch_path: &Path,
+    ) -> Result<AppliedPatch, anyhow::Error> {
+        let reader = match &self.config.patch_config.blob_store {
+            Some(blob_store) => PatchReader::with_blob_store(patch_path, blob_store),
+            None => PatchReader::new(patch_path),
+        };
+        reader.validate().await?;
+        let patch_db = reader.open_patch_db().await?;
+        let patch_info = patch_db.db.get_patch_info().await?;
+        patch_db.db.close().await;
+        let patch_info = patch_info
+            .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+
+        PatchApplier::new(install_path).apply(&reader).await?;
+        let hash_code = hash_install(install_path, patch_info.hash_algorithm).await?;
+        let install = InstallManager::new(self.db.clone())
+            .find_install(install_path)
+            .await?;
+        if let Some(install) = &install {
+            self.db
+                .record_install_patch(
+                    install.id,
+                    &patch_info.patch_version,
+                    &hash_code,
+                    patch_info.hash_algorithm,
+                )
+                .await?;
+        }
+        Ok(AppliedPatch {
+            app_name: patch_info.app_name,
+            base_version: patch_info.base_version,
+            version: patch_info.patch_version,
+            hash_code,
+            registered: install.is_some(),
+        })
+    }
+
+    async fn get_app(&self, name: &str, target: &AppTarget) -> Result<Application, anyhow::Error> {
+        self.db
+            .get_application(name, target)
+            .await?
+            .ok_or_else(|| anyhow!("Application not found"))
+    }
+
+    /// Create the update package and validate it, returning the path to the zip file.
+    /// Nothing is left in the output directory if this fails.
+    async fn create_zip_package(
+        &self,
+        app: &Application,
+        new_version: &str,
+        file_changes: &[FileChange],
+        base_chunks: Vec<FileChunkIndex>,
+        shared_entries: HashMap<String, (PathBuf, String)>,
+        full_package: bool,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let patch_config = &self.config.patch_config;
+        // Make sure output directory exists
+        let out_dir = self.config.patch_dir.join(&app.channel);
+        fs::create_dir_all(&out_dir)?;
+
+        // Initialize the patch (creates the database and zip file)
+        let mut zip = PatchZip::new(&out_dir, app, patch_config.clone());
+        zip.set_base_chunks(base_chunks);
+        zip.set_shared_entries(shared_entries);
+        zip.set_full_package(full_package);
+        write_patch(zip, new_version, file_changes).await
+    }
+
+    /// Entries of the patches already built for the other platforms of the same version, by
+    /// content hash, along with the patches they come from. Patches that can't be read are
+    /// skipped, their files are compressed again instead.
+    async fn shared_entries(
+        &self,
+        app: &Application,
+        version: &str,
+    ) -> Result<(HashMap<String, (PathBuf, String)>, Vec<SharedPatch>), anyhow::Error> {
+        let mut entries = HashMap::new();
+        let mut shared_patches = Vec::new();
+        for other in self.db.list_applications().await {
+            if other.name != app.name || other.channel != app.channel || other.id == app.id {
+                continue;
+            }
+            let Some(patch_path) = self
+                .db
+                .list_versions(other.id)
+                .await?
+                .into_iter()
+                .find(|other_version| other_version.version == version)
+                .and_then(|other_version| other_version.patch_path)
+            else {
+                continue;
+            };
+            let reader = PatchReader::new(Path::new(&patch_path));
+            let (stored_entries, error) = match reader.list_stored_entries().await {
+                Ok(stored_entries) => (stored_entries, None),
+                Err(e) => (Vec::new(), Some(e.to_string())),
+            };
+            shared_patches.push(SharedPatch {
+                platform: other.platform,
+                patch_path,
+                file_count: stored_entries.len(),
+                error,
+            });
+            for (hash_code, entry) in stored_entries {
+                entries.entry(hash_code).or_insert(entry);
+            }
+        }
+        Ok((entries, shared_patches))
+    }
+}
+
+/// Hashes of the file contents put into the blob store for the given changes.
+fn blob_hashes(file_changes: &[FileChange], patch_config: &PatchConfig) -> Vec<String> {
+    if patch_config.blob_store.is_none() {
+        return Vec::new();
+    }
+    file_changes
+        .iter()
+        .filter(|change| change.has_content())
+        .filter_map(|change| change.hash_code.clone())
+        .collect()
+}
+
+/// Write the patch of the changes between two directory trees into `out_dir`, returning the path
+/// to its first part. Nothing is recorded in any database besides the one inside the patch.
+pub async fn create_diff_patch(
+    diff: &TreeDiff,
+    labels: &DiffPatch,
+    out_dir: &Path,
+    patch_config: &PatchConfig,
+) -> Result<PathBuf, anyhow::Error> {
+    fs::create_dir_all(out_dir)?;
+    // Stands for the old tree, the contents of the changes are read from the new one
+    let base = Application {
+        id: 0,
+        name: labels.app_name.clone(),
+        channel: DEFAULT_CHANNEL.to_string(),
+        platform: DEFAULT_PLATFORM.to_string(),
+        version: labels.base_version.clone(),
+        version_scheme: VersionScheme::FreeForm,
+        hash_algorithm: diff.hash_algorithm,
+        hash_code: Some(diff.old_hash.clone()),
+        source_path: diff.new_dir.clone(),
+        keeps_snapshots: false,
+    };
+    let zip = PatchZip::new(out_dir, &base, patch_config.clone());
+    write_patch(zip, &labels.version, &diff.changes).await
+}
+
+/// Append the changes to the patch and validate it, returning the path to the zip file.
+/// Nothing is left in the output directory if this fails.
+async fn write_patch(
+    mut zip: PatchZip,
+    new_version: &str,
+    file_changes: &[FileChange],
+) -> Result<PathBuf, anyhow::Error> {
+    zip.set_total_changes(file_changes.len());
+    if let Err(e) = append_file_changes(&mut zip, new_version, file_changes).await {
+        zip.abort().await;
+        return Err(e);
+    }
+    let blob_store = zip.config.blob_store.clone();
+    let zip_path = zip.finalize().await?;
+
+    // Make sure the archive can be read back before publishing it
+    let reader = match &blob_store {
+        Some(blob_store) => PatchReader::with_blob_store(&zip_path, blob_store),
+        None => PatchReader::new(&zip_path),
+    };
+    if let Err(e) = reader.validate().await {
+        remove_patch_files(&zip_path);
+        return Err(anyhow!("Patch validation failed: {}", e));
+    }
+    Ok(zip_path)
+}
+
+async fn append_file_changes(
+    zip: &mut PatchZip,
+    new_version: &str,
+    file_changes: &[FileChange],
+) -> Result<(), anyhow::Error> {
+    zip.initialize_patch(new_version).await?;
+    for change in file_changes {
+        // Add change to the patch database
+        zip.append_changed_file(change).await?;
+    }
+    Ok(())
+}
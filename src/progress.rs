@@ -0,0 +1,154 @@
+use std::{
+    fmt::{Display, Formatter},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Kind of long running operation whose progress is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressStage {
+    // Hashing the files of a tree
+    Indexing,
+    // Writing the changes of a version to a patch
+    Packaging,
+}
+
+impl Display for ProgressStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgressStage::Indexing => write!(f, "Indexing"),
+            ProgressStage::Packaging => write!(f, "Packaging"),
+        }
+    }
+}
+
+/// Snapshot of the progress of an operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub stage: ProgressStage,
+    // Files hashed or found unchanged when indexing, changes written when packaging
+    pub files_scanned: u64,
+    // Number of files expected, None until known
+    pub total_files: Option<u64>,
+    // Bytes read to compute file hashes, files with a cached hash don't count
+    pub bytes_hashed: u64,
+    // Uncompressed bytes written to the patch
+    pub bytes_compressed: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn new(stage: ProgressStage) -> Self {
+        Progress {
+            stage,
+            files_scanned: 0,
+            total_files: None,
+            bytes_hashed: 0,
+            bytes_compressed: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Estimated time left, from the rate files were processed at so far.
+    /// None until the total is known and the first file is done.
+    pub fn eta(&self) -> Option<Duration> {
+        let total_files = self.total_files?;
+        if self.files_scanned == 0 {
+            return None;
+        }
+        let remaining = total_files.saturating_sub(self.files_scanned);
+        Some(
+            self.elapsed
+                .mul_f64(remaining as f64 / self.files_scanned as f64),
+        )
+    }
+}
+
+/// Receives the progress of indexing and packaging runs, e.g. to render it in a UI.
+/// Called from the thread doing the work, so implementations should return quickly.
+pub trait ProgressReporter: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+
+    /// Called once when the operation is over, whether it succeeded or not.
+    fn on_finish(&self, _progress: &Progress) {}
+}
+
+/// Accumulates the progress of one operation and forwards every update to a reporter.
+/// Clones share the same counters.
+#[derive(Clone)]
+pub struct ProgressTracker {
+    reporter: Arc<dyn ProgressReporter>,
+    progress: Arc<Mutex<Progress>>,
+    started: Instant,
+}
+
+impl ProgressTracker {
+    pub fn new(stage: ProgressStage, reporter: Arc<dyn ProgressReporter>) -> Self {
+        ProgressTracker {
+            reporter,
+            progress: Arc::new(Mutex::new(Progress::new(stage))),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn set_total_files(&self, total_files: u64) {
+        self.update(|progress| progress.total_files = Some(total_files));
+    }
+
+    /// Count a file as done, with the number of bytes read to hash it.
+    pub fn add_file(&self, bytes_hashed: u64) {
+        self.update(|progress| {
+            progress.files_scanned += 1;
+            progress.bytes_hashed += bytes_hashed;
+        });
+    }
+
+    pub fn add_compressed(&self, bytes_compressed: u64) {
+        self.update(|progress| progress.bytes_compressed += bytes_compressed);
+    }
+
+    pub fn snapshot(&self) -> Progress {
+        let mut progress = self.progress.lock().unwrap().clone();
+        progress.elapsed = self.started.elapsed();
+        progress
+    }
+
+    pub fn finish(&self) {
+        self.reporter.on_finish(&self.snapshot());
+    }
+
+    fn update(&self, apply: impl FnOnce(&mut Progress)) {
+        let snapshot = {
+            let mut progress = self.progress.lock().unwrap();
+            apply(&mut progress);
+            progress.elapsed = self.started.elapsed();
+            progress.clone()
+        };
+        // Report outside of the lock, updates from other threads don't wait for the reporter
+        self.reporter.on_progress(&snapshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Progress, ProgressStage};
+
+    #[test]
+    fn eta_test() {
+        let mut progress = Progress::new(ProgressStage::Indexing);
+        progress.elapsed = Duration::from_secs(10);
+        assert_eq!(progress.eta(), None);
+
+        progress.files_scanned = 25;
+        assert_eq!(progress.eta(), None);
+
+        progress.total_files = Some(100);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(30)));
+
+        // More files than expected, e.g. created while indexing
+        progress.files_scanned = 120;
+        assert_eq!(progress.eta(), Some(Duration::ZERO));
+    }
+}
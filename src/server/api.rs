@@ -0,0 +1,77 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    indexer::{hash_algorithm::HashAlgorithm, merkle::MERKLE_FORMAT_VERSION},
+    storage::application_data::{AppTarget, DEFAULT_CHANNEL, DEFAULT_PLATFORM},
+};
+
+/// An application as listed by the patch server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AppSummary {
+    pub name: String,
+    pub channel: String,
+    pub platform: String,
+    pub version: String,
+    pub hash_code: Option<String>,
+    // Algorithm the application is hashed with, servers without it only use SHA-256
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// Query parameters selecting the release channel and platform of an application.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetQuery {
+    // Defaults to the stable channel
+    pub channel: Option<String>,
+    // Defaults to the platform independent tree
+    pub platform: Option<String>,
+}
+
+impl TargetQuery {
+    pub fn target(&self) -> AppTarget {
+        AppTarget::new(
+            self.channel.as_deref().unwrap_or(DEFAULT_CHANNEL),
+            self.platform.as_deref().unwrap_or(DEFAULT_PLATFORM),
+        )
+    }
+}
+
+/// A released version of an application as listed by the patch server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VersionSummary {
+    pub version: String,
+    // Version the patch applies to, None for the initial version
+    pub base_version: Option<String>,
+    pub hash_code: String,
+    // Format version of the hash, see `merkle`, servers without it only list current hashes
+    #[serde(default = "current_hash_format")]
+    pub hash_format: u8,
+    pub created_at: NaiveDateTime,
+    // Parts of the patch available for download, empty if the version has no patch
+    pub parts: Vec<PartSummary>,
+}
+
+/// A downloadable part of a patch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PartSummary {
+    // Number of the part, starting from 1
+    pub part_number: i64,
+    pub size: u64,
+    // SHA-256 hash of the file, None for patches recorded without one
+    pub hash_code: Option<String>,
+}
+
+/// The most recent version of an application.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LatestVersion {
+    pub version: String,
+    pub hash_code: Option<String>,
+    // Algorithm installs must be hashed with to compare them with the hash
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+fn current_hash_format() -> u8 {
+    MERKLE_FORMAT_VERSION
+}
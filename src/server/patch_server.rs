@@ -0,0 +1,259 @@
+use std::{fs, path::PathBuf};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::{
+    server::api::{AppSummary, LatestVersion, PartSummary, TargetQuery, VersionSummary},
+    storage::{
+        app_version::AppVersion,
+        application_data::Application,
+        blob_store::BlobStore,
+        patch_zip::{existing_parts, part_path},
+        patcher_db::PatcherDatabase,
+    },
+};
+
+/// HTTP server distributing the patches recorded in the patcher database.
+///
+/// Endpoints:
+/// - `GET /apps`: list applications
+/// - `GET /apps/{name}/latest`: latest version and hash of an application
+/// - `GET /apps/{name}/versions`: released versions of an application with their patch parts, in
+///   version order
+/// - `GET /apps/{name}/versions/{version}/parts/{part}`: download a part of a patch
+/// - `GET /blobs/{hash}`: download a blob, only when a blob store is configured
+///
+/// Application endpoints take `channel` and `platform` query parameters selecting the release
+/// channel and platform, the stable channel and platform independent tree are used when omitted.
+///
+/// Downloads support range requests so that interrupted transfers can be resumed.
+#[derive(Clone)]
+pub struct PatchServer {
+    db: PatcherDatabase,
+    blob_store: Option<BlobStore>,
+}
+
+impl PatchServer {
+    pub fn new(db: PatcherDatabase, blob_store: Option<BlobStore>) -> Self {
+        PatchServer { db, blob_store }
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/apps", get(list_apps))
+            .route("/apps/{name}/latest", get(latest_version))
+            .route("/apps/{name}/versions", get(list_versions))
+            .route(
+                "/apps/{name}/versions/{version}/parts/{part}",
+                get(download_part),
+            )
+            .route("/blobs/{hash}", get(download_blob))
+            .with_state(self.clone())
+    }
+
+    /// Serve requests on the given listener until the server fails.
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), anyhow::Error> {
+        tracing::info!("Serving patches on http://{}", listener.local_addr()?);
+        axum::serve(listener, self.router()).await?;
+        Ok(())
+    }
+
+    async fn find_application(
+        &self,
+        name: &str,
+        query: &TargetQuery,
+    ) -> Result<Application, ApiError> {
+        let target = query.target();
+        self.db
+            .get_application(name, &target)
+            .await?
+            .ok_or_else(|| {
+                ApiError::NotFound(format!("Application {} not found in {}", name, target))
+            })
+    }
+
+    /// Released versions of an application, the latest one last.
+    async fn sorted_versions(&self, app: &Application) -> Result<Vec<AppVersion>, ApiError> {
+        let mut versions = self.db.list_versions(app.id).await?;
+        app.version_scheme.sort_versions(&mut versions);
+        Ok(versions)
+    }
+}
+
+/// Errors returned by the API handlers.
+enum ApiError {
+    NotFound(String),
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError::Internal(e.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message).into_response(),
+            ApiError::Internal(e) => {
+                tracing::error!("Error handling request: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+            }
+        }
+    }
+}
+
+async fn list_apps(State(server): State<PatchServer>) -> Json<Vec<AppSummary>> {
+    let apps = server
+        .db
+        .list_applications()
+        .await
+        .into_iter()
+        .map(|app| AppSummary {
+            name: app.name,
+            channel: app.channel,
+            platform: app.platform,
+            version: app.version,
+            hash_code: app.hash_code,
+            hash_algorithm: app.hash_algorithm,
+        })
+        .collect();
+    Json(apps)
+}
+
+async fn latest_version(
+    State(server): State<PatchServer>,
+    Path(name): Path<String>,
+    Query(query): Query<TargetQuery>,
+) -> Result<Json<LatestVersion>, ApiError> {
+    let app = server.find_application(&name, &query).await?;
+    let latest = match server.sorted_versions(&app).await?.pop() {
+        Some(version) => LatestVersion {
+            version: version.version,
+            hash_code: Some(version.hash_code),
+            hash_algorithm: app.hash_algorithm,
+        },
+        None => LatestVersion {
+            version: app.version,
+            hash_code: app.hash_code,
+            hash_algorithm: app.hash_algorithm,
+        },
+    };
+    Ok(Json(latest))
+}
+
+async fn list_versions(
+    State(server): State<PatchServer>,
+    Path(name): Path<String>,
+    Query(query): Query<TargetQuery>,
+) -> Result<Json<Vec<VersionSummary>>, ApiError> {
+    let app = server.find_application(&name, &query).await?;
+    let mut versions = Vec::new();
+    for version in server.sorted_versions(&app).await? {
+        let recorded_parts = server.db.list_version_parts(version.id).await?;
+        let parts = match &version.patch_path {
+            Some(_) if !recorded_parts.is_empty() => recorded_parts
+                .into_iter()
+                .map(|part| PartSummary {
+                    part_number: part.part_number,
+                    size: part.size as u64,
+                    hash_code: Some(part.hash_code),
+                })
+                .collect(),
+            // Older versions don't have their parts recorded, list them from the disk
+            Some(patch_path) => list_parts(patch_path)?,
+            None => Vec::new(),
+        };
+        versions.push(VersionSummary {
+            version: version.version,
+            base_version: version.base_version,
+            hash_code: version.hash_code,
+            hash_format: version.hash_format,
+            created_at: version.created_at,
+            parts,
+        });
+    }
+    Ok(Json(versions))
+}
+
+async fn download_part(
+    State(server): State<PatchServer>,
+    Path((name, version, part)): Path<(String, String, i64)>,
+    Query(query): Query<TargetQuery>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    let app = server.find_application(&name, &query).await?;
+    let app_version = find_version(&server.db, &app, &version).await?;
+    let patch_path = app_version
+        .patch_path
+        .ok_or_else(|| ApiError::NotFound(format!("Version {} has no patch", version)))?;
+    let part_path = part_path(&PathBuf::from(patch_path), part);
+    if part < 1 || !part_path.is_file() {
+        return Err(ApiError::NotFound(format!(
+            "Part {} of version {} not found",
+            part, version
+        )));
+    }
+    serve_file(part_path, request).await
+}
+
+async fn download_blob(
+    State(server): State<PatchServer>,
+    Path(hash): Path<String>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    let blob_store = server
+        .blob_store
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("No blob store is configured".to_string()))?;
+    // Only accept names made of hex digits of a supported hash length, 64 for both SHA-256 and
+    // BLAKE3, so that the path can't escape the store
+    let valid_hash = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+    if !valid_hash || !blob_store.contains(&hash) {
+        return Err(ApiError::NotFound(format!("Blob {} not found", hash)));
+    }
+    serve_file(blob_store.blob_path(&hash), request).await
+}
+
+async fn find_version(
+    db: &PatcherDatabase,
+    app: &Application,
+    version: &str,
+) -> Result<AppVersion, ApiError> {
+    db.list_versions(app.id)
+        .await?
+        .into_iter()
+        .find(|app_version| app_version.version == version)
+        .ok_or_else(|| ApiError::NotFound(format!("Version {} of {} not found", version, app.name)))
+}
+
+fn list_parts(patch_path: &str) -> Result<Vec<PartSummary>, ApiError> {
+    let mut parts = Vec::new();
+    for (i, part) in existing_parts(&PathBuf::from(patch_path))
+        .iter()
+        .enumerate()
+    {
+        parts.push(PartSummary {
+            part_number: i as i64 + 1,
+            size: fs::metadata(part)?.len(),
+            hash_code: None,
+        });
+    }
+    Ok(parts)
+}
+
+/// Send a file, honoring range requests.
+async fn serve_file(path: PathBuf, request: Request) -> Result<Response, ApiError> {
+    let response = ServeFile::new(path).oneshot(request).await?;
+    Ok(response.into_response())
+}
@@ -1,40 +1,294 @@
-use std::path::PathBuf;
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
 
 use crate::{
-    indexer::{dir_hasher::DirHasher, indexer_config::IndexerConfig},
-    storage::{application_data::Application, patcher_db::PatcherDatabase},
+    cancellation::CancellationToken,
+    indexer::{
+        chunking::ChunkingConfig,
+        dir_hasher::DirHasher,
+        indexer_config::IndexerConfig,
+        merkle::{InclusionProof, MERKLE_FORMAT_VERSION, MerkleEntry},
+        staged_index::StagedIndex,
+    },
+    progress::ProgressReporter,
+    storage::{
+        app_version::{AppVersion, Release},
+        application_data::{AppSettings, AppTarget, Application},
+        blob_store::BlobStore,
+        db_utils,
+        patcher_db::PatcherDatabase,
+        version_manifest::store_snapshot,
+    },
 };
 
 pub struct AppManager {
     db: PatcherDatabase,
+    // Reporter of the progress of indexing new applications
+    progress: Option<Arc<dyn ProgressReporter>>,
+    // Stops indexing new applications, which are then left out of the database
+    cancellation: CancellationToken,
+    // When set, the files of the first version of new applications are kept here
+    snapshot_store: Option<BlobStore>,
 }
 
 impl AppManager {
     pub fn new(database: PatcherDatabase) -> Self {
-        AppManager { db: database }
+        AppManager {
+            db: database,
+            progress: None,
+            cancellation: CancellationToken::default(),
+            snapshot_store: None,
+        }
+    }
+
+    pub fn with_progress(mut self, progress: Option<Arc<dyn ProgressReporter>>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub fn with_snapshot_store(mut self, snapshot_store: Option<BlobStore>) -> Self {
+        self.snapshot_store = snapshot_store;
+        self
     }
 
     pub async fn create_application(
         &self,
         name: &str,
+        target: &AppTarget,
         version: &str,
+        settings: AppSettings,
         path: &PathBuf,
+        chunking: Option<ChunkingConfig>,
     ) -> Result<Application, anyhow::Error> {
+        settings.version_scheme.validate(version)?;
+        if self.snapshot_store.is_some() {
+            BlobStore::check_algorithm(settings.hash_algorithm)?;
+        }
         // Add new app to db
-        let app = self.db.add_application(name, version, path).await?;
+        let app = self
+            .db
+            .add_application(name, target, version, settings, path)
+            .await?;
 
-        // Compute hash code for the app
-        // Hash code is the SHA256 hash of the hash from all files in the app directory
-        // order by their names.
-        let indexer_config = IndexerConfig::new(app.id, self.db.clone(), true);
+        // Compute hash code for the app, the hash of its directory in the Merkle tree format.
+        // The index is only written along with the first version, so that an application whose
+        // hashing failed or was cancelled can be removed without leaving a partial index behind.
+        let staged_index = StagedIndex::new();
+        let indexer_config = IndexerConfig::staged(app.id, self.db.clone(), staged_index.clone())
+            .with_chunking(chunking)
+            .with_hash_algorithm(settings.hash_algorithm)
+            .with_progress(self.progress.clone())
+            .with_cancellation(self.cancellation.clone());
         let hasher = DirHasher::new(indexer_config);
-        let app_hasher = hasher.dir_hash(path).await?;
-        let (hash, _) = app_hasher.finalize().await;
+        let result = match hasher.dir_hash(path).await {
+            Ok(app_hasher) => app_hasher.finalize().await,
+            Err(e) => Err(e),
+        };
+        let (hash, changes) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.discard_application(name, target).await;
+                return Err(e);
+            }
+        };
         tracing::info!("Application hash is {}", hash);
+        if let Some(snapshot_store) = &self.snapshot_store
+            && let Err(e) = store_snapshot(
+                snapshot_store,
+                settings.hash_algorithm,
+                &[],
+                &changes,
+                &self.cancellation,
+            )
+        {
+            self.discard_application(name, target).await;
+            return Err(e);
+        }
 
-        // Update the application with the computed hash
-        self.db.update_application(&app.id, version, &hash).await;
+        // Update the application with the computed hash and record it as its first version
+        let release = Release {
+            app_id: app.id,
+            version: version.to_string(),
+            base_version: None,
+            hash_code: hash,
+            patch_path: None,
+            blob_hashes: Vec::new(),
+            parts: Vec::new(),
+            keeps_snapshot: self.snapshot_store.is_some(),
+        };
+        self.db
+            .commit_application_update(&release, &staged_index.take())
+            .await?;
 
         Ok(app)
     }
+
+    /// Remove an application whose creation failed. The error that made it fail is the one
+    /// returned, so failing to remove it is only logged.
+    async fn discard_application(&self, name: &str, target: &AppTarget) {
+        if let Err(e) = self.db.remove_application(name, target).await {
+            tracing::warn!("Error removing application {}: {}", name, e);
+        }
+    }
+
+    /// Prove that a file of the current version of an application is part of its tree, the proof
+    /// can be checked against the hash of the version without the rest of the tree.
+    pub async fn prove_file(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        file_path: &Path,
+    ) -> Result<InclusionProof, anyhow::Error> {
+        let app = self
+            .db
+            .get_application(name, target)
+            .await?
+            .ok_or_else(|| anyhow!("{} of {} not found", target, name))?;
+        let root = PathBuf::from(&app.source_path);
+        let names: Vec<_> = file_path
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => Ok(name.to_string_lossy().to_string()),
+                _ => Err(anyhow!("{} is not a relative path", file_path.display())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let index = self
+            .db
+            .get_file_index(app.id, &root.join(file_path).display().to_string())
+            .await?
+            .filter(|index| index.file_type == "FILE")
+            .ok_or_else(|| anyhow!("{} is not a file of {}", file_path.display(), name))?;
+        let mut levels = Vec::new();
+        let mut dir = root;
+        for name in &names {
+            let children = db_utils::list_indexed_files(app.id, &dir, true, &self.db).await?;
+            levels.push(
+                children
+                    .into_iter()
+                    .map(|child| MerkleEntry {
+                        name: Path::new(&child.file_path)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_default(),
+                        file_type: child.file_type,
+                        mode: child.mode,
+                        hash_code: child.hash_code.unwrap_or_default(),
+                    })
+                    .collect(),
+            );
+            dir = dir.join(name);
+        }
+
+        let proof = InclusionProof {
+            format_version: MERKLE_FORMAT_VERSION,
+            hash_algorithm: app.hash_algorithm,
+            file_path: names.join("/"),
+            hash_code: index.hash_code.unwrap_or_default(),
+            levels,
+        };
+        let root_hash = app.hash_code.unwrap_or_default();
+        if !proof.verify(&root_hash) {
+            return Err(anyhow!(
+                "The index of {} doesn't match version {}",
+                name,
+                app.version
+            ));
+        }
+        Ok(proof)
+    }
+
+    /// Promote the current version of a channel to another channel of the same application and
+    /// platform, reusing the patches built for the source channel instead of building a new one.
+    ///
+    /// The versions of the source channel after the one matching the current version of the
+    /// target channel are promoted along with it, so that clients of the target channel can
+    /// still patch their way up. The source tree of the target channel has to be synced with the
+    /// promoted version afterwards, see `PatcherDatabase::promote_versions`. Returns the promoted
+    /// versions.
+    pub async fn promote_version(
+        &self,
+        name: &str,
+        from: &AppTarget,
+        to_channel: &str,
+        version: &str,
+    ) -> Result<Vec<AppVersion>, anyhow::Error> {
+        let from_channel = &from.channel;
+        let source = self
+            .db
+            .get_application(name, from)
+            .await?
+            .ok_or_else(|| anyhow!("{} of {} not found", from, name))?;
+        let to = from.with_channel(to_channel);
+        let target = self
+            .db
+            .get_application(name, &to)
+            .await?
+            .ok_or_else(|| anyhow!("{} of {} not found", to, name))?;
+        if source.id == target.id {
+            return Err(anyhow!("Cannot promote a version to its own channel"));
+        }
+        // The index of the source channel only describes its current version
+        if source.version != version {
+            return Err(anyhow!(
+                "Only the current version of {} ({}) can be promoted",
+                from_channel,
+                source.version
+            ));
+        }
+
+        let versions = self.db.list_versions(source.id).await?;
+        let start = versions
+            .iter()
+            .rposition(|source_version| Some(&source_version.hash_code) == target.hash_code.as_ref())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No version of {} matches version {} of {}, build the patch with update instead",
+                    from_channel,
+                    target.version,
+                    to_channel
+                )
+            })?;
+        let promoted = versions[start + 1..].to_vec();
+        if promoted.is_empty() {
+            return Err(anyhow!(
+                "{} already has the content of version {}",
+                to_channel,
+                version
+            ));
+        }
+        let target_versions = self.db.list_versions(target.id).await?;
+        if let Some(existing) = promoted.iter().find(|promoted| {
+            target_versions
+                .iter()
+                .any(|target_version| target_version.version == promoted.version)
+        }) {
+            return Err(anyhow!(
+                "Version {} already exists in {}",
+                existing.version,
+                to_channel
+            ));
+        }
+
+        self.db
+            .promote_versions(&source, &target, &promoted)
+            .await?;
+        tracing::info!(
+            "Promoted {} version(s) of {} from {} to {}",
+            promoted.len(),
+            name,
+            from_channel,
+            to_channel
+        );
+        Ok(promoted)
+    }
 }
@@ -0,0 +1,199 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::{
+    indexer::{
+        file_change::{FileChange, FileChangeType},
+        live_index::{LiveIndex, diff_live_index},
+    },
+    storage::{application_data::Application, patcher_db::PatcherDatabase},
+};
+
+/// How often a running watcher reports that its live index is up to date.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Events arriving within this delay of each other are applied together
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+/// The source tree of an application as seen by a running watcher.
+pub struct LiveStatus {
+    pub hash_code: String,
+    // Changes since the last release
+    pub changes: Vec<FileChange>,
+}
+
+/// Watches the source tree of an application and keeps its live index up to date, so that the
+/// tree hash is known without rescanning it.
+pub struct IndexWatcher {
+    app: Application,
+    db: PatcherDatabase,
+}
+
+impl IndexWatcher {
+    pub fn new(app: Application, db: PatcherDatabase) -> Self {
+        IndexWatcher { app, db }
+    }
+
+    /// Watch the source tree until the shutdown future completes, then drop the live index.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<(), anyhow::Error> {
+        let root = PathBuf::from(&self.app.source_path);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver is only gone once we stopped watching
+            let _ = sender.send(event);
+        })?;
+        // Start watching before the initial scan so that no change is missed
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        let mut live_index = LiveIndex::load(&self.app, self.db.clone()).await?;
+        let hash_code = live_index.root_hash()?;
+        tracing::info!(
+            "Watching {} for application {}, hash: {}",
+            root.display(),
+            self.app.name,
+            hash_code
+        );
+        self.log_release_status(&hash_code);
+
+        let result = self.watch(&mut live_index, &mut receiver, shutdown).await;
+        drop(watcher);
+        self.db.clear_live_index(self.app.id).await?;
+        result
+    }
+
+    /// Get the status of the source tree of an application from the live index, None if no
+    /// watcher is keeping it up to date.
+    pub async fn live_status(
+        app: &Application,
+        db: &PatcherDatabase,
+    ) -> Result<Option<LiveStatus>, anyhow::Error> {
+        let max_age = chrono::Duration::from_std(HEARTBEAT_INTERVAL * 3)?;
+        let state = match db.get_live_state(app.id).await? {
+            Some(state) if state.is_fresh(max_age) => state,
+            _ => return Ok(None),
+        };
+        let release = db.list_file_index(app.id).await?;
+        let live = db.list_live_index(app.id).await?;
+        let changes = diff_live_index(Path::new(&app.source_path), &release, &live);
+        Ok(Some(LiveStatus {
+            hash_code: state.hash_code,
+            changes,
+        }))
+    }
+
+    async fn watch(
+        &self,
+        live_index: &mut LiveIndex,
+        receiver: &mut mpsc::UnboundedReceiver<notify::Result<Event>>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), anyhow::Error> {
+        tokio::pin!(shutdown);
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let canonical_root = fs::canonicalize(live_index.root())?;
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = heartbeat.tick() => self.db.touch_live_state(self.app.id).await?,
+                event = receiver.recv() => {
+                    let Some(event) = event else {
+                        return Err(anyhow!("File watcher stopped unexpectedly"));
+                    };
+                    let mut events = vec![event];
+                    tokio::time::sleep(DEBOUNCE_DELAY).await;
+                    while let Ok(event) = receiver.try_recv() {
+                        events.push(event);
+                    }
+                    self.apply_events(live_index, &canonical_root, events).await?;
+                }
+            }
+        }
+    }
+
+    async fn apply_events(
+        &self,
+        live_index: &mut LiveIndex,
+        canonical_root: &Path,
+        events: Vec<notify::Result<Event>>,
+    ) -> Result<(), anyhow::Error> {
+        let root = live_index.root().to_path_buf();
+        let mut paths = Vec::new();
+        let mut rescan = false;
+        for event in events {
+            match event {
+                Ok(event) if event.need_rescan() => rescan = true,
+                // Reading files doesn't change them
+                Ok(Event {
+                    kind: EventKind::Access(_),
+                    ..
+                }) => {}
+                Ok(event) => paths.extend(
+                    event
+                        .paths
+                        .iter()
+                        .filter_map(|path| index_path(&root, canonical_root, path)),
+                ),
+                Err(e) => {
+                    tracing::warn!("Error watching {}: {}", root.display(), e);
+                    rescan = true;
+                }
+            }
+        }
+        if rescan {
+            paths = vec![root];
+        }
+        paths.sort();
+        paths.dedup();
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        for path in &paths {
+            live_index.sync_path(path)?;
+        }
+        let changes = live_index.flush().await?;
+        for change in &changes {
+            // Every parent of a changed file is modified too, they are not worth reporting
+            if change.file_type == "FILE" || change.change_type != FileChangeType::Modified {
+                tracing::info!(" - {}", change);
+            }
+        }
+        if !changes.is_empty() {
+            self.log_release_status(&live_index.root_hash()?);
+        }
+        Ok(())
+    }
+
+    fn log_release_status(&self, hash_code: &str) {
+        if self.app.hash_code.as_deref() == Some(hash_code) {
+            tracing::info!(
+                "Application {} matches version {}",
+                self.app.name,
+                self.app.version
+            );
+        } else {
+            tracing::info!(
+                "Application {} changed since version {}, new hash: {}",
+                self.app.name,
+                self.app.version,
+                hash_code
+            );
+        }
+    }
+}
+
+/// Map a path reported by the file watcher to the form used in the index.
+fn index_path(root: &Path, canonical_root: &Path, path: &Path) -> Option<PathBuf> {
+    if path.starts_with(root) {
+        return Some(path.to_path_buf());
+    }
+    path.strip_prefix(canonical_root)
+        .ok()
+        .map(|relative| root.join(relative))
+}
@@ -0,0 +1,140 @@
+use std::{fmt, fs, path::Path};
+
+use anyhow::anyhow;
+
+use crate::{
+    client::update_client::InstallHash,
+    storage::{application_data::AppTarget, install::Install, patcher_db::PatcherDatabase},
+};
+
+/// How an install compares to the latest version of its application.
+#[derive(Debug, PartialEq)]
+pub enum InstallStatus {
+    UpToDate,
+    OutOfDate { latest_version: String },
+    // The install didn't match any known version when it was last verified
+    UnknownVersion,
+    // No version of the application is known
+    NotPublished,
+}
+
+impl fmt::Display for InstallStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallStatus::UpToDate => write!(f, "up to date"),
+            InstallStatus::OutOfDate { latest_version } => {
+                write!(f, "out of date (latest is {})", latest_version)
+            }
+            InstallStatus::UnknownVersion => write!(f, "unknown version"),
+            InstallStatus::NotPublished => write!(f, "not published"),
+        }
+    }
+}
+
+/// Keeps track of the copies of applications installed on this machine, separately from the
+/// release sources the applications are published from.
+pub struct InstallManager {
+    db: PatcherDatabase,
+}
+
+impl InstallManager {
+    pub fn new(database: PatcherDatabase) -> Self {
+        InstallManager { db: database }
+    }
+
+    /// Register an installed copy of an application following the given channel and platform, and
+    /// verify it.
+    pub async fn register_install(
+        &self,
+        app_name: &str,
+        target: &AppTarget,
+        install_path: &Path,
+    ) -> Result<Install, anyhow::Error> {
+        let install_path = fs::canonicalize(install_path)?;
+        if self.db.get_install(&install_path).await?.is_some() {
+            return Err(anyhow!("{} is already registered", install_path.display()));
+        }
+        let install = self.db.add_install(app_name, target, &install_path).await?;
+        self.verify_install(&install).await
+    }
+
+    /// Find a registered install by path, None if the path is not registered.
+    pub async fn find_install(
+        &self,
+        install_path: &Path,
+    ) -> Result<Option<Install>, anyhow::Error> {
+        let Ok(install_path) = fs::canonicalize(install_path) else {
+            return Ok(None);
+        };
+        Ok(self.db.get_install(&install_path).await?)
+    }
+
+    /// Hash an install and record the version it matches among the ones published from this
+    /// database. Installs of applications only known from a patch server are hashed with the
+    /// algorithm they were last verified with.
+    pub async fn verify_install(&self, install: &Install) -> Result<Install, anyhow::Error> {
+        let app = self
+            .db
+            .get_application(&install.app_name, &install.target())
+            .await?;
+        let hash_algorithm = app
+            .as_ref()
+            .map_or(install.hash_algorithm, |app| app.hash_algorithm);
+        let install_hash = InstallHash::compute(&install.install_path, hash_algorithm).await?;
+        let hash_code = install_hash.hash_code.clone();
+        let installed_version = match app {
+            Some(app) => {
+                let mut versions = self.db.list_versions(app.id).await?;
+                app.version_scheme.sort_versions(&mut versions);
+                // The highest version wins when several have the same content
+                versions
+                    .into_iter()
+                    .rev()
+                    .find(|version| install_hash.matches(&version.hash_code, version.hash_format))
+                    .map(|version| version.version)
+            }
+            // Only known from a patch server, still valid if the install didn't change
+            None if install.verified_hash.as_ref() == Some(&hash_code) => {
+                install.installed_version.clone()
+            }
+            None => None,
+        };
+        match &installed_version {
+            Some(version) => tracing::info!(
+                "{} matches version {} of {}",
+                install.install_path.display(),
+                version,
+                install.app_name
+            ),
+            None => tracing::info!(
+                "{} doesn't match any known version of {}",
+                install.install_path.display(),
+                install.app_name
+            ),
+        }
+        self.db
+            .record_install_verification(
+                install.id,
+                installed_version.as_deref(),
+                &hash_code,
+                hash_algorithm,
+            )
+            .await?;
+        self.db
+            .get_install(&install.install_path)
+            .await?
+            .ok_or_else(|| anyhow!("Install {} was removed", install.install_path.display()))
+    }
+
+    /// Compare the recorded version of an install with the latest version of its application.
+    pub fn status(install: &Install, latest_version: Option<&str>) -> InstallStatus {
+        match (&install.installed_version, latest_version) {
+            (_, None) => InstallStatus::NotPublished,
+            (None, Some(_)) => InstallStatus::UnknownVersion,
+            (Some(installed), Some(latest)) if installed == latest => InstallStatus::UpToDate,
+            (Some(_), Some(latest)) => InstallStatus::OutOfDate {
+                latest_version: latest.to_string(),
+            },
+        }
+    }
+}
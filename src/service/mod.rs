@@ -1 +1,3 @@
 pub mod app_manager;
+pub mod index_watcher;
+pub mod install_manager;
@@ -0,0 +1,50 @@
+use chrono::NaiveDateTime;
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+use crate::storage::version_part::VersionPart;
+
+/// A released version of an application.
+#[derive(Clone)]
+pub struct AppVersion {
+    pub id: i64,
+    pub app_id: i64,
+    pub version: String,
+    // Version the patch for this version applies to, None for the initial version
+    pub base_version: Option<String>,
+    pub hash_code: String,
+    // Format version of the hash, see `merkle`
+    pub hash_format: u8,
+    // Path to the patch zip file creating this version, None if no patch was built
+    pub patch_path: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl FromRow<'_, SqliteRow> for AppVersion {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(AppVersion {
+            id: row.try_get("id")?,
+            app_id: row.try_get("app_id")?,
+            version: row.try_get("version")?,
+            base_version: row.try_get("base_version")?,
+            hash_code: row.try_get("hash_code")?,
+            hash_format: row.try_get::<i64, _>("hash_format")? as u8,
+            patch_path: row.try_get("patch_path")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+/// A new version of an application to be recorded.
+pub struct Release {
+    pub app_id: i64,
+    pub version: String,
+    pub base_version: Option<String>,
+    pub hash_code: String,
+    pub patch_path: Option<String>,
+    // Hashes of the blobs referenced by the patch of this version
+    pub blob_hashes: Vec<String>,
+    // Files of the patch with their hashes, empty if no patch was built
+    pub parts: Vec<VersionPart>,
+    // The files of the version were kept in a snapshot store
+    pub keeps_snapshot: bool,
+}
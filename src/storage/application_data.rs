@@ -1,15 +1,91 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf};
 
 use sqlx::{FromRow, Row, sqlite::SqliteRow};
 
-/// Store application information
+use crate::{
+    indexer::hash_algorithm::HashAlgorithm,
+    storage::{db_utils, version_scheme::VersionScheme},
+};
+
+/// Channel used when none is given.
+pub const DEFAULT_CHANNEL: &str = "stable";
+/// Platform of applications shipping the same tree everywhere, used when none is given.
+pub const DEFAULT_PLATFORM: &str = "any";
+
+/// Release channel and platform of an application. Every target of an application has its own
+/// source tree, versions and index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppTarget {
+    pub channel: String,
+    // Platform the source tree is built for, e.g. linux-x86_64 or windows-x86_64
+    pub platform: String,
+}
+
+impl AppTarget {
+    pub fn new(channel: &str, platform: &str) -> Self {
+        AppTarget {
+            channel: channel.to_string(),
+            platform: platform.to_string(),
+        }
+    }
+
+    /// Same platform in another channel.
+    pub fn with_channel(&self, channel: &str) -> Self {
+        AppTarget::new(channel, &self.platform)
+    }
+}
+
+impl Default for AppTarget {
+    fn default() -> Self {
+        AppTarget::new(DEFAULT_CHANNEL, DEFAULT_PLATFORM)
+    }
+}
+
+impl fmt::Display for AppTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.channel, self.platform)
+    }
+}
+
+/// Settings chosen when an application is added, shared by all its versions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AppSettings {
+    pub version_scheme: VersionScheme,
+    pub hash_algorithm: HashAlgorithm,
+}
+
+impl AppSettings {
+    pub fn new(version_scheme: VersionScheme) -> Self {
+        AppSettings {
+            version_scheme,
+            hash_algorithm: HashAlgorithm::default(),
+        }
+    }
+
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+}
+
+/// Store application information, as published from its release source.
+/// Every release channel and platform of an application has its own version lineage and source.
 #[derive(Clone)]
 pub struct Application {
     pub id: i64,
     pub name: String,
+    pub channel: String,
+    pub platform: String,
     pub version: String,
+    // How the versions of the application are validated and ordered
+    pub version_scheme: VersionScheme,
+    // Algorithm the files and directories of the application are hashed with
+    pub hash_algorithm: HashAlgorithm,
     pub hash_code: Option<String>,
-    pub install_path: PathBuf,
+    // Directory the publisher builds releases from, `update` diffs it against the index
+    pub source_path: PathBuf,
+    // Released versions are kept in a snapshot store, so new versions have to be kept there too
+    pub keeps_snapshots: bool,
 }
 
 impl FromRow<'_, SqliteRow> for Application {
@@ -17,9 +93,30 @@ impl FromRow<'_, SqliteRow> for Application {
         Ok(Application {
             id: row.try_get("id")?,
             name: row.try_get("name")?,
+            channel: row.try_get("channel")?,
+            platform: row.try_get("platform")?,
             version: row.try_get("version")?,
+            version_scheme: row
+                .try_get::<String, _>("version_scheme")?
+                .parse()
+                .map_err(|e: anyhow::Error| sqlx::Error::ColumnDecode {
+                    index: "version_scheme".to_string(),
+                    source: e.into(),
+                })?,
+            hash_algorithm: db_utils::get_hash_algorithm(row)?,
             hash_code: row.try_get("hash_code").ok(),
-            install_path: PathBuf::from(row.try_get::<String, _>("install_path")?),
+            source_path: PathBuf::from(row.try_get::<String, _>("source_path")?),
+            keeps_snapshots: row.try_get("keeps_snapshots")?,
         })
     }
 }
+
+impl Application {
+    pub fn settings(&self) -> AppSettings {
+        AppSettings::new(self.version_scheme).with_hash_algorithm(self.hash_algorithm)
+    }
+
+    pub fn target(&self) -> AppTarget {
+        AppTarget::new(&self.channel, &self.platform)
+    }
+}
@@ -0,0 +1,20 @@
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+/// Location of a chunk reused from a file of the base version.
+pub struct BaseChunk {
+    pub hash_code: String,
+    pub file_path: String,
+    pub chunk_offset: i64,
+    pub chunk_length: i64,
+}
+
+impl FromRow<'_, SqliteRow> for BaseChunk {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(BaseChunk {
+            hash_code: row.try_get("hash_code")?,
+            file_path: row.try_get("file_path")?,
+            chunk_offset: row.try_get("chunk_offset")?,
+            chunk_length: row.try_get("chunk_length")?,
+        })
+    }
+}
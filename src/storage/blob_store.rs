@@ -0,0 +1,150 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use tempfile::NamedTempFile;
+
+use crate::indexer::hash_algorithm::HashAlgorithm;
+
+/// Content-addressed storage for file contents.
+///
+/// Each blob is stored once under `<root>/<first 2 hash chars>/<hash>`, keyed by the hash
+/// computed by the indexer with the algorithm of its application, so identical files are only
+/// kept once no matter how many applications, versions or paths they appear in. Only
+/// cryptographic algorithms are accepted, a crafted collision would otherwise replace the
+/// content of every application sharing the blob.
+#[derive(Clone, Debug)]
+pub struct BlobStore {
+    pub root: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(root: &Path) -> Self {
+        BlobStore {
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn blob_path(&self, hash_code: &str) -> PathBuf {
+        let prefix = hash_code.get(..2).unwrap_or(hash_code);
+        self.root.join(prefix).join(hash_code)
+    }
+
+    /// Make sure contents hashed with the given algorithm can be stored.
+    pub fn check_algorithm(hash_algorithm: HashAlgorithm) -> Result<(), anyhow::Error> {
+        if !hash_algorithm.is_cryptographic() {
+            return Err(anyhow!(
+                "Contents hashed with {} can't be stored by hash, use a cryptographic algorithm",
+                hash_algorithm
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, hash_code: &str) -> bool {
+        self.blob_path(hash_code).is_file()
+    }
+
+    /// Copy a file into the store under the given hash.
+    ///
+    /// The content is hashed while copying and rejected if it doesn't match, which happens
+    /// when the file was modified after being indexed. Returns false if the blob was already stored.
+    pub fn put_file(
+        &self,
+        hash_code: &str,
+        file_path: &Path,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<bool, anyhow::Error> {
+        BlobStore::check_algorithm(hash_algorithm)?;
+        if self.contains(hash_code) {
+            return Ok(false);
+        }
+
+        let blob_path = self.blob_path(hash_code);
+        let blob_dir = blob_path.parent().unwrap();
+        fs::create_dir_all(blob_dir)?;
+
+        // Write into a temporary file first so that a partially copied blob is never visible
+        let mut tmp_file = NamedTempFile::new_in(blob_dir)?;
+        let hex_hash = copy_and_hash(
+            &mut File::open(file_path)?,
+            tmp_file.as_file_mut(),
+            hash_algorithm,
+        )?;
+        if hex_hash != hash_code {
+            return Err(anyhow!(
+                "Content of {} doesn't match its indexed hash, it might have been modified",
+                file_path.display()
+            ));
+        }
+        tmp_file.persist(&blob_path)?;
+        Ok(true)
+    }
+
+    pub fn open(&self, hash_code: &str) -> Result<File, anyhow::Error> {
+        File::open(self.blob_path(hash_code))
+            .map_err(|e| anyhow!("Error opening blob {}: {}", hash_code, e))
+    }
+
+    /// Re-hash a stored blob and make sure it matches its name.
+    pub fn verify(
+        &self,
+        hash_code: &str,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(), anyhow::Error> {
+        let hex_hash = copy_and_hash(&mut self.open(hash_code)?, &mut io::sink(), hash_algorithm)?;
+        if hex_hash != hash_code {
+            return Err(anyhow!("Blob {} is corrupted", hash_code));
+        }
+        Ok(())
+    }
+
+    /// List the hashes of all blobs in the store.
+    pub fn list_blobs(&self) -> Result<Vec<String>, anyhow::Error> {
+        let mut blobs = Vec::new();
+        if !self.root.exists() {
+            return Ok(blobs);
+        }
+        for prefix_dir in fs::read_dir(&self.root)? {
+            let prefix_dir = prefix_dir?;
+            if !prefix_dir.file_type()?.is_dir() {
+                continue;
+            }
+            for blob in fs::read_dir(prefix_dir.path())? {
+                let blob = blob?;
+                if blob.file_type()?.is_file() {
+                    blobs.push(blob.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+        blobs.sort();
+        Ok(blobs)
+    }
+
+    pub fn remove(&self, hash_code: &str) -> Result<(), anyhow::Error> {
+        fs::remove_file(self.blob_path(hash_code))?;
+        Ok(())
+    }
+}
+
+/// Copy everything from the reader to the writer, returning the hex hash of the content.
+pub(crate) fn copy_and_hash(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    hash_algorithm: HashAlgorithm,
+) -> Result<String, io::Error> {
+    let mut hasher = hash_algorithm.hasher();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        writer.write_all(&buffer[..bytes_read])?;
+    }
+    Ok(hasher.finalize_hex())
+}
@@ -1,11 +1,33 @@
 /// A utility module for database operations.
 use std::path::Path;
 
-use crate::storage::{file_index::FileIndex, patcher_db::PatcherDatabase};
+use sqlx::{Row, sqlite::SqliteRow};
 
-pub async fn last_index(app_id: i64, file_path: &Path, db: &PatcherDatabase) -> Option<FileIndex> {
+use crate::{
+    indexer::hash_algorithm::HashAlgorithm,
+    storage::{file_index::FileIndex, index_store::IndexStore},
+};
+
+pub async fn last_index(
+    app_id: i64,
+    file_path: &Path,
+    store: &dyn IndexStore,
+) -> Option<FileIndex> {
     let file_path = file_path.display().to_string();
-    db.get_file_index(app_id, &file_path).await.unwrap_or(None)
+    store
+        .get_file_index(app_id, &file_path)
+        .await
+        .unwrap_or(None)
+}
+
+/// Read the hash algorithm recorded in the `hash_algorithm` column of a row.
+pub fn get_hash_algorithm(row: &SqliteRow) -> Result<HashAlgorithm, sqlx::Error> {
+    row.try_get::<String, _>("hash_algorithm")?
+        .parse()
+        .map_err(|e: anyhow::Error| sqlx::Error::ColumnDecode {
+            index: "hash_algorithm".to_string(),
+            source: e.into(),
+        })
 }
 
 /// List all direct indexed files under a given directory for an application.
@@ -13,10 +35,10 @@ pub async fn list_indexed_files(
     app_id: i64,
     parent_dir: &Path,
     direct_children: bool,
-    db: &PatcherDatabase,
-) -> Result<Vec<FileIndex>, sqlx::Error> {
+    store: &dyn IndexStore,
+) -> Result<Vec<FileIndex>, anyhow::Error> {
     let dir_path = parent_dir.display().to_string();
-    let mut children = db.get_files_in_directory(app_id, &dir_path).await?;
+    let mut children = store.get_files_in_directory(app_id, &dir_path).await?;
     if direct_children {
         children = get_direct_children(parent_dir, &children);
     }
@@ -0,0 +1,26 @@
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+/// A content-defined chunk of an indexed file.
+#[derive(Clone, Debug)]
+pub struct FileChunkIndex {
+    pub app_id: i64,
+    pub file_path: String,
+    // Position of the chunk in the file, starting from 0
+    pub chunk_index: i64,
+    pub chunk_offset: i64,
+    pub chunk_length: i64,
+    pub hash_code: String,
+}
+
+impl FromRow<'_, SqliteRow> for FileChunkIndex {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(FileChunkIndex {
+            app_id: row.try_get("app_id")?,
+            file_path: row.try_get("file_path")?,
+            chunk_index: row.try_get("chunk_index")?,
+            chunk_offset: row.try_get("chunk_offset")?,
+            chunk_length: row.try_get("chunk_length")?,
+            hash_code: row.try_get("hash_code")?,
+        })
+    }
+}
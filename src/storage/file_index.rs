@@ -1,12 +1,24 @@
 use chrono::NaiveDateTime;
 use sqlx::{FromRow, Row, sqlite::SqliteRow};
 
+use crate::{
+    indexer::{
+        hash_algorithm::HashAlgorithm,
+        merkle::{MODE_DIRECTORY, MODE_FILE},
+    },
+    storage::db_utils,
+};
+
 #[derive(Clone)]
 pub struct FileIndex {
     pub app_id: i64,
     pub file_path: String,
     pub file_type: String,
     pub hash_code: Option<String>,
+    // Algorithm the hash was computed with
+    pub hash_algorithm: HashAlgorithm,
+    // Mode of the entry in the Merkle tree, see `merkle::entry_mode`
+    pub mode: u32,
     // Modified time should be stored in UTC
     pub modified_time: NaiveDateTime,
 }
@@ -20,9 +32,30 @@ impl FileIndex {
             file_path: file_path.to_string(),
             file_type: file_type.to_string(),
             hash_code: Some("mock_hash".to_string()),
+            hash_algorithm: HashAlgorithm::default(),
+            mode: if file_type == "DIRECTORY" {
+                MODE_DIRECTORY
+            } else {
+                MODE_FILE
+            },
             modified_time: chrono::Utc::now().naive_utc(),
         }
     }
+
+    /// Whether the indexed hash of a file can be reused for a file with the given modified time
+    /// and mode, without reading it again.
+    pub fn is_current_file(
+        &self,
+        modified_time: &NaiveDateTime,
+        mode: u32,
+        hash_algorithm: HashAlgorithm,
+    ) -> bool {
+        self.file_type == "FILE"
+            && self.modified_time == *modified_time
+            && self.mode == mode
+            && self.hash_algorithm == hash_algorithm
+            && self.hash_code.is_some()
+    }
 }
 
 impl FromRow<'_, SqliteRow> for FileIndex {
@@ -32,6 +65,8 @@ impl FromRow<'_, SqliteRow> for FileIndex {
             file_path: row.try_get("file_path")?,
             file_type: row.try_get("file_type")?,
             hash_code: row.try_get("hash_code").ok(),
+            hash_algorithm: db_utils::get_hash_algorithm(row)?,
+            mode: row.try_get::<i64, _>("mode")? as u32,
             modified_time: row.try_get("modified_time")?,
         })
     }
@@ -0,0 +1,378 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+
+use crate::{
+    indexer::chunking::FileChunk,
+    storage::{
+        application_data::{AppSettings, AppTarget, Application},
+        file_chunk_index::FileChunkIndex,
+        file_index::FileIndex,
+        patcher_db::PatcherDatabase,
+    },
+};
+
+/// Storage of the applications and of the file index the indexer reads cached hashes from and
+/// records new ones in. Paths are stored as strings, like the indexer produces them.
+#[async_trait]
+pub trait IndexStore: Send + Sync {
+    async fn add_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        version: &str,
+        settings: AppSettings,
+        source_path: &Path,
+    ) -> Result<Application, anyhow::Error>;
+
+    async fn get_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<Option<Application>, anyhow::Error>;
+
+    async fn list_applications(&self) -> Result<Vec<Application>, anyhow::Error>;
+
+    /// Set the current version of an application and its hash.
+    async fn update_application(
+        &self,
+        id: i64,
+        version: &str,
+        hash_code: &str,
+    ) -> Result<(), anyhow::Error>;
+
+    async fn remove_application(&self, name: &str, target: &AppTarget)
+    -> Result<(), anyhow::Error>;
+
+    async fn get_file_index(
+        &self,
+        app_id: i64,
+        file_path: &str,
+    ) -> Result<Option<FileIndex>, anyhow::Error>;
+
+    async fn list_file_index(&self, app_id: i64) -> Result<Vec<FileIndex>, anyhow::Error>;
+
+    /// List the indexed files and directories anywhere under a directory.
+    async fn get_files_in_directory(
+        &self,
+        app_id: i64,
+        dir_path: &str,
+    ) -> Result<Vec<FileIndex>, anyhow::Error>;
+
+    /// Insert or replace the index of a path, clearing the chunks recorded for it.
+    async fn upsert_file_index(&self, index: &FileIndex) -> Result<(), anyhow::Error>;
+
+    /// Remove the index of a path, returning whether it was indexed.
+    async fn delete_file_index(&self, app_id: i64, file_path: &str) -> Result<bool, anyhow::Error>;
+
+    /// Replace the chunks recorded for an indexed file.
+    async fn set_file_chunks(
+        &self,
+        app_id: i64,
+        file_path: &str,
+        chunks: &[FileChunk],
+    ) -> Result<(), anyhow::Error>;
+
+    /// List the chunks of all indexed files of an application, in file order.
+    async fn list_file_chunks(&self, app_id: i64) -> Result<Vec<FileChunkIndex>, anyhow::Error>;
+}
+
+#[async_trait]
+impl IndexStore for PatcherDatabase {
+    async fn add_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        version: &str,
+        settings: AppSettings,
+        source_path: &Path,
+    ) -> Result<Application, anyhow::Error> {
+        Ok(
+            PatcherDatabase::add_application(self, name, target, version, settings, source_path)
+                .await?,
+        )
+    }
+
+    async fn get_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<Option<Application>, anyhow::Error> {
+        Ok(PatcherDatabase::get_application(self, name, target).await?)
+    }
+
+    async fn list_applications(&self) -> Result<Vec<Application>, anyhow::Error> {
+        Ok(PatcherDatabase::list_applications(self).await)
+    }
+
+    async fn update_application(
+        &self,
+        id: i64,
+        version: &str,
+        hash_code: &str,
+    ) -> Result<(), anyhow::Error> {
+        Ok(PatcherDatabase::update_application(self, id, version, hash_code).await?)
+    }
+
+    async fn remove_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<(), anyhow::Error> {
+        Ok(PatcherDatabase::remove_application(self, name, target).await?)
+    }
+
+    async fn get_file_index(
+        &self,
+        app_id: i64,
+        file_path: &str,
+    ) -> Result<Option<FileIndex>, anyhow::Error> {
+        Ok(PatcherDatabase::get_file_index(self, app_id, file_path).await?)
+    }
+
+    async fn list_file_index(&self, app_id: i64) -> Result<Vec<FileIndex>, anyhow::Error> {
+        Ok(PatcherDatabase::list_file_index(self, app_id).await?)
+    }
+
+    async fn get_files_in_directory(
+        &self,
+        app_id: i64,
+        dir_path: &str,
+    ) -> Result<Vec<FileIndex>, anyhow::Error> {
+        Ok(PatcherDatabase::get_files_in_directory(self, app_id, dir_path).await?)
+    }
+
+    async fn upsert_file_index(&self, index: &FileIndex) -> Result<(), anyhow::Error> {
+        PatcherDatabase::upsert_file_index(self, index).await?;
+        Ok(())
+    }
+
+    async fn delete_file_index(&self, app_id: i64, file_path: &str) -> Result<bool, anyhow::Error> {
+        Ok(PatcherDatabase::delete_file_index(self, app_id, file_path).await?)
+    }
+
+    async fn set_file_chunks(
+        &self,
+        app_id: i64,
+        file_path: &str,
+        chunks: &[FileChunk],
+    ) -> Result<(), anyhow::Error> {
+        Ok(PatcherDatabase::set_file_chunks(self, app_id, file_path, chunks).await?)
+    }
+
+    async fn list_file_chunks(&self, app_id: i64) -> Result<Vec<FileChunkIndex>, anyhow::Error> {
+        Ok(PatcherDatabase::list_file_chunks(self, app_id).await?)
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    applications: Vec<Application>,
+    next_app_id: i64,
+    // Index entries by application and path, ordered like the paths
+    file_index: BTreeMap<(i64, String), FileIndex>,
+    file_chunks: HashMap<(i64, String), Vec<FileChunk>>,
+}
+
+/// Index store kept in memory and lost when dropped, for tests and for hashing trees without
+/// any persistent state. Clones share the same content.
+#[derive(Clone, Default)]
+pub struct MemoryIndexStore {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryIndexStore {
+    pub fn new() -> Self {
+        MemoryIndexStore::default()
+    }
+}
+
+#[async_trait]
+impl IndexStore for MemoryIndexStore {
+    async fn add_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+        version: &str,
+        settings: AppSettings,
+        source_path: &Path,
+    ) -> Result<Application, anyhow::Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.applications.iter().any(|app| {
+            app.name == name && app.channel == target.channel && app.platform == target.platform
+        }) {
+            return Err(anyhow!("{} of {} already exists", target, name));
+        }
+        state.next_app_id += 1;
+        let app = Application {
+            id: state.next_app_id,
+            name: name.to_string(),
+            channel: target.channel.clone(),
+            platform: target.platform.clone(),
+            version: version.to_string(),
+            version_scheme: settings.version_scheme,
+            hash_algorithm: settings.hash_algorithm,
+            hash_code: None,
+            source_path: PathBuf::from(source_path),
+            keeps_snapshots: false,
+        };
+        state.applications.push(app.clone());
+        Ok(app)
+    }
+
+    async fn get_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<Option<Application>, anyhow::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .applications
+            .iter()
+            .find(|app| {
+                app.name == name && app.channel == target.channel && app.platform == target.platform
+            })
+            .cloned())
+    }
+
+    async fn list_applications(&self) -> Result<Vec<Application>, anyhow::Error> {
+        Ok(self.state.lock().unwrap().applications.clone())
+    }
+
+    async fn update_application(
+        &self,
+        id: i64,
+        version: &str,
+        hash_code: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(app) = state.applications.iter_mut().find(|app| app.id == id) {
+            app.version = version.to_string();
+            app.hash_code = Some(hash_code.to_string());
+        }
+        Ok(())
+    }
+
+    async fn remove_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<(), anyhow::Error> {
+        let mut state = self.state.lock().unwrap();
+        let removed: Vec<i64> = state
+            .applications
+            .iter()
+            .filter(|app| {
+                app.name == name && app.channel == target.channel && app.platform == target.platform
+            })
+            .map(|app| app.id)
+            .collect();
+        state.applications.retain(|app| !removed.contains(&app.id));
+        state
+            .file_index
+            .retain(|(app_id, _), _| !removed.contains(app_id));
+        state
+            .file_chunks
+            .retain(|(app_id, _), _| !removed.contains(app_id));
+        Ok(())
+    }
+
+    async fn get_file_index(
+        &self,
+        app_id: i64,
+        file_path: &str,
+    ) -> Result<Option<FileIndex>, anyhow::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .file_index
+            .get(&(app_id, file_path.to_string()))
+            .cloned())
+    }
+
+    async fn list_file_index(&self, app_id: i64) -> Result<Vec<FileIndex>, anyhow::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .file_index
+            .values()
+            .filter(|index| index.app_id == app_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_files_in_directory(
+        &self,
+        app_id: i64,
+        dir_path: &str,
+    ) -> Result<Vec<FileIndex>, anyhow::Error> {
+        let prefix = format!("{}/", dir_path.trim_end_matches('/'));
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .file_index
+            .values()
+            .filter(|index| index.app_id == app_id && index.file_path.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn upsert_file_index(&self, index: &FileIndex) -> Result<(), anyhow::Error> {
+        let key = (index.app_id, index.file_path.clone());
+        let mut state = self.state.lock().unwrap();
+        // The content changed, its previous chunks are not valid anymore
+        state.file_chunks.remove(&key);
+        state.file_index.insert(key, index.clone());
+        Ok(())
+    }
+
+    async fn delete_file_index(&self, app_id: i64, file_path: &str) -> Result<bool, anyhow::Error> {
+        let key = (app_id, file_path.to_string());
+        let mut state = self.state.lock().unwrap();
+        state.file_chunks.remove(&key);
+        Ok(state.file_index.remove(&key).is_some())
+    }
+
+    async fn set_file_chunks(
+        &self,
+        app_id: i64,
+        file_path: &str,
+        chunks: &[FileChunk],
+    ) -> Result<(), anyhow::Error> {
+        let key = (app_id, file_path.to_string());
+        self.state
+            .lock()
+            .unwrap()
+            .file_chunks
+            .insert(key, chunks.to_vec());
+        Ok(())
+    }
+
+    async fn list_file_chunks(&self, app_id: i64) -> Result<Vec<FileChunkIndex>, anyhow::Error> {
+        let state = self.state.lock().unwrap();
+        let mut file_chunks: Vec<_> = state
+            .file_chunks
+            .iter()
+            .filter(|((chunk_app_id, _), _)| *chunk_app_id == app_id)
+            .collect();
+        file_chunks.sort_by(|(a, _), (b, _)| a.1.cmp(&b.1));
+        Ok(file_chunks
+            .into_iter()
+            .flat_map(|((app_id, file_path), chunks)| {
+                chunks
+                    .iter()
+                    .enumerate()
+                    .map(|(chunk_index, chunk)| FileChunkIndex {
+                        app_id: *app_id,
+                        file_path: file_path.clone(),
+                        chunk_index: chunk_index as i64,
+                        chunk_offset: chunk.offset as i64,
+                        chunk_length: chunk.length as i64,
+                        hash_code: chunk.hash_code.clone(),
+                    })
+            })
+            .collect())
+    }
+}
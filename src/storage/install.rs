@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDateTime;
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+use crate::{
+    indexer::hash_algorithm::HashAlgorithm,
+    storage::{application_data::AppTarget, db_utils},
+};
+
+/// A client-side installed copy of an application.
+#[derive(Clone, Debug)]
+pub struct Install {
+    pub id: i64,
+    // Name of the installed application, it doesn't have to be published from this database
+    pub app_name: String,
+    // Release channel the install follows
+    pub channel: String,
+    // Platform of the tree the install was installed from
+    pub platform: String,
+    pub install_path: PathBuf,
+    // Version the install was last found to match, None if it doesn't match any known version
+    pub installed_version: Option<String>,
+    // Hash of the install when it was last verified
+    pub verified_hash: Option<String>,
+    // Algorithm the verified hash was computed with
+    pub hash_algorithm: HashAlgorithm,
+    pub verified_at: Option<NaiveDateTime>,
+    // Version brought by the last patch applied to the install
+    pub last_patch_version: Option<String>,
+    pub last_patched_at: Option<NaiveDateTime>,
+}
+
+impl FromRow<'_, SqliteRow> for Install {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Install {
+            id: row.try_get("id")?,
+            app_name: row.try_get("app_name")?,
+            channel: row.try_get("channel")?,
+            platform: row.try_get("platform")?,
+            install_path: PathBuf::from(row.try_get::<String, _>("install_path")?),
+            installed_version: row.try_get("installed_version")?,
+            verified_hash: row.try_get("verified_hash")?,
+            hash_algorithm: db_utils::get_hash_algorithm(row)?,
+            verified_at: row.try_get("verified_at")?,
+            last_patch_version: row.try_get("last_patch_version")?,
+            last_patched_at: row.try_get("last_patched_at")?,
+        })
+    }
+}
+
+impl Install {
+    pub fn target(&self) -> AppTarget {
+        AppTarget::new(&self.channel, &self.platform)
+    }
+}
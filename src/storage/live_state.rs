@@ -0,0 +1,29 @@
+use chrono::NaiveDateTime;
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+/// The state of the live index kept by a running `watch` operation.
+#[derive(Clone, Debug)]
+pub struct LiveState {
+    pub app_id: i64,
+    // Hash of the source tree as currently on disk
+    pub hash_code: String,
+    // Last time the watcher reported it was alive, in UTC
+    pub updated_at: NaiveDateTime,
+}
+
+impl LiveState {
+    /// Whether the watcher reported being alive within the given time.
+    pub fn is_fresh(&self, max_age: chrono::Duration) -> bool {
+        chrono::Utc::now().naive_utc() - self.updated_at <= max_age
+    }
+}
+
+impl FromRow<'_, SqliteRow> for LiveState {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(LiveState {
+            app_id: row.try_get("app_id")?,
+            hash_code: row.try_get("hash_code")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
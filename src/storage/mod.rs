@@ -1,7 +1,22 @@
+pub mod app_version;
 pub mod application_data;
+pub mod base_chunk;
+pub mod blob_store;
 pub mod db_utils;
+pub mod file_chunk_index;
 pub mod file_index;
+pub mod index_store;
+pub mod install;
+pub mod live_state;
+pub mod patch_compression;
+pub mod patch_config;
 pub mod patch_db;
+pub mod patch_file_change;
+pub mod patch_file_chunk;
 pub mod patch_info;
+pub mod patch_reader;
 pub mod patch_zip;
 pub mod patcher_db;
+pub mod version_manifest;
+pub mod version_part;
+pub mod version_scheme;
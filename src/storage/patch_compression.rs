@@ -0,0 +1,190 @@
+use std::{
+    fmt::{Display, Formatter},
+    fs::File,
+    io::{Read, Write},
+    ops::RangeInclusive,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use flate2::{Compression, write::DeflateEncoder};
+use zip::{CompressionMethod, write::SimpleFileOptions};
+
+/// Extensions of file formats that are already compressed and gain nothing from
+/// being compressed again.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "flac", "gif", "gz", "heic", "jar", "jpeg",
+    "jpg", "ktx2", "lz4", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "ogg", "opus", "png", "rar",
+    "webm", "webp", "woff", "woff2", "xz", "zip", "zst",
+];
+
+/// Number of bytes sampled from the start of a file to decide whether it is compressible.
+const SAMPLE_SIZE: usize = 64 * 1024;
+/// Files smaller than this are always compressed, the sample would not be meaningful.
+const MIN_SAMPLE_SIZE: usize = 4 * 1024;
+/// The sample must shrink below this ratio for the file to be worth compressing.
+const MIN_COMPRESSION_RATIO: f64 = 0.95;
+
+/// Compression method and level used for the entries of a patch zip file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchCompression {
+    Stored,
+    Deflated(Option<i64>),
+    Bzip2(Option<i64>),
+    Zstd(Option<i64>),
+}
+
+impl Default for PatchCompression {
+    fn default() -> Self {
+        PatchCompression::Deflated(None)
+    }
+}
+
+impl PatchCompression {
+    /// Zip file options using this compression method.
+    pub fn file_options(&self) -> SimpleFileOptions {
+        let (method, level) = match self {
+            PatchCompression::Stored => (CompressionMethod::Stored, None),
+            PatchCompression::Deflated(level) => (CompressionMethod::Deflated, *level),
+            PatchCompression::Bzip2(level) => (CompressionMethod::Bzip2, *level),
+            PatchCompression::Zstd(level) => (CompressionMethod::Zstd, *level),
+        };
+        SimpleFileOptions::default()
+            .compression_method(method)
+            .compression_level(level)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PatchCompression::Stored => "stored",
+            PatchCompression::Deflated(_) => "deflate",
+            PatchCompression::Bzip2(_) => "bzip2",
+            PatchCompression::Zstd(_) => "zstd",
+        }
+    }
+
+    fn level(&self) -> Option<i64> {
+        match self {
+            PatchCompression::Stored => None,
+            PatchCompression::Deflated(level)
+            | PatchCompression::Bzip2(level)
+            | PatchCompression::Zstd(level) => *level,
+        }
+    }
+}
+
+impl Display for PatchCompression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.level() {
+            Some(level) => write!(f, "{}:{}", self.name(), level),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Parse a compression in the form of `<method>[:<level>]`, e.g. `stored`, `deflate:9` or `zstd:19`.
+impl FromStr for PatchCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, level) = match s.split_once(':') {
+            Some((method, level)) => {
+                let level = level
+                    .parse::<i64>()
+                    .map_err(|_| anyhow!("Invalid compression level: {}", level))?;
+                (method, Some(level))
+            }
+            None => (s, None),
+        };
+
+        let compression = match method.to_lowercase().as_str() {
+            "stored" | "store" => PatchCompression::Stored,
+            "deflate" | "deflated" => PatchCompression::Deflated(level),
+            "bzip2" => PatchCompression::Bzip2(level),
+            "zstd" => PatchCompression::Zstd(level),
+            _ => return Err(anyhow!("Unsupported compression method: {}", method)),
+        };
+
+        let level_range: Option<RangeInclusive<i64>> = match compression {
+            PatchCompression::Stored if level.is_some() => {
+                return Err(anyhow!("Stored compression doesn't take a level"));
+            }
+            PatchCompression::Stored => None,
+            PatchCompression::Deflated(_) | PatchCompression::Bzip2(_) => Some(1..=9),
+            PatchCompression::Zstd(_) => Some(-7..=22),
+        };
+        if let (Some(level), Some(range)) = (level, level_range)
+            && !range.contains(&level)
+        {
+            return Err(anyhow!(
+                "Compression level for {} must be between {} and {}",
+                method,
+                range.start(),
+                range.end()
+            ));
+        }
+        Ok(compression)
+    }
+}
+
+/// Check whether a file is already compressed, either from its extension or
+/// by compressing a sample of its content.
+pub fn is_compressed_file(file_path: &Path) -> Result<bool, anyhow::Error> {
+    let known_extension = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    if known_extension {
+        return Ok(true);
+    }
+
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    File::open(file_path)?
+        .take(SAMPLE_SIZE as u64)
+        .read_to_end(&mut sample)?;
+    if sample.len() < MIN_SAMPLE_SIZE {
+        return Ok(false);
+    }
+
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&sample)?;
+    let compressed = encoder.finish()?;
+    Ok(compressed.len() as f64 >= sample.len() as f64 * MIN_COMPRESSION_RATIO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PatchCompression;
+
+    #[test]
+    fn parse_compression_test() {
+        assert_eq!(
+            "stored".parse::<PatchCompression>().unwrap(),
+            PatchCompression::Stored
+        );
+        assert_eq!(
+            "deflate".parse::<PatchCompression>().unwrap(),
+            PatchCompression::Deflated(None)
+        );
+        assert_eq!(
+            "deflate:9".parse::<PatchCompression>().unwrap(),
+            PatchCompression::Deflated(Some(9))
+        );
+        assert_eq!(
+            "zstd:19".parse::<PatchCompression>().unwrap(),
+            PatchCompression::Zstd(Some(19))
+        );
+        assert_eq!(
+            "bzip2:1".parse::<PatchCompression>().unwrap(),
+            PatchCompression::Bzip2(Some(1))
+        );
+        assert_eq!(PatchCompression::Zstd(Some(19)).to_string(), "zstd:19");
+        assert_eq!(PatchCompression::Stored.to_string(), "stored");
+
+        assert!("stored:1".parse::<PatchCompression>().is_err());
+        assert!("deflate:10".parse::<PatchCompression>().is_err());
+        assert!("zstd:fast".parse::<PatchCompression>().is_err());
+        assert!("lzma".parse::<PatchCompression>().is_err());
+    }
+}
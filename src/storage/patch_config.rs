@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use crate::{
+    cancellation::CancellationToken,
+    progress::ProgressReporter,
+    storage::{blob_store::BlobStore, patch_compression::PatchCompression},
+};
+
+/// Options used when building a patch zip file.
+#[derive(Clone)]
+pub struct PatchConfig {
+    pub compression: PatchCompression,
+    // Store already compressed files (images, audio, archives...) without compressing them again
+    pub auto_store: bool,
+    // Maximum size of a single zip file, larger patches are split into several parts
+    pub max_part_size: Option<u64>,
+    // When set, file contents are put in the blob store and the patch only references them by hash
+    pub blob_store: Option<BlobStore>,
+    // When set, the changes written and bytes compressed are reported here
+    pub progress: Option<Arc<dyn ProgressReporter>>,
+    // Checked between changes and while writing them, appending fails with `Cancelled` once
+    // cancelled
+    pub cancellation: CancellationToken,
+    // When set, the files of every released version are kept here, so that patches between
+    // any two of them can be built later
+    pub snapshot_store: Option<BlobStore>,
+}
+
+impl PatchConfig {
+    pub fn new(
+        compression: PatchCompression,
+        auto_store: bool,
+        max_part_size: Option<u64>,
+        blob_store: Option<BlobStore>,
+    ) -> Self {
+        PatchConfig {
+            compression,
+            auto_store,
+            max_part_size,
+            blob_store,
+            progress: None,
+            cancellation: CancellationToken::default(),
+            snapshot_store: None,
+        }
+    }
+
+    pub fn with_progress(mut self, progress: Option<Arc<dyn ProgressReporter>>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    pub fn with_snapshot_store(mut self, snapshot_store: Option<BlobStore>) -> Self {
+        self.snapshot_store = snapshot_store;
+        self
+    }
+}
+
+impl Default for PatchConfig {
+    fn default() -> Self {
+        PatchConfig::new(PatchCompression::default(), true, None, None)
+    }
+}
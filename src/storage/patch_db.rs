@@ -1,6 +1,16 @@
 use sqlx::{Executor, SqlitePool};
 
-use crate::storage::patch_info::PatchInfo;
+use crate::{
+    indexer::{
+        chunking::FileChunk,
+        file_change::{FileChange, FileChangeType},
+    },
+    storage::{
+        application_data::Application, base_chunk::BaseChunk, file_chunk_index::FileChunkIndex,
+        patch_file_change::PatchFileChange, patch_file_chunk::PatchFileChunk,
+        patch_info::PatchInfo,
+    },
+};
 
 /// Database containing information about created patches.
 /// This database should be attached to the zip file for the patch.
@@ -19,8 +29,14 @@ impl PatchDatabase {
             CREATE TABLE IF NOT EXISTS patch_info (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 app_name TEXT NOT NULL,
+                platform TEXT NOT NULL DEFAULT 'any',
                 base_version TEXT NOT NULL,
                 patch_version TEXT NOT NULL,
+                source_path TEXT NOT NULL,
+                hash_algorithm TEXT NOT NULL DEFAULT 'sha256',
+                part_count INTEGER NOT NULL DEFAULT 1,
+                uses_blob_store BOOLEAN NOT NULL DEFAULT FALSE,
+                is_full_package BOOLEAN NOT NULL DEFAULT FALSE,
                 created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
             );
         ";
@@ -32,52 +48,233 @@ impl PatchDatabase {
                 patch_id INTEGER NOT NULL,
                 file_path TEXT NOT NULL,
                 file_type TEXT CHECK( file_type IN ('FILE','DIRECTORY') ) NOT NULL,
-                change_type TEXT CHECK( change_type IN ('CREATED','MODIFIED','DELETED') ) NOT NULL,
+                change_type TEXT CHECK( change_type IN ('CREATED','MODIFIED','DELETED','RENAMED') ) NOT NULL,
+                renamed_from TEXT,
+                hash_code TEXT,
+                mode INTEGER,
+                entry_name TEXT,
+                part_number INTEGER,
                 FOREIGN KEY (patch_id) REFERENCES patch_info (id) ON DELETE CASCADE
             );
 
             CREATE INDEX IF NOT EXISTS ix_patch_file_path ON file_changes (file_path);
         ";
         self.db_pool.execute(file_changes_table).await.unwrap();
+
+        // Reassembly recipe of chunked files
+        let file_chunks_table = "
+            CREATE TABLE IF NOT EXISTS file_chunks (
+                file_change_id INTEGER NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                chunk_length INTEGER NOT NULL,
+                hash_code TEXT NOT NULL,
+                entry_name TEXT,
+                part_number INTEGER,
+                PRIMARY KEY (file_change_id, chunk_index),
+                FOREIGN KEY (file_change_id) REFERENCES file_changes (id) ON DELETE CASCADE
+            );
+        ";
+        self.db_pool.execute(file_chunks_table).await.unwrap();
+
+        // Where the chunks that are not shipped can be found in the base version
+        let base_chunks_table = "
+            CREATE TABLE IF NOT EXISTS base_chunks (
+                hash_code TEXT PRIMARY KEY,
+                file_path TEXT NOT NULL,
+                chunk_offset INTEGER NOT NULL,
+                chunk_length INTEGER NOT NULL
+            );
+        ";
+        self.db_pool.execute(base_chunks_table).await.unwrap();
     }
 
+    /// Record the patch of an application, built from its source path for its platform.
     pub async fn create_patch(
         &self,
-        app_name: &str,
+        app: &Application,
         base_version: &str,
         patch_version: &str,
+        uses_blob_store: bool,
+        is_full_package: bool,
     ) -> Result<PatchInfo, sqlx::Error> {
         let query = "
-            INSERT INTO patch_info (app_name, base_version, patch_version)
-            VALUES (?, ?, ?)
+            INSERT INTO patch_info
+                (app_name, platform, base_version, patch_version, source_path, hash_algorithm,
+                    uses_blob_store, is_full_package)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
             RETURNING *
         ";
         sqlx::query_as(query)
-            .bind(app_name)
+            .bind(&app.name)
+            .bind(&app.platform)
             .bind(base_version)
             .bind(patch_version)
+            .bind(app.source_path.display().to_string())
+            .bind(app.hash_algorithm.to_string())
+            .bind(uses_blob_store)
+            .bind(is_full_package)
             .fetch_one(&self.db_pool)
             .await
     }
 
+    /// Record a file change, along with where its content is stored in the zip if any,
+    /// returning the ID of the recorded change.
     pub async fn add_file_change(
         &self,
         patch_id: i64,
-        file_path: &str,
-        file_type: &str,
-        change_type: &str,
-    ) -> Result<bool, sqlx::Error> {
+        change: &FileChange,
+        entry_name: Option<&str>,
+        part_number: Option<i64>,
+    ) -> Result<i64, sqlx::Error> {
+        let change_type = change.change_type.to_string().to_uppercase();
+        let renamed_from = match &change.change_type {
+            FileChangeType::Renamed { from } => Some(from.as_str()),
+            _ => None,
+        };
         let query = "
-            INSERT INTO file_changes (patch_id, file_path, file_type, change_type)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO file_changes
+                (patch_id, file_path, file_type, change_type, renamed_from, hash_code, mode,
+                    entry_name, part_number)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
         ";
         sqlx::query(query)
             .bind(patch_id)
-            .bind(file_path)
-            .bind(file_type)
+            .bind(&change.file_path)
+            .bind(&change.file_type)
             .bind(change_type)
+            .bind(renamed_from)
+            .bind(&change.hash_code)
+            .bind(change.mode.map(i64::from))
+            .bind(entry_name)
+            .bind(part_number)
+            .execute(&self.db_pool)
+            .await
+            .map(|result| result.last_insert_rowid())
+    }
+
+    /// Record a chunk of a file change, along with where its content is stored in the zip.
+    /// Chunks without an entry are taken from the base version.
+    pub async fn add_file_chunk(
+        &self,
+        file_change_id: i64,
+        chunk_index: i64,
+        chunk: &FileChunk,
+        entry_name: Option<&str>,
+        part_number: Option<i64>,
+    ) -> Result<(), sqlx::Error> {
+        let query = "
+            INSERT INTO file_chunks
+                (file_change_id, chunk_index, chunk_length, hash_code, entry_name, part_number)
+            VALUES (?, ?, ?, ?, ?, ?)
+        ";
+        sqlx::query(query)
+            .bind(file_change_id)
+            .bind(chunk_index)
+            .bind(chunk.length as i64)
+            .bind(&chunk.hash_code)
+            .bind(entry_name)
+            .bind(part_number)
+            .execute(&self.db_pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Record where a chunk can be found in the base version, only the first location
+    /// of each chunk is kept.
+    pub async fn add_base_chunk(&self, chunk: &FileChunkIndex) -> Result<(), sqlx::Error> {
+        let query = "
+            INSERT OR IGNORE INTO base_chunks (hash_code, file_path, chunk_offset, chunk_length)
+            VALUES (?, ?, ?, ?)
+        ";
+        sqlx::query(query)
+            .bind(&chunk.hash_code)
+            .bind(&chunk.file_path)
+            .bind(chunk.chunk_offset)
+            .bind(chunk.chunk_length)
+            .execute(&self.db_pool)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn set_part_count(&self, patch_id: i64, part_count: i64) -> Result<(), sqlx::Error> {
+        let query = "
+            UPDATE patch_info
+            SET part_count = ?
+            WHERE id = ?;
+        ";
+        sqlx::query(query)
+            .bind(part_count)
+            .bind(patch_id)
             .execute(&self.db_pool)
             .await
-            .map(|result| result.rows_affected() == 1)
+            .map(|_| ())
+    }
+
+    pub async fn get_patch_info(&self) -> Result<Option<PatchInfo>, sqlx::Error> {
+        let query = "
+            SELECT id, app_name, platform, base_version, patch_version, source_path, hash_algorithm,
+                part_count, uses_blob_store, is_full_package, created_at
+            FROM patch_info
+            ORDER BY id DESC
+            LIMIT 1;
+        ";
+        sqlx::query_as(query)
+            .fetch_optional(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error fetching patch info: {}", e))
+    }
+
+    pub async fn list_file_changes(
+        &self,
+        patch_id: i64,
+    ) -> Result<Vec<PatchFileChange>, sqlx::Error> {
+        let query = "
+            SELECT id, patch_id, file_path, file_type, change_type, renamed_from, hash_code, mode,
+                entry_name, part_number
+            FROM file_changes
+            WHERE patch_id = ?
+            ORDER BY id;
+        ";
+        sqlx::query_as(query)
+            .bind(patch_id)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing file changes: {}", e))
+    }
+
+    /// List the chunks of all chunked file changes of a patch, in reassembly order.
+    pub async fn list_file_chunks(
+        &self,
+        patch_id: i64,
+    ) -> Result<Vec<PatchFileChunk>, sqlx::Error> {
+        let query = "
+            SELECT c.file_change_id, c.chunk_index, c.chunk_length, c.hash_code, c.entry_name,
+                c.part_number
+            FROM file_chunks c
+            JOIN file_changes f ON f.id = c.file_change_id
+            WHERE f.patch_id = ?
+            ORDER BY c.file_change_id, c.chunk_index;
+        ";
+        sqlx::query_as(query)
+            .bind(patch_id)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing file chunks: {}", e))
+    }
+
+    pub async fn list_base_chunks(&self) -> Result<Vec<BaseChunk>, sqlx::Error> {
+        let query = "
+            SELECT hash_code, file_path, chunk_offset, chunk_length
+            FROM base_chunks;
+        ";
+        sqlx::query_as(query)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing base chunks: {}", e))
+    }
+
+    /// Close the underlying connection pool, flushing all pending writes to disk.
+    pub async fn close(&self) {
+        self.db_pool.close().await;
     }
 }
@@ -0,0 +1,47 @@
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+/// A file change recorded in the patch database.
+pub struct PatchFileChange {
+    pub id: i64,
+    pub patch_id: i64,
+    pub file_path: String,
+    pub file_type: String,
+    pub change_type: String,
+    // Previous path of a renamed file
+    pub renamed_from: Option<String>,
+    // Content hash of the file, used to find it in a blob store
+    pub hash_code: Option<String>,
+    // Mode of the file in the Merkle tree, None for patches recorded without one
+    pub mode: Option<u32>,
+    // Name of the entry holding the file content in the zip, None if nothing was stored
+    pub entry_name: Option<String>,
+    // Part of the patch containing the entry, None if nothing was stored
+    pub part_number: Option<i64>,
+}
+
+impl PatchFileChange {
+    /// Whether the new content of the file is shipped with the patch.
+    pub fn has_content(&self) -> bool {
+        self.file_type == "FILE"
+            && (self.change_type == "CREATED" || self.change_type == "MODIFIED")
+    }
+}
+
+impl FromRow<'_, SqliteRow> for PatchFileChange {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(PatchFileChange {
+            id: row.try_get("id")?,
+            patch_id: row.try_get("patch_id")?,
+            file_path: row.try_get("file_path")?,
+            file_type: row.try_get("file_type")?,
+            change_type: row.try_get("change_type")?,
+            renamed_from: row.try_get("renamed_from")?,
+            hash_code: row.try_get("hash_code")?,
+            mode: row
+                .try_get::<Option<i64>, _>("mode")?
+                .map(|mode| mode as u32),
+            entry_name: row.try_get("entry_name")?,
+            part_number: row.try_get("part_number")?,
+        })
+    }
+}
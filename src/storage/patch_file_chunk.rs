@@ -0,0 +1,29 @@
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+/// A chunk of a file change recorded in the patch database.
+///
+/// The chunks of a change are concatenated in order to rebuild the new file content.
+/// Chunks without an entry are copied from the base version, see `BaseChunk`.
+pub struct PatchFileChunk {
+    pub file_change_id: i64,
+    pub chunk_index: i64,
+    pub chunk_length: i64,
+    pub hash_code: String,
+    // Name of the entry holding the chunk content in the zip, None if the base version has it
+    pub entry_name: Option<String>,
+    // Part of the patch containing the entry, None if the base version has it
+    pub part_number: Option<i64>,
+}
+
+impl FromRow<'_, SqliteRow> for PatchFileChunk {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(PatchFileChunk {
+            file_change_id: row.try_get("file_change_id")?,
+            chunk_index: row.try_get("chunk_index")?,
+            chunk_length: row.try_get("chunk_length")?,
+            hash_code: row.try_get("hash_code")?,
+            entry_name: row.try_get("entry_name")?,
+            part_number: row.try_get("part_number")?,
+        })
+    }
+}
@@ -1,11 +1,25 @@
 use chrono::NaiveDateTime;
 use sqlx::{FromRow, Row, sqlite::SqliteRow};
 
+use crate::{indexer::hash_algorithm::HashAlgorithm, storage::db_utils};
+
 pub struct PatchInfo {
     pub id: i64,
     pub app_name: String,
+    // Platform of the source tree the patch was built from
+    pub platform: String,
     pub base_version: String,
     pub patch_version: String,
+    // Source path of the application the patch was built from, recorded file paths start with it
+    pub source_path: String,
+    // Algorithm the hashes of the patch were computed with
+    pub hash_algorithm: HashAlgorithm,
+    // Number of zip files the patch is split into
+    pub part_count: i64,
+    // File contents are kept in a blob store instead of the zip
+    pub uses_blob_store: bool,
+    // Every file of the version is recorded as created, so the patch doesn't need a base
+    pub is_full_package: bool,
     pub created_at: NaiveDateTime,
 }
 
@@ -14,8 +28,14 @@ impl FromRow<'_, SqliteRow> for PatchInfo {
         Ok(PatchInfo {
             id: row.try_get("id")?,
             app_name: row.try_get("app_name")?,
+            platform: row.try_get("platform")?,
             base_version: row.try_get("base_version")?,
             patch_version: row.try_get("patch_version")?,
+            source_path: row.try_get("source_path")?,
+            hash_algorithm: db_utils::get_hash_algorithm(row)?,
+            part_count: row.try_get("part_count")?,
+            uses_blob_store: row.try_get("uses_blob_store")?,
+            is_full_package: row.try_get("is_full_package")?,
             created_at: row.try_get("created_at")?,
         })
     }
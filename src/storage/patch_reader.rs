@@ -0,0 +1,312 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use sqlx::SqlitePool;
+use tempfile::NamedTempFile;
+use zip::ZipArchive;
+
+use crate::{
+    indexer::hash_algorithm::HashAlgorithm,
+    storage::{
+        base_chunk::BaseChunk,
+        blob_store::{BlobStore, copy_and_hash},
+        patch_db::PatchDatabase,
+        patch_file_change::PatchFileChange,
+        patch_file_chunk::PatchFileChunk,
+        patch_zip::{existing_parts, part_path},
+        version_part::VersionPart,
+    },
+};
+
+/// Name of the patch database entry inside the zip, relative to the app directory.
+const PATCH_DB_ENTRY: &str = "patch.db";
+
+/// Reads back a patch zip file created by `PatchZip`.
+/// Patches split into several parts are read through the path of their first part.
+pub struct PatchReader {
+    pub zip_path: PathBuf,
+    // Blob store holding the file contents of patches built with one
+    pub blob_store: Option<BlobStore>,
+}
+
+/// Patch database extracted from a patch zip into a temporary file.
+/// The file is removed once this struct is dropped.
+pub struct ExtractedPatchDb {
+    pub db: PatchDatabase,
+    _db_file: NamedTempFile,
+}
+
+impl PatchReader {
+    pub fn new(zip_path: &Path) -> Self {
+        PatchReader {
+            zip_path: zip_path.to_path_buf(),
+            blob_store: None,
+        }
+    }
+
+    pub fn with_blob_store(zip_path: &Path, blob_store: &BlobStore) -> Self {
+        PatchReader {
+            zip_path: zip_path.to_path_buf(),
+            blob_store: Some(blob_store.clone()),
+        }
+    }
+
+    /// Paths of all the parts of the patch found on disk, in order.
+    pub fn part_paths(&self) -> Vec<PathBuf> {
+        existing_parts(&self.zip_path)
+    }
+
+    /// Size and SHA-256 hash of every part of the patch, in order. Parts are always hashed with
+    /// SHA-256 whatever the algorithm of the application, they are checked by downloaders.
+    pub fn hash_parts(&self) -> Result<Vec<VersionPart>, anyhow::Error> {
+        let mut parts = Vec::new();
+        for (i, part) in self.part_paths().iter().enumerate() {
+            let mut file = File::open(part)?;
+            let size = file.metadata()?.len();
+            parts.push(VersionPart {
+                part_number: i as i64 + 1,
+                size: size as i64,
+                hash_code: copy_and_hash(&mut file, &mut io::sink(), HashAlgorithm::Sha256)?,
+            });
+        }
+        Ok(parts)
+    }
+
+    /// Open every part of the patch, in order.
+    pub fn open_parts(&self) -> Result<Vec<ZipArchive<File>>, anyhow::Error> {
+        let mut archives = Vec::new();
+        for part in self.part_paths() {
+            archives.push(ZipArchive::new(File::open(&part)?)?);
+        }
+        Ok(archives)
+    }
+
+    /// Extract the embedded patch database and open it.
+    pub async fn open_patch_db(&self) -> Result<ExtractedPatchDb, anyhow::Error> {
+        // The database is always written into the last part
+        let last_part = self
+            .part_paths()
+            .pop()
+            .ok_or_else(|| anyhow!("Patch {} not found", self.zip_path.display()))?;
+        let mut archive = ZipArchive::new(File::open(&last_part)?)?;
+        // The database is stored as <app name>/patch.db and is always the last entry written
+        let db_entry = archive
+            .file_names()
+            .filter(|name| {
+                name.split_once('/')
+                    .is_some_and(|(_, file_name)| file_name == PATCH_DB_ENTRY)
+            })
+            .last()
+            .map(|name| name.to_string())
+            .ok_or_else(|| anyhow!("Patch database not found in {}", last_part.display()))?;
+
+        let mut db_file = NamedTempFile::new()?;
+        let mut entry = archive.by_name(&db_entry)?;
+        io::copy(&mut entry, db_file.as_file_mut())?;
+
+        let db_conn = format!("sqlite:{}?mode=ro", db_file.path().display());
+        let db_pool = SqlitePool::connect(&db_conn).await?;
+        Ok(ExtractedPatchDb {
+            db: PatchDatabase::new(db_pool),
+            _db_file: db_file,
+        })
+    }
+
+    /// List the whole files stored in the zip by content hash, with the part and entry they are
+    /// stored in.
+    pub async fn list_stored_entries(
+        &self,
+    ) -> Result<Vec<(String, (PathBuf, String))>, anyhow::Error> {
+        let patch_db = self.open_patch_db().await?;
+        let patch_info = patch_db
+            .db
+            .get_patch_info()
+            .await?
+            .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+        let file_changes = patch_db.db.list_file_changes(patch_info.id).await?;
+        patch_db.db.close().await;
+
+        let entries = file_changes
+            .into_iter()
+            .filter_map(|change| {
+                let part_path = part_path(&self.zip_path, change.part_number?);
+                Some((change.hash_code?, (part_path, change.entry_name?)))
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    /// Validate the patch zip file.
+    ///
+    /// Every entry of every part is read back in full so that its CRC is checked, then the
+    /// embedded patch database is opened and its file changes are matched against the zip entries.
+    /// For patches relying on a blob store, every referenced blob is verified instead.
+    pub async fn validate(&self) -> Result<(), anyhow::Error> {
+        let parts = self.part_paths();
+        // Zip entry names mapped to the part they are stored in
+        let mut entries = HashMap::new();
+        for (i, part) in parts.iter().enumerate() {
+            let part_number = i as i64 + 1;
+            let mut archive = ZipArchive::new(File::open(part)?)?;
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i)?;
+                // The CRC is verified by the zip reader once the entry is fully read
+                io::copy(&mut entry, &mut io::sink())
+                    .map_err(|e| anyhow!("Corrupted entry {} in patch: {}", entry.name(), e))?;
+                if entry.is_file() {
+                    entries.insert(entry.name().to_string(), part_number);
+                }
+            }
+        }
+
+        let patch_db = self.open_patch_db().await?;
+        let patch_info = patch_db
+            .db
+            .get_patch_info()
+            .await?
+            .ok_or_else(|| anyhow!("Patch information is missing from the patch database"))?;
+        let file_changes = patch_db.db.list_file_changes(patch_info.id).await?;
+        let file_chunks = patch_db.db.list_file_chunks(patch_info.id).await?;
+        let base_chunks = patch_db.db.list_base_chunks().await?;
+        patch_db.db.close().await;
+
+        if patch_info.part_count != parts.len() as i64 {
+            return Err(anyhow!(
+                "Patch has {} part(s) but {} were found",
+                patch_info.part_count,
+                parts.len()
+            ));
+        }
+        let db_entry = format!("{}/{}", patch_info.app_name, PATCH_DB_ENTRY);
+        if entries.remove(&db_entry) != Some(patch_info.part_count) {
+            return Err(anyhow!("Unexpected patch database entry in patch"));
+        }
+        verify_entries(&file_changes, &file_chunks, entries)?;
+        verify_chunks(
+            &file_changes,
+            &file_chunks,
+            &base_chunks,
+            patch_info.uses_blob_store,
+        )?;
+
+        if patch_info.uses_blob_store {
+            let blob_store = self
+                .blob_store
+                .as_ref()
+                .ok_or_else(|| anyhow!("Patch contents are stored in a blob store"))?;
+            verify_blobs(&file_changes, blob_store, patch_info.hash_algorithm)?;
+        }
+        Ok(())
+    }
+}
+
+/// Make sure the recorded file changes and chunks match the zip entries,
+/// identical files and chunks may share the same entry.
+fn verify_entries(
+    file_changes: &[PatchFileChange],
+    file_chunks: &[PatchFileChunk],
+    mut entries: HashMap<String, i64>,
+) -> Result<(), anyhow::Error> {
+    let recorded_entries = file_changes
+        .iter()
+        .map(|change| (&change.entry_name, change.part_number))
+        .chain(
+            file_chunks
+                .iter()
+                .map(|chunk| (&chunk.entry_name, chunk.part_number)),
+        );
+    let mut expected_entries = HashMap::new();
+    for (entry_name, part_number) in recorded_entries {
+        let Some(entry_name) = entry_name else {
+            continue;
+        };
+        let part_number = part_number.unwrap_or(1);
+        if *expected_entries
+            .entry(entry_name.as_str())
+            .or_insert(part_number)
+            != part_number
+        {
+            return Err(anyhow!("Entry {} is recorded in several parts", entry_name));
+        }
+    }
+
+    for (entry_name, part_number) in expected_entries {
+        if entries.remove(entry_name) != Some(part_number) {
+            return Err(anyhow!(
+                "Missing entry {} in part {}",
+                entry_name,
+                part_number
+            ));
+        }
+    }
+
+    if let Some(entry_name) = entries.keys().next() {
+        return Err(anyhow!(
+            "Entry {} is not recorded in the patch database",
+            entry_name
+        ));
+    }
+    Ok(())
+}
+
+/// Make sure the content of every changed file can be rebuilt, chunks that are not shipped
+/// must be located in the base version.
+fn verify_chunks(
+    file_changes: &[PatchFileChange],
+    file_chunks: &[PatchFileChunk],
+    base_chunks: &[BaseChunk],
+    uses_blob_store: bool,
+) -> Result<(), anyhow::Error> {
+    let base_hashes: HashSet<&str> = base_chunks
+        .iter()
+        .map(|chunk| chunk.hash_code.as_str())
+        .collect();
+    let mut chunked_changes = HashSet::new();
+    for chunk in file_chunks {
+        chunked_changes.insert(chunk.file_change_id);
+        if chunk.entry_name.is_none() && !base_hashes.contains(chunk.hash_code.as_str()) {
+            return Err(anyhow!(
+                "Chunk {} is neither in the patch nor in the base version",
+                chunk.hash_code
+            ));
+        }
+    }
+    for change in file_changes {
+        let chunked = chunked_changes.contains(&change.id);
+        if chunked && change.entry_name.is_some() {
+            return Err(anyhow!(
+                "File {} is recorded both as an entry and as chunks",
+                change.file_path
+            ));
+        }
+        if change.has_content() && change.entry_name.is_none() && !chunked && !uses_blob_store {
+            return Err(anyhow!("Missing content for {}", change.file_path));
+        }
+    }
+    Ok(())
+}
+
+fn verify_blobs(
+    file_changes: &[PatchFileChange],
+    blob_store: &BlobStore,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(), anyhow::Error> {
+    for change in file_changes {
+        if !change.has_content() {
+            continue;
+        }
+        let hash_code = change
+            .hash_code
+            .as_ref()
+            .ok_or_else(|| anyhow!("Missing hash for changed file {}", change.file_path))?;
+        blob_store
+            .verify(hash_code, hash_algorithm)
+            .map_err(|e| anyhow!("Invalid blob for {}: {}", change.file_path, e))?;
+    }
+    Ok(())
+}
@@ -1,29 +1,69 @@
 use std::{
+    collections::HashMap,
     fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 
 use sqlx::SqlitePool;
-use zip::{ZipWriter, write::SimpleFileOptions};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
 
 use crate::{
-    indexer::file_change::{FileChange, FileChangeType},
-    storage::{application_data::Application, patch_db::PatchDatabase},
+    indexer::{chunking::FileChunk, file_change::FileChange, hash_algorithm::HashAlgorithm},
+    progress::{ProgressStage, ProgressTracker},
+    storage::{
+        application_data::{Application, DEFAULT_PLATFORM},
+        file_chunk_index::FileChunkIndex,
+        patch_compression::{PatchCompression, is_compressed_file},
+        patch_config::PatchConfig,
+        patch_db::PatchDatabase,
+    },
 };
 
+/// Files larger than this need zip64 extensions.
+const LARGE_FILE_THRESHOLD: u64 = u32::MAX as u64;
+/// Estimated size of the local and central directory headers of an entry, excluding its name.
+const ENTRY_HEADER_SIZE: u64 = 128;
+/// Directory of the chunk entries inside the app directory of the zip.
+pub const CHUNKS_DIR: &str = ".chunks";
+
 pub struct PatchZip {
     // ID of the patch in the database, None if not initialized
     pub patch_id: Option<i64>,
     pub app: Application,
     pub out_dir: PathBuf,
+    pub config: PatchConfig,
     pub db: PatchDatabase,
     // Zip writer for creating the patch zip file, None if not initialized
     pub zip_writer: Option<ZipWriter<File>>,
+    // Path to the patch zip file, None if not initialized
+    // For multi-part patches this is the first part, the others are named after it
+    pub zip_path: Option<PathBuf>,
+    // Number of the part currently being written, starting from 1
+    pub part_number: i64,
+    // Estimated size of the part currently being written
+    pub part_size: u64,
+    // Entries already written to the zip by content hash, with the part containing them,
+    // so that identical files are only stored once
+    pub stored_entries: HashMap<String, (String, i64)>,
+    // Chunks of the files in the base version by content hash, chunked files only ship
+    // the chunks missing from here
+    pub base_chunks: HashMap<String, FileChunkIndex>,
+    // Package containing every file of the version instead of the changes from the current one
+    pub full_package: bool,
+    // Entries of the patches built for the other platforms of the same version by content hash,
+    // with the part containing them, so that files identical across platforms are copied
+    // without being compressed again
+    pub shared_entries: HashMap<String, (PathBuf, String)>,
+    // Parts of the other platforms opened to copy shared entries from, by path
+    pub shared_archives: HashMap<PathBuf, ZipArchive<File>>,
+    // Progress of the changes appended, None if the config has no reporter
+    pub progress: Option<ProgressTracker>,
 }
 
 impl PatchZip {
-    pub fn new(out_dir: &Path, app: &Application) -> Self {
+    pub fn new(out_dir: &Path, app: &Application, config: PatchConfig) -> Self {
         // Create database file for the patch, this file will be added to the zip
         let db_path = format!("{}/patch.db", out_dir.display());
         // Make sure to remove any existing database file
@@ -32,12 +72,52 @@ impl PatchZip {
         let db_pool = futures::executor::block_on(SqlitePool::connect(&db_conn)).unwrap();
         let patch_db = PatchDatabase::new(db_pool);
         futures::executor::block_on(patch_db.initialize());
+        let progress = config
+            .progress
+            .clone()
+            .map(|reporter| ProgressTracker::new(ProgressStage::Packaging, reporter));
         PatchZip {
             app: app.clone(),
             patch_id: None,
             out_dir: out_dir.to_path_buf(),
+            config,
             db: patch_db,
             zip_writer: None,
+            zip_path: None,
+            part_number: 1,
+            part_size: 0,
+            stored_entries: HashMap::new(),
+            base_chunks: HashMap::new(),
+            full_package: false,
+            shared_entries: HashMap::new(),
+            shared_archives: HashMap::new(),
+            progress,
+        }
+    }
+
+    /// Set the chunks of the indexed files of the base version.
+    pub fn set_base_chunks(&mut self, chunks: Vec<FileChunkIndex>) {
+        self.base_chunks = chunks
+            .into_iter()
+            .map(|chunk| (chunk.hash_code.clone(), chunk))
+            .collect();
+    }
+
+    /// Build a full package, the changes appended are expected to create every file of the
+    /// version so that it can be installed or repaired without a base version.
+    pub fn set_full_package(&mut self, full_package: bool) {
+        self.full_package = full_package;
+    }
+
+    /// Set the entries of the patches of other platforms that identical files can be copied from.
+    pub fn set_shared_entries(&mut self, shared_entries: HashMap<String, (PathBuf, String)>) {
+        self.shared_entries = shared_entries;
+    }
+
+    /// Set the number of changes that will be appended, used to estimate the time left.
+    pub fn set_total_changes(&self, total_changes: usize) {
+        if let Some(progress) = &self.progress {
+            progress.set_total_files(total_changes as u64);
         }
     }
 
@@ -48,78 +128,415 @@ impl PatchZip {
         }
 
         let app_name = &self.app.name;
-        let old_version = &self.app.version;
+        // A full package doesn't apply on top of another version
+        let old_version = if self.full_package {
+            ""
+        } else {
+            &self.app.version
+        };
         let patch = self
             .db
-            .create_patch(app_name, old_version, new_version)
+            .create_patch(
+                &self.app,
+                old_version,
+                new_version,
+                self.config.blob_store.is_some(),
+                self.full_package,
+            )
             .await?;
 
         // Create zip file for the changes
         let sanitized_app_name = app_name.replace(" ", "_");
-        let package_name = format!("{}_{}_update", sanitized_app_name, new_version);
-        let zip_path = format!("{}/{}.zip", self.out_dir.display(), package_name);
-        let _ = fs::remove_file(&zip_path);
+        let suffix = if self.full_package { "full" } else { "update" };
+        let package_name = if self.app.platform == DEFAULT_PLATFORM {
+            format!("{}_{}_{}", sanitized_app_name, new_version, suffix)
+        } else {
+            format!(
+                "{}_{}_{}_{}",
+                sanitized_app_name, new_version, self.app.platform, suffix
+            )
+        };
+        let zip_path = self.out_dir.join(format!("{}.zip", package_name));
+        // Parts of a previous build would otherwise be taken for parts of this one
+        remove_patch_files(&zip_path);
         let zip_file = File::create(&zip_path)?;
 
         self.zip_writer = Some(ZipWriter::new(zip_file));
+        self.zip_path = Some(zip_path);
         self.patch_id = Some(patch.id);
         Ok(patch.id)
     }
 
     pub async fn append_changed_file(&mut self, change: &FileChange) -> Result<(), anyhow::Error> {
+        self.append_change(change).await?;
+        if let Some(progress) = &self.progress {
+            progress.add_file(0);
+        }
+        Ok(())
+    }
+
+    async fn append_change(&mut self, change: &FileChange) -> Result<(), anyhow::Error> {
+        self.config.cancellation.check()?;
         if self.patch_id.is_none() || self.zip_writer.is_none() {
             return Err(anyhow::anyhow!("PatchZip not initialized"));
         }
 
         let patch_id = self.patch_id.unwrap();
-        let mut zip_writer = self.zip_writer.as_mut().unwrap();
-        let change_type = change.change_type.to_string().to_uppercase();
-        self.db
-            .add_file_change(patch_id, &change.file_path, &change.file_type, &change_type)
-            .await?;
 
-        // Skip deleted files
-        if change.change_type == FileChangeType::Deleted {
+        // Deleted or renamed files and directories don't carry any content
+        if !change.has_content() {
+            self.db
+                .add_file_change(patch_id, change, None, None)
+                .await?;
             return Ok(());
         }
 
         let file_path = PathBuf::from(&change.file_path);
-        let file_metadata = fs::metadata(&file_path)?;
-        if file_path.is_file() {
-            let options = SimpleFileOptions::default()
-                .compression_method(zip::CompressionMethod::Deflated)
-                .unix_permissions(file_metadata.permissions().mode());
-            let trimmed_path = file_path.strip_prefix(&self.app.install_path)?;
-            let path_in_zip = format!("{}/{}", self.app.name, trimmed_path.display());
-            zip_writer.start_file(path_in_zip, options)?;
+        if let Some(blob_store) = &self.config.blob_store {
+            // The patch only references the content, which is stored once in the blob store
+            let hash_code = change
+                .hash_code
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Missing hash for {}", change.file_path))?;
+            if blob_store.put_file(hash_code, &file_path, self.app.hash_algorithm)? {
+                tracing::debug!("Stored blob {} for {}", hash_code, change.file_path);
+            }
+            self.db
+                .add_file_change(patch_id, change, None, None)
+                .await?;
+            return Ok(());
+        }
+
+        // Reuse the entry of an identical file already in this patch
+        if let Some(hash_code) = &change.hash_code
+            && let Some((entry_name, part_number)) = self.stored_entries.get(hash_code)
+        {
+            self.db
+                .add_file_change(patch_id, change, Some(entry_name), Some(*part_number))
+                .await?;
+            return Ok(());
+        }
+
+        // Only ship the chunks that the base version doesn't have
+        if let Some(chunks) = &change.chunks
+            && chunks
+                .iter()
+                .any(|chunk| self.base_chunks.contains_key(&chunk.hash_code))
+        {
+            return self.append_chunked_file(patch_id, change, chunks).await;
+        }
+
+        let trimmed_path = file_path.strip_prefix(&self.app.source_path)?;
+        let path_in_zip = format!("{}/{}", self.app.name, trimmed_path.display());
+        let shared_entry = change
+            .hash_code
+            .as_ref()
+            .and_then(|hash_code| self.shared_entries.get(hash_code))
+            .cloned();
+        if let Some((part_path, entry_name)) = shared_entry {
+            self.copy_entry(&part_path, &entry_name, &path_in_zip)?;
+        } else {
+            let file_metadata = fs::metadata(&file_path)?;
+            let options = self
+                .entry_options(&file_path)?
+                .unix_permissions(file_metadata.permissions().mode())
+                .large_file(file_metadata.len() >= LARGE_FILE_THRESHOLD);
             let mut f = File::open(&file_path)?;
-            std::io::copy(&mut f, &mut zip_writer)?;
+            self.write_entry(&path_in_zip, options, &mut f, file_metadata.len())?;
+        }
+
+        self.db
+            .add_file_change(patch_id, change, Some(&path_in_zip), Some(self.part_number))
+            .await?;
+        if let Some(hash_code) = &change.hash_code {
+            self.stored_entries
+                .insert(hash_code.clone(), (path_in_zip, self.part_number));
+        }
+        Ok(())
+    }
+
+    /// Record a file as its list of chunks, only the chunks missing from the base version
+    /// and from this patch are written to the zip.
+    async fn append_chunked_file(
+        &mut self,
+        patch_id: i64,
+        change: &FileChange,
+        chunks: &[FileChunk],
+    ) -> Result<(), anyhow::Error> {
+        let file_path = PathBuf::from(&change.file_path);
+        let change_id = self
+            .db
+            .add_file_change(patch_id, change, None, None)
+            .await?;
+        let options = self.entry_options(&file_path)?;
+        let mut file = File::open(&file_path)?;
+        let mut reused = 0;
+        for (i, chunk) in chunks.iter().enumerate() {
+            self.config.cancellation.check()?;
+            let chunk_index = i as i64;
+            if let Some(base_chunk) = self.base_chunks.get(&chunk.hash_code) {
+                self.db.add_base_chunk(base_chunk).await?;
+                self.db
+                    .add_file_chunk(change_id, chunk_index, chunk, None, None)
+                    .await?;
+                reused += 1;
+                continue;
+            }
+            if let Some((entry_name, part_number)) = self.stored_entries.get(&chunk.hash_code) {
+                self.db
+                    .add_file_chunk(
+                        change_id,
+                        chunk_index,
+                        chunk,
+                        Some(entry_name),
+                        Some(*part_number),
+                    )
+                    .await?;
+                continue;
+            }
+
+            let data = read_chunk(&mut file, chunk, self.app.hash_algorithm)
+                .map_err(|e| anyhow::anyhow!("{}: {}", change.file_path, e))?;
+            let entry_name = format!("{}/{}/{}", self.app.name, CHUNKS_DIR, chunk.hash_code);
+            self.write_entry(&entry_name, options, &mut data.as_slice(), chunk.length)?;
+            self.db
+                .add_file_chunk(
+                    change_id,
+                    chunk_index,
+                    chunk,
+                    Some(&entry_name),
+                    Some(self.part_number),
+                )
+                .await?;
+            self.stored_entries
+                .insert(chunk.hash_code.clone(), (entry_name, self.part_number));
+        }
+        tracing::debug!(
+            "Chunked {}: {} of {} chunk(s) reused from the base version",
+            change.file_path,
+            reused,
+            chunks.len()
+        );
+        Ok(())
+    }
+
+    /// Zip options for the content of the given file.
+    fn entry_options(&self, file_path: &Path) -> Result<SimpleFileOptions, anyhow::Error> {
+        let compression = if self.config.auto_store && is_compressed_file(file_path)? {
+            PatchCompression::Stored
+        } else {
+            self.config.compression
+        };
+        Ok(compression.file_options())
+    }
+
+    /// Write an entry of the given uncompressed size to the zip.
+    ///
+    /// A new part is started if the entry would push the current one over the limit.
+    /// The uncompressed size is used as an upper bound of what will be written,
+    /// entries bigger than the limit end up alone in their own part.
+    fn write_entry(
+        &mut self,
+        entry_name: &str,
+        options: SimpleFileOptions,
+        reader: &mut impl Read,
+        size: u64,
+    ) -> Result<(), anyhow::Error> {
+        let entry_size = size + ENTRY_HEADER_SIZE + 2 * entry_name.len() as u64;
+        self.reserve_part_size(entry_size)?;
+
+        let mut reader = self.config.cancellation.reader(reader);
+        let mut zip_writer = self.zip_writer.as_mut().unwrap();
+        zip_writer.start_file(entry_name, options)?;
+        let copied = std::io::copy(&mut reader, &mut zip_writer)?;
+        self.part_size += entry_size;
+        if let Some(progress) = &self.progress {
+            progress.add_compressed(copied);
         }
         Ok(())
     }
 
-    pub async fn finalize(mut self) -> Result<(), anyhow::Error> {
+    /// Copy an entry of another patch as it is, without decompressing it.
+    fn copy_entry(
+        &mut self,
+        part_path: &Path,
+        entry_name: &str,
+        new_name: &str,
+    ) -> Result<(), anyhow::Error> {
+        // Parts are only parsed once, most entries of a patch come from the same few parts
+        if !self.shared_archives.contains_key(part_path) {
+            let archive = ZipArchive::new(File::open(part_path)?)?;
+            self.shared_archives
+                .insert(part_path.to_path_buf(), archive);
+        }
+        let archive = self.shared_archives.get_mut(part_path).unwrap();
+        let compressed_size = archive.by_name(entry_name)?.compressed_size();
+        let entry_size = compressed_size + ENTRY_HEADER_SIZE + 2 * new_name.len() as u64;
+        self.reserve_part_size(entry_size)?;
+
+        let archive = self.shared_archives.get_mut(part_path).unwrap();
+        let entry = archive.by_name(entry_name)?;
+        self.zip_writer
+            .as_mut()
+            .unwrap()
+            .raw_copy_file_rename(entry, new_name)?;
+        self.part_size += entry_size;
+        Ok(())
+    }
+
+    /// Start a new part if an entry of the given size would push the current one over the limit.
+    fn reserve_part_size(&mut self, entry_size: u64) -> Result<(), anyhow::Error> {
+        if let Some(max_part_size) = self.config.max_part_size
+            && self.part_size > 0
+            && self.part_size + entry_size > max_part_size
+        {
+            self.start_next_part()?;
+        }
+        Ok(())
+    }
+
+    /// Finish writing the patch zip file and return its path.
+    /// The partially written zip file is removed if this fails.
+    pub async fn finalize(mut self) -> Result<PathBuf, anyhow::Error> {
         if self.patch_id.is_none() || self.zip_writer.is_none() {
             return Err(anyhow::anyhow!("PatchZip not initialized"));
         }
-        let mut zip_writer = self.zip_writer.take().unwrap();
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+        let patch_id = self.patch_id.unwrap();
+        let zip_writer = self.zip_writer.take().unwrap();
+        let zip_path = self.zip_path.take().unwrap();
 
+        // Record the number of parts, the database always goes into the last one
+        if let Err(e) = self.db.set_part_count(patch_id, self.part_number).await {
+            self.db.close().await;
+            remove_patch_files(&zip_path);
+            return Err(e.into());
+        }
         // Ensure the database connection is closed before finishing the zip
-        drop(self.db);
+        self.db.close().await;
         // Then add the database file to the zip
+        if let Err(e) = self.write_patch_db(zip_writer) {
+            remove_patch_files(&zip_path);
+            return Err(e);
+        }
+        tracing::info!(
+            "Update package created successfully at {} ({} part(s))",
+            zip_path.display(),
+            self.part_number
+        );
+        Ok(zip_path)
+    }
+
+    /// Stop building the patch and remove any partially written output.
+    pub async fn abort(mut self) {
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+        drop(self.zip_writer.take());
+        self.db.close().await;
+        if let Some(zip_path) = self.zip_path.take() {
+            remove_patch_files(&zip_path);
+            tracing::info!("Removed incomplete update package {}", zip_path.display());
+        }
+        let _ = fs::remove_file(self.db_path());
+    }
+
+    /// Finish the current part and continue writing into a new one.
+    fn start_next_part(&mut self) -> Result<(), anyhow::Error> {
+        let zip_writer = self.zip_writer.take().unwrap();
+        zip_writer.finish()?;
+
+        self.part_number += 1;
+        self.part_size = 0;
+        let zip_path = part_path(self.zip_path.as_ref().unwrap(), self.part_number);
+        let _ = fs::remove_file(&zip_path);
+        let zip_file = File::create(&zip_path)?;
+        self.zip_writer = Some(ZipWriter::new(zip_file));
+        tracing::info!("Continuing update package in {}", zip_path.display());
+        Ok(())
+    }
+
+    fn write_patch_db(&self, mut zip_writer: ZipWriter<File>) -> Result<(), anyhow::Error> {
         zip_writer.start_file(
             format!("{}/patch.db", self.app.name),
-            SimpleFileOptions::default(),
+            self.config.compression.file_options(),
         )?;
 
-        let db_path = format!("{}/patch.db", self.out_dir.display());
-        let mut db_file = File::open(db_path)?;
+        let mut db_file = File::open(self.db_path())?;
         std::io::copy(&mut db_file, &mut zip_writer)?;
         zip_writer.finish()?;
-        tracing::info!(
-            "Update package created successfully at {}",
-            self.out_dir.display()
-        );
         Ok(())
     }
+
+    fn db_path(&self) -> PathBuf {
+        self.out_dir.join("patch.db")
+    }
+}
+
+/// Read the content of a chunk, making sure it still matches its hash.
+fn read_chunk(
+    file: &mut File,
+    chunk: &FileChunk,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut data = Vec::with_capacity(chunk.length as usize);
+    file.seek(SeekFrom::Start(chunk.offset))?;
+    file.take(chunk.length).read_to_end(&mut data)?;
+    if hash_algorithm.digest(&data) != chunk.hash_code {
+        return Err(anyhow::anyhow!(
+            "Chunk at offset {} doesn't match its indexed hash, the file might have been modified",
+            chunk.offset
+        ));
+    }
+    Ok(data)
+}
+
+/// Path of the given part of a patch, the first part is the patch zip file itself
+/// and the following ones are named `<name>.part<N>.zip`.
+pub fn part_path(zip_path: &Path, part_number: i64) -> PathBuf {
+    if part_number <= 1 {
+        return zip_path.to_path_buf();
+    }
+    let stem = zip_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    zip_path.with_file_name(format!("{}.part{}.zip", stem, part_number))
+}
+
+/// List the parts of a patch that exist on disk, in order.
+pub fn existing_parts(zip_path: &Path) -> Vec<PathBuf> {
+    let mut parts = Vec::new();
+    let mut part_number = 1;
+    loop {
+        let path = part_path(zip_path, part_number);
+        if !path.exists() {
+            break;
+        }
+        parts.push(path);
+        part_number += 1;
+    }
+    parts
+}
+
+/// Remove every part of a patch.
+pub fn remove_patch_files(zip_path: &Path) {
+    for part in existing_parts(zip_path) {
+        let _ = fs::remove_file(part);
+    }
+}
+
+/// Move the parts of a patch to another path, replacing the patch already there part by part.
+pub fn move_patch_files(from: &Path, to: &Path) -> Result<(), io::Error> {
+    let parts = existing_parts(from);
+    for (index, part) in parts.iter().enumerate() {
+        fs::rename(part, part_path(to, index as i64 + 1))?;
+    }
+    // Parts of the replaced patch past the last part of the new one
+    for part in existing_parts(to).into_iter().skip(parts.len()) {
+        fs::remove_file(part)?;
+    }
+    Ok(())
 }
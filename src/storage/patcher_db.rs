@@ -1,9 +1,52 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
-use chrono::NaiveDateTime;
-use sqlx::{Executor, SqlitePool};
+use sqlx::{Executor, SqliteConnection, SqlitePool};
 
-use crate::storage::{application_data::Application, file_index::FileIndex};
+use crate::{
+    indexer::{
+        chunking::FileChunk,
+        hash_algorithm::HashAlgorithm,
+        merkle::{self, MERKLE_FORMAT_VERSION, MODE_DIRECTORY, MODE_FILE, MerkleEntry},
+        staged_index::IndexUpdate,
+    },
+    storage::{
+        app_version::{AppVersion, Release},
+        application_data::{AppSettings, AppTarget, Application},
+        file_chunk_index::FileChunkIndex,
+        file_index::FileIndex,
+        install::Install,
+        live_state::LiveState,
+        version_manifest::ManifestEntry,
+        version_part::VersionPart,
+    },
+};
+
+const UPSERT_FILE_INDEX_QUERY: &str = "
+    INSERT INTO file_index
+        (app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ON CONFLICT (app_id, file_path) DO UPDATE
+    SET file_type = $3, hash_code = $4, hash_algorithm = $5, mode = $6, modified_time = $7
+    RETURNING *;
+";
+
+const DELETE_FILE_INDEX_QUERY: &str = "
+    DELETE FROM file_index
+    WHERE app_id = ? AND file_path = ?;
+";
+
+const DELETE_FILE_CHUNKS_QUERY: &str = "
+    DELETE FROM file_chunks
+    WHERE app_id = ? AND file_path = ?;
+";
+
+const INSERT_FILE_CHUNK_QUERY: &str = "
+    INSERT INTO file_chunks (app_id, file_path, chunk_index, chunk_offset, chunk_length, hash_code)
+    VALUES (?, ?, ?, ?, ?, ?);
+";
 
 #[derive(Clone)]
 pub struct PatcherDatabase {
@@ -16,19 +59,27 @@ impl PatcherDatabase {
     }
 
     /// Initialize tables in the database
-    pub async fn initialize(&self) {
+    pub async fn initialize(&self) -> Result<(), sqlx::Error> {
         let application_table = "
             CREATE TABLE IF NOT EXISTS applications (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 name TEXT NOT NULL,
+                channel TEXT NOT NULL DEFAULT 'stable',
+                platform TEXT NOT NULL DEFAULT 'any',
                 version TEXT NOT NULL,
+                version_scheme TEXT NOT NULL DEFAULT 'semver',
+                hash_algorithm TEXT NOT NULL DEFAULT 'sha256',
                 hash_code TEXT,
-                install_path TEXT NOT NULL
+                source_path TEXT NOT NULL,
+                keeps_snapshots INTEGER NOT NULL DEFAULT 0
             );
-
-            CREATE UNIQUE INDEX IF NOT EXISTS ux_app_name ON applications (name);
         ";
-        self.db_pool.execute(application_table).await.unwrap();
+        self.db_pool.execute(application_table).await?;
+        self.upgrade_applications().await?;
+        let application_index = "
+            CREATE UNIQUE INDEX IF NOT EXISTS ux_app_target ON applications (name, channel, platform);
+        ";
+        self.db_pool.execute(application_index).await?;
 
         let file_index_table = "
             CREATE TABLE IF NOT EXISTS file_index (
@@ -36,6 +87,8 @@ impl PatcherDatabase {
                 file_path TEXT NOT NULL,
                 file_type TEXT CHECK( file_type IN ('FILE','DIRECTORY') ) NOT NULL,
                 hash_code TEXT NOT NULL,
+                hash_algorithm TEXT NOT NULL DEFAULT 'sha256',
+                mode INTEGER NOT NULL DEFAULT 33188,
                 modified_time TIMESTAMP,
                 PRIMARY KEY (app_id, file_path),
                 FOREIGN KEY (app_id) REFERENCES applications (id) ON DELETE CASCADE
@@ -43,62 +96,665 @@ impl PatcherDatabase {
 
             CREATE INDEX IF NOT EXISTS ix_file_path ON file_index (file_path);
         ";
-        self.db_pool.execute(file_index_table).await.unwrap();
+        self.db_pool.execute(file_index_table).await?;
+        // Modes were recorded along with the Merkle tree format of directory hashes
+        let legacy_index = !self.has_column("file_index", "mode").await?;
+        self.upgrade_index("file_index").await?;
+
+        let file_chunks_table = "
+            CREATE TABLE IF NOT EXISTS file_chunks (
+                app_id INTEGER NOT NULL,
+                file_path TEXT NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                chunk_offset INTEGER NOT NULL,
+                chunk_length INTEGER NOT NULL,
+                hash_code TEXT NOT NULL,
+                PRIMARY KEY (app_id, file_path, chunk_index),
+                FOREIGN KEY (app_id, file_path) REFERENCES file_index (app_id, file_path)
+                    ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS ix_file_chunk_hash ON file_chunks (hash_code);
+        ";
+        self.db_pool.execute(file_chunks_table).await?;
+
+        // Hashes of the source tree as currently on disk, kept by the watch operation.
+        // The file index is left as of the last release so that updates still see the changes.
+        let live_index_table = "
+            CREATE TABLE IF NOT EXISTS live_index (
+                app_id INTEGER NOT NULL,
+                file_path TEXT NOT NULL,
+                file_type TEXT CHECK( file_type IN ('FILE','DIRECTORY') ) NOT NULL,
+                hash_code TEXT NOT NULL,
+                hash_algorithm TEXT NOT NULL DEFAULT 'sha256',
+                mode INTEGER NOT NULL DEFAULT 33188,
+                modified_time TIMESTAMP,
+                PRIMARY KEY (app_id, file_path),
+                FOREIGN KEY (app_id) REFERENCES applications (id) ON DELETE CASCADE
+            );
+
+            CREATE TABLE IF NOT EXISTS live_state (
+                app_id INTEGER PRIMARY KEY,
+                hash_code TEXT NOT NULL,
+                updated_at TIMESTAMP NOT NULL,
+                FOREIGN KEY (app_id) REFERENCES applications (id) ON DELETE CASCADE
+            );
+        ";
+        self.db_pool.execute(live_index_table).await?;
+        self.upgrade_index("live_index").await?;
+
+        let app_versions_table = "
+            CREATE TABLE IF NOT EXISTS app_versions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                app_id INTEGER NOT NULL,
+                version TEXT NOT NULL,
+                base_version TEXT,
+                hash_code TEXT NOT NULL,
+                hash_format INTEGER NOT NULL DEFAULT 0,
+                patch_path TEXT,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (app_id) REFERENCES applications (id) ON DELETE CASCADE
+            );
+
+            CREATE UNIQUE INDEX IF NOT EXISTS ux_app_version ON app_versions (app_id, version);
+        ";
+        self.db_pool.execute(app_versions_table).await?;
+        if !self.has_column("app_versions", "hash_format").await? {
+            let query =
+                "ALTER TABLE app_versions ADD COLUMN hash_format INTEGER NOT NULL DEFAULT 0;";
+            self.db_pool.execute(query).await?;
+            // Only versions released before the index had modes are in the legacy format
+            if !legacy_index {
+                sqlx::query("UPDATE app_versions SET hash_format = ?;")
+                    .bind(MERKLE_FORMAT_VERSION)
+                    .execute(&self.db_pool)
+                    .await?;
+            }
+        }
+
+        let version_blobs_table = "
+            CREATE TABLE IF NOT EXISTS version_blobs (
+                version_id INTEGER NOT NULL,
+                hash_code TEXT NOT NULL,
+                PRIMARY KEY (version_id, hash_code),
+                FOREIGN KEY (version_id) REFERENCES app_versions (id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS ix_version_blob_hash ON version_blobs (hash_code);
+        ";
+        self.db_pool.execute(version_blobs_table).await?;
+
+        let version_parts_table = "
+            CREATE TABLE IF NOT EXISTS version_parts (
+                version_id INTEGER NOT NULL,
+                part_number INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                hash_code TEXT NOT NULL,
+                PRIMARY KEY (version_id, part_number),
+                FOREIGN KEY (version_id) REFERENCES app_versions (id) ON DELETE CASCADE
+            );
+        ";
+        self.db_pool.execute(version_parts_table).await?;
+
+        // Every file and directory of a released version, with paths relative to the source
+        let version_manifests_table = "
+            CREATE TABLE IF NOT EXISTS version_manifests (
+                version_id INTEGER NOT NULL,
+                file_path TEXT NOT NULL,
+                file_type TEXT CHECK( file_type IN ('FILE','DIRECTORY') ) NOT NULL,
+                hash_code TEXT NOT NULL,
+                mode INTEGER NOT NULL,
+                PRIMARY KEY (version_id, file_path),
+                FOREIGN KEY (version_id) REFERENCES app_versions (id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS ix_version_manifest_hash ON version_manifests (hash_code);
+        ";
+        self.db_pool.execute(version_manifests_table).await?;
+
+        // Copies of applications installed on this machine, on the client side
+        let installs_table = "
+            CREATE TABLE IF NOT EXISTS installs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                app_name TEXT NOT NULL,
+                channel TEXT NOT NULL DEFAULT 'stable',
+                platform TEXT NOT NULL DEFAULT 'any',
+                install_path TEXT NOT NULL,
+                installed_version TEXT,
+                verified_hash TEXT,
+                hash_algorithm TEXT NOT NULL DEFAULT 'sha256',
+                verified_at TIMESTAMP,
+                last_patch_version TEXT,
+                last_patched_at TIMESTAMP
+            );
+
+            CREATE UNIQUE INDEX IF NOT EXISTS ux_install_path ON installs (install_path);
+        ";
+        self.db_pool.execute(installs_table).await?;
+        self.upgrade_installs().await?;
+
+        if legacy_index {
+            self.rehash_legacy_index().await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the directory hashes of an index recorded before the Merkle tree format from the
+    /// hashes of its files, along with the hash of the current version of each application.
+    /// Older versions keep their hash in the legacy format, which installs are still matched
+    /// against. Modes were not recorded back then, files are taken as not executable.
+    async fn rehash_legacy_index(&self) -> Result<(), sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let apps: Vec<(i64, String, String, Option<String>)> =
+            sqlx::query_as("SELECT id, source_path, version, hash_code FROM applications;")
+                .fetch_all(&mut *tx)
+                .await?;
+        for (app_id, source_path, version, old_hash) in apps {
+            let rows: Vec<(String, String, String)> = sqlx::query_as(
+                "SELECT file_path, file_type, hash_code FROM file_index WHERE app_id = ?;",
+            )
+            .bind(app_id)
+            .fetch_all(&mut *tx)
+            .await?;
+            if rows.is_empty() {
+                continue;
+            }
+            let mut tree: BTreeMap<PathBuf, MerkleEntry> = rows
+                .iter()
+                .map(|(file_path, file_type, hash_code)| {
+                    let mode = if file_type == "DIRECTORY" {
+                        MODE_DIRECTORY
+                    } else {
+                        MODE_FILE
+                    };
+                    let path = PathBuf::from(file_path);
+                    let entry = MerkleEntry::new(&path, file_type, mode, hash_code);
+                    (path, entry)
+                })
+                .collect();
+            // Every index was hashed with SHA-256 before the Merkle tree format
+            let hash_code = merkle::rehash_tree(
+                HashAlgorithm::Sha256,
+                MERKLE_FORMAT_VERSION,
+                Path::new(&source_path),
+                &mut tree,
+            )
+            .map_err(|e| sqlx::Error::Decode(e.into()))?;
+
+            let query = "
+                UPDATE file_index SET hash_code = ?, mode = ?
+                WHERE app_id = ? AND file_path = ?;
+            ";
+            for (file_path, entry) in tree.iter().filter(|(_, e)| e.file_type == "DIRECTORY") {
+                sqlx::query(query)
+                    .bind(&entry.hash_code)
+                    .bind(entry.mode)
+                    .bind(app_id)
+                    .bind(file_path.display().to_string())
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            let Some(old_hash) = old_hash else {
+                continue;
+            };
+            sqlx::query("UPDATE applications SET hash_code = ? WHERE id = ?;")
+                .bind(&hash_code)
+                .bind(app_id)
+                .execute(&mut *tx)
+                .await?;
+            let query = "
+                UPDATE app_versions SET hash_code = ?, hash_format = ?
+                WHERE app_id = ? AND version = ? AND hash_code = ?;
+            ";
+            sqlx::query(query)
+                .bind(&hash_code)
+                .bind(MERKLE_FORMAT_VERSION)
+                .bind(app_id)
+                .bind(&version)
+                .bind(&old_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        // Rebuilt by the next watcher from the rewritten index
+        tx.execute("DELETE FROM live_index; DELETE FROM live_state;")
+            .await?;
+        tx.commit().await
+    }
+
+    /// Bring an applications table created by an older version up to the current schema.
+    async fn upgrade_applications(&self) -> Result<(), sqlx::Error> {
+        // The source of a release used to be named like the path of an install
+        if self.has_column("applications", "install_path").await? {
+            self.db_pool
+                .execute("ALTER TABLE applications RENAME COLUMN install_path TO source_path;")
+                .await?;
+        }
+        self.add_missing_column("applications", "channel", "TEXT NOT NULL DEFAULT 'stable'")
+            .await?;
+        self.add_missing_column(
+            "applications",
+            "version_scheme",
+            "TEXT NOT NULL DEFAULT 'semver'",
+        )
+        .await?;
+        self.add_missing_column("applications", "platform", "TEXT NOT NULL DEFAULT 'any'")
+            .await?;
+        self.add_missing_column(
+            "applications",
+            "hash_algorithm",
+            "TEXT NOT NULL DEFAULT 'sha256'",
+        )
+        .await?;
+        self.add_missing_column(
+            "applications",
+            "keeps_snapshots",
+            "INTEGER NOT NULL DEFAULT 0",
+        )
+        .await?;
+        // Names were unique before applications had several channels, then several platforms
+        self.db_pool
+            .execute("DROP INDEX IF EXISTS ux_app_name; DROP INDEX IF EXISTS ux_app_name_channel;")
+            .await?;
+        Ok(())
+    }
+
+    /// Bring a file index table created by an older version up to the current schema.
+    async fn upgrade_index(&self, table: &str) -> Result<(), sqlx::Error> {
+        self.add_missing_column(table, "mode", "INTEGER NOT NULL DEFAULT 33188")
+            .await?;
+        self.add_missing_column(table, "hash_algorithm", "TEXT NOT NULL DEFAULT 'sha256'")
+            .await?;
+        Ok(())
+    }
+
+    /// Bring an installs table created by an older version up to the current schema.
+    async fn upgrade_installs(&self) -> Result<(), sqlx::Error> {
+        self.add_missing_column("installs", "channel", "TEXT NOT NULL DEFAULT 'stable'")
+            .await?;
+        self.add_missing_column("installs", "platform", "TEXT NOT NULL DEFAULT 'any'")
+            .await?;
+        self.add_missing_column(
+            "installs",
+            "hash_algorithm",
+            "TEXT NOT NULL DEFAULT 'sha256'",
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn has_column(&self, table: &str, column: &str) -> Result<bool, sqlx::Error> {
+        let query = "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?;";
+        let count: i64 = sqlx::query_scalar(query)
+            .bind(table)
+            .bind(column)
+            .fetch_one(&self.db_pool)
+            .await?;
+        Ok(count > 0)
+    }
+
+    /// Add a column missing from a table created by an older version, existing rows get its
+    /// default value.
+    async fn add_missing_column(
+        &self,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<(), sqlx::Error> {
+        if !self.has_column(table, column).await? {
+            let query = format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table, column, definition
+            );
+            self.db_pool.execute(query.as_str()).await?;
+        }
+        Ok(())
     }
 
     pub async fn add_application(
         &self,
         name: &str,
+        target: &AppTarget,
         version: &str,
-        install_path: &Path,
+        settings: AppSettings,
+        source_path: &Path,
     ) -> Result<Application, sqlx::Error> {
-        let install_path = install_path.to_string_lossy();
+        let source_path = source_path.to_string_lossy();
         let query = "
-            INSERT INTO applications (name, version, install_path)
-            VALUES (?, ?, ?)
+            INSERT INTO applications
+                (name, channel, platform, version, version_scheme, hash_algorithm, source_path)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
             RETURNING *
         ";
         sqlx::query_as(query)
             .bind(name)
+            .bind(&target.channel)
+            .bind(&target.platform)
             .bind(version)
-            .bind(install_path.as_ref())
+            .bind(settings.version_scheme.to_string())
+            .bind(settings.hash_algorithm.to_string())
+            .bind(source_path.as_ref())
             .fetch_one(&self.db_pool)
             .await
     }
 
-    pub async fn update_application(&self, id: &i64, version: &str, hash_code: &str) {
+    /// Apply staged index changes and record the new version of an application, along with
+    /// the blobs it references, the files of its patch and its manifest, in a single
+    /// transaction. Nothing is changed if any of the statements fail.
+    pub async fn commit_application_update(
+        &self,
+        release: &Release,
+        updates: &[IndexUpdate],
+    ) -> Result<AppVersion, sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        apply_index_updates(&mut tx, updates).await?;
+        // Once a version is kept in a snapshot store, the following ones have to be kept too
         let query = "
             UPDATE applications
-            SET version = ?, hash_code = ?
+            SET version = ?, hash_code = ?, keeps_snapshots = keeps_snapshots OR ?
             WHERE id = ?;
         ";
-        let _result = self
-            .db_pool
-            .execute(sqlx::query(query).bind(version).bind(hash_code).bind(id))
+        sqlx::query(query)
+            .bind(&release.version)
+            .bind(&release.hash_code)
+            .bind(release.keeps_snapshot)
+            .bind(release.app_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let query = "
+            INSERT INTO app_versions
+                (app_id, version, base_version, hash_code, hash_format, patch_path)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING *
+        ";
+        let app_version: AppVersion = sqlx::query_as(query)
+            .bind(release.app_id)
+            .bind(&release.version)
+            .bind(&release.base_version)
+            .bind(&release.hash_code)
+            .bind(MERKLE_FORMAT_VERSION)
+            .bind(&release.patch_path)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let query = "
+            INSERT OR IGNORE INTO version_blobs (version_id, hash_code)
+            VALUES (?, ?)
+        ";
+        for hash_code in &release.blob_hashes {
+            sqlx::query(query)
+                .bind(app_version.id)
+                .bind(hash_code)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let query = "
+            INSERT INTO version_parts (version_id, part_number, size, hash_code)
+            VALUES (?, ?, ?, ?)
+        ";
+        for part in &release.parts {
+            sqlx::query(query)
+                .bind(app_version.id)
+                .bind(part.part_number)
+                .bind(part.size)
+                .bind(&part.hash_code)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        // The index now describes the tree of the new version
+        record_manifest(&mut tx, release.app_id, app_version.id).await?;
+        tx.commit().await?;
+        Ok(app_version)
+    }
+
+    /// Replace the patch recorded for a version, e.g. once it has been built again, along with
+    /// the files and blobs it references, in a single transaction.
+    pub async fn replace_version_patch(
+        &self,
+        version_id: i64,
+        patch_path: &str,
+        parts: &[VersionPart],
+        blob_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query("UPDATE app_versions SET patch_path = ? WHERE id = ?;")
+            .bind(patch_path)
+            .bind(version_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM version_parts WHERE version_id = ?;")
+            .bind(version_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM version_blobs WHERE version_id = ?;")
+            .bind(version_id)
+            .execute(&mut *tx)
+            .await?;
+        let query = "
+            INSERT OR IGNORE INTO version_blobs (version_id, hash_code)
+            VALUES (?, ?)
+        ";
+        for hash_code in blob_hashes {
+            sqlx::query(query)
+                .bind(version_id)
+                .bind(hash_code)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let query = "
+            INSERT INTO version_parts (version_id, part_number, size, hash_code)
+            VALUES (?, ?, ?, ?)
+        ";
+        for part in parts {
+            sqlx::query(query)
+                .bind(version_id)
+                .bind(part.part_number)
+                .bind(part.size)
+                .bind(&part.hash_code)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    /// List the files and directories of a released version, in path order. Empty for
+    /// versions released before manifests were recorded.
+    pub async fn list_version_manifest(
+        &self,
+        version_id: i64,
+    ) -> Result<Vec<ManifestEntry>, sqlx::Error> {
+        let query = "
+            SELECT version_id, file_path, file_type, hash_code, mode
+            FROM version_manifests
+            WHERE version_id = ?
+            ORDER BY file_path;
+        ";
+        sqlx::query_as(query)
+            .bind(version_id)
+            .fetch_all(&self.db_pool)
             .await
-            .inspect_err(|e| tracing::info!("Error updating application: {}", e));
+            .inspect_err(|e| tracing::info!("Error listing version manifest: {}", e))
+    }
+
+    /// Record versions of another channel as the next versions of the target channel, reusing
+    /// their patches, parts and blobs, in a single transaction. The index of the source channel
+    /// replaces the one of the target channel, as the target channel now has the same content:
+    /// the source tree of the target channel has to be synced with the promoted version before
+    /// it is checked or updated again, otherwise every difference shows up as a change.
+    pub async fn promote_versions(
+        &self,
+        source: &Application,
+        target: &Application,
+        versions: &[AppVersion],
+    ) -> Result<(), sqlx::Error> {
+        let Some(latest) = versions.last() else {
+            return Ok(());
+        };
+        let mut tx = self.db_pool.begin().await?;
+        let mut base_version = target.version.clone();
+        for version in versions {
+            let query = "
+                INSERT INTO app_versions
+                    (app_id, version, base_version, hash_code, hash_format, patch_path)
+                VALUES (?, ?, ?, ?, ?, ?)
+                RETURNING *
+            ";
+            let promoted: AppVersion = sqlx::query_as(query)
+                .bind(target.id)
+                .bind(&version.version)
+                .bind(&base_version)
+                .bind(&version.hash_code)
+                .bind(version.hash_format)
+                .bind(&version.patch_path)
+                .fetch_one(&mut *tx)
+                .await?;
+            let query = "
+                INSERT INTO version_blobs (version_id, hash_code)
+                SELECT ?, hash_code FROM version_blobs WHERE version_id = ?
+            ";
+            sqlx::query(query)
+                .bind(promoted.id)
+                .bind(version.id)
+                .execute(&mut *tx)
+                .await?;
+            let query = "
+                INSERT INTO version_parts (version_id, part_number, size, hash_code)
+                SELECT ?, part_number, size, hash_code FROM version_parts WHERE version_id = ?
+            ";
+            sqlx::query(query)
+                .bind(promoted.id)
+                .bind(version.id)
+                .execute(&mut *tx)
+                .await?;
+            let query = "
+                INSERT INTO version_manifests (version_id, file_path, file_type, hash_code, mode)
+                SELECT ?, file_path, file_type, hash_code, mode
+                FROM version_manifests WHERE version_id = ?
+            ";
+            sqlx::query(query)
+                .bind(promoted.id)
+                .bind(version.id)
+                .execute(&mut *tx)
+                .await?;
+            base_version = version.version.clone();
+        }
+
+        // Promoted versions kept in a snapshot store require the next ones to be kept there too
+        let query = "
+            UPDATE applications
+            SET version = ?, hash_code = ?, keeps_snapshots = keeps_snapshots OR ?
+            WHERE id = ?;
+        ";
+        sqlx::query(query)
+            .bind(&latest.version)
+            .bind(&latest.hash_code)
+            .bind(source.keeps_snapshots)
+            .bind(target.id)
+            .execute(&mut *tx)
+            .await?;
+
+        // Indexed paths start with the source path of their channel
+        let source_path = source.source_path.to_string_lossy();
+        let target_path = target.source_path.to_string_lossy();
+        sqlx::query("DELETE FROM file_index WHERE app_id = ?;")
+            .bind(target.id)
+            .execute(&mut *tx)
+            .await?;
+        let query = "
+            INSERT INTO file_index
+                (app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time)
+            SELECT ?, ? || substr(file_path, length(?) + 1), file_type, hash_code, hash_algorithm,
+                mode, modified_time
+            FROM file_index
+            WHERE app_id = ?;
+        ";
+        sqlx::query(query)
+            .bind(target.id)
+            .bind(target_path.as_ref())
+            .bind(source_path.as_ref())
+            .bind(source.id)
+            .execute(&mut *tx)
+            .await?;
+        let query = "
+            INSERT INTO file_chunks
+                (app_id, file_path, chunk_index, chunk_offset, chunk_length, hash_code)
+            SELECT ?, ? || substr(file_path, length(?) + 1), chunk_index, chunk_offset,
+                chunk_length, hash_code
+            FROM file_chunks
+            WHERE app_id = ?;
+        ";
+        sqlx::query(query)
+            .bind(target.id)
+            .bind(target_path.as_ref())
+            .bind(source_path.as_ref())
+            .bind(source.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    /// Apply staged index changes in a single transaction.
+    pub async fn commit_index_updates(&self, updates: &[IndexUpdate]) -> Result<(), sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        apply_index_updates(&mut tx, updates).await?;
+        tx.commit().await
     }
 
-    pub async fn remove_application(&self, name: &str) {
+    pub async fn update_application(
+        &self,
+        id: i64,
+        version: &str,
+        hash_code: &str,
+    ) -> Result<(), sqlx::Error> {
+        let query = "
+            UPDATE applications
+            SET version = ?, hash_code = ?
+            WHERE id = ?;
+        ";
+        sqlx::query(query)
+            .bind(version)
+            .bind(hash_code)
+            .bind(id)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<(), sqlx::Error> {
         let query = "
             DELETE FROM applications
-            WHERE name = ?;
+            WHERE name = ? AND channel = ? AND platform = ?;
         ";
-        let _result = self
-            .db_pool
-            .execute(sqlx::query(query).bind(name))
-            .await
-            .inspect_err(|e| tracing::info!("Error removing application: {}", e));
+        sqlx::query(query)
+            .bind(name)
+            .bind(&target.channel)
+            .bind(&target.platform)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
     }
 
-    pub async fn get_application(&self, name: &str) -> Result<Option<Application>, sqlx::Error> {
+    pub async fn get_application(
+        &self,
+        name: &str,
+        target: &AppTarget,
+    ) -> Result<Option<Application>, sqlx::Error> {
         let query = "
-            SELECT id, name, version, hash_code, install_path
+            SELECT id, name, channel, platform, version, version_scheme, hash_algorithm, hash_code,
+                source_path, keeps_snapshots
             FROM applications
-            WHERE name = ?;
+            WHERE name = ? AND channel = ? AND platform = ?;
         ";
         sqlx::query_as(query)
             .bind(name)
+            .bind(&target.channel)
+            .bind(&target.platform)
             .fetch_optional(&self.db_pool)
             .await
             .inspect_err(|e| tracing::info!("Error fetching application: {}", e))
@@ -106,8 +762,10 @@ impl PatcherDatabase {
 
     pub async fn list_applications(&self) -> Vec<Application> {
         let query = "
-            SELECT id, name, version, hash_code, install_path
+            SELECT id, name, channel, platform, version, version_scheme, hash_algorithm, hash_code,
+                source_path, keeps_snapshots
             FROM applications
+            ORDER BY name, channel, platform
         ";
         sqlx::query_as(query)
             .fetch_all(&self.db_pool)
@@ -116,37 +774,254 @@ impl PatcherDatabase {
             .unwrap_or_default()
     }
 
-    pub async fn upsert_file_index(
+    /// List the recorded versions of an application, oldest first.
+    pub async fn list_versions(&self, app_id: i64) -> Result<Vec<AppVersion>, sqlx::Error> {
+        let query = "
+            SELECT id, app_id, version, base_version, hash_code, hash_format, patch_path, created_at
+            FROM app_versions
+            WHERE app_id = ?
+            ORDER BY id;
+        ";
+        sqlx::query_as(query)
+            .bind(app_id)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing versions: {}", e))
+    }
+
+    /// List the recorded files of the patch of a version, in order.
+    pub async fn list_version_parts(
+        &self,
+        version_id: i64,
+    ) -> Result<Vec<VersionPart>, sqlx::Error> {
+        let query = "
+            SELECT part_number, size, hash_code
+            FROM version_parts
+            WHERE version_id = ?
+            ORDER BY part_number;
+        ";
+        sqlx::query_as(query)
+            .bind(version_id)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing version parts: {}", e))
+    }
+
+    /// Remove all but the `keep` most recent versions of every application,
+    /// returning the versions removed.
+    pub async fn prune_versions(&self, keep: usize) -> Result<Vec<AppVersion>, sqlx::Error> {
+        let query = "
+            DELETE FROM app_versions
+            WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (PARTITION BY app_id ORDER BY id DESC) AS rank
+                    FROM app_versions
+                )
+                WHERE rank > ?
+            )
+            RETURNING *;
+        ";
+        sqlx::query_as(query)
+            .bind(keep as i64)
+            .fetch_all(&self.db_pool)
+            .await
+    }
+
+    /// List the patches recorded for any version, a patch promoted to another channel is
+    /// recorded for both versions.
+    pub async fn list_patch_paths(&self) -> Result<Vec<String>, sqlx::Error> {
+        let query = "
+            SELECT DISTINCT patch_path FROM app_versions
+            WHERE patch_path IS NOT NULL;
+        ";
+        sqlx::query_scalar(query).fetch_all(&self.db_pool).await
+    }
+
+    /// List the hashes of all blobs referenced by the patch of a recorded version.
+    pub async fn list_referenced_blobs(&self) -> Result<Vec<String>, sqlx::Error> {
+        let query = "SELECT DISTINCT hash_code FROM version_blobs;";
+        sqlx::query_scalar(query)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing referenced blobs: {}", e))
+    }
+
+    /// List the hashes of the files of all recorded versions, the contents a snapshot store keeps.
+    pub async fn list_snapshot_blobs(&self) -> Result<Vec<String>, sqlx::Error> {
+        let query = "SELECT DISTINCT hash_code FROM version_manifests WHERE file_type = 'FILE';";
+        sqlx::query_scalar(query)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing snapshot blobs: {}", e))
+    }
+
+    pub async fn add_install(
+        &self,
+        app_name: &str,
+        target: &AppTarget,
+        install_path: &Path,
+    ) -> Result<Install, sqlx::Error> {
+        let query = "
+            INSERT INTO installs (app_name, channel, platform, install_path)
+            VALUES (?, ?, ?, ?)
+            RETURNING *
+        ";
+        sqlx::query_as(query)
+            .bind(app_name)
+            .bind(&target.channel)
+            .bind(&target.platform)
+            .bind(install_path.to_string_lossy().as_ref())
+            .fetch_one(&self.db_pool)
+            .await
+    }
+
+    pub async fn get_install(&self, install_path: &Path) -> Result<Option<Install>, sqlx::Error> {
+        let query = "
+            SELECT id, app_name, channel, platform, install_path, installed_version, verified_hash,
+                hash_algorithm, verified_at, last_patch_version, last_patched_at
+            FROM installs
+            WHERE install_path = ?;
+        ";
+        sqlx::query_as(query)
+            .bind(install_path.to_string_lossy().as_ref())
+            .fetch_optional(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error fetching install: {}", e))
+    }
+
+    pub async fn list_installs(&self) -> Result<Vec<Install>, sqlx::Error> {
+        let query = "
+            SELECT id, app_name, channel, platform, install_path, installed_version, verified_hash,
+                hash_algorithm, verified_at, last_patch_version, last_patched_at
+            FROM installs
+            ORDER BY app_name, channel, platform, install_path;
+        ";
+        sqlx::query_as(query)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing installs: {}", e))
+    }
+
+    /// Forget an install, returning whether it was registered. Its files are left untouched.
+    pub async fn remove_install(&self, install_path: &Path) -> Result<bool, sqlx::Error> {
+        let query = "
+            DELETE FROM installs
+            WHERE install_path = ?;
+        ";
+        sqlx::query(query)
+            .bind(install_path.to_string_lossy().as_ref())
+            .execute(&self.db_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+    }
+
+    /// Record the hash an install was found to have and the version it matches, if any.
+    pub async fn record_install_verification(
+        &self,
+        install_id: i64,
+        installed_version: Option<&str>,
+        verified_hash: &str,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(), sqlx::Error> {
+        let query = "
+            UPDATE installs
+            SET installed_version = ?, verified_hash = ?, hash_algorithm = ?,
+                verified_at = CURRENT_TIMESTAMP
+            WHERE id = ?;
+        ";
+        sqlx::query(query)
+            .bind(installed_version)
+            .bind(verified_hash)
+            .bind(hash_algorithm.to_string())
+            .bind(install_id)
+            .execute(&self.db_pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Record that a patch brought an install to the given version and hash.
+    pub async fn record_install_patch(
+        &self,
+        install_id: i64,
+        version: &str,
+        verified_hash: &str,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(), sqlx::Error> {
+        let query = "
+            UPDATE installs
+            SET installed_version = ?, verified_hash = ?, hash_algorithm = ?,
+                verified_at = CURRENT_TIMESTAMP, last_patch_version = ?,
+                last_patched_at = CURRENT_TIMESTAMP
+            WHERE id = ?;
+        ";
+        sqlx::query(query)
+            .bind(version)
+            .bind(verified_hash)
+            .bind(hash_algorithm.to_string())
+            .bind(version)
+            .bind(install_id)
+            .execute(&self.db_pool)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn upsert_file_index(&self, index: &FileIndex) -> Result<FileIndex, sqlx::Error> {
+        tracing::debug!(
+            "Upserting file index: app_id={}, file_path={}, file_type={}, hash_code={:?}, hash_algorithm={}, mode={:o}, modified_time={}",
+            index.app_id,
+            index.file_path,
+            index.file_type,
+            index.hash_code,
+            index.hash_algorithm,
+            index.mode,
+            index.modified_time
+        );
+        let mut tx = self.db_pool.begin().await?;
+        let file_index = sqlx::query_as(UPSERT_FILE_INDEX_QUERY)
+            .bind(index.app_id)
+            .bind(&index.file_path)
+            .bind(&index.file_type)
+            .bind(&index.hash_code)
+            .bind(index.hash_algorithm.to_string())
+            .bind(index.mode as i64)
+            .bind(index.modified_time)
+            .fetch_one(&mut *tx)
+            .await?;
+        // The content changed, its previous chunks are not valid anymore
+        sqlx::query(DELETE_FILE_CHUNKS_QUERY)
+            .bind(index.app_id)
+            .bind(&index.file_path)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(file_index)
+    }
+
+    /// Replace the chunks recorded for an indexed file.
+    pub async fn set_file_chunks(
         &self,
         app_id: i64,
         file_path: &str,
-        file_type: &str,
-        hash_code: &str,
-        modified_time: &NaiveDateTime,
-    ) -> Result<FileIndex, sqlx::Error> {
-        tracing::info!(
-            "Upserting file index: app_id={}, file_path={}, file_type={}, hash_code={}, modified_time={}",
-            app_id,
-            file_path,
-            file_type,
-            hash_code,
-            modified_time
-        );
+        chunks: &[FileChunk],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        replace_file_chunks(&mut tx, app_id, file_path, chunks).await?;
+        tx.commit().await
+    }
+
+    /// List the chunks of all indexed files of an application, in file order.
+    pub async fn list_file_chunks(&self, app_id: i64) -> Result<Vec<FileChunkIndex>, sqlx::Error> {
         let query = "
-            INSERT INTO file_index (app_id, file_path, file_type, hash_code, modified_time)
-            VALUES ($1, $2, $3, $4, $5)
-            ON CONFLICT (app_id, file_path) DO UPDATE
-            SET file_type = $3, hash_code = $4, modified_time = $5
-            RETURNING *;
+            SELECT app_id, file_path, chunk_index, chunk_offset, chunk_length, hash_code
+            FROM file_chunks
+            WHERE app_id = ?
+            ORDER BY file_path, chunk_index;
         ";
         sqlx::query_as(query)
             .bind(app_id)
-            .bind(file_path)
-            .bind(file_type)
-            .bind(hash_code)
-            .bind(modified_time)
-            .fetch_one(&self.db_pool)
+            .fetch_all(&self.db_pool)
             .await
+            .inspect_err(|e| tracing::info!("Error listing file chunks: {}", e))
     }
 
     pub async fn delete_file_index(
@@ -154,17 +1029,13 @@ impl PatcherDatabase {
         app_id: i64,
         file_path: &str,
     ) -> Result<bool, sqlx::Error> {
-        tracing::info!(
+        tracing::debug!(
             "Deleting file index: app_id={}, file_path={}",
             app_id,
             file_path
         );
 
-        let query = "
-            DELETE FROM file_index
-            WHERE app_id = ? AND file_path = ?;
-        ";
-        sqlx::query(query)
+        sqlx::query(DELETE_FILE_INDEX_QUERY)
             .bind(app_id)
             .bind(file_path)
             .execute(&self.db_pool)
@@ -178,7 +1049,7 @@ impl PatcherDatabase {
         file_path: &str,
     ) -> Result<Option<FileIndex>, sqlx::Error> {
         let query = "
-            SELECT app_id, file_path, file_type, hash_code, modified_time
+            SELECT app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time
             FROM file_index
             WHERE app_id = ? AND file_path = ?;
         ";
@@ -190,6 +1061,129 @@ impl PatcherDatabase {
             .inspect_err(|e| tracing::info!("Error fetching file index: {}", e))
     }
 
+    /// List all indexed files of an application.
+    pub async fn list_file_index(&self, app_id: i64) -> Result<Vec<FileIndex>, sqlx::Error> {
+        let query = "
+            SELECT app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time
+            FROM file_index
+            WHERE app_id = ?;
+        ";
+        sqlx::query_as(query)
+            .bind(app_id)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing file index: {}", e))
+    }
+
+    /// List the live index of an application, as last written by the watcher.
+    pub async fn list_live_index(&self, app_id: i64) -> Result<Vec<FileIndex>, sqlx::Error> {
+        let query = "
+            SELECT app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time
+            FROM live_index
+            WHERE app_id = ?;
+        ";
+        sqlx::query_as(query)
+            .bind(app_id)
+            .fetch_all(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error listing live index: {}", e))
+    }
+
+    /// Apply changes to the live index of an application and record its new tree hash.
+    pub async fn apply_live_index(
+        &self,
+        app_id: i64,
+        upserts: &[FileIndex],
+        deletes: &[String],
+        hash_code: &str,
+    ) -> Result<(), sqlx::Error> {
+        let upsert_query = "
+            INSERT INTO live_index
+                (app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (app_id, file_path) DO UPDATE
+            SET file_type = $3, hash_code = $4, hash_algorithm = $5, mode = $6,
+                modified_time = $7;
+        ";
+        let delete_query = "
+            DELETE FROM live_index
+            WHERE app_id = ? AND file_path = ?;
+        ";
+        let state_query = "
+            INSERT INTO live_state (app_id, hash_code, updated_at)
+            VALUES ($1, $2, CURRENT_TIMESTAMP)
+            ON CONFLICT (app_id) DO UPDATE
+            SET hash_code = $2, updated_at = CURRENT_TIMESTAMP;
+        ";
+        let mut tx = self.db_pool.begin().await?;
+        for index in upserts {
+            sqlx::query(upsert_query)
+                .bind(app_id)
+                .bind(&index.file_path)
+                .bind(&index.file_type)
+                .bind(&index.hash_code)
+                .bind(index.hash_algorithm.to_string())
+                .bind(index.mode as i64)
+                .bind(index.modified_time)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for file_path in deletes {
+            sqlx::query(delete_query)
+                .bind(app_id)
+                .bind(file_path)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(state_query)
+            .bind(app_id)
+            .bind(hash_code)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    /// Record that the watcher of an application is still alive.
+    pub async fn touch_live_state(&self, app_id: i64) -> Result<(), sqlx::Error> {
+        let query = "
+            UPDATE live_state
+            SET updated_at = CURRENT_TIMESTAMP
+            WHERE app_id = ?;
+        ";
+        sqlx::query(query)
+            .bind(app_id)
+            .execute(&self.db_pool)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn get_live_state(&self, app_id: i64) -> Result<Option<LiveState>, sqlx::Error> {
+        let query = "
+            SELECT app_id, hash_code, updated_at
+            FROM live_state
+            WHERE app_id = ?;
+        ";
+        sqlx::query_as(query)
+            .bind(app_id)
+            .fetch_optional(&self.db_pool)
+            .await
+            .inspect_err(|e| tracing::info!("Error fetching live state: {}", e))
+    }
+
+    /// Remove the live index of an application, once its watcher stops.
+    pub async fn clear_live_index(&self, app_id: i64) -> Result<(), sqlx::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        sqlx::query("DELETE FROM live_index WHERE app_id = ?;")
+            .bind(app_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM live_state WHERE app_id = ?;")
+            .bind(app_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
     pub async fn get_files_in_directory(
         &self,
         app_id: i64,
@@ -197,7 +1191,7 @@ impl PatcherDatabase {
     ) -> Result<Vec<FileIndex>, sqlx::Error> {
         let like_pattern = format!("{}/%", dir_path.trim_end_matches('/'));
         let query = "
-            SELECT app_id, file_path, file_type, hash_code, modified_time
+            SELECT app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time
             FROM file_index
             WHERE app_id = ? AND file_path LIKE ?;
         ";
@@ -209,3 +1203,114 @@ impl PatcherDatabase {
             .inspect_err(|e| tracing::info!("Error fetching files in directory: {}", e))
     }
 }
+
+/// Record the indexed tree of an application as the manifest of one of its versions.
+async fn record_manifest(
+    conn: &mut SqliteConnection,
+    app_id: i64,
+    version_id: i64,
+) -> Result<(), sqlx::Error> {
+    let source_path: String =
+        sqlx::query_scalar("SELECT source_path FROM applications WHERE id = ?;")
+            .bind(app_id)
+            .fetch_one(&mut *conn)
+            .await?;
+    let query = "
+        SELECT app_id, file_path, file_type, hash_code, hash_algorithm, mode, modified_time
+        FROM file_index
+        WHERE app_id = ?;
+    ";
+    let indexed: Vec<FileIndex> = sqlx::query_as(query)
+        .bind(app_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let query = "
+        INSERT INTO version_manifests (version_id, file_path, file_type, hash_code, mode)
+        VALUES (?, ?, ?, ?, ?)
+    ";
+    for index in indexed {
+        // The root is recorded as the version itself
+        let Ok(relative_path) = Path::new(&index.file_path).strip_prefix(&source_path) else {
+            continue;
+        };
+        if relative_path.as_os_str().is_empty() {
+            continue;
+        }
+        sqlx::query(query)
+            .bind(version_id)
+            .bind(relative_path.display().to_string())
+            .bind(&index.file_type)
+            .bind(&index.hash_code)
+            .bind(index.mode as i64)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+async fn apply_index_updates(
+    conn: &mut SqliteConnection,
+    updates: &[IndexUpdate],
+) -> Result<(), sqlx::Error> {
+    for update in updates {
+        match update {
+            IndexUpdate::Upsert(index) => {
+                sqlx::query(UPSERT_FILE_INDEX_QUERY)
+                    .bind(index.app_id)
+                    .bind(&index.file_path)
+                    .bind(&index.file_type)
+                    .bind(&index.hash_code)
+                    .bind(index.hash_algorithm.to_string())
+                    .bind(index.mode as i64)
+                    .bind(index.modified_time)
+                    .execute(&mut *conn)
+                    .await?;
+                sqlx::query(DELETE_FILE_CHUNKS_QUERY)
+                    .bind(index.app_id)
+                    .bind(&index.file_path)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            IndexUpdate::Delete { app_id, file_path } => {
+                sqlx::query(DELETE_FILE_INDEX_QUERY)
+                    .bind(app_id)
+                    .bind(file_path)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            IndexUpdate::Chunks {
+                app_id,
+                file_path,
+                chunks,
+            } => {
+                replace_file_chunks(conn, *app_id, file_path, chunks).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn replace_file_chunks(
+    conn: &mut SqliteConnection,
+    app_id: i64,
+    file_path: &str,
+    chunks: &[FileChunk],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(DELETE_FILE_CHUNKS_QUERY)
+        .bind(app_id)
+        .bind(file_path)
+        .execute(&mut *conn)
+        .await?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        sqlx::query(INSERT_FILE_CHUNK_QUERY)
+            .bind(app_id)
+            .bind(file_path)
+            .bind(i as i64)
+            .bind(chunk.offset as i64)
+            .bind(chunk.length as i64)
+            .bind(&chunk.hash_code)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
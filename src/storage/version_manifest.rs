@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, Permissions},
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+use crate::{
+    cancellation::CancellationToken,
+    indexer::{
+        file_change::{FileChange, FileChangeType},
+        hash_algorithm::HashAlgorithm,
+        tree_diff::TreeEntry,
+    },
+    storage::{blob_store::BlobStore, file_index::FileIndex},
+};
+
+/// A file or directory of a released version, recorded so that patches between any two
+/// released versions can be built later on.
+#[derive(Clone, Debug)]
+pub struct ManifestEntry {
+    pub version_id: i64,
+    // Path relative to the source directory of the application
+    pub file_path: String,
+    pub file_type: String,
+    pub hash_code: String,
+    pub mode: u32,
+}
+
+impl FromRow<'_, SqliteRow> for ManifestEntry {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(ManifestEntry {
+            version_id: row.try_get("version_id")?,
+            file_path: row.try_get("file_path")?,
+            file_type: row.try_get("file_type")?,
+            hash_code: row.try_get("hash_code")?,
+            mode: row.try_get::<i64, _>("mode")? as u32,
+        })
+    }
+}
+
+/// Entries of a manifest by relative path, as compared by `tree_diff::diff_entries`.
+pub fn manifest_tree(manifest: Vec<ManifestEntry>) -> BTreeMap<PathBuf, TreeEntry> {
+    manifest
+        .into_iter()
+        .map(|entry| {
+            let tree_entry = TreeEntry {
+                file_type: entry.file_type,
+                hash_code: Some(entry.hash_code),
+                mode: entry.mode,
+            };
+            (PathBuf::from(entry.file_path), tree_entry)
+        })
+        .collect()
+}
+
+/// Copy the content of every file of a new version into the snapshot store, given the index of
+/// the base version and the changes since then. Contents already in the store are not copied
+/// again, so only new contents are copied unless the store was just enabled. Returns the number
+/// of contents copied.
+pub fn store_snapshot(
+    snapshot_store: &BlobStore,
+    hash_algorithm: HashAlgorithm,
+    base_index: &[FileIndex],
+    changes: &[FileChange],
+    cancellation: &CancellationToken,
+) -> Result<usize, anyhow::Error> {
+    // Content hash of every file of the new version by path
+    let mut files: HashMap<&str, &str> = base_index
+        .iter()
+        .filter(|index| index.file_type == "FILE")
+        .filter_map(|index| Some((index.file_path.as_str(), index.hash_code.as_deref()?)))
+        .collect();
+    for change in changes.iter().filter(|change| change.file_type == "FILE") {
+        match &change.change_type {
+            FileChangeType::Deleted => {
+                files.remove(change.file_path.as_str());
+            }
+            FileChangeType::Renamed { from } => {
+                files.remove(from.as_str());
+            }
+            _ => {}
+        }
+        if change.change_type != FileChangeType::Deleted
+            && let Some(hash_code) = &change.hash_code
+        {
+            files.insert(&change.file_path, hash_code);
+        }
+    }
+
+    let mut stored = 0;
+    for (file_path, hash_code) in files {
+        cancellation.check()?;
+        if snapshot_store.put_file(hash_code, Path::new(file_path), hash_algorithm)? {
+            stored += 1;
+        }
+    }
+    Ok(stored)
+}
+
+/// Write the content of the given changes under `root` from the snapshot store, with their
+/// recorded mode, so that a patch can be built from them.
+pub fn restore_snapshot(
+    snapshot_store: &BlobStore,
+    root: &Path,
+    changes: &[FileChange],
+) -> Result<(), anyhow::Error> {
+    for change in changes {
+        let file_path = Path::new(&change.file_path);
+        // Checked before anything is created, directories included
+        let escapes_root = file_path
+            .components()
+            .any(|component| component == Component::ParentDir);
+        if escapes_root || !file_path.starts_with(root) {
+            return Err(anyhow::anyhow!(
+                "{} is outside of {}",
+                file_path.display(),
+                root.display()
+            ));
+        }
+        if change.file_type == "DIRECTORY" && change.change_type != FileChangeType::Deleted {
+            fs::create_dir_all(file_path)?;
+            continue;
+        }
+        if !change.has_content() {
+            continue;
+        }
+        let Some(hash_code) = &change.hash_code else {
+            continue;
+        };
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut blob = snapshot_store.open(hash_code).map_err(|e| {
+            anyhow::anyhow!(
+                "Content of {} is missing from the snapshot store: {}",
+                change.file_path,
+                e
+            )
+        })?;
+        io::copy(&mut blob, &mut File::create(file_path)?)?;
+        if let Some(mode) = change.mode {
+            fs::set_permissions(file_path, Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+    Ok(())
+}
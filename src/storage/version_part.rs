@@ -0,0 +1,21 @@
+use sqlx::{FromRow, Row, sqlite::SqliteRow};
+
+/// A file of the patch of a released version, recorded so that downloads can be verified.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionPart {
+    // Number of the part, starting from 1
+    pub part_number: i64,
+    pub size: i64,
+    // SHA-256 hash of the whole file
+    pub hash_code: String,
+}
+
+impl FromRow<'_, SqliteRow> for VersionPart {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(VersionPart {
+            part_number: row.try_get("part_number")?,
+            size: row.try_get("size")?,
+            hash_code: row.try_get("hash_code")?,
+        })
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    cmp::Ordering,
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use semver::Version;
+
+use crate::storage::app_version::AppVersion;
+
+/// How the versions of an application are validated and ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VersionScheme {
+    // Semantic versions, e.g. 1.2.0 or 2.0.0-beta.1
+    #[default]
+    Semver,
+    // Any text, versions are ordered by release
+    FreeForm,
+}
+
+impl VersionScheme {
+    /// Check that a version can be used with this scheme.
+    pub fn validate(&self, version: &str) -> Result<(), anyhow::Error> {
+        match self {
+            VersionScheme::Semver => Version::parse(version)
+                .map(|_| ())
+                .map_err(|e| anyhow!("Invalid semantic version {}: {}", version, e)),
+            VersionScheme::FreeForm if version.trim().is_empty() => {
+                Err(anyhow!("Version cannot be empty"))
+            }
+            VersionScheme::FreeForm => Ok(()),
+        }
+    }
+
+    /// Compare two versions, None if this scheme can't order them.
+    pub fn compare(&self, a: &str, b: &str) -> Option<Ordering> {
+        match self {
+            VersionScheme::Semver => Some(Version::parse(a).ok()?.cmp(&Version::parse(b).ok()?)),
+            VersionScheme::FreeForm => None,
+        }
+    }
+
+    /// Check that a new version comes after the current version of an application.
+    /// Free-form versions can't be ordered, they only have to be different.
+    pub fn check_increasing(&self, current: &str, new: &str) -> Result<(), anyhow::Error> {
+        self.validate(new)?;
+        match self.compare(current, new) {
+            Some(Ordering::Less) => Ok(()),
+            None if *self == VersionScheme::Semver => Err(anyhow!(
+                "Current version {} is not a semantic version, {} can't be ordered after it",
+                current,
+                new
+            )),
+            None if current != new => Ok(()),
+            _ => Err(anyhow!(
+                "Version {} is not greater than the current version {}",
+                new,
+                current
+            )),
+        }
+    }
+
+    /// Sort versions in version order, keeping the release order of the ones that compare equal.
+    /// Versions recorded before the scheme was enforced come first.
+    pub fn sort_versions(&self, versions: &mut [AppVersion]) {
+        if *self == VersionScheme::Semver {
+            versions.sort_by_cached_key(|version| Version::parse(&version.version).ok());
+        }
+    }
+}
+
+impl Display for VersionScheme {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionScheme::Semver => write!(f, "semver"),
+            VersionScheme::FreeForm => write!(f, "free-form"),
+        }
+    }
+}
+
+impl FromStr for VersionScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "semver" => Ok(VersionScheme::Semver),
+            "free-form" | "freeform" => Ok(VersionScheme::FreeForm),
+            _ => Err(anyhow!("Unsupported version scheme: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VersionScheme;
+
+    #[test]
+    fn check_increasing_test() {
+        let semver = VersionScheme::Semver;
+        assert!(semver.check_increasing("1.2.0", "1.10.0").is_ok());
+        assert!(semver.check_increasing("1.0.0-beta.2", "1.0.0").is_ok());
+        assert!(semver.check_increasing("1.2.0", "1.2.0").is_err());
+        assert!(semver.check_increasing("1.10.0", "1.9.0").is_err());
+        // The order can't be checked against a current version that isn't semantic
+        assert!(semver.check_increasing("release-1", "1.0.0").is_err());
+        assert!(semver.validate("1.2").is_err());
+        assert!(semver.validate("2.0.0-rc.1").is_ok());
+
+        let free_form = VersionScheme::FreeForm;
+        assert!(free_form.validate("2024 spring").is_ok());
+        assert!(free_form.check_increasing("b", "a").is_ok());
+        assert!(free_form.check_increasing("a", "a").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use reqwest::Url;
+use secret_online_patcher::{
+    client::downloader::{Downloader, ExpectedFile, RetryPolicy, partial_path},
+    indexer::hash_algorithm::HashAlgorithm,
+};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+
+use crate::common::test_util::{incompressible_bytes, initialize_test_dir};
+
+/// A file server that records the ranges it was asked for and can fail the first requests.
+struct TestServer {
+    content: Vec<u8>,
+    ranges: Mutex<Vec<Option<String>>>,
+    failures: AtomicUsize,
+}
+
+async fn serve_content(State(server): State<Arc<TestServer>>, headers: HeaderMap) -> Response {
+    let range = headers
+        .get(header::RANGE)
+        .map(|range| range.to_str().unwrap().to_string());
+    server.ranges.lock().unwrap().push(range.clone());
+    if server.failures.load(Ordering::SeqCst) > 0 {
+        server.failures.fetch_sub(1, Ordering::SeqCst);
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    match range {
+        Some(range) => {
+            let start: usize = range
+                .trim_start_matches("bytes=")
+                .trim_end_matches('-')
+                .parse()
+                .unwrap();
+            if start >= server.content.len() {
+                return StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+            }
+            (
+                StatusCode::PARTIAL_CONTENT,
+                server.content[start..].to_vec(),
+            )
+                .into_response()
+        }
+        None => server.content.clone().into_response(),
+    }
+}
+
+async fn missing(State(server): State<Arc<TestServer>>) -> StatusCode {
+    server.ranges.lock().unwrap().push(None);
+    StatusCode::NOT_FOUND
+}
+
+async fn start_server(content: Vec<u8>, failures: usize) -> (Arc<TestServer>, Url) {
+    let server = Arc::new(TestServer {
+        content,
+        ranges: Mutex::new(Vec::new()),
+        failures: AtomicUsize::new(failures),
+    });
+    let router = Router::new()
+        .route("/file", get(serve_content))
+        .route("/missing", get(missing))
+        .with_state(server.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    (server, url)
+}
+
+fn expected_file(content: &[u8]) -> ExpectedFile {
+    ExpectedFile {
+        size: Some(content.len() as u64),
+        hash_code: Some(base16ct::lower::encode_string(&Sha256::digest(content))),
+        hash_algorithm: HashAlgorithm::Sha256,
+    }
+}
+
+fn downloader(max_attempts: u32) -> Downloader {
+    Downloader::new(RetryPolicy::new(
+        max_attempts,
+        Duration::from_millis(1),
+        Duration::from_millis(10),
+    ))
+}
+
+#[tokio::test]
+async fn download_resumes_partial_file() {
+    let test_dir = initialize_test_dir("download_resumes_partial_file");
+    let content = incompressible_bytes(10_000, 1);
+    let (server, url) = start_server(content.clone(), 0).await;
+    let destination = PathBuf::from(format!("{}/file.bin", test_dir));
+    fs::write(partial_path(&destination), &content[..4_000]).unwrap();
+
+    downloader(3)
+        .download(
+            url.join("file").unwrap(),
+            &destination,
+            &expected_file(&content),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fs::read(&destination).unwrap(), content);
+    assert!(!partial_path(&destination).exists());
+    assert_eq!(
+        *server.ranges.lock().unwrap(),
+        vec![Some("bytes=4000-".to_string())]
+    );
+
+    // A verified file is not downloaded again
+    downloader(3)
+        .download(
+            url.join("file").unwrap(),
+            &destination,
+            &expected_file(&content),
+        )
+        .await
+        .unwrap();
+    assert_eq!(server.ranges.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn download_restarts_corrupted_partial_file() {
+    let test_dir = initialize_test_dir("download_restarts_corrupted_partial_file");
+    let content = incompressible_bytes(10_000, 2);
+    let (server, url) = start_server(content.clone(), 0).await;
+    let destination = PathBuf::from(format!("{}/file.bin", test_dir));
+    fs::write(partial_path(&destination), vec![0u8; 4_000]).unwrap();
+
+    downloader(3)
+        .download(
+            url.join("file").unwrap(),
+            &destination,
+            &expected_file(&content),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(fs::read(&destination).unwrap(), content);
+    // The resumed file fails verification and is downloaded again from the start
+    assert_eq!(
+        *server.ranges.lock().unwrap(),
+        vec![Some("bytes=4000-".to_string()), None]
+    );
+}
+
+#[tokio::test]
+async fn download_retries_server_errors() {
+    let test_dir = initialize_test_dir("download_retries_server_errors");
+    let content = incompressible_bytes(1_000, 3);
+    let destination = PathBuf::from(format!("{}/file.bin", test_dir));
+
+    let (server, url) = start_server(content.clone(), 2).await;
+    downloader(3)
+        .download(
+            url.join("file").unwrap(),
+            &destination,
+            &expected_file(&content),
+        )
+        .await
+        .unwrap();
+    assert_eq!(fs::read(&destination).unwrap(), content);
+    assert_eq!(server.ranges.lock().unwrap().len(), 3);
+
+    fs::remove_file(&destination).unwrap();
+    let (server, url) = start_server(content.clone(), 2).await;
+    let result = downloader(2)
+        .download(
+            url.join("file").unwrap(),
+            &destination,
+            &expected_file(&content),
+        )
+        .await;
+    assert!(result.is_err());
+    assert!(!destination.exists());
+    assert_eq!(server.ranges.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn download_does_not_retry_missing_file() {
+    let test_dir = initialize_test_dir("download_does_not_retry_missing_file");
+    let (server, url) = start_server(Vec::new(), 0).await;
+    let destination = PathBuf::from(format!("{}/file.bin", test_dir));
+
+    let result = downloader(5)
+        .download(
+            url.join("missing").unwrap(),
+            &destination,
+            &ExpectedFile::default(),
+        )
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(server.ranges.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use secret_online_patcher::{
+    cli,
+    client::install_repairer::{InstallRepairer, RepairReport},
+    service::app_manager::AppManager,
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        patch_config::PatchConfig,
+        patch_reader::PatchReader,
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+
+use crate::common::test_util::{
+    copy_dir, incompressible_bytes, initialize_test_db, initialize_test_dir,
+};
+
+#[sqlx::test]
+async fn repair_restores_only_damaged_files(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("repair_restores_only_damaged_files");
+    let publisher_dir = PathBuf::from(format!("{}/publisher", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(publisher_dir.join("assets/empty")).unwrap();
+    fs::write(publisher_dir.join("keep.txt"), "Unchanged").unwrap();
+    fs::write(publisher_dir.join("config.txt"), "volume = 5").unwrap();
+    fs::write(publisher_dir.join("assets/music.ogg"), "Music").unwrap();
+    let archive = incompressible_bytes(16 * 1024, 11);
+    fs::write(publisher_dir.join("data.pak"), &archive).unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Repair App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &publisher_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    let package_path = cli::package_app(
+        "Repair App",
+        &AppTarget::default(),
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+    copy_dir(&publisher_dir, &install_dir);
+
+    // Damage the install, the player also owns a few files
+    fs::remove_file(install_dir.join("keep.txt")).unwrap();
+    fs::remove_dir_all(install_dir.join("assets")).unwrap();
+    let mut damaged_archive = archive.clone();
+    damaged_archive[100..200].copy_from_slice(&[0; 100]);
+    fs::write(install_dir.join("data.pak"), &damaged_archive).unwrap();
+    fs::write(install_dir.join("cheat.dll"), "Extra").unwrap();
+    fs::create_dir_all(install_dir.join("mods/old")).unwrap();
+    fs::write(install_dir.join("mods/old/mod.txt"), "Extra").unwrap();
+    fs::write(
+        install_dir.join(".patcherignore"),
+        "# Player files\nsaves/\n*.log\n/config.txt\n",
+    )
+    .unwrap();
+    fs::create_dir_all(install_dir.join("saves")).unwrap();
+    fs::write(install_dir.join("saves/slot1.dat"), "Save").unwrap();
+    fs::write(install_dir.join("game.log"), "Log").unwrap();
+    fs::write(install_dir.join("config.txt"), "volume = 9").unwrap();
+
+    let reader = PatchReader::new(&package_path);
+    let repairer = InstallRepairer::new(&install_dir).unwrap();
+    let expected_report = RepairReport {
+        missing: vec![
+            PathBuf::from("assets"),
+            PathBuf::from("assets/empty"),
+            PathBuf::from("assets/music.ogg"),
+            PathBuf::from("keep.txt"),
+        ],
+        extra: vec![
+            PathBuf::from("cheat.dll"),
+            PathBuf::from("mods"),
+            PathBuf::from("mods/old"),
+            PathBuf::from("mods/old/mod.txt"),
+        ],
+        corrupted: vec![PathBuf::from("data.pak")],
+    };
+    assert_eq!(repairer.scan(&reader).await.unwrap(), expected_report);
+
+    let report = repairer.repair(&reader).await.unwrap();
+    assert_eq!(report, expected_report);
+    assert!(repairer.scan(&reader).await.unwrap().is_clean());
+    assert_eq!(
+        fs::read_to_string(install_dir.join("keep.txt")).unwrap(),
+        "Unchanged"
+    );
+    assert_eq!(
+        fs::read_to_string(install_dir.join("assets/music.ogg")).unwrap(),
+        "Music"
+    );
+    assert!(install_dir.join("assets/empty").is_dir());
+    assert_eq!(fs::read(install_dir.join("data.pak")).unwrap(), archive);
+    assert!(!install_dir.join("cheat.dll").exists());
+    assert!(!install_dir.join("mods").exists());
+
+    // Files owned by the player are left alone
+    assert!(install_dir.join(".patcherignore").exists());
+    assert_eq!(
+        fs::read_to_string(install_dir.join("saves/slot1.dat")).unwrap(),
+        "Save"
+    );
+    assert!(install_dir.join("game.log").exists());
+    assert_eq!(
+        fs::read_to_string(install_dir.join("config.txt")).unwrap(),
+        "volume = 9"
+    );
+}
+
+#[sqlx::test]
+async fn repair_requires_full_package(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("repair_requires_full_package");
+    let publisher_dir = PathBuf::from(format!("{}/publisher", test_dir));
+    fs::create_dir_all(&publisher_dir).unwrap();
+    fs::write(publisher_dir.join("file.txt"), "Version 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Repair Update App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &publisher_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    fs::write(publisher_dir.join("file.txt"), "Version 2").unwrap();
+    cli::update_app(
+        "Repair Update App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        None,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+    let update_path = Path::new("fs_tests/patches/stable/Repair_Update_App_1.0.1_update.zip");
+
+    let repairer = InstallRepairer::new(&publisher_dir).unwrap();
+    assert!(repairer.scan(&PatchReader::new(update_path)).await.is_err());
+
+    // The install has to match the recorded version to be packaged
+    fs::write(publisher_dir.join("file.txt"), "Version 3").unwrap();
+    assert!(
+        cli::package_app(
+            "Repair Update App",
+            &AppTarget::default(),
+            &PatchConfig::default(),
+            &db
+        )
+        .await
+        .is_err()
+    );
+}
@@ -0,0 +1,3 @@
+mod downloader_test;
+mod install_repairer_test;
+mod update_client_test;
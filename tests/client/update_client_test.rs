@@ -0,0 +1,374 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use secret_online_patcher::{
+    cli,
+    client::update_client::{UpdateClient, hash_install},
+    indexer::{chunking::ChunkingConfig, hash_algorithm::HashAlgorithm},
+    server::patch_server::PatchServer,
+    service::app_manager::AppManager,
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        blob_store::BlobStore,
+        patch_compression::PatchCompression,
+        patch_config::PatchConfig,
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+use tokio::net::TcpListener;
+
+use crate::common::test_util::{
+    copy_dir, incompressible_bytes, initialize_test_db, initialize_test_dir,
+};
+
+#[sqlx::test]
+async fn update_client_applies_patch_chain(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("update_client_applies_patch_chain");
+    let publisher_dir = PathBuf::from(format!("{}/publisher", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    let cache_dir = PathBuf::from(format!("{}/downloads", test_dir));
+    fs::create_dir_all(publisher_dir.join("assets")).unwrap();
+    fs::write(publisher_dir.join("keep.txt"), "Unchanged").unwrap();
+    fs::write(publisher_dir.join("config.txt"), "version = 1").unwrap();
+    fs::write(publisher_dir.join("obsolete.txt"), "Removed in 1.0.1").unwrap();
+    fs::write(publisher_dir.join("assets/music.ogg"), "Moved in 1.0.1").unwrap();
+    let archive = incompressible_bytes(64 * 1024, 7);
+    fs::write(publisher_dir.join("data.pak"), &archive).unwrap();
+
+    // Publish the initial version, the player installs a copy of it
+    let db = initialize_test_db(&db_pool).await;
+    let chunking = Some(ChunkingConfig::new(1024, 64, 256, 1024).unwrap());
+    AppManager::new(db.clone())
+        .create_application(
+            "Client App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &publisher_dir,
+            chunking,
+        )
+        .await
+        .unwrap();
+    copy_dir(&publisher_dir, &install_dir);
+
+    // Publish two updates
+    fs::write(publisher_dir.join("config.txt"), "version = 2").unwrap();
+    fs::remove_file(publisher_dir.join("obsolete.txt")).unwrap();
+    fs::create_dir_all(publisher_dir.join("audio")).unwrap();
+    fs::rename(
+        publisher_dir.join("assets/music.ogg"),
+        publisher_dir.join("audio/music.ogg"),
+    )
+    .unwrap();
+    let mut edited_archive = archive.clone();
+    edited_archive[30_000..30_100].copy_from_slice(&[0xAB; 100]);
+    fs::write(publisher_dir.join("data.pak"), &edited_archive).unwrap();
+    let patch_config = PatchConfig::default();
+    cli::update_app(
+        "Client App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        chunking,
+        &patch_config,
+        &db,
+    )
+    .await
+    .unwrap();
+
+    fs::write(publisher_dir.join("config.txt"), "version = 3").unwrap();
+    fs::write(publisher_dir.join("audio/voice.ogg"), "Added in 1.0.2").unwrap();
+    // Modes are part of the tree hash, a file only made executable is shipped again
+    fs::write(publisher_dir.join("launch.sh"), "#!/bin/sh").unwrap();
+    for file in ["launch.sh", "keep.txt"] {
+        fs::set_permissions(publisher_dir.join(file), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    cli::update_app(
+        "Client App",
+        &AppTarget::default(),
+        "1.0.2",
+        false,
+        chunking,
+        &patch_config,
+        &db,
+    )
+    .await
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let client = UpdateClient::new(&url, &cache_dir).unwrap();
+    // Parts are published with their hash so that downloads can be verified
+    let versions = client.client.list_versions("Client App").await.unwrap();
+    assert!(
+        versions
+            .iter()
+            .flat_map(|version| &version.parts)
+            .all(|part| part.hash_code.is_some())
+    );
+    let outcome = client.update("Client App", &install_dir).await.unwrap();
+    assert!(outcome.updated);
+    assert_eq!(outcome.version, "1.0.2");
+    assert_eq!(outcome.hash_algorithm, HashAlgorithm::Sha256);
+    assert_eq!(
+        outcome.hash_code,
+        hash_install(&publisher_dir, HashAlgorithm::Sha256)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        hash_install(&install_dir, HashAlgorithm::Sha256)
+            .await
+            .unwrap(),
+        hash_install(&publisher_dir, HashAlgorithm::Sha256)
+            .await
+            .unwrap()
+    );
+    assert_same_files(&publisher_dir, &install_dir);
+    assert!(!install_dir.join("obsolete.txt").exists());
+    assert!(!install_dir.join("assets/music.ogg").exists());
+    for file in ["launch.sh", "keep.txt"] {
+        let mode = fs::metadata(install_dir.join(file))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111, "{}", file);
+    }
+
+    // Nothing left to do
+    let outcome = client.update("Client App", &install_dir).await.unwrap();
+    assert!(!outcome.updated);
+    assert_eq!(outcome.version, "1.0.2");
+}
+
+#[sqlx::test]
+async fn update_client_rejects_unknown_install(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("update_client_rejects_unknown_install");
+    let publisher_dir = PathBuf::from(format!("{}/publisher", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(&publisher_dir).unwrap();
+    fs::write(publisher_dir.join("file.txt"), "Published").unwrap();
+    fs::create_dir_all(&install_dir).unwrap();
+    fs::write(install_dir.join("file.txt"), "Locally modified").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Unknown Install App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &publisher_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let client = UpdateClient::new(&url, &Path::new(&test_dir).join("downloads")).unwrap();
+    let result = client.update("Unknown Install App", &install_dir).await;
+    assert!(result.is_err());
+    assert_eq!(
+        fs::read_to_string(install_dir.join("file.txt")).unwrap(),
+        "Locally modified"
+    );
+}
+
+#[sqlx::test]
+async fn update_client_uses_app_hash_algorithm(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("update_client_uses_app_hash_algorithm");
+    let publisher_dir = PathBuf::from(format!("{}/publisher", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    let cache_dir = PathBuf::from(format!("{}/downloads", test_dir));
+    fs::create_dir_all(publisher_dir.join("data")).unwrap();
+    fs::write(publisher_dir.join("data/level.txt"), "Level 1").unwrap();
+    fs::write(publisher_dir.join("readme.txt"), "Version 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let chunking = Some(ChunkingConfig::new(1024, 64, 256, 1024).unwrap());
+    AppManager::new(db.clone())
+        .create_application(
+            "Xxh3 App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver).with_hash_algorithm(HashAlgorithm::Xxh3),
+            &publisher_dir,
+            chunking,
+        )
+        .await
+        .unwrap();
+    copy_dir(&publisher_dir, &install_dir);
+
+    fs::write(publisher_dir.join("readme.txt"), "Version 2").unwrap();
+    fs::write(
+        publisher_dir.join("data/music.ogg"),
+        incompressible_bytes(16 * 1024, 3),
+    )
+    .unwrap();
+    cli::update_app(
+        "Xxh3 App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        chunking,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+
+    // Every hash of the application is an XXH3 hash
+    let app = db
+        .get_application("Xxh3 App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(app.hash_algorithm, HashAlgorithm::Xxh3);
+    let xxh3_hash = hash_install(&publisher_dir, HashAlgorithm::Xxh3)
+        .await
+        .unwrap();
+    assert_eq!(app.hash_code.as_deref(), Some(xxh3_hash.as_str()));
+    assert_eq!(xxh3_hash.len(), 32);
+    let index = db.list_file_index(app.id).await.unwrap();
+    assert!(!index.is_empty());
+    assert!(index.iter().all(|index| {
+        index.hash_algorithm == HashAlgorithm::Xxh3
+            && index
+                .hash_code
+                .as_ref()
+                .is_some_and(|hash| hash.len() == 32)
+    }));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let client = UpdateClient::new(&url, &cache_dir).unwrap();
+    let latest = client.client.latest_version("Xxh3 App").await.unwrap();
+    assert_eq!(latest.hash_algorithm, HashAlgorithm::Xxh3);
+    let outcome = client.update("Xxh3 App", &install_dir).await.unwrap();
+    assert!(outcome.updated);
+    assert_eq!(outcome.version, "1.0.1");
+    assert_eq!(
+        hash_install(&install_dir, HashAlgorithm::Xxh3)
+            .await
+            .unwrap(),
+        xxh3_hash
+    );
+    assert_same_files(&publisher_dir, &install_dir);
+    // The same tree has another hash with another algorithm
+    assert_ne!(
+        hash_install(&install_dir, HashAlgorithm::Blake3)
+            .await
+            .unwrap(),
+        xxh3_hash
+    );
+}
+
+#[sqlx::test]
+async fn update_client_downloads_blake3_blobs(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("update_client_downloads_blake3_blobs");
+    let publisher_dir = PathBuf::from(format!("{}/publisher", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    let cache_dir = PathBuf::from(format!("{}/downloads", test_dir));
+    let blob_store = BlobStore::new(Path::new(&format!("{}/blobs", test_dir)));
+    fs::create_dir_all(publisher_dir.join("data")).unwrap();
+    fs::write(publisher_dir.join("data/level.txt"), "Level 1").unwrap();
+    fs::write(publisher_dir.join("readme.txt"), "Version 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Blake3 App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver).with_hash_algorithm(HashAlgorithm::Blake3),
+            &publisher_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    copy_dir(&publisher_dir, &install_dir);
+
+    fs::write(publisher_dir.join("readme.txt"), "Version 2").unwrap();
+    fs::write(publisher_dir.join("data/level.txt"), "Level 2").unwrap();
+    let patch_config = PatchConfig::new(
+        PatchCompression::default(),
+        true,
+        None,
+        Some(blob_store.clone()),
+    );
+    cli::update_app(
+        "Blake3 App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        None,
+        &patch_config,
+        &db,
+    )
+    .await
+    .unwrap();
+    // Blobs are named after their BLAKE3 hash
+    let blobs = blob_store.list_blobs().unwrap();
+    assert!(blobs.contains(&HashAlgorithm::Blake3.digest(b"Version 2")));
+    assert!(blobs.contains(&HashAlgorithm::Blake3.digest(b"Level 2")));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), Some(blob_store));
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let client = UpdateClient::new(&url, &cache_dir).unwrap();
+    let outcome = client.update("Blake3 App", &install_dir).await.unwrap();
+    assert!(outcome.updated);
+    assert_eq!(outcome.version, "1.0.1");
+    assert_same_files(&publisher_dir, &install_dir);
+    assert_eq!(
+        hash_install(&install_dir, HashAlgorithm::Blake3)
+            .await
+            .unwrap(),
+        hash_install(&publisher_dir, HashAlgorithm::Blake3)
+            .await
+            .unwrap()
+    );
+}
+
+fn assert_same_files(expected: &Path, actual: &Path) {
+    let mut expected_entries: Vec<_> = fs::read_dir(expected)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    let mut actual_entries: Vec<_> = fs::read_dir(actual)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    expected_entries.sort();
+    actual_entries.sort();
+    assert_eq!(expected_entries, actual_entries);
+    for name in expected_entries {
+        let expected_path = expected.join(&name);
+        let actual_path = actual.join(&name);
+        if expected_path.is_dir() {
+            assert_same_files(&expected_path, &actual_path);
+        } else {
+            assert_eq!(
+                fs::read(&expected_path).unwrap(),
+                fs::read(&actual_path).unwrap(),
+                "{}",
+                actual_path.display()
+            );
+        }
+    }
+}
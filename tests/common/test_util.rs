@@ -1,6 +1,14 @@
-use std::{fs, path::Path};
+use std::{fs, path::Path, sync::Mutex};
 
-use secret_online_patcher::storage::{application_data::Application, patcher_db::PatcherDatabase};
+use secret_online_patcher::{
+    cancellation::CancellationToken,
+    progress::{Progress, ProgressReporter},
+    storage::{
+        application_data::{AppSettings, AppTarget, Application},
+        patcher_db::PatcherDatabase,
+        version_scheme::VersionScheme,
+    },
+};
 use sqlx::SqlitePool;
 
 pub fn initialize_test_dir(test_name: &str) -> String {
@@ -18,13 +26,77 @@ pub fn initialize_test_dir(test_name: &str) -> String {
 
 pub async fn initialize_test_db(db_pool: &SqlitePool) -> PatcherDatabase {
     let db = PatcherDatabase::new(db_pool.clone());
-    db.initialize().await;
+    db.initialize().await.unwrap();
     db
 }
 
 pub async fn initialize_test_app(app_path: &str, db: &PatcherDatabase) -> Application {
     // Initialise application in the database
-    db.add_application("Test App", "0.0.1", Path::new(app_path))
-        .await
-        .unwrap()
+    db.add_application(
+        "Test App",
+        &AppTarget::default(),
+        "0.0.1",
+        AppSettings::new(VersionScheme::Semver),
+        Path::new(app_path),
+    )
+    .await
+    .unwrap()
+}
+
+/// Generate bytes that don't compress, like the content of media files.
+pub fn incompressible_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state: u64 = 0x2545f4914f6cdd1d ^ seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+/// Copy a directory recursively.
+pub fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+/// Progress reporter keeping every update, and the final progress once finished.
+#[derive(Default)]
+pub struct RecordingReporter {
+    pub updates: Mutex<Vec<Progress>>,
+    pub finished: Mutex<Option<Progress>>,
+}
+
+impl ProgressReporter for RecordingReporter {
+    fn on_progress(&self, progress: &Progress) {
+        self.updates.lock().unwrap().push(progress.clone());
+    }
+
+    fn on_finish(&self, progress: &Progress) {
+        self.finished.lock().unwrap().replace(progress.clone());
+    }
+}
+
+/// Progress reporter cancelling the token once the given number of files were scanned.
+pub struct CancelAfter {
+    pub files: u64,
+    pub cancellation: CancellationToken,
+}
+
+impl ProgressReporter for CancelAfter {
+    fn on_progress(&self, progress: &Progress) {
+        if progress.files_scanned >= self.files {
+            self.cancellation.cancel();
+        }
+    }
 }
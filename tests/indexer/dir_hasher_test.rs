@@ -1,15 +1,30 @@
-use std::{fs, path::Path};
+use std::{fs, path::Path, sync::Arc};
 
-use secret_online_patcher::indexer::{
-    dir_hasher::DirHasher,
-    file_change::{FileChange, FileChangeType},
-    indexer_config::IndexerConfig,
+use secret_online_patcher::{
+    cancellation::{CancellationToken, Cancelled},
+    client::update_client::hash_install,
+    indexer::{
+        dir_hasher::DirHasher,
+        file_change::{FileChange, FileChangeType},
+        hash_algorithm::HashAlgorithm,
+        indexer_config::IndexerConfig,
+        staged_index::StagedIndex,
+    },
+    progress::ProgressStage,
+    storage::{
+        app_version::Release,
+        application_data::AppTarget,
+        index_store::{IndexStore, MemoryIndexStore},
+    },
 };
 use sqlx::SqlitePool;
 
 use crate::common::{
     db_util::verify_index,
-    test_util::{initialize_test_app, initialize_test_db, initialize_test_dir},
+    test_util::{
+        CancelAfter, RecordingReporter, initialize_test_app, initialize_test_db,
+        initialize_test_dir,
+    },
 };
 
 fn verify_change(expected_file: &str, expected_type: FileChangeType, changed_files: &[FileChange]) {
@@ -40,11 +55,11 @@ async fn dir_hasher_with_new_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "2ab14938127707cd534778654ef4d4400f9e26571acfe316074ead23155c734b"
+        "84b96828c1b5a80cffe9485b36671ec1638fc51199435ebfdef645f7312f5100"
     );
     assert_eq!(changed_files.len(), 4);
     verify_change(&outer_file, FileChangeType::Created, &changed_files);
@@ -66,7 +81,7 @@ async fn dir_hasher_with_new_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir,
         true,
-        Some("b8bdf07b28bdfe1bb646d7680c762988efa523e1bad0f442b1dda1f11ca4b405"),
+        Some("89c7d7c89f9195866bd09046f9dee10482fe80835d65e20cf2506b8148c2e040"),
         &db,
     )
     .await;
@@ -100,11 +115,11 @@ async fn dir_hasher_with_empty_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        "1909d14c5433cab5a932f3a18885f08731d919adfb3f9686eec0ad068274965f"
     );
     assert_eq!(changed_files.len(), 0);
 
@@ -134,11 +149,11 @@ async fn dir_hasher_with_modified_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "2ab14938127707cd534778654ef4d4400f9e26571acfe316074ead23155c734b"
+        "84b96828c1b5a80cffe9485b36671ec1638fc51199435ebfdef645f7312f5100"
     );
     assert_eq!(changed_files.len(), 4);
 
@@ -158,11 +173,11 @@ async fn dir_hasher_with_modified_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "dd00581998351b5602b046662cc4f9a32d2c9846d4134dfb981e9055b0184785"
+        "9c29af0368a774ff0362eb9f22e89c9652b17a07400f429387bf1ee560117a7f"
     );
     assert_eq!(changed_files.len(), 6);
     verify_change(&outer_file, FileChangeType::Modified, &changed_files);
@@ -187,7 +202,7 @@ async fn dir_hasher_with_modified_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir,
         true,
-        Some("7610896fb921054ec3d0cdc3ca737fcd7b06caab41a093ba7a2a2720c1633b82"),
+        Some("4298fa4d5698bd0ccc3af64040fa6f830c4409acbd1f0d209f2aeb20103a580c"),
         &db,
     )
     .await;
@@ -212,7 +227,7 @@ async fn dir_hasher_with_modified_dir(db_pool: SqlitePool) {
         app.id,
         &new_subdir,
         true,
-        Some("3ec975592ead4b395aad08b0fbddde83eda0d4f3fc2a279ae4b0a47345a0d7a5"),
+        Some("bf8fb98cf4f7f7d7b9d4de037184430e622a46bc2515ad5040d1325e3db5e4cb"),
         &db,
     )
     .await;
@@ -248,11 +263,11 @@ async fn dir_hasher_with_modified_nested_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "688540ba952dec4d91cd29a8ba08c23e7d6ea9a607d94a4d2ca535428c8db6b1"
+        "91c49145ac37efd0f19c46c70dccd9cbaba539880c647820586be80ae1258775"
     );
     assert_eq!(changed_files.len(), 4);
     verify_change(&outer_file, FileChangeType::Created, &changed_files);
@@ -273,11 +288,11 @@ async fn dir_hasher_with_modified_nested_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "baf9ebe6389e9b7d222c44277100b9521d5caf42a04fea2bd0aa8f34f154dc21"
+        "1be364b4f2b18c68d07435d87faf09c65a7542f80c321fc9a66cdcc179afe566"
     );
     assert_eq!(changed_files.len(), 5);
     verify_change(&sub_dir_level1, FileChangeType::Modified, &changed_files);
@@ -301,7 +316,7 @@ async fn dir_hasher_with_modified_nested_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir_level1,
         true,
-        Some("1d38ab6eb27461f90ef7b0298eb6fb98c2cec9c23c879646b90553ac4db15808"),
+        Some("5ff398f9d0a4d47074938642aff56440d5ac5ef4ba62c521a83d6925142c9caf"),
         &db,
     )
     .await;
@@ -309,7 +324,7 @@ async fn dir_hasher_with_modified_nested_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir_level2,
         true,
-        Some("a3411127799065ee3b76db6ca685e4a701f377d5563a324c86af7df0c2df2197"),
+        Some("e146703e4a5a3738c56594b22b5d66a8d64813334964d833563a98b07428d80f"),
         &db,
     )
     .await;
@@ -325,7 +340,7 @@ async fn dir_hasher_with_modified_nested_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir_level3,
         true,
-        Some("d55f9557d85969b84af7aa2f9a7e64e280e8fbdf2e6961b0e4b1176e578afbba"),
+        Some("db5dac0273d805b3ff269b074fa1bfbff12a0160228c824148e714df21acd39f"),
         &db,
     )
     .await;
@@ -359,11 +374,11 @@ async fn dir_hasher_with_modified_nested_empty_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "35b230e403816a4c075d1dd30dc619311299688ae28136e23707992b2c3a2e69"
+        "6bb33431503463a06900a974ebe87449e1f6b7a733c8eefca1e7d14746aad8e0"
     );
     assert_eq!(changed_files.len(), 3);
     verify_change(&sub_dir_level1, FileChangeType::Created, &changed_files);
@@ -378,11 +393,11 @@ async fn dir_hasher_with_modified_nested_empty_dir(db_pool: SqlitePool) {
         .dir_hash(&Path::new(&test_dir).to_path_buf())
         .await
         .expect("failed to hash directory");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
-        "e67e72111b363d80c8124d28193926000980e1211c7986cacbd26aacc5528d48"
+        "53f1f6d48e08bf56a10b9177b5db81fe9f0fd2d4d6a9bf6b9fe3303e3578dfa9"
     );
     assert_eq!(changed_files.len(), 3);
     verify_change(&sub_dir_level1, FileChangeType::Modified, &changed_files);
@@ -395,7 +410,7 @@ async fn dir_hasher_with_modified_nested_empty_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir_level1,
         true,
-        Some("cd372fb85148700fa88095e3492d3f9f5beb43e555e5ff26d95f5a6adc36f8e6"),
+        Some("c226885a453ae0038468c42ab76fd390c8caaafa2ddb633489fc300c1b1a85b4"),
         &db,
     )
     .await;
@@ -403,9 +418,295 @@ async fn dir_hasher_with_modified_nested_empty_dir(db_pool: SqlitePool) {
         app.id,
         &sub_dir_level2,
         true,
-        Some("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        Some("1909d14c5433cab5a932f3a18885f08731d919adfb3f9686eec0ad068274965f"),
         &db,
     )
     .await;
     verify_index(app.id, &inner_file, false, None, &db).await;
 }
+
+#[sqlx::test]
+async fn dir_hasher_with_staged_index(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("dir_hasher_with_staged_index");
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&test_dir, &db).await;
+
+    let outer_file = format!("{}/outer_file1.txt", test_dir);
+    let sub_dir = format!("{}/subdir", test_dir);
+    let inner_file = format!("{}/inner_file1.txt", sub_dir);
+    fs::write(&outer_file, "Outer file 1 content").unwrap();
+    fs::create_dir_all(&sub_dir).unwrap();
+    fs::write(&inner_file, "Inner file 1 content").unwrap();
+
+    let config = IndexerConfig::new(app.id, db.clone(), true);
+    let (old_hash, _) = DirHasher::new(config)
+        .dir_hash(&Path::new(&test_dir).to_path_buf())
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+
+    // Modify the directory and hash it again through a stage
+    fs::write(&outer_file, "Outer file 1 updated content").unwrap();
+    fs::remove_file(&inner_file).unwrap();
+    let staged_index = StagedIndex::new();
+    let config = IndexerConfig::staged(app.id, db.clone(), staged_index.clone());
+    let (new_hash, changed_files) = DirHasher::new(config)
+        .dir_hash(&Path::new(&test_dir).to_path_buf())
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+    assert_ne!(new_hash, old_hash);
+    assert_eq!(changed_files.len(), 3);
+    verify_change(&outer_file, FileChangeType::Modified, &changed_files);
+    verify_change(&sub_dir, FileChangeType::Modified, &changed_files);
+    verify_change(&inner_file, FileChangeType::Deleted, &changed_files);
+
+    // Nothing should be written to the database before committing
+    verify_index(app.id, &test_dir, true, Some(&old_hash), &db).await;
+    verify_index(app.id, &inner_file, true, None, &db).await;
+    let application = db
+        .get_application(&app.name, &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(application.version, "0.0.1");
+
+    let release = Release {
+        app_id: app.id,
+        version: "0.0.2".to_string(),
+        base_version: Some("0.0.1".to_string()),
+        hash_code: new_hash.clone(),
+        patch_path: None,
+        blob_hashes: Vec::new(),
+        parts: Vec::new(),
+        keeps_snapshot: false,
+    };
+    db.commit_application_update(&release, &staged_index.take())
+        .await
+        .expect("failed to commit update");
+    assert!(staged_index.is_empty());
+    verify_index(app.id, &test_dir, true, Some(&new_hash), &db).await;
+    verify_index(
+        app.id,
+        &outer_file,
+        true,
+        Some("711eb61f4cde35df5859281add666399cce1d2506dba6c01c0de58e315d93a57"),
+        &db,
+    )
+    .await;
+    verify_index(app.id, &inner_file, false, None, &db).await;
+    let application = db
+        .get_application(&app.name, &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(application.version, "0.0.2");
+    assert_eq!(application.hash_code, Some(new_hash.clone()));
+    let versions = db.list_versions(app.id).await.unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].version, "0.0.2");
+    assert_eq!(versions[0].hash_code, new_hash);
+}
+
+#[sqlx::test]
+async fn dir_hasher_with_renamed_file(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("dir_hasher_with_renamed_file");
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&test_dir, &db).await;
+
+    let big_asset = format!("{}/big_asset.bin", test_dir);
+    let other_file = format!("{}/other_file.txt", test_dir);
+    fs::write(&big_asset, "Big asset content").unwrap();
+    fs::write(&other_file, "Other file content").unwrap();
+
+    let config = IndexerConfig::new(app.id, db.clone(), true);
+    let dir_hasher = DirHasher::new(config);
+    let (_, changed_files) = dir_hasher
+        .dir_hash(&Path::new(&test_dir).to_path_buf())
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+    assert_eq!(changed_files.len(), 2);
+
+    // Move the asset into a new folder and delete the other file
+    let assets_dir = format!("{}/assets", test_dir);
+    let moved_asset = format!("{}/big_asset.bin", assets_dir);
+    fs::create_dir_all(&assets_dir).unwrap();
+    fs::rename(&big_asset, &moved_asset).unwrap();
+    fs::remove_file(&other_file).unwrap();
+
+    let (_, changed_files) = dir_hasher
+        .dir_hash(&Path::new(&test_dir).to_path_buf())
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+    assert_eq!(changed_files.len(), 3);
+    verify_change(&assets_dir, FileChangeType::Created, &changed_files);
+    verify_change(
+        &moved_asset,
+        FileChangeType::Renamed {
+            from: big_asset.clone(),
+        },
+        &changed_files,
+    );
+    verify_change(&other_file, FileChangeType::Deleted, &changed_files);
+    assert!(!changed_files.iter().any(|f| f.file_path == big_asset));
+
+    // Verify data in the database
+    verify_index(app.id, &big_asset, false, None, &db).await;
+    verify_index(
+        app.id,
+        &moved_asset,
+        true,
+        Some("baa51b2783691b8d5805f0f579c5355ede9b0f028d5adffe1e708c38ded9891e"),
+        &db,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn dir_hash_covers_file_names() {
+    let test_dir = initialize_test_dir("dir_hash_covers_file_names");
+    let first_dir = Path::new(&test_dir).join("first");
+    let second_dir = Path::new(&test_dir).join("second");
+    fs::create_dir_all(&first_dir).unwrap();
+    fs::create_dir_all(&second_dir).unwrap();
+    fs::write(first_dir.join("a.txt"), "Content A").unwrap();
+    fs::write(first_dir.join("c.txt"), "Content C").unwrap();
+    // Renamed to a name that still sorts first
+    fs::write(second_dir.join("b.txt"), "Content A").unwrap();
+    fs::write(second_dir.join("c.txt"), "Content C").unwrap();
+
+    assert_ne!(
+        hash_install(&first_dir, HashAlgorithm::Sha256)
+            .await
+            .unwrap(),
+        hash_install(&second_dir, HashAlgorithm::Sha256)
+            .await
+            .unwrap()
+    );
+}
+
+#[sqlx::test]
+async fn dir_hasher_reports_progress(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("dir_hasher_reports_progress");
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&test_dir, &db).await;
+    fs::create_dir_all(format!("{}/subdir", test_dir)).unwrap();
+    fs::write(format!("{}/file1.txt", test_dir), "File 1 content").unwrap();
+    fs::write(format!("{}/subdir/file2.txt", test_dir), "File 2").unwrap();
+
+    let reporter = Arc::new(RecordingReporter::default());
+    let config = IndexerConfig::new(app.id, db.clone(), true).with_progress(Some(reporter.clone()));
+    DirHasher::new(config)
+        .dir_hash(&app.source_path)
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+
+    // The total is known before the first file is hashed
+    let first_update = reporter.updates.lock().unwrap()[0].clone();
+    assert_eq!(first_update.total_files, Some(2));
+    assert_eq!(first_update.files_scanned, 0);
+    let finished = reporter.finished.lock().unwrap().clone().unwrap();
+    assert_eq!(finished.stage, ProgressStage::Indexing);
+    assert_eq!(finished.files_scanned, 2);
+    assert_eq!(finished.bytes_hashed, 20);
+    assert_eq!(finished.eta(), Some(std::time::Duration::ZERO));
+
+    // Unchanged files are scanned without being hashed again
+    let reporter = Arc::new(RecordingReporter::default());
+    let config = IndexerConfig::new(app.id, db.clone(), true).with_progress(Some(reporter.clone()));
+    DirHasher::new(config)
+        .dir_hash(&app.source_path)
+        .await
+        .expect("failed to hash directory");
+    let finished = reporter.finished.lock().unwrap().clone().unwrap();
+    assert_eq!(finished.files_scanned, 2);
+    assert_eq!(finished.bytes_hashed, 0);
+}
+
+#[sqlx::test]
+async fn dir_hasher_stops_when_cancelled(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("dir_hasher_stops_when_cancelled");
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&test_dir, &db).await;
+    for i in 1..=3 {
+        fs::write(format!("{}/file{}.txt", test_dir, i), "File content").unwrap();
+    }
+
+    let cancellation = CancellationToken::new();
+    let reporter = Arc::new(CancelAfter {
+        files: 1,
+        cancellation: cancellation.clone(),
+    });
+    let staged_index = StagedIndex::new();
+    let config = IndexerConfig::staged(app.id, db.clone(), staged_index.clone())
+        .with_progress(Some(reporter))
+        .with_cancellation(cancellation);
+    let error = DirHasher::new(config)
+        .dir_hash(&app.source_path)
+        .await
+        .err()
+        .expect("hashing should be cancelled");
+    assert!(Cancelled::is(&error));
+
+    // Hashing stopped after the first file, and the staged changes are never committed
+    assert!(staged_index.len() <= 1);
+    let first_file = format!("{}/file1.txt", test_dir);
+    verify_index(app.id, &first_file, false, None, &db).await;
+    verify_index(app.id, &test_dir, false, None, &db).await;
+}
+
+#[tokio::test]
+async fn dir_hasher_with_memory_store() {
+    let test_dir = initialize_test_dir("dir_hasher_with_memory_store");
+    let outer_file = format!("{}/outer_file1.txt", test_dir);
+    let sub_dir = format!("{}/subdir", test_dir);
+    let inner_file1 = format!("{}/inner_file1.txt", sub_dir);
+    let inner_file2 = format!("{}/inner_file2.txt", sub_dir);
+    fs::write(&outer_file, "Outer file 1 content").unwrap();
+    fs::create_dir_all(&sub_dir).unwrap();
+    fs::write(&inner_file1, "Inner file 1 content").unwrap();
+    fs::write(&inner_file2, "Inner file 2 content").unwrap();
+
+    // No database, the index only lives as long as the store
+    let store = MemoryIndexStore::new();
+    let dir_hasher = DirHasher::new(IndexerConfig::new(1, store.clone(), true));
+    let (hex_hash, changed_files) = dir_hasher
+        .dir_hash(&Path::new(&test_dir).to_path_buf())
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+    assert_eq!(
+        hex_hash,
+        "84b96828c1b5a80cffe9485b36671ec1638fc51199435ebfdef645f7312f5100"
+    );
+    assert_eq!(changed_files.len(), 4);
+    assert_eq!(store.list_file_index(1).await.unwrap().len(), 5);
+
+    // Hashing again finds the cached hashes in the store
+    fs::write(&inner_file1, "Inner file 1 updated content").unwrap();
+    let (_, changed_files) = dir_hasher
+        .dir_hash(&Path::new(&test_dir).to_path_buf())
+        .await
+        .expect("failed to hash directory")
+        .finalize()
+        .await
+        .unwrap();
+    assert_eq!(changed_files.len(), 2);
+    verify_change(&sub_dir, FileChangeType::Modified, &changed_files);
+    verify_change(&inner_file1, FileChangeType::Modified, &changed_files);
+}
@@ -1,13 +1,25 @@
-use std::{fs, path::Path};
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
 
-use secret_online_patcher::indexer::{
-    file_change::FileChangeType, file_hasher::FileHasher, indexer_config::IndexerConfig,
+use secret_online_patcher::{
+    cancellation::CancellationToken,
+    indexer::{
+        file_change::FileChangeType,
+        file_hasher::{FileHasher, MMAP_THRESHOLD, ReadStrategy, hash_content},
+        hash_algorithm::HashAlgorithm,
+        indexer_config::IndexerConfig,
+    },
 };
 use sqlx::SqlitePool;
 
 use crate::common::{
     db_util::verify_index,
-    test_util::{initialize_test_app, initialize_test_db, initialize_test_dir},
+    test_util::{
+        incompressible_bytes, initialize_test_app, initialize_test_db, initialize_test_dir,
+    },
 };
 
 #[sqlx::test]
@@ -26,7 +38,7 @@ async fn file_hasher_with_new_file(db_pool: SqlitePool) {
         .file_hash(&Path::new(&test_file).to_path_buf())
         .await
         .expect("failed to hash file");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
@@ -56,7 +68,7 @@ async fn file_hasher_with_modified_file(db_pool: SqlitePool) {
         .file_hash(&Path::new(&test_file).to_path_buf())
         .await
         .expect("failed to hash file");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
@@ -74,7 +86,7 @@ async fn file_hasher_with_modified_file(db_pool: SqlitePool) {
         .file_hash(&Path::new(&test_file).to_path_buf())
         .await
         .expect("failed to hash file");
-    let (hex_hash, changed_files) = hash_result.finalize().await;
+    let (hex_hash, changed_files) = hash_result.finalize().await.unwrap();
     assert_eq!(hex_hash.len(), 64); // SHA-256 hash length in hex
     assert_eq!(
         hex_hash,
@@ -114,3 +126,84 @@ async fn file_hasher_fail_with_non_existing_file(db_pool: SqlitePool) {
     // Verify data in the database
     verify_index(app.id, &test_file, false, None, &db).await;
 }
+
+#[test]
+fn hash_content_read_strategies_match() {
+    let test_dir = initialize_test_dir("hash_content_read_strategies_match");
+    let test_file = format!("{}/data.pak", test_dir);
+    let content = incompressible_bytes(3 * 1024 * 1024 + 17, 5);
+    fs::write(&test_file, &content).unwrap();
+
+    for algorithm in [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Blake3,
+        HashAlgorithm::Xxh3,
+    ] {
+        for strategy in [ReadStrategy::Buffered, ReadStrategy::Mmap] {
+            let mut hasher = algorithm.hasher();
+            let mut file = File::open(&test_file).unwrap();
+            let cancellation = CancellationToken::new();
+            hash_content(
+                &mut file,
+                content.len() as u64,
+                strategy,
+                hasher.as_mut(),
+                &cancellation,
+            )
+            .unwrap();
+            assert_eq!(
+                hasher.finalize_hex(),
+                algorithm.digest(&content),
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
+
+#[test]
+fn read_strategy_only_maps_files_when_enabled() {
+    assert_eq!(
+        ReadStrategy::for_size(MMAP_THRESHOLD, false),
+        ReadStrategy::Buffered
+    );
+    assert_eq!(
+        ReadStrategy::for_size(MMAP_THRESHOLD, true),
+        ReadStrategy::Mmap
+    );
+    assert_eq!(
+        ReadStrategy::for_size(MMAP_THRESHOLD - 1, true),
+        ReadStrategy::Buffered
+    );
+}
+
+#[test]
+fn hash_content_fails_instead_of_hashing_partial_content() {
+    let test_dir = initialize_test_dir("hash_content_fails_instead_of_hashing_partial_content");
+    let test_file = format!("{}/data.pak", test_dir);
+    fs::write(&test_file, "Hello, world!").unwrap();
+
+    // The file is shorter than expected, e.g. truncated while being hashed
+    let cancellation = CancellationToken::new();
+    for strategy in [ReadStrategy::Buffered, ReadStrategy::Mmap] {
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        let mut file = File::open(&test_file).unwrap();
+        let error =
+            hash_content(&mut file, 1024, strategy, hasher.as_mut(), &cancellation).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    // Read errors are returned instead of ending the content early
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    let mut dir = File::open(&test_dir).unwrap();
+    assert!(
+        hash_content(
+            &mut dir,
+            0,
+            ReadStrategy::Buffered,
+            hasher.as_mut(),
+            &cancellation
+        )
+        .is_err()
+    );
+}
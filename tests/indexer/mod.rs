@@ -1,2 +1,3 @@
 mod dir_hasher_test;
 mod file_hasher_test;
+mod tree_diff_test;
@@ -0,0 +1,113 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use secret_online_patcher::{
+    cancellation::CancellationToken,
+    client::{patch_applier::PatchApplier, update_client::hash_install},
+    indexer::{
+        file_change::{FileChange, FileChangeType},
+        hash_algorithm::HashAlgorithm,
+        tree_diff::diff_trees,
+    },
+    patcher::{DiffPatch, create_diff_patch},
+    storage::{patch_config::PatchConfig, patch_reader::PatchReader},
+};
+
+use crate::common::test_util::{copy_dir, initialize_test_dir};
+
+fn find_change<'a>(changes: &'a [FileChange], file_path: &Path) -> &'a FileChange {
+    let file_path = file_path.display().to_string();
+    changes
+        .iter()
+        .find(|change| change.file_path == file_path)
+        .expect("change not found")
+}
+
+#[tokio::test]
+async fn diff_trees_builds_patch_between_directories() {
+    let test_dir = initialize_test_dir("diff_trees_builds_patch_between_directories");
+    let old_dir = PathBuf::from(format!("{}/old", test_dir));
+    let new_dir = PathBuf::from(format!("{}/new", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(old_dir.join("data")).unwrap();
+    fs::write(old_dir.join("unchanged.txt"), "Unchanged").unwrap();
+    fs::write(old_dir.join("data/modified.txt"), "Version 1").unwrap();
+    fs::write(old_dir.join("deleted.txt"), "Deleted").unwrap();
+    fs::write(old_dir.join("moved.txt"), "Moved").unwrap();
+    copy_dir(&old_dir, &install_dir);
+
+    // Copies have new modified times, only their content is compared
+    copy_dir(&old_dir, &new_dir);
+    fs::write(new_dir.join("data/modified.txt"), "Version 2").unwrap();
+    fs::remove_file(new_dir.join("deleted.txt")).unwrap();
+    fs::rename(new_dir.join("moved.txt"), new_dir.join("data/moved.txt")).unwrap();
+    fs::write(new_dir.join("created.txt"), "Created").unwrap();
+
+    let diff = diff_trees(
+        &old_dir,
+        &new_dir,
+        HashAlgorithm::Sha256,
+        None,
+        &CancellationToken::new(),
+    )
+    .await
+    .expect("failed to diff directories");
+    assert!(diff.has_changes());
+    assert_eq!(diff.changes.len(), 5);
+    let expected = [
+        ("data", FileChangeType::Modified),
+        ("data/modified.txt", FileChangeType::Modified),
+        ("created.txt", FileChangeType::Created),
+        ("deleted.txt", FileChangeType::Deleted),
+        (
+            "data/moved.txt",
+            FileChangeType::Renamed {
+                from: new_dir.join("moved.txt").display().to_string(),
+            },
+        ),
+    ];
+    for (path, change_type) in expected {
+        assert_eq!(
+            find_change(&diff.changes, &new_dir.join(path)).change_type,
+            change_type
+        );
+    }
+
+    // The patch turns a copy of the old directory into the new one
+    let patch_path = create_diff_patch(
+        &diff,
+        &DiffPatch::new("Diff App", "old", "new"),
+        &PathBuf::from(format!("{}/patches", test_dir)),
+        &PatchConfig::default(),
+    )
+    .await
+    .expect("failed to create patch");
+    PatchApplier::new(&install_dir)
+        .apply(&PatchReader::new(&patch_path))
+        .await
+        .expect("failed to apply patch");
+    assert_eq!(
+        hash_install(&install_dir, HashAlgorithm::Sha256)
+            .await
+            .unwrap(),
+        diff.new_hash
+    );
+    assert_eq!(
+        fs::read_to_string(install_dir.join("data/moved.txt")).unwrap(),
+        "Moved"
+    );
+
+    let diff = diff_trees(
+        &new_dir,
+        &install_dir,
+        HashAlgorithm::Sha256,
+        None,
+        &CancellationToken::new(),
+    )
+    .await
+    .unwrap();
+    assert!(!diff.has_changes());
+    assert!(diff.changes.is_empty());
+}
@@ -1,2 +1,7 @@
+mod client;
 mod common;
 mod indexer;
+mod patcher;
+mod server;
+mod service;
+mod storage;
@@ -0,0 +1 @@
+mod patcher_test;
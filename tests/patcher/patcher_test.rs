@@ -0,0 +1,260 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use secret_online_patcher::{
+    cli,
+    indexer::hash_algorithm::HashAlgorithm,
+    patcher::{PatchOutcome, Patcher, PatcherConfig},
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        blob_store::BlobStore,
+        patch_reader::PatchReader,
+        version_scheme::VersionScheme,
+    },
+};
+
+use crate::common::test_util::{copy_dir, initialize_test_dir};
+
+#[tokio::test]
+async fn patcher_builds_and_applies_patches() {
+    let test_dir = initialize_test_dir("patcher_builds_and_applies_patches");
+    let app_dir = PathBuf::from(format!("{}/app", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(app_dir.join("data")).unwrap();
+    fs::write(app_dir.join("file.txt"), "Version 1").unwrap();
+    fs::write(app_dir.join("data/unchanged.txt"), "Unchanged").unwrap();
+    copy_dir(&app_dir, &install_dir);
+
+    let config = PatcherConfig::new(
+        Path::new(&format!("{}/db/app_data.db", test_dir)),
+        Path::new(&format!("{}/patches", test_dir)),
+    );
+    let patcher = Patcher::open(config).await.unwrap();
+    let target = AppTarget::default();
+    let app = patcher
+        .add_app(
+            "Facade App",
+            &target,
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &app_dir,
+        )
+        .await
+        .unwrap();
+    assert_eq!(app.version, "1.0.0");
+    assert!(app.hash_code.is_some());
+
+    let result = patcher.check("Facade App", &target).await.unwrap();
+    assert!(!result.has_changes());
+    assert!(result.changes.is_empty());
+    assert!(matches!(
+        patcher
+            .create_patch("Facade App", &target, "1.0.1", false)
+            .await
+            .unwrap(),
+        PatchOutcome::Unchanged
+    ));
+
+    fs::write(app_dir.join("file.txt"), "Version 2").unwrap();
+    let result = patcher.check("Facade App", &target).await.unwrap();
+    assert!(result.has_changes());
+    assert!(!result.from_live_index);
+    let PatchOutcome::Created(patch) = patcher
+        .create_patch("Facade App", &target, "1.1.0", false)
+        .await
+        .unwrap()
+    else {
+        panic!("a patch should be created");
+    };
+    assert_eq!(patch.base_version, "1.0.0");
+    assert_eq!(patch.hash_code, result.hash_code);
+    assert_eq!(patch.part_count, 1);
+    assert!(
+        patch
+            .patch_path
+            .starts_with(format!("{}/patches/stable", test_dir))
+    );
+    assert!(
+        patcher
+            .create_patch("Facade App", &target, "1.0.5", false)
+            .await
+            .is_err()
+    );
+
+    // The install ends up with the content of the new version
+    let applied = patcher
+        .apply_patch(&install_dir, &patch.patch_path)
+        .await
+        .unwrap();
+    assert_eq!(applied.app_name, "Facade App");
+    assert_eq!(applied.version, "1.1.0");
+    assert_eq!(applied.hash_code, patch.hash_code);
+    assert!(!applied.registered);
+    assert_eq!(
+        fs::read_to_string(install_dir.join("file.txt")).unwrap(),
+        "Version 2"
+    );
+}
+
+#[tokio::test]
+async fn patcher_builds_patches_between_released_versions() {
+    let test_dir = initialize_test_dir("patcher_builds_patches_between_released_versions");
+    let app_dir = PathBuf::from(format!("{}/app", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(app_dir.join("data")).unwrap();
+    fs::write(app_dir.join("file.txt"), "Version 1").unwrap();
+    fs::write(app_dir.join("data/removed.txt"), "Removed").unwrap();
+    fs::write(app_dir.join("data/unchanged.txt"), "Unchanged").unwrap();
+    copy_dir(&app_dir, &install_dir);
+
+    let config = PatcherConfig::new(
+        Path::new(&format!("{}/db/app_data.db", test_dir)),
+        Path::new(&format!("{}/patches", test_dir)),
+    );
+    let patcher = Patcher::open(config.clone()).await.unwrap();
+    let target = AppTarget::default();
+    patcher
+        .add_app(
+            "Snapshot App",
+            &target,
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &app_dir,
+        )
+        .await
+        .unwrap();
+    // Building patches between released versions needs a snapshot store
+    assert!(
+        patcher
+            .create_patch_between("Snapshot App", &target, "1.0.0", "1.0.0", false)
+            .await
+            .is_err()
+    );
+
+    let snapshot_store = BlobStore::new(Path::new(&format!("{}/snapshots", test_dir)));
+    let unkept_patcher = Patcher::with_database(patcher.database().clone(), config.clone());
+    let patcher = Patcher::with_database(
+        patcher.database().clone(),
+        config.with_snapshot_store(Some(snapshot_store.clone())),
+    );
+    // The contents of 1.0.0 are stored along with 1.1.0, as they are still unchanged
+    fs::write(app_dir.join("file.txt"), "Version 2").unwrap();
+    let PatchOutcome::Created(patch_1_1) = patcher
+        .create_patch("Snapshot App", &target, "1.1.0", false)
+        .await
+        .unwrap()
+    else {
+        panic!("a patch should be created");
+    };
+    assert_eq!(snapshot_store.list_blobs().unwrap().len(), 3);
+
+    fs::write(app_dir.join("file.txt"), "Version 3").unwrap();
+    fs::remove_file(app_dir.join("data/removed.txt")).unwrap();
+    fs::write(app_dir.join("data/added.txt"), "Added").unwrap();
+    let PatchOutcome::Created(patch_1_2) = patcher
+        .create_patch("Snapshot App", &target, "1.2.0", false)
+        .await
+        .unwrap()
+    else {
+        panic!("a patch should be created");
+    };
+
+    // Once versions are kept in the snapshot store, new ones can't be released without it
+    fs::write(app_dir.join("file.txt"), "Version 4").unwrap();
+    assert!(
+        unkept_patcher
+            .create_patch("Snapshot App", &target, "1.3.0", false)
+            .await
+            .is_err()
+    );
+    fs::write(app_dir.join("file.txt"), "Version 3").unwrap();
+
+    // 1.0.0 only has a manifest, its files are never shipped by a patch from it
+    let patch = patcher
+        .create_patch_between("Snapshot App", &target, "1.0.0", "1.2.0", false)
+        .await
+        .unwrap();
+    assert_eq!(patch.base_version, "1.0.0");
+    assert_eq!(patch.hash_code, patch_1_2.hash_code);
+    assert!(
+        patch
+            .patch_path
+            .starts_with(format!("{}/patches/stable/from_1.0.0", test_dir))
+    );
+    let applied = patcher
+        .apply_patch(&install_dir, &patch.patch_path)
+        .await
+        .unwrap();
+    assert_eq!(applied.version, "1.2.0");
+    assert_eq!(applied.hash_code, patch_1_2.hash_code);
+    assert!(!install_dir.join("data/removed.txt").exists());
+    assert_eq!(
+        fs::read_to_string(install_dir.join("data/added.txt")).unwrap(),
+        "Added"
+    );
+
+    // A recorded patch is only replaced while it exists if forced
+    assert!(
+        patcher
+            .create_patch_between("Snapshot App", &target, "1.0.0", "1.1.0", false)
+            .await
+            .is_err()
+    );
+    let forced = patcher
+        .create_patch_between("Snapshot App", &target, "1.0.0", "1.1.0", true)
+        .await
+        .unwrap();
+    assert_eq!(forced.patch_path, patch_1_1.patch_path);
+
+    // A lost patch is built again from the snapshots and recorded in place of the previous one
+    fs::remove_file(&patch_1_1.patch_path).unwrap();
+    let rebuilt = patcher
+        .create_patch_between("Snapshot App", &target, "1.0.0", "1.1.0", false)
+        .await
+        .unwrap();
+    assert_eq!(rebuilt.patch_path, patch_1_1.patch_path);
+    assert_eq!(rebuilt.changes.len(), 1);
+    let db = patcher.database();
+    let app = db
+        .get_application("Snapshot App", &target)
+        .await
+        .unwrap()
+        .unwrap();
+    let versions = db.list_versions(app.id).await.unwrap();
+    let recorded_parts = db.list_version_parts(versions[1].id).await.unwrap();
+    assert_eq!(
+        recorded_parts,
+        PatchReader::new(&rebuilt.patch_path).hash_parts().unwrap()
+    );
+    assert!(
+        patcher
+            .create_patch_between("Snapshot App", &target, "1.0.0", "2.0.0", false)
+            .await
+            .is_err()
+    );
+
+    // The snapshot store keeps the files of recorded versions, the blob store only the blobs
+    // referenced by their patches
+    let blob_store = BlobStore::new(Path::new(&format!("{}/blobs", test_dir)));
+    let unchanged_hash = HashAlgorithm::Sha256.digest(b"Unchanged");
+    let unchanged_path = app_dir.join("data/unchanged.txt");
+    blob_store
+        .put_file(&unchanged_hash, &unchanged_path, HashAlgorithm::Sha256)
+        .unwrap();
+    let orphan_path = PathBuf::from(format!("{}/orphan.txt", test_dir));
+    fs::write(&orphan_path, "Orphan").unwrap();
+    let orphan_hash = HashAlgorithm::Sha256.digest(b"Orphan");
+    snapshot_store
+        .put_file(&orphan_hash, &orphan_path, HashAlgorithm::Sha256)
+        .unwrap();
+    cli::collect_garbage(Some(&blob_store), Some(&snapshot_store), None, db)
+        .await
+        .unwrap();
+    assert!(blob_store.list_blobs().unwrap().is_empty());
+    let snapshot_blobs = snapshot_store.list_blobs().unwrap();
+    assert_eq!(snapshot_blobs.len(), 5);
+    assert!(snapshot_blobs.contains(&unchanged_hash));
+    assert!(!snapshot_blobs.contains(&orphan_hash));
+}
@@ -0,0 +1 @@
+mod patch_server_test;
@@ -0,0 +1,129 @@
+use std::fs;
+
+use reqwest::{StatusCode, header};
+use secret_online_patcher::{
+    indexer::hash_algorithm::HashAlgorithm,
+    server::{
+        api::{AppSummary, LatestVersion, VersionSummary},
+        patch_server::PatchServer,
+    },
+    storage::{app_version::Release, patcher_db::PatcherDatabase},
+};
+use sqlx::SqlitePool;
+use tokio::net::TcpListener;
+
+use crate::common::test_util::{initialize_test_app, initialize_test_db, initialize_test_dir};
+
+/// Start a patch server on a random local port and return its base URL.
+async fn start_server(db: &PatcherDatabase) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+    url
+}
+
+#[sqlx::test]
+async fn patch_server_lists_and_downloads_patches(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_server_lists_and_downloads_patches");
+    let app_dir = format!("{}/app", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    let patch_path = format!("{}/Test_App_0.0.2_update.zip", test_dir);
+    fs::write(&patch_path, "0123456789").unwrap();
+    fs::write(
+        format!("{}/Test_App_0.0.2_update.part2.zip", test_dir),
+        "part 2",
+    )
+    .unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    for (version, base_version, patch_path) in [
+        ("0.0.1", None, None),
+        ("0.0.2", Some("0.0.1"), Some(patch_path.clone())),
+    ] {
+        let release = Release {
+            app_id: app.id,
+            version: version.to_string(),
+            base_version: base_version.map(str::to_string),
+            hash_code: format!("hash_{}", version),
+            patch_path,
+            blob_hashes: Vec::new(),
+            parts: Vec::new(),
+            keeps_snapshot: false,
+        };
+        db.commit_application_update(&release, &[]).await.unwrap();
+    }
+
+    let url = start_server(&db).await;
+    let apps: Vec<AppSummary> = reqwest::get(format!("{}/apps", url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        apps,
+        vec![AppSummary {
+            name: "Test App".to_string(),
+            channel: "stable".to_string(),
+            platform: "any".to_string(),
+            version: "0.0.2".to_string(),
+            hash_code: Some("hash_0.0.2".to_string()),
+            hash_algorithm: HashAlgorithm::Sha256,
+        }]
+    );
+
+    let latest: LatestVersion = reqwest::get(format!("{}/apps/Test App/latest", url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(latest.version, "0.0.2");
+    assert_eq!(latest.hash_code.as_deref(), Some("hash_0.0.2"));
+
+    let versions: Vec<VersionSummary> = reqwest::get(format!("{}/apps/Test App/versions", url))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(versions.len(), 2);
+    assert!(versions[0].parts.is_empty());
+    assert_eq!(versions[1].base_version.as_deref(), Some("0.0.1"));
+    assert_eq!(versions[1].parts.len(), 2);
+    assert_eq!(versions[1].parts[0].size, 10);
+    assert_eq!(versions[1].parts[1].size, 6);
+    // Parts were not recorded with the release, so their hash is unknown
+    assert_eq!(versions[1].parts[0].hash_code, None);
+
+    let part_url = format!("{}/apps/Test App/versions/0.0.2/parts", url);
+    let body = reqwest::get(format!("{}/2", part_url))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "part 2");
+
+    // Resume a download from the middle of the file
+    let response = reqwest::Client::new()
+        .get(format!("{}/1", part_url))
+        .header(header::RANGE, "bytes=4-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.text().await.unwrap(), "456789");
+
+    for missing in [
+        format!("{}/3", part_url),
+        format!("{}/apps/Test App/versions/0.0.1/parts/1", url),
+        format!("{}/apps/Other App/latest", url),
+        format!("{}/blobs/{}", url, "0".repeat(64)),
+    ] {
+        let response = reqwest::get(&missing).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", missing);
+    }
+}
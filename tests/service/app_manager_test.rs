@@ -0,0 +1,648 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use secret_online_patcher::{
+    cancellation::{CancellationToken, Cancelled},
+    cli,
+    client::update_client::UpdateClient,
+    patcher::{Patcher, PatcherConfig},
+    server::patch_server::PatchServer,
+    service::app_manager::AppManager,
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        patch_compression::PatchCompression,
+        patch_config::PatchConfig,
+        patch_reader::PatchReader,
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+use tokio::net::TcpListener;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::common::test_util::{CancelAfter, copy_dir, initialize_test_db, initialize_test_dir};
+
+#[sqlx::test]
+async fn channels_have_separate_versions(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("channels_have_separate_versions");
+    let stable_dir = PathBuf::from(format!("{}/stable", test_dir));
+    let beta_dir = PathBuf::from(format!("{}/beta", test_dir));
+    fs::create_dir_all(&stable_dir).unwrap();
+    fs::write(stable_dir.join("file.txt"), "Version 1").unwrap();
+    copy_dir(&stable_dir, &beta_dir);
+
+    let db = initialize_test_db(&db_pool).await;
+    let app_manager = AppManager::new(db.clone());
+    app_manager
+        .create_application(
+            "Channel App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &stable_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    app_manager
+        .create_application(
+            "Channel App",
+            &AppTarget::new("beta", "any"),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &beta_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(
+        app_manager
+            .create_application(
+                "Channel App",
+                &AppTarget::new("beta", "any"),
+                "1.0.0",
+                AppSettings::new(VersionScheme::Semver),
+                &beta_dir,
+                None
+            )
+            .await
+            .is_err()
+    );
+
+    fs::write(beta_dir.join("file.txt"), "Version 2").unwrap();
+    cli::update_app(
+        "Channel App",
+        &AppTarget::new("beta", "any"),
+        "1.1.0",
+        false,
+        None,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+
+    let stable = db
+        .get_application("Channel App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    let beta = db
+        .get_application("Channel App", &AppTarget::new("beta", "any"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stable.version, "1.0.0");
+    assert_eq!(beta.version, "1.1.0");
+    assert_ne!(stable.hash_code, beta.hash_code);
+    let beta_versions = db.list_versions(beta.id).await.unwrap();
+    let patch_path = beta_versions[1].patch_path.clone().unwrap();
+    assert!(patch_path.starts_with("fs_tests/patches/beta/"));
+
+    // Only the current version of a channel can be promoted
+    assert!(
+        app_manager
+            .promote_version(
+                "Channel App",
+                &AppTarget::new("beta", "any"),
+                "stable",
+                "1.0.0"
+            )
+            .await
+            .is_err()
+    );
+    let promoted = app_manager
+        .promote_version(
+            "Channel App",
+            &AppTarget::new("beta", "any"),
+            "stable",
+            "1.1.0",
+        )
+        .await
+        .unwrap();
+    assert_eq!(promoted.len(), 1);
+
+    let stable = db
+        .get_application("Channel App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stable.version, "1.1.0");
+    assert_eq!(stable.hash_code, beta.hash_code);
+    let stable_versions = db.list_versions(stable.id).await.unwrap();
+    assert_eq!(stable_versions.len(), 2);
+    assert_eq!(stable_versions[1].version, "1.1.0");
+    assert_eq!(stable_versions[1].base_version.as_deref(), Some("1.0.0"));
+    assert_eq!(stable_versions[1].patch_path, Some(patch_path));
+    assert_eq!(
+        db.list_version_parts(stable_versions[1].id).await.unwrap(),
+        db.list_version_parts(beta_versions[1].id).await.unwrap()
+    );
+
+    // The index follows the promoted content
+    let stable_file = db
+        .get_file_index(stable.id, &stable_dir.join("file.txt").to_string_lossy())
+        .await
+        .unwrap()
+        .unwrap();
+    let beta_file = db
+        .get_file_index(beta.id, &beta_dir.join("file.txt").to_string_lossy())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stable_file.hash_code, beta_file.hash_code);
+    assert!(!stable.keeps_snapshots);
+
+    // Once its source directory is synced, the target channel has no change to report
+    fs::write(stable_dir.join("file.txt"), "Version 2").unwrap();
+    let patcher = Patcher::with_database(
+        db.clone(),
+        PatcherConfig::new(Path::new(":memory:"), Path::new("fs_tests/patches")),
+    );
+    let check = patcher
+        .check("Channel App", &AppTarget::default())
+        .await
+        .unwrap();
+    assert!(!check.has_changes());
+
+    // Promoting again has nothing to bring
+    assert!(
+        app_manager
+            .promote_version(
+                "Channel App",
+                &AppTarget::new("beta", "any"),
+                "stable",
+                "1.1.0"
+            )
+            .await
+            .is_err()
+    );
+}
+
+#[sqlx::test]
+async fn promote_requires_shared_lineage(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("promote_requires_shared_lineage");
+    let stable_dir = PathBuf::from(format!("{}/stable", test_dir));
+    let beta_dir = PathBuf::from(format!("{}/beta", test_dir));
+    fs::create_dir_all(&stable_dir).unwrap();
+    fs::create_dir_all(&beta_dir).unwrap();
+    fs::write(stable_dir.join("file.txt"), "Stable").unwrap();
+    fs::write(beta_dir.join("file.txt"), "Beta").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app_manager = AppManager::new(db.clone());
+    app_manager
+        .create_application(
+            "Lineage App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &stable_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    app_manager
+        .create_application(
+            "Lineage App",
+            &AppTarget::new("beta", "any"),
+            "2.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &beta_dir,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Stable clients couldn't patch from 1.0.0 with the patches of beta
+    assert!(
+        app_manager
+            .promote_version(
+                "Lineage App",
+                &AppTarget::new("beta", "any"),
+                "stable",
+                "2.0.0"
+            )
+            .await
+            .is_err()
+    );
+    assert!(
+        app_manager
+            .promote_version(
+                "Lineage App",
+                &AppTarget::new("beta", "any"),
+                "release",
+                "2.0.0"
+            )
+            .await
+            .is_err()
+    );
+}
+
+#[sqlx::test]
+async fn update_requires_increasing_versions(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("update_requires_increasing_versions");
+    let source_dir = PathBuf::from(format!("{}/source", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("file.txt"), "Version 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app_manager = AppManager::new(db.clone());
+    assert!(
+        app_manager
+            .create_application(
+                "Semver App",
+                &AppTarget::default(),
+                "1.9",
+                AppSettings::new(VersionScheme::Semver),
+                &source_dir,
+                None
+            )
+            .await
+            .is_err()
+    );
+    app_manager
+        .create_application(
+            "Semver App",
+            &AppTarget::default(),
+            "1.9.0",
+            AppSettings::new(VersionScheme::Semver),
+            &source_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    copy_dir(&source_dir, &install_dir);
+
+    let patch_config = PatchConfig::default();
+    fs::write(source_dir.join("file.txt"), "Version 2").unwrap();
+    for version in ["1.9.0", "1.8.0", "1.9.0-beta.1", "next"] {
+        assert!(
+            cli::update_app(
+                "Semver App",
+                &AppTarget::default(),
+                version,
+                false,
+                None,
+                &patch_config,
+                &db
+            )
+            .await
+            .is_err()
+        );
+    }
+    cli::update_app(
+        "Semver App",
+        &AppTarget::default(),
+        "1.10.0",
+        false,
+        None,
+        &patch_config,
+        &db,
+    )
+    .await
+    .unwrap();
+
+    // Forcing accepts a lower version, but never one that was already released
+    fs::write(source_dir.join("file.txt"), "Version 3").unwrap();
+    cli::update_app(
+        "Semver App",
+        &AppTarget::default(),
+        "1.9.5",
+        true,
+        None,
+        &patch_config,
+        &db,
+    )
+    .await
+    .unwrap();
+    assert!(
+        cli::update_app(
+            "Semver App",
+            &AppTarget::default(),
+            "1.10.0",
+            true,
+            None,
+            &patch_config,
+            &db
+        )
+        .await
+        .is_err()
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+
+    let client = UpdateClient::new(&url, &PathBuf::from(format!("{}/cache", test_dir))).unwrap();
+    let versions: Vec<String> = client
+        .client
+        .list_versions("Semver App")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|version| version.version)
+        .collect();
+    assert_eq!(versions, vec!["1.9.0", "1.9.5", "1.10.0"]);
+    let latest = client.client.latest_version("Semver App").await.unwrap();
+    assert_eq!(latest.version, "1.10.0");
+
+    // Only the patch leading to the latest version is applied
+    let outcome = client.update("Semver App", &install_dir).await.unwrap();
+    assert!(outcome.updated);
+    assert_eq!(outcome.version, "1.10.0");
+    assert_eq!(
+        fs::read_to_string(install_dir.join("file.txt")).unwrap(),
+        "Version 2"
+    );
+}
+
+#[sqlx::test]
+async fn free_form_versions_only_have_to_differ(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("free_form_versions_only_have_to_differ");
+    let source_dir = PathBuf::from(format!("{}/source", test_dir));
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("file.txt"), "Spring").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Season App",
+            &AppTarget::default(),
+            "spring",
+            AppSettings::new(VersionScheme::FreeForm),
+            &source_dir,
+            None,
+        )
+        .await
+        .unwrap();
+
+    let patch_config = PatchConfig::default();
+    fs::write(source_dir.join("file.txt"), "Autumn").unwrap();
+    cli::update_app(
+        "Season App",
+        &AppTarget::default(),
+        "autumn",
+        false,
+        None,
+        &patch_config,
+        &db,
+    )
+    .await
+    .unwrap();
+    fs::write(source_dir.join("file.txt"), "Spring again").unwrap();
+    assert!(
+        cli::update_app(
+            "Season App",
+            &AppTarget::default(),
+            "spring",
+            false,
+            None,
+            &patch_config,
+            &db
+        )
+        .await
+        .is_err()
+    );
+
+    let app = db
+        .get_application("Season App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(app.version_scheme, VersionScheme::FreeForm);
+    assert_eq!(app.version, "autumn");
+}
+
+#[sqlx::test]
+async fn platforms_share_identical_files(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("platforms_share_identical_files");
+    let linux = AppTarget::new("stable", "linux-x86_64");
+    let windows = AppTarget::new("stable", "windows-x86_64");
+    let linux_dir = PathBuf::from(format!("{}/linux", test_dir));
+    let windows_dir = PathBuf::from(format!("{}/windows", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(&linux_dir).unwrap();
+    fs::create_dir_all(&windows_dir).unwrap();
+    fs::write(linux_dir.join("data.pak"), "Shared data 1\n".repeat(1000)).unwrap();
+    fs::write(windows_dir.join("data.pak"), "Shared data 1\n".repeat(1000)).unwrap();
+    fs::write(linux_dir.join("game"), "Linux binary 1").unwrap();
+    fs::write(windows_dir.join("game.exe"), "Windows binary 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app_manager = AppManager::new(db.clone());
+    for (target, dir) in [(&linux, &linux_dir), (&windows, &windows_dir)] {
+        app_manager
+            .create_application(
+                "Platform App",
+                target,
+                "1.0.0",
+                AppSettings::new(VersionScheme::Semver),
+                dir,
+                None,
+            )
+            .await
+            .unwrap();
+    }
+    copy_dir(&windows_dir, &install_dir);
+
+    fs::write(linux_dir.join("data.pak"), "Shared data 2\n".repeat(1000)).unwrap();
+    fs::write(windows_dir.join("data.pak"), "Shared data 2\n".repeat(1000)).unwrap();
+    fs::write(linux_dir.join("game"), "Linux binary 2").unwrap();
+    fs::write(windows_dir.join("game.exe"), "Windows binary 2").unwrap();
+    let zstd_config = PatchConfig::new(PatchCompression::Zstd(None), true, None, None);
+    cli::update_app(
+        "Platform App",
+        &linux,
+        "1.1.0",
+        false,
+        None,
+        &zstd_config,
+        &db,
+    )
+    .await
+    .unwrap();
+    cli::update_app(
+        "Platform App",
+        &windows,
+        "1.1.0",
+        false,
+        None,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+
+    let windows_app = db
+        .get_application("Platform App", &windows)
+        .await
+        .unwrap()
+        .unwrap();
+    let versions = db.list_versions(windows_app.id).await.unwrap();
+    let patch_path = PathBuf::from(versions[1].patch_path.as_ref().unwrap());
+    assert!(
+        patch_path.ends_with("stable/Platform_App_1.1.0_windows-x86_64_update.zip"),
+        "{}",
+        patch_path.display()
+    );
+    let reader = PatchReader::new(&patch_path);
+    reader.validate().await.unwrap();
+    let patch_db = reader.open_patch_db().await.unwrap();
+    let patch_info = patch_db.db.get_patch_info().await.unwrap().unwrap();
+    assert_eq!(patch_info.platform, "windows-x86_64");
+    patch_db.db.close().await;
+
+    // The shared file is copied from the Linux patch instead of being compressed again
+    let mut archive = ZipArchive::new(fs::File::open(&patch_path).unwrap()).unwrap();
+    assert_eq!(
+        archive
+            .by_name("Platform App/data.pak")
+            .unwrap()
+            .compression(),
+        CompressionMethod::Zstd
+    );
+    assert_eq!(
+        archive
+            .by_name("Platform App/game.exe")
+            .unwrap()
+            .compression(),
+        CompressionMethod::Deflated
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+    let client = UpdateClient::new(&url, &PathBuf::from(format!("{}/cache", test_dir)))
+        .unwrap()
+        .with_target(&windows);
+    let outcome = client.update("Platform App", &install_dir).await.unwrap();
+    assert!(outcome.updated);
+    assert_eq!(outcome.version, "1.1.0");
+    assert_eq!(
+        fs::read_to_string(install_dir.join("data.pak")).unwrap(),
+        "Shared data 2\n".repeat(1000)
+    );
+    assert_eq!(
+        fs::read_to_string(install_dir.join("game.exe")).unwrap(),
+        "Windows binary 2"
+    );
+    assert!(!install_dir.join("game").exists());
+}
+
+#[sqlx::test]
+async fn prove_file_against_version_hash(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("prove_file_against_version_hash");
+    let app_dir = PathBuf::from(format!("{}/app", test_dir));
+    fs::create_dir_all(app_dir.join("assets/sounds")).unwrap();
+    fs::write(app_dir.join("readme.txt"), "Read me").unwrap();
+    fs::write(app_dir.join("assets/image.png"), "Image").unwrap();
+    fs::write(app_dir.join("assets/sounds/theme.ogg"), "Theme").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app_manager = AppManager::new(db.clone());
+    app_manager
+        .create_application(
+            "Proof App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &app_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    let app = db
+        .get_application("Proof App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    let root_hash = app.hash_code.unwrap();
+
+    let proof = app_manager
+        .prove_file(
+            "Proof App",
+            &AppTarget::default(),
+            Path::new("assets/sounds/theme.ogg"),
+        )
+        .await
+        .unwrap();
+    assert_eq!(proof.file_path, "assets/sounds/theme.ogg");
+    assert_eq!(proof.levels.len(), 3);
+    assert!(proof.verify(&root_hash));
+
+    // A proof doesn't hold for another content or another path
+    let mut forged = proof.clone();
+    forged.hash_code = "0".repeat(64);
+    assert!(!forged.verify(&root_hash));
+    let mut moved = proof.clone();
+    moved.file_path = "assets/sounds/other.ogg".to_string();
+    assert!(!moved.verify(&root_hash));
+    let mut renamed = proof.clone();
+    renamed.levels[1][0].name = "images.png".to_string();
+    assert!(!renamed.verify(&root_hash));
+
+    for missing in ["assets/sounds", "missing.txt", "../readme.txt"] {
+        assert!(
+            app_manager
+                .prove_file("Proof App", &AppTarget::default(), Path::new(missing))
+                .await
+                .is_err(),
+            "{}",
+            missing
+        );
+    }
+}
+
+#[sqlx::test]
+async fn cancelled_application_is_not_added(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("cancelled_application_is_not_added");
+    let app_dir = PathBuf::from(format!("{}/app", test_dir));
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::write(app_dir.join("file1.txt"), "File 1 content").unwrap();
+    fs::write(app_dir.join("file2.txt"), "File 2 content").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let cancellation = CancellationToken::new();
+    let reporter = Arc::new(CancelAfter {
+        files: 1,
+        cancellation: cancellation.clone(),
+    });
+    let error = AppManager::new(db.clone())
+        .with_progress(Some(reporter))
+        .with_cancellation(cancellation)
+        .create_application(
+            "Cancelled App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &app_dir,
+            None,
+        )
+        .await
+        .err()
+        .expect("adding the application should be cancelled");
+    assert!(Cancelled::is(&error));
+    assert!(db.list_applications().await.is_empty());
+
+    // The application can be added again from scratch
+    let app = AppManager::new(db.clone())
+        .create_application(
+            "Cancelled App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &app_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    let indexed_files = db.list_file_index(app.id).await.unwrap();
+    assert_eq!(indexed_files.len(), 3);
+}
@@ -0,0 +1,146 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use secret_online_patcher::{
+    cli,
+    client::update_client::hash_install,
+    indexer::{file_change::FileChangeType, hash_algorithm::HashAlgorithm},
+    service::{
+        app_manager::AppManager,
+        index_watcher::{IndexWatcher, LiveStatus},
+    },
+    storage::{
+        application_data::{AppSettings, AppTarget, Application},
+        patch_config::PatchConfig,
+        patcher_db::PatcherDatabase,
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+use tokio::sync::oneshot;
+
+use crate::common::test_util::{initialize_test_db, initialize_test_dir};
+
+#[sqlx::test]
+async fn watcher_keeps_live_index_up_to_date(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("watcher_keeps_live_index_up_to_date");
+    let app_dir = PathBuf::from(format!("{}/app", test_dir));
+    fs::create_dir_all(app_dir.join("assets")).unwrap();
+    fs::create_dir_all(app_dir.join("old")).unwrap();
+    fs::write(app_dir.join("config.txt"), "version = 1").unwrap();
+    fs::write(app_dir.join("assets/image.png"), "Image").unwrap();
+    fs::write(app_dir.join("old/legacy.txt"), "Legacy").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Watched App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &app_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    let app = db
+        .get_application("Watched App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        IndexWatcher::live_status(&app, &db)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let watcher = IndexWatcher::new(app.clone(), db.clone());
+    let handle = tokio::spawn(watcher.run_until(async {
+        let _ = stopped.await;
+    }));
+
+    // The live index starts from the released tree
+    let released_hash = app.hash_code.clone().unwrap();
+    let status = wait_for_hash(&app, &db, &released_hash).await;
+    assert!(status.changes.is_empty());
+
+    fs::write(app_dir.join("config.txt"), "version = 2").unwrap();
+    fs::write(app_dir.join("assets/sound.ogg"), "Sound").unwrap();
+    fs::remove_dir_all(app_dir.join("old")).unwrap();
+    fs::create_dir_all(app_dir.join("levels/forest")).unwrap();
+    fs::write(app_dir.join("levels/forest/map.dat"), "Forest").unwrap();
+
+    let new_hash = hash_install(&app_dir, HashAlgorithm::Sha256).await.unwrap();
+    let status = wait_for_hash(&app, &db, &new_hash).await;
+    let changes: Vec<_> = status
+        .changes
+        .iter()
+        .map(|change| {
+            let path = PathBuf::from(&change.file_path);
+            let path = path.strip_prefix(&app_dir).unwrap().display().to_string();
+            (path, change.change_type.clone())
+        })
+        .collect();
+    for expected in [
+        ("config.txt", FileChangeType::Modified),
+        ("assets/sound.ogg", FileChangeType::Created),
+        ("old/legacy.txt", FileChangeType::Deleted),
+        ("old", FileChangeType::Deleted),
+        ("levels/forest/map.dat", FileChangeType::Created),
+    ] {
+        let expected = (expected.0.to_string(), expected.1);
+        assert!(
+            changes.contains(&expected),
+            "{:?} in {:?}",
+            expected,
+            changes
+        );
+    }
+    // Hashing the tree for the check didn't touch the index of the release
+    assert!(!changes.iter().any(|(path, _)| path == "assets/image.png"));
+
+    // The changes are still there to be released
+    cli::update_app(
+        "Watched App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        None,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+    let app = db
+        .get_application("Watched App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(app.hash_code.as_deref(), Some(new_hash.as_str()));
+    let status = IndexWatcher::live_status(&app, &db).await.unwrap().unwrap();
+    assert!(status.changes.is_empty());
+
+    // The live index is dropped with the watcher
+    stop.send(()).unwrap();
+    handle.await.unwrap().unwrap();
+    assert!(
+        IndexWatcher::live_status(&app, &db)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+/// Wait until the live index of an application has the given hash.
+async fn wait_for_hash(app: &Application, db: &PatcherDatabase, hash_code: &str) -> LiveStatus {
+    for _ in 0..100 {
+        if let Some(status) = IndexWatcher::live_status(app, db).await.unwrap()
+            && status.hash_code == hash_code
+        {
+            return status;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Live index did not reach hash {}", hash_code);
+}
@@ -0,0 +1,182 @@
+use std::{fs, path::PathBuf};
+
+use secret_online_patcher::{
+    cli,
+    server::patch_server::PatchServer,
+    service::{
+        app_manager::AppManager,
+        install_manager::{InstallManager, InstallStatus},
+    },
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        patch_config::PatchConfig,
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+use tokio::net::TcpListener;
+
+use crate::common::test_util::{copy_dir, initialize_test_db, initialize_test_dir};
+
+#[sqlx::test]
+async fn install_manager_tracks_installs(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("install_manager_tracks_installs");
+    let source_dir = PathBuf::from(format!("{}/source", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("file.txt"), "Version 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Install App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &source_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    copy_dir(&source_dir, &install_dir);
+
+    let install_manager = InstallManager::new(db.clone());
+    let install = install_manager
+        .register_install("Install App", &AppTarget::default(), &install_dir)
+        .await
+        .unwrap();
+    assert_eq!(
+        install.install_path,
+        fs::canonicalize(&install_dir).unwrap()
+    );
+    assert_eq!(install.installed_version.as_deref(), Some("1.0.0"));
+    assert!(install.verified_hash.is_some());
+    assert!(install.verified_at.is_some());
+    assert_eq!(install.last_patch_version, None);
+    assert_eq!(
+        InstallManager::status(&install, Some("1.0.0")),
+        InstallStatus::UpToDate
+    );
+    assert!(
+        install_manager
+            .register_install("Install App", &AppTarget::default(), &install_dir)
+            .await
+            .is_err()
+    );
+
+    // Publishing a new version from the source leaves the install behind
+    fs::write(source_dir.join("file.txt"), "Version 2").unwrap();
+    cli::update_app(
+        "Install App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        None,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+    let app = db
+        .get_application("Install App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    let install = install_manager
+        .find_install(&install_dir)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        InstallManager::status(&install, Some(&app.version)),
+        InstallStatus::OutOfDate {
+            latest_version: "1.0.1".to_string()
+        }
+    );
+
+    // A modified install doesn't match any version anymore
+    fs::write(install_dir.join("file.txt"), "Modified").unwrap();
+    let install = install_manager.verify_install(&install).await.unwrap();
+    assert_eq!(install.installed_version, None);
+    assert_eq!(
+        InstallManager::status(&install, Some(&app.version)),
+        InstallStatus::UnknownVersion
+    );
+
+    assert!(db.remove_install(&install.install_path).await.unwrap());
+    assert!(db.list_installs().await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn client_update_records_registered_install(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("client_update_records_registered_install");
+    let source_dir = PathBuf::from(format!("{}/source", test_dir));
+    let install_dir = PathBuf::from(format!("{}/install", test_dir));
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("file.txt"), "Version 1").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    AppManager::new(db.clone())
+        .create_application(
+            "Recorded Install App",
+            &AppTarget::default(),
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            &source_dir,
+            None,
+        )
+        .await
+        .unwrap();
+    copy_dir(&source_dir, &install_dir);
+    cli::add_install(
+        "Recorded Install App",
+        &AppTarget::default(),
+        &install_dir,
+        &db,
+    )
+    .await
+    .unwrap();
+    fs::write(source_dir.join("file.txt"), "Version 2").unwrap();
+    cli::update_app(
+        "Recorded Install App",
+        &AppTarget::default(),
+        "1.0.1",
+        false,
+        None,
+        &PatchConfig::default(),
+        &db,
+    )
+    .await
+    .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = PatchServer::new(db.clone(), None);
+    tokio::spawn(async move { server.serve(listener).await });
+    let cache_dir = PathBuf::from(format!("{}/cache", test_dir));
+    cli::client_update(
+        &url,
+        "Recorded Install App",
+        &AppTarget::default(),
+        &install_dir,
+        Some(&cache_dir),
+        &db,
+    )
+    .await
+    .unwrap();
+
+    let app = db
+        .get_application("Recorded Install App", &AppTarget::default())
+        .await
+        .unwrap()
+        .unwrap();
+    let install = db
+        .get_install(&fs::canonicalize(&install_dir).unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(install.installed_version.as_deref(), Some("1.0.1"));
+    assert_eq!(install.verified_hash, app.hash_code);
+    assert_eq!(install.last_patch_version.as_deref(), Some("1.0.1"));
+    assert!(install.last_patched_at.is_some());
+}
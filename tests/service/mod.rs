@@ -0,0 +1,3 @@
+mod app_manager_test;
+mod index_watcher_test;
+mod install_manager_test;
@@ -0,0 +1,185 @@
+use std::{fs, path::Path};
+
+use secret_online_patcher::{
+    cli,
+    indexer::hash_algorithm::HashAlgorithm,
+    storage::{
+        app_version::Release,
+        application_data::{AppSettings, AppTarget, DEFAULT_PLATFORM},
+        blob_store::BlobStore,
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+
+use crate::common::test_util::{initialize_test_app, initialize_test_db, initialize_test_dir};
+
+// SHA-256 of "Hello, world!"
+const HELLO_HASH: &str = "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3";
+// SHA-256 of "Hello, Rust!"
+const RUST_HASH: &str = "12a967da1e8654e129d41e3c016f14e81e751e073feb383125bf82080256ca19";
+
+#[test]
+fn blob_store_put_file() {
+    let test_dir = initialize_test_dir("blob_store_put_file");
+    let store = BlobStore::new(&Path::new(&test_dir).join("blobs"));
+    let file1 = format!("{}/file1.txt", test_dir);
+    let file2 = format!("{}/file2.txt", test_dir);
+    fs::write(&file1, "Hello, world!").unwrap();
+    fs::write(&file2, "Hello, world!").unwrap();
+
+    assert!(
+        store
+            .put_file(HELLO_HASH, Path::new(&file1), HashAlgorithm::Sha256)
+            .unwrap()
+    );
+    // Identical content is only stored once
+    assert!(
+        !store
+            .put_file(HELLO_HASH, Path::new(&file2), HashAlgorithm::Sha256)
+            .unwrap()
+    );
+    assert!(store.contains(HELLO_HASH));
+    assert_eq!(
+        store.blob_path(HELLO_HASH),
+        Path::new(&test_dir).join("blobs/31").join(HELLO_HASH)
+    );
+    assert_eq!(store.list_blobs().unwrap(), vec![HELLO_HASH]);
+    store.verify(HELLO_HASH, HashAlgorithm::Sha256).unwrap();
+
+    // Content not matching the hash is rejected
+    fs::write(&file2, "Hello, Rust!").unwrap();
+    assert!(
+        store
+            .put_file(HELLO_HASH, Path::new(&file2), HashAlgorithm::Sha256)
+            .is_ok()
+    );
+    assert!(
+        store
+            .put_file(RUST_HASH, Path::new(&file1), HashAlgorithm::Sha256)
+            .is_err()
+    );
+    assert!(!store.contains(RUST_HASH));
+
+    // Corrupted blobs are detected
+    fs::write(store.blob_path(HELLO_HASH), "Corrupted").unwrap();
+    assert!(store.verify(HELLO_HASH, HashAlgorithm::Sha256).is_err());
+}
+
+#[test]
+fn blob_store_rejects_non_cryptographic_hashes() {
+    let test_dir = initialize_test_dir("blob_store_rejects_non_cryptographic_hashes");
+    let store = BlobStore::new(&Path::new(&test_dir).join("blobs"));
+    let file = format!("{}/file.txt", test_dir);
+    fs::write(&file, "Hello, world!").unwrap();
+
+    let hash_code = HashAlgorithm::Xxh3.digest(b"Hello, world!");
+    assert!(
+        store
+            .put_file(&hash_code, Path::new(&file), HashAlgorithm::Xxh3)
+            .is_err()
+    );
+    assert!(!store.contains(&hash_code));
+    assert!(BlobStore::check_algorithm(HashAlgorithm::Blake3).is_ok());
+}
+
+#[sqlx::test]
+async fn blob_store_collect_garbage(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("blob_store_collect_garbage");
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&test_dir, &db).await;
+    let store = BlobStore::new(&Path::new(&test_dir).join("blobs"));
+    let file1 = format!("{}/file1.txt", test_dir);
+    let file2 = format!("{}/file2.txt", test_dir);
+    fs::write(&file1, "Hello, world!").unwrap();
+    fs::write(&file2, "Hello, Rust!").unwrap();
+    store
+        .put_file(HELLO_HASH, Path::new(&file1), HashAlgorithm::Sha256)
+        .unwrap();
+    store
+        .put_file(RUST_HASH, Path::new(&file2), HashAlgorithm::Sha256)
+        .unwrap();
+
+    for (version, blob_hash) in [("0.0.2", HELLO_HASH), ("0.0.3", RUST_HASH)] {
+        let release = Release {
+            app_id: app.id,
+            version: version.to_string(),
+            base_version: None,
+            hash_code: blob_hash.to_string(),
+            patch_path: None,
+            blob_hashes: vec![blob_hash.to_string()],
+            parts: Vec::new(),
+            keeps_snapshot: false,
+        };
+        db.commit_application_update(&release, &[]).await.unwrap();
+    }
+
+    // Every blob is still referenced
+    cli::collect_garbage(Some(&store), None, None, &db)
+        .await
+        .unwrap();
+    assert_eq!(store.list_blobs().unwrap(), vec![RUST_HASH, HELLO_HASH]);
+
+    // Only keep the latest version
+    cli::collect_garbage(Some(&store), None, Some(1), &db)
+        .await
+        .unwrap();
+    assert_eq!(store.list_blobs().unwrap(), vec![RUST_HASH]);
+    let versions = db.list_versions(app.id).await.unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].version, "0.0.3");
+}
+
+#[sqlx::test]
+async fn collect_garbage_removes_patches_of_pruned_versions(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("collect_garbage_removes_patches_of_pruned_versions");
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&test_dir, &db).await;
+    let beta = db
+        .add_application(
+            "Test App",
+            &AppTarget::new("beta", DEFAULT_PLATFORM),
+            "0.0.1",
+            AppSettings::new(VersionScheme::Semver),
+            Path::new(&test_dir),
+        )
+        .await
+        .unwrap();
+    let patch_path = |version: &str| format!("{}/{}_update.zip", test_dir, version);
+    for version in ["0.0.2", "0.0.3", "0.0.4"] {
+        fs::write(patch_path(version), "Part 1").unwrap();
+    }
+    fs::write(format!("{}/0.0.2_update.part2.zip", test_dir), "Part 2").unwrap();
+    // The patch of 0.0.3 was promoted to the beta channel
+    let releases = [
+        (app.id, "0.0.2"),
+        (app.id, "0.0.3"),
+        (app.id, "0.0.4"),
+        (beta.id, "0.0.3"),
+    ];
+    for (app_id, version) in releases {
+        let release = Release {
+            app_id,
+            version: version.to_string(),
+            base_version: None,
+            hash_code: format!("hash_{}", version),
+            patch_path: Some(patch_path(version)),
+            blob_hashes: Vec::new(),
+            parts: Vec::new(),
+            keeps_snapshot: false,
+        };
+        db.commit_application_update(&release, &[]).await.unwrap();
+    }
+
+    let store = BlobStore::new(&Path::new(&test_dir).join("blobs"));
+    cli::collect_garbage(Some(&store), None, Some(1), &db)
+        .await
+        .unwrap();
+    let mut files: Vec<String> = fs::read_dir(&test_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".zip"))
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["0.0.3_update.zip", "0.0.4_update.zip"]);
+}
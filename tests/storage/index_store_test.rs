@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use secret_online_patcher::{
+    indexer::chunking::FileChunk,
+    storage::{
+        application_data::{AppSettings, AppTarget},
+        file_index::FileIndex,
+        index_store::{IndexStore, MemoryIndexStore},
+        version_scheme::VersionScheme,
+    },
+};
+use sqlx::SqlitePool;
+
+use crate::common::test_util::{initialize_test_app, initialize_test_db};
+
+fn chunk(offset: u64, hash_code: &str) -> FileChunk {
+    FileChunk {
+        offset,
+        length: 16,
+        hash_code: hash_code.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn memory_index_store_test() {
+    let store = MemoryIndexStore::new();
+    let target = AppTarget::default();
+    let app = store
+        .add_application(
+            "Test App",
+            &target,
+            "1.0.0",
+            AppSettings::new(VersionScheme::Semver),
+            Path::new("/var/app"),
+        )
+        .await
+        .unwrap();
+    assert!(
+        store
+            .add_application(
+                "Test App",
+                &target,
+                "1.0.0",
+                AppSettings::new(VersionScheme::Semver),
+                Path::new("/var/app"),
+            )
+            .await
+            .is_err()
+    );
+    store
+        .update_application(app.id, "1.1.0", "abcd")
+        .await
+        .unwrap();
+    let updated = store
+        .get_application("Test App", &target)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(updated.version, "1.1.0");
+    assert_eq!(updated.hash_code.as_deref(), Some("abcd"));
+
+    for (path, file_type) in [
+        ("/var/app/file1.txt", "FILE"),
+        ("/var/app/subdir", "DIRECTORY"),
+        ("/var/app/subdir/file2.txt", "FILE"),
+        ("/var/app2/file3.txt", "FILE"),
+    ] {
+        let mut index = FileIndex::mock(path, file_type);
+        index.app_id = app.id;
+        store.upsert_file_index(&index).await.unwrap();
+    }
+    let files = store
+        .get_files_in_directory(app.id, "/var/app")
+        .await
+        .unwrap();
+    assert_eq!(files.len(), 3);
+    assert!(files.iter().all(|f| f.file_path.starts_with("/var/app/")));
+
+    // Updating the index of a file clears its chunks
+    let file_path = "/var/app/subdir/file2.txt";
+    store
+        .set_file_chunks(app.id, file_path, &[chunk(0, "c1"), chunk(16, "c2")])
+        .await
+        .unwrap();
+    let chunks = store.list_file_chunks(app.id).await.unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[1].chunk_index, 1);
+    assert_eq!(chunks[1].chunk_offset, 16);
+    let index = store
+        .get_file_index(app.id, file_path)
+        .await
+        .unwrap()
+        .unwrap();
+    store.upsert_file_index(&index).await.unwrap();
+    assert!(store.list_file_chunks(app.id).await.unwrap().is_empty());
+
+    assert!(store.delete_file_index(app.id, file_path).await.unwrap());
+    assert!(!store.delete_file_index(app.id, file_path).await.unwrap());
+
+    store.remove_application("Test App", &target).await.unwrap();
+    assert!(store.list_applications().await.unwrap().is_empty());
+    assert!(store.list_file_index(app.id).await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn database_index_store_reports_failed_writes(db_pool: SqlitePool) {
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app("/var/app", &db).await;
+    let store: &dyn IndexStore = &db;
+    store
+        .update_application(app.id, "1.1.0", "abcd")
+        .await
+        .unwrap();
+
+    // Writes to a closed database fail instead of being reported as done
+    db_pool.close().await;
+    assert!(
+        store
+            .update_application(app.id, "1.2.0", "efgh")
+            .await
+            .is_err()
+    );
+    assert!(
+        store
+            .remove_application(&app.name, &app.target())
+            .await
+            .is_err()
+    );
+}
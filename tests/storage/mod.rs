@@ -0,0 +1,5 @@
+mod blob_store_test;
+mod index_store_test;
+mod patch_zip_test;
+mod patcher_db_test;
+mod version_manifest_test;
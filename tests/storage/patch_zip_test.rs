@@ -0,0 +1,523 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use secret_online_patcher::{
+    cancellation::{CancellationToken, Cancelled},
+    indexer::{
+        chunking::ChunkingConfig, dir_hasher::DirHasher, indexer_config::IndexerConfig,
+        staged_index::StagedIndex,
+    },
+    progress::ProgressStage,
+    storage::{
+        application_data::Application, blob_store::BlobStore, patch_compression::PatchCompression,
+        patch_config::PatchConfig, patch_reader::PatchReader, patch_zip::PatchZip,
+        patcher_db::PatcherDatabase,
+    },
+};
+use sqlx::SqlitePool;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::common::test_util::{
+    RecordingReporter, incompressible_bytes, initialize_test_app, initialize_test_db,
+    initialize_test_dir,
+};
+
+async fn create_patch(
+    app: &Application,
+    out_dir: &str,
+    config: PatchConfig,
+    db: &PatcherDatabase,
+) -> PathBuf {
+    let indexer_config = IndexerConfig::new(app.id, db.clone(), true);
+    let hash_result = DirHasher::new(indexer_config)
+        .dir_hash(&app.source_path)
+        .await
+        .expect("failed to hash directory");
+    let (_, changed_files) = hash_result.finalize().await.unwrap();
+
+    let mut zip = PatchZip::new(Path::new(out_dir), app, config);
+    zip.initialize_patch("0.0.2").await.unwrap();
+    for change in &changed_files {
+        zip.append_changed_file(change).await.unwrap();
+    }
+    zip.finalize().await.unwrap()
+}
+
+#[sqlx::test]
+async fn patch_zip_validates_after_finalize(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_validates_after_finalize");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(format!("{}/subdir", app_dir)).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/file1.txt", app_dir), "File 1 content").unwrap();
+    fs::write(format!("{}/subdir/file2.txt", app_dir), "File 2 content").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let zip_path = create_patch(&app, &out_dir, PatchConfig::default(), &db).await;
+    assert_eq!(
+        zip_path,
+        Path::new(&out_dir).join("Test_App_0.0.2_update.zip")
+    );
+
+    let reader = PatchReader::new(&zip_path);
+    reader.validate().await.expect("patch should be valid");
+
+    // Verify the embedded database records the zip entries
+    let patch_db = reader.open_patch_db().await.unwrap();
+    let patch_info = patch_db.db.get_patch_info().await.unwrap().unwrap();
+    assert_eq!(patch_info.base_version, "0.0.1");
+    assert_eq!(patch_info.patch_version, "0.0.2");
+    let file_changes = patch_db.db.list_file_changes(patch_info.id).await.unwrap();
+    assert_eq!(file_changes.len(), 3);
+    let mut entry_names: Vec<_> = file_changes
+        .iter()
+        .filter_map(|change| change.entry_name.clone())
+        .collect();
+    entry_names.sort();
+    assert_eq!(
+        entry_names,
+        vec!["Test App/file1.txt", "Test App/subdir/file2.txt"]
+    );
+}
+
+#[sqlx::test]
+async fn patch_zip_fails_validation_when_corrupted(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_fails_validation_when_corrupted");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(
+        format!("{}/file1.txt", app_dir),
+        "File 1 content ".repeat(100),
+    )
+    .unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let zip_path = create_patch(&app, &out_dir, PatchConfig::default(), &db).await;
+
+    // Flip a byte inside the data of the first entry
+    let mut bytes = fs::read(&zip_path).unwrap();
+    let name_len = u16::from_le_bytes([bytes[26], bytes[27]]) as usize;
+    let extra_len = u16::from_le_bytes([bytes[28], bytes[29]]) as usize;
+    let data_start = 30 + name_len + extra_len;
+    bytes[data_start + 2] ^= 0xff;
+    fs::write(&zip_path, bytes).unwrap();
+
+    let result = PatchReader::new(&zip_path).validate().await;
+    assert!(result.is_err());
+}
+
+fn entry_compression(zip_path: &Path, entry_name: &str) -> CompressionMethod {
+    let mut archive = ZipArchive::new(File::open(zip_path).unwrap()).unwrap();
+    archive.by_name(entry_name).unwrap().compression()
+}
+
+#[sqlx::test]
+async fn patch_zip_with_configured_compression(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_with_configured_compression");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/text.txt", app_dir), "Text content ".repeat(100)).unwrap();
+    fs::write(format!("{}/image.png", app_dir), "Not really an image").unwrap();
+    fs::write(
+        format!("{}/data.bin", app_dir),
+        incompressible_bytes(16 * 1024, 0),
+    )
+    .unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let config = PatchConfig::new(PatchCompression::Zstd(Some(19)), true, None, None);
+    let zip_path = create_patch(&app, &out_dir, config, &db).await;
+    PatchReader::new(&zip_path).validate().await.unwrap();
+
+    assert_eq!(
+        entry_compression(&zip_path, "Test App/text.txt"),
+        CompressionMethod::Zstd
+    );
+    // Already compressed files are stored as they are
+    assert_eq!(
+        entry_compression(&zip_path, "Test App/image.png"),
+        CompressionMethod::Stored
+    );
+    assert_eq!(
+        entry_compression(&zip_path, "Test App/data.bin"),
+        CompressionMethod::Stored
+    );
+    assert_eq!(
+        entry_compression(&zip_path, "Test App/patch.db"),
+        CompressionMethod::Zstd
+    );
+}
+
+#[sqlx::test]
+async fn patch_zip_without_auto_store(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_without_auto_store");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/image.png", app_dir), "Not really an image").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let config = PatchConfig::new(PatchCompression::Bzip2(None), false, None, None);
+    let zip_path = create_patch(&app, &out_dir, config, &db).await;
+    PatchReader::new(&zip_path).validate().await.unwrap();
+
+    assert_eq!(
+        entry_compression(&zip_path, "Test App/image.png"),
+        CompressionMethod::Bzip2
+    );
+}
+
+#[sqlx::test]
+async fn patch_zip_split_into_parts(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_split_into_parts");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    for i in 1..=3 {
+        fs::write(
+            format!("{}/file{}.bin", app_dir, i),
+            incompressible_bytes(1000, i),
+        )
+        .unwrap();
+    }
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let config = PatchConfig::new(PatchCompression::default(), true, Some(1500), None);
+    let zip_path = create_patch(&app, &out_dir, config, &db).await;
+
+    let reader = PatchReader::new(&zip_path);
+    assert_eq!(
+        reader.part_paths(),
+        vec![
+            Path::new(&out_dir).join("Test_App_0.0.2_update.zip"),
+            Path::new(&out_dir).join("Test_App_0.0.2_update.part2.zip"),
+            Path::new(&out_dir).join("Test_App_0.0.2_update.part3.zip"),
+        ]
+    );
+    reader.validate().await.expect("patch should be valid");
+
+    let patch_db = reader.open_patch_db().await.unwrap();
+    let patch_info = patch_db.db.get_patch_info().await.unwrap().unwrap();
+    assert_eq!(patch_info.part_count, 3);
+    let file_changes = patch_db.db.list_file_changes(patch_info.id).await.unwrap();
+    let mut part_numbers: Vec<_> = file_changes
+        .iter()
+        .filter_map(|change| change.part_number)
+        .collect();
+    part_numbers.sort();
+    assert_eq!(part_numbers, vec![1, 2, 3]);
+
+    // A patch with a missing part is not valid
+    fs::remove_file(Path::new(&out_dir).join("Test_App_0.0.2_update.part2.zip")).unwrap();
+    assert!(reader.validate().await.is_err());
+}
+
+#[sqlx::test]
+async fn patch_zip_rebuilt_with_fewer_parts(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_rebuilt_with_fewer_parts");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    for i in 1..=3 {
+        fs::write(
+            format!("{}/file{}.bin", app_dir, i),
+            incompressible_bytes(1000, i),
+        )
+        .unwrap();
+    }
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let config = PatchConfig::new(PatchCompression::default(), true, Some(1500), None);
+    let zip_path = create_patch(&app, &out_dir, config, &db).await;
+    assert_eq!(PatchReader::new(&zip_path).part_paths().len(), 3);
+
+    // Rebuild the same patch without a part limit, from a fresh index
+    db.remove_application(&app.name, &app.target())
+        .await
+        .unwrap();
+    let app = initialize_test_app(&app_dir, &db).await;
+    let zip_path = create_patch(&app, &out_dir, PatchConfig::default(), &db).await;
+
+    // The parts of the previous build are gone
+    let reader = PatchReader::new(&zip_path);
+    assert_eq!(reader.part_paths(), vec![zip_path.clone()]);
+    reader.validate().await.expect("patch should be valid");
+    assert!(
+        !Path::new(&out_dir)
+            .join("Test_App_0.0.2_update.part2.zip")
+            .exists()
+    );
+}
+
+#[sqlx::test]
+async fn patch_zip_stores_identical_files_once(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_stores_identical_files_once");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(format!("{}/copy", app_dir)).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/file1.txt", app_dir), "Same content").unwrap();
+    fs::write(format!("{}/copy/file1.txt", app_dir), "Same content").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let zip_path = create_patch(&app, &out_dir, PatchConfig::default(), &db).await;
+    let reader = PatchReader::new(&zip_path);
+    reader.validate().await.expect("patch should be valid");
+
+    // Both files point to the same entry, which is stored once
+    let patch_db = reader.open_patch_db().await.unwrap();
+    let patch_info = patch_db.db.get_patch_info().await.unwrap().unwrap();
+    let file_changes = patch_db.db.list_file_changes(patch_info.id).await.unwrap();
+    let entry_names: Vec<_> = file_changes
+        .iter()
+        .filter_map(|change| change.entry_name.clone())
+        .collect();
+    assert_eq!(entry_names.len(), 2);
+    assert_eq!(entry_names[0], entry_names[1]);
+    let archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    assert_eq!(archive.len(), 2);
+}
+
+#[sqlx::test]
+async fn patch_zip_with_blob_store(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_with_blob_store");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(format!("{}/copy", app_dir)).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/file1.txt", app_dir), "Hello, world!").unwrap();
+    fs::write(format!("{}/copy/file1.txt", app_dir), "Hello, world!").unwrap();
+    fs::write(format!("{}/file2.txt", app_dir), "Hello, Rust!").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let blob_store = BlobStore::new(&Path::new(&test_dir).join("blobs"));
+    let config = PatchConfig::new(
+        PatchCompression::default(),
+        true,
+        None,
+        Some(blob_store.clone()),
+    );
+    let zip_path = create_patch(&app, &out_dir, config, &db).await;
+
+    // Only the patch database is in the zip, contents are in the blob store
+    let archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    assert_eq!(archive.len(), 1);
+    assert_eq!(
+        blob_store.list_blobs().unwrap(),
+        vec![
+            "12a967da1e8654e129d41e3c016f14e81e751e073feb383125bf82080256ca19",
+            "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3",
+        ]
+    );
+
+    // Validating requires the blob store
+    assert!(PatchReader::new(&zip_path).validate().await.is_err());
+    let reader = PatchReader::with_blob_store(&zip_path, &blob_store);
+    reader.validate().await.expect("patch should be valid");
+
+    let patch_db = reader.open_patch_db().await.unwrap();
+    let patch_info = patch_db.db.get_patch_info().await.unwrap().unwrap();
+    assert!(patch_info.uses_blob_store);
+
+    // A missing blob makes the patch invalid
+    blob_store
+        .remove("12a967da1e8654e129d41e3c016f14e81e751e073feb383125bf82080256ca19")
+        .unwrap();
+    assert!(reader.validate().await.is_err());
+}
+
+#[sqlx::test]
+async fn patch_zip_ships_only_new_chunks(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_ships_only_new_chunks");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    let content = incompressible_bytes(64 * 1024, 1);
+    fs::write(format!("{}/archive.pak", app_dir), &content).unwrap();
+    fs::write(format!("{}/small.txt", app_dir), "Too small to be chunked").unwrap();
+
+    // Index the base version with chunking enabled
+    let chunking = ChunkingConfig::new(1024, 64, 256, 1024).unwrap();
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let indexer_config = IndexerConfig::new(app.id, db.clone(), true).with_chunking(Some(chunking));
+    let (_, _) = DirHasher::new(indexer_config)
+        .dir_hash(&app.source_path)
+        .await
+        .unwrap()
+        .finalize()
+        .await
+        .unwrap();
+    let base_chunks = db.list_file_chunks(app.id).await.unwrap();
+    assert!(base_chunks.len() > 1);
+    assert!(
+        base_chunks
+            .iter()
+            .all(|chunk| chunk.file_path.ends_with("archive.pak"))
+    );
+
+    // Insert a few bytes at the start of the archive
+    let mut new_content = b"new header".to_vec();
+    new_content.extend_from_slice(&content);
+    fs::write(format!("{}/archive.pak", app_dir), &new_content).unwrap();
+
+    let staged_index = StagedIndex::new();
+    let indexer_config = IndexerConfig::staged(app.id, db.clone(), staged_index.clone())
+        .with_chunking(Some(chunking));
+    let (_, changed_files) = DirHasher::new(indexer_config)
+        .dir_hash(&app.source_path)
+        .await
+        .unwrap()
+        .finalize()
+        .await
+        .unwrap();
+    assert_eq!(changed_files.len(), 1);
+    let new_chunks = changed_files[0].chunks.clone().unwrap();
+
+    let mut zip = PatchZip::new(Path::new(&out_dir), &app, PatchConfig::default());
+    zip.set_base_chunks(base_chunks);
+    zip.initialize_patch("0.0.2").await.unwrap();
+    zip.append_changed_file(&changed_files[0]).await.unwrap();
+    let zip_path = zip.finalize().await.unwrap();
+
+    let reader = PatchReader::new(&zip_path);
+    reader.validate().await.expect("patch should be valid");
+
+    // Only the edited chunks are shipped, the others are taken from the base version
+    let patch_db = reader.open_patch_db().await.unwrap();
+    let patch_info = patch_db.db.get_patch_info().await.unwrap().unwrap();
+    let file_changes = patch_db.db.list_file_changes(patch_info.id).await.unwrap();
+    let file_chunks = patch_db.db.list_file_chunks(patch_info.id).await.unwrap();
+    let base_chunks = patch_db.db.list_base_chunks().await.unwrap();
+    patch_db.db.close().await;
+    assert_eq!(file_changes.len(), 1);
+    assert_eq!(file_changes[0].entry_name, None);
+    assert_eq!(file_chunks.len(), new_chunks.len());
+    let shipped = file_chunks
+        .iter()
+        .filter(|chunk| chunk.entry_name.is_some())
+        .count();
+    assert!(shipped >= 1);
+    assert!(shipped * 5 <= file_chunks.len());
+    assert_eq!(base_chunks.len(), file_chunks.len() - shipped);
+    let total_length: i64 = file_chunks.iter().map(|chunk| chunk.chunk_length).sum();
+    assert_eq!(total_length, new_content.len() as i64);
+
+    let archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    assert_eq!(archive.len(), shipped + 1);
+    assert!(
+        archive
+            .file_names()
+            .filter(|name| *name != "Test App/patch.db")
+            .all(|name| name.starts_with("Test App/.chunks/"))
+    );
+
+    // Committing the staged index replaces the chunks of the archive
+    db.commit_index_updates(&staged_index.take()).await.unwrap();
+    let indexed_chunks = db.list_file_chunks(app.id).await.unwrap();
+    let indexed_hashes: Vec<_> = indexed_chunks.iter().map(|c| &c.hash_code).collect();
+    let new_hashes: Vec<_> = new_chunks.iter().map(|c| &c.hash_code).collect();
+    assert_eq!(indexed_hashes, new_hashes);
+}
+
+#[sqlx::test]
+async fn patch_zip_reports_progress(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_reports_progress");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(format!("{}/subdir", app_dir)).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/file1.txt", app_dir), "File 1 content").unwrap();
+    fs::write(format!("{}/subdir/file2.txt", app_dir), "File 2").unwrap();
+    // Identical content is only compressed once
+    fs::write(format!("{}/subdir/file3.txt", app_dir), "File 2").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let indexer_config = IndexerConfig::new(app.id, db.clone(), true);
+    let hash_result = DirHasher::new(indexer_config)
+        .dir_hash(&app.source_path)
+        .await
+        .expect("failed to hash directory");
+    let (_, changed_files) = hash_result.finalize().await.unwrap();
+
+    let reporter = Arc::new(RecordingReporter::default());
+    let config = PatchConfig::default().with_progress(Some(reporter.clone()));
+    let mut zip = PatchZip::new(Path::new(&out_dir), &app, config);
+    zip.set_total_changes(changed_files.len());
+    zip.initialize_patch("0.0.2").await.unwrap();
+    for change in &changed_files {
+        zip.append_changed_file(change).await.unwrap();
+    }
+    zip.finalize().await.unwrap();
+
+    let finished = reporter.finished.lock().unwrap().clone().unwrap();
+    assert_eq!(finished.stage, ProgressStage::Packaging);
+    // The subdirectory is a change too
+    assert_eq!(finished.total_files, Some(4));
+    assert_eq!(finished.files_scanned, 4);
+    assert_eq!(finished.bytes_compressed, 20);
+    assert_eq!(finished.bytes_hashed, 0);
+    let updates = reporter.updates.lock().unwrap();
+    assert!(
+        updates
+            .windows(2)
+            .all(|pair| pair[0].files_scanned <= pair[1].files_scanned)
+    );
+}
+
+#[sqlx::test]
+async fn patch_zip_stops_when_cancelled(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("patch_zip_stops_when_cancelled");
+    let app_dir = format!("{}/app", test_dir);
+    let out_dir = format!("{}/patches", test_dir);
+    fs::create_dir_all(&app_dir).unwrap();
+    fs::create_dir_all(&out_dir).unwrap();
+    fs::write(format!("{}/file1.txt", app_dir), "File 1 content").unwrap();
+    fs::write(format!("{}/file2.txt", app_dir), "File 2 content").unwrap();
+
+    let db = initialize_test_db(&db_pool).await;
+    let app = initialize_test_app(&app_dir, &db).await;
+    let indexer_config = IndexerConfig::new(app.id, db.clone(), true);
+    let hash_result = DirHasher::new(indexer_config)
+        .dir_hash(&app.source_path)
+        .await
+        .expect("failed to hash directory");
+    let (_, changed_files) = hash_result.finalize().await.unwrap();
+
+    let cancellation = CancellationToken::new();
+    let config = PatchConfig::default().with_cancellation(cancellation.clone());
+    let mut zip = PatchZip::new(Path::new(&out_dir), &app, config);
+    zip.initialize_patch("0.0.2").await.unwrap();
+    zip.append_changed_file(&changed_files[0]).await.unwrap();
+    cancellation.cancel();
+    let error = zip
+        .append_changed_file(&changed_files[1])
+        .await
+        .unwrap_err();
+    assert!(Cancelled::is(&error));
+
+    // The partial patch is removed
+    zip.abort().await;
+    assert_eq!(fs::read_dir(&out_dir).unwrap().count(), 0);
+}
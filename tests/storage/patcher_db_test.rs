@@ -0,0 +1,147 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use secret_online_patcher::{
+    cancellation::CancellationToken,
+    client::update_client::index_install,
+    indexer::{
+        dir_hasher::DirHasher,
+        hash_algorithm::HashAlgorithm,
+        indexer_config::IndexerConfig,
+        merkle::{self, LEGACY_FORMAT_VERSION, MERKLE_FORMAT_VERSION, MerkleEntry},
+    },
+    storage::{application_data::AppTarget, patcher_db::PatcherDatabase},
+};
+use sqlx::{Executor, SqlitePool};
+
+use crate::common::test_util::initialize_test_dir;
+
+// Schema of databases created before the Merkle tree format
+const LEGACY_SCHEMA: &str = "
+    CREATE TABLE applications (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        hash_code TEXT,
+        install_path TEXT NOT NULL
+    );
+
+    CREATE UNIQUE INDEX ux_app_name ON applications (name);
+
+    CREATE TABLE file_index (
+        app_id INTEGER NOT NULL,
+        file_path TEXT NOT NULL,
+        file_type TEXT CHECK( file_type IN ('FILE','DIRECTORY') ) NOT NULL,
+        hash_code TEXT NOT NULL,
+        modified_time TIMESTAMP,
+        PRIMARY KEY (app_id, file_path),
+        FOREIGN KEY (app_id) REFERENCES applications (id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE app_versions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        app_id INTEGER NOT NULL,
+        version TEXT NOT NULL,
+        base_version TEXT,
+        hash_code TEXT NOT NULL,
+        patch_path TEXT,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        FOREIGN KEY (app_id) REFERENCES applications (id) ON DELETE CASCADE
+    );
+";
+
+#[sqlx::test]
+async fn initialize_upgrades_legacy_database(db_pool: SqlitePool) {
+    let test_dir = initialize_test_dir("initialize_upgrades_legacy_database");
+    let app_dir = fs::canonicalize(&test_dir).unwrap();
+    fs::create_dir_all(app_dir.join("data/nested")).unwrap();
+    fs::write(app_dir.join("file.txt"), "Root file").unwrap();
+    fs::write(app_dir.join("data/nested/inner.txt"), "Inner file").unwrap();
+
+    // Index the application the way it was indexed before the Merkle tree format
+    let (_, entries) = index_install(&app_dir, HashAlgorithm::Sha256, &CancellationToken::new())
+        .await
+        .unwrap();
+    let mut tree: BTreeMap<PathBuf, MerkleEntry> = entries
+        .iter()
+        .map(|entry| {
+            let path = PathBuf::from(&entry.file_path);
+            let hash_code = entry.hash_code.as_deref().unwrap_or_default();
+            let merkle_entry = MerkleEntry::new(&path, &entry.file_type, 0, hash_code);
+            (path, merkle_entry)
+        })
+        .collect();
+    let legacy_hash = merkle::rehash_tree(
+        HashAlgorithm::Sha256,
+        LEGACY_FORMAT_VERSION,
+        &app_dir,
+        &mut tree,
+    )
+    .unwrap();
+
+    db_pool.execute(LEGACY_SCHEMA).await.unwrap();
+    sqlx::query(
+        "INSERT INTO applications (name, version, hash_code, install_path) VALUES (?, ?, ?, ?)",
+    )
+    .bind("Legacy App")
+    .bind("1.1.0")
+    .bind(&legacy_hash)
+    .bind(app_dir.to_string_lossy())
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    for (path, entry) in &tree {
+        let modified_time = DateTime::<Utc>::from(fs::metadata(path).unwrap().modified().unwrap());
+        sqlx::query("INSERT INTO file_index VALUES (1, ?, ?, ?, ?)")
+            .bind(path.to_string_lossy())
+            .bind(&entry.file_type)
+            .bind(&entry.hash_code)
+            .bind(modified_time.naive_utc())
+            .execute(&db_pool)
+            .await
+            .unwrap();
+    }
+    let versions = [
+        ("1.0.0", "old_release_hash"),
+        ("1.1.0", legacy_hash.as_str()),
+    ];
+    for (version, hash_code) in versions {
+        sqlx::query("INSERT INTO app_versions (app_id, version, hash_code) VALUES (1, ?, ?)")
+            .bind(version)
+            .bind(hash_code)
+            .execute(&db_pool)
+            .await
+            .unwrap();
+    }
+
+    let db = PatcherDatabase::new(db_pool.clone());
+    db.initialize().await.unwrap();
+
+    let app = db
+        .get_application("Legacy App", &AppTarget::default())
+        .await
+        .unwrap()
+        .expect("application should be kept");
+    assert_eq!(app.source_path, app_dir);
+    let hash_code = app.hash_code.clone().unwrap();
+    assert_ne!(hash_code, legacy_hash);
+
+    // The upgraded index matches a fresh scan, without reporting any change
+    let config = IndexerConfig::new(app.id, db.clone(), false);
+    let (scanned_hash, changes) = DirHasher::new(config)
+        .dir_hash(&app_dir)
+        .await
+        .unwrap()
+        .finalize()
+        .await
+        .unwrap();
+    assert_eq!(scanned_hash, hash_code);
+    assert!(changes.is_empty(), "unexpected changes: {:?}", changes);
+
+    // The current version is rehashed, older ones keep the format they were released with
+    let versions = db.list_versions(app.id).await.unwrap();
+    assert_eq!(versions[0].hash_code, "old_release_hash");
+    assert_eq!(versions[0].hash_format, LEGACY_FORMAT_VERSION);
+    assert_eq!(versions[1].hash_code, hash_code);
+    assert_eq!(versions[1].hash_format, MERKLE_FORMAT_VERSION);
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use secret_online_patcher::{
+    indexer::file_change::{FileChange, FileChangeType},
+    storage::{blob_store::BlobStore, version_manifest::restore_snapshot},
+};
+
+use crate::common::test_util::initialize_test_dir;
+
+fn created_dir(file_path: String) -> FileChange {
+    FileChange {
+        file_path,
+        file_type: "DIRECTORY".to_string(),
+        change_type: FileChangeType::Created,
+        hash_code: None,
+        mode: None,
+        chunks: None,
+    }
+}
+
+#[test]
+fn restore_snapshot_stays_under_root() {
+    let test_dir = initialize_test_dir("restore_snapshot_stays_under_root");
+    let root = Path::new(&test_dir).join("staging");
+    let store = BlobStore::new(&Path::new(&test_dir).join("snapshots"));
+
+    let inside = created_dir(format!("{}/staging/data", test_dir));
+    restore_snapshot(&store, &root, &[inside]).unwrap();
+    assert!(root.join("data").is_dir());
+
+    // Directories outside of the root are refused before being created
+    for outside in [
+        format!("{}/outside", test_dir),
+        format!("{}/staging/../escaped", test_dir),
+    ] {
+        assert!(restore_snapshot(&store, &root, &[created_dir(outside)]).is_err());
+    }
+    assert!(!Path::new(&test_dir).join("outside").exists());
+    assert!(!Path::new(&test_dir).join("escaped").exists());
+}